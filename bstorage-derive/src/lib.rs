@@ -0,0 +1,245 @@
+//! Derive macros of `bstorage::Record` and `bstorage::StorageKey`. Use them via the `derive`
+//! feature of `bstorage`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, LitStr, Type};
+
+/// Derives `bstorage::Record` for a struct with named fields. The key of the record is defined by
+/// the template `#[record(key = "...")]`, where `{field}` placeholders are replaced with values
+/// of fields. Types of placeholder fields make up the id of the record: the type of the field for a
+/// single placeholder, or a tuple of types in the order of placeholders.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, Record)]
+/// #[record(key = "user:{id}")]
+/// struct User {
+///     id: u64,
+///     name: String,
+/// }
+/// ```
+#[proc_macro_derive(Record, attributes(record))]
+pub fn derive_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Splits the key template into literal parts and names of placeholders.
+fn parse_template(template: &LitStr) -> Result<(String, Vec<String>), Error> {
+    let value = template.value();
+    let mut format = String::new();
+    let mut names = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(Error::new(
+                        template.span(),
+                        format!("Invalid placeholder \"{{{name}}}\" in the key template"),
+                    ));
+                }
+                format.push_str("{}");
+                names.push(name);
+            }
+            '}' => {
+                return Err(Error::new(
+                    template.span(),
+                    "Unmatched \"}\" in the key template",
+                ));
+            }
+            c => format.push(c),
+        }
+    }
+    if names.is_empty() {
+        return Err(Error::new(
+            template.span(),
+            "Key template should have at least one placeholder, e.g. \"user:{id}\"",
+        ));
+    }
+    Ok((format, names))
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let mut template: Option<LitStr> = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("record")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("key") {
+                template = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("Unsupported attribute; expected `key = \"...\"`"))
+            }
+        })?;
+    }
+    let template = template.ok_or_else(|| {
+        Error::new(
+            Span::call_site(),
+            "Missing key template: #[record(key = \"...\")]",
+        )
+    })?;
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Record can be derived only for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Record can be derived only for structs with named fields",
+        ));
+    };
+    let (format, names) = parse_template(&template)?;
+    let mut idents = Vec::new();
+    let mut types: Vec<&Type> = Vec::new();
+    for name in names.iter() {
+        let field = fields
+            .named
+            .iter()
+            .find(|f| f.ident.as_ref().is_some_and(|ident| ident == name))
+            .ok_or_else(|| {
+                Error::new(template.span(), format!("Field \"{name}\" doesn't exist"))
+            })?;
+        idents.push(field.ident.clone());
+        types.push(&field.ty);
+    }
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let (id, key_of) = if types.len() == 1 {
+        let ty = types[0];
+        (quote! { #ty }, quote! { format!(#format, id) })
+    } else {
+        let indexes = (0..types.len()).map(syn::Index::from);
+        (
+            quote! { (#(#types),*) },
+            quote! { format!(#format, #(id.#indexes),*) },
+        )
+    };
+    Ok(quote! {
+        impl #impl_generics ::bstorage::Record for #ident #ty_generics #where_clause {
+            type Id = #id;
+
+            fn key(&self) -> String {
+                format!(#format, #(self.#idents),*)
+            }
+
+            fn key_of(id: &Self::Id) -> String {
+                #key_of
+            }
+        }
+    })
+}
+
+/// Derives `bstorage::StorageKey` for newtypes and enums.
+///
+/// * For a newtype (`struct UserId(u64)`) the key is the inner value formatted with `Display`.
+/// * For an enum the key of a unit variant is the name of the variant; the key of a variant with
+///   one field is the name of the variant and the value of the field separated by `:`. A variant
+///   can be renamed with `#[storage_key(rename = "...")]`.
+///
+/// All keys can be prefixed with `#[storage_key(prefix = "...")]` on the type.
+///
+/// ```ignore
+/// #[derive(StorageKey)]
+/// #[storage_key(prefix = "user:")]
+/// struct UserId(u64);
+///
+/// #[derive(StorageKey)]
+/// enum Settings {
+///     #[storage_key(rename = "theme")]
+///     Theme,
+///     Plugin(String),
+/// }
+/// ```
+#[proc_macro_derive(StorageKey, attributes(storage_key))]
+pub fn derive_storage_key(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_storage_key(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Reads the value of `#[storage_key(name = "...")]`.
+fn storage_key_attr(attrs: &[Attribute], name: &str) -> Result<Option<String>, Error> {
+    let mut value: Option<String> = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("storage_key")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(name) {
+                value = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error(format!(
+                    "Unsupported attribute; expected `{name} = \"...\"`"
+                )))
+            }
+        })?;
+    }
+    Ok(value)
+}
+
+fn expand_storage_key(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let prefix = storage_key_attr(&input.attrs, "prefix")?.unwrap_or_default();
+    let ident = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let format = format!("{prefix}{{}}");
+                quote! { ::std::borrow::Cow::Owned(format!(#format, self.0)) }
+            }
+            _ => {
+                return Err(Error::new_spanned(
+                    ident,
+                    "StorageKey can be derived only for newtypes (structs with one unnamed field)",
+                ));
+            }
+        },
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for variant in data.variants.iter() {
+                let name = storage_key_attr(&variant.attrs, "rename")?
+                    .unwrap_or_else(|| variant.ident.to_string());
+                let var = &variant.ident;
+                arms.push(match &variant.fields {
+                    Fields::Unit => {
+                        let key = format!("{prefix}{name}");
+                        quote! { Self::#var => ::std::borrow::Cow::Borrowed(#key) }
+                    }
+                    Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                        let format = format!("{prefix}{name}:{{}}");
+                        quote! { Self::#var(value) => ::std::borrow::Cow::Owned(format!(#format, value)) }
+                    }
+                    _ => {
+                        return Err(Error::new_spanned(
+                            variant,
+                            "StorageKey supports only unit variants and variants with one unnamed field",
+                        ));
+                    }
+                });
+            }
+            quote! {
+                match self {
+                    #(#arms),*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                ident,
+                "StorageKey cannot be derived for unions",
+            ));
+        }
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bstorage::StorageKey for #ident #ty_generics #where_clause {
+            fn to_key(&self) -> ::std::borrow::Cow<'_, str> {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,131 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use crate::{Event, Field, Storage, E};
+
+/// Tracking of the time of the last access to records. Reading a record updates its access time
+/// in memory; access times are persisted in the map of the storage with the next write of the map
+/// or with `Storage::flush()`, so reads never cause extra writes by themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessTracking {
+    /// Accesses, which happen within this period since the previous recorded access, are not
+    /// recorded. It keeps frequently read records from marking the map as changed all the time.
+    pub resolution: Duration,
+}
+
+impl Default for AccessTracking {
+    fn default() -> Self {
+        Self {
+            resolution: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Storage {
+    /// Updates the access time of the field, if access times are tracked (see
+    /// `StorageOptions::access` and `StorageOptions::eviction`).
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - The field, which has been accessed.
+    pub(crate) fn accessed(&self, field: &Field) {
+        let resolution = match (&self.options.access, &self.options.eviction) {
+            (_, Some(_)) => Duration::ZERO,
+            (Some(tracking), None) => tracking.resolution,
+            (None, None) => return,
+        };
+        if field.touch(resolution) {
+            self.touched.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes records, which haven't been accessed for the given period. Without access tracking
+    /// (see `StorageOptions::access`) only writes of records are taken into account. Pinned
+    /// records (see `Storage::pin()`) are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `age` - Records, which haven't been accessed for this period, are removed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns removed keys, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{AccessTracking, Storage, StorageOptions};
+    /// use std::{env::temp_dir, time::Duration};
+    /// use uuid::Uuid;
+    ///
+    /// let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+    /// let options = StorageOptions {
+    ///     access: Some(AccessTracking::default()),
+    ///     ..Default::default()
+    /// };
+    /// let mut storage = Storage::create_with_options(&storage_path, options).unwrap();
+    /// storage.set("my_record", &1u8).unwrap();
+    /// // Records used within the last day are kept
+    /// assert!(storage
+    ///     .sweep_older_than(Duration::from_secs(24 * 60 * 60))
+    ///     .unwrap()
+    ///     .is_empty());
+    /// assert!(storage.has("my_record"));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn sweep_older_than(&mut self, age: Duration) -> Result<Vec<String>, E> {
+        self.prune()?;
+        let stale = self
+            .fields
+            .iter()
+            .filter(|(_, field)| !field.meta().pinned && field.meta().accessed.elapsed() > age)
+            .map(|(key, _)| key.to_owned())
+            .collect::<Vec<String>>();
+        for key in stale.iter() {
+            self.retain(&[key])?;
+            self.discard(key)?;
+        }
+        if !stale.is_empty() {
+            self.map.write(&self.fields)?;
+            self.save_deadlines()?;
+        }
+        for key in stale.iter() {
+            self.publish(key, None);
+            self.emit(Event::Evicted {
+                key: key.to_owned(),
+            });
+        }
+        Ok(stale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AccessTracking, Storage, StorageOptions, E};
+    use std::{env::temp_dir, thread, time::Duration};
+    use uuid::Uuid;
+
+    #[test]
+    fn sweep() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            access: Some(AccessTracking {
+                resolution: Duration::ZERO,
+            }),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        storage.set("a", &1u8)?;
+        storage.set("b", &2u8)?;
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(storage.get::<u8, &str>("a")?, Some(1));
+        // Access time of "a" is persisted with flush
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        let swept = storage.sweep_older_than(Duration::from_millis(100))?;
+        assert_eq!(swept, vec![String::from("b")]);
+        assert!(storage.has("a"));
+        assert!(!storage.has("b"));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+//! Packing into and unpacking from standard tar archives (`tar` feature). Unlike `Bundle`, the
+//! archive can be inspected and repaired with common tools: each record file is an entry of the
+//! archive, and each storage folder has `manifest.json` with keys of records. Child storages are
+//! placed into `children/{name}/` as in the storage folder.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, read_dir, remove_file},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    fs,
+    nested::{children_of, CHILDREN_DIR},
+    trace::op,
+    Field, KeyHasher, KeyResolver, Map, Meta, Operation, Storage, StorageOptions, E,
+};
+
+/// Name of the manifest entry in each storage folder of the archive
+pub const TAR_MANIFEST: &str = "manifest.json";
+const MANIFEST_VERSION: u8 = 1;
+
+/// Manifest of the storage folder in the archive
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    version: u8,
+    records: Vec<ManifestRecord>,
+}
+
+/// Record of the manifest
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestRecord {
+    /// Key of the record
+    key: String,
+    /// Name of the record's entry in the folder
+    file: String,
+    /// Type tag of the value; 0 if unknown
+    tag: u64,
+    /// Size of the record's file in bytes
+    size: u64,
+}
+
+/// Creates the header of a regular file entry.
+fn header(size: u64, mtime: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header
+}
+
+/// Writes records of the storage and all its child storages into the archive.
+///
+/// # Arguments
+///
+/// * `storage` - The storage to write.
+/// * `archive` - The archive.
+/// * `prefix` - Path of the storage folder in the archive.
+///
+/// # Returns
+///
+/// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+fn write_tree<W: Write>(
+    storage: &Storage,
+    archive: &mut tar::Builder<W>,
+    prefix: &Path,
+) -> Result<(), E> {
+    let mut fields = storage.fields.iter().collect::<Vec<(&String, &Field)>>();
+    fields.sort_by_key(|(key, _)| *key);
+    let mut manifest = Manifest {
+        version: MANIFEST_VERSION,
+        records: Vec::with_capacity(fields.len()),
+    };
+    // Files shared by several keys (see `Layout::ContentAddressed`) are written once
+    let mut written: HashSet<&str> = HashSet::new();
+    for (key, field) in fields {
+        if written.insert(field.file_name()) {
+            // Records are written as they are, so encrypted values stay encrypted
+            let content = storage
+                .extract_raw(field)
+                .map_err(|e| e.record(Operation::Pack, key, &field.path(storage.cwd())))?;
+            let mut header = header(content.len() as u64, field.meta().accessed.get() / 1000);
+            archive.append_data(
+                &mut header,
+                prefix.join(field.file_name()),
+                content.as_slice(),
+            )?;
+        }
+        manifest.records.push(ManifestRecord {
+            key: key.to_owned(),
+            file: field.file_name().to_owned(),
+            tag: field.meta().tag,
+            size: field.meta().size,
+        });
+    }
+    let buffer = serde_json::to_vec_pretty(&manifest)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut header = header(buffer.len() as u64, now);
+    archive.append_data(&mut header, prefix.join(TAR_MANIFEST), buffer.as_slice())?;
+    for (name, cwd) in children_of(storage.cwd())? {
+        let child = Storage::open_with_options(cwd, storage.options.clone())?;
+        write_tree(&child, archive, &prefix.join(CHILDREN_DIR).join(name))?;
+    }
+    Ok(())
+}
+
+/// Writes the map of the unpacked storage folder based on its manifest. Record files, which
+/// aren't listed in the manifest (or all files, if the manifest is missing), are added with keys
+/// from their headers (see `KeyResolver::Embedded`).
+///
+/// # Arguments
+///
+/// * `cwd` - A path reference to the storage folder.
+///
+/// # Returns
+///
+/// * `Result<Storage, E>` - Returns the opened storage, or an error.
+fn restore_tree(cwd: &Path) -> Result<Storage, E> {
+    for (_, child) in children_of(cwd)? {
+        restore_tree(&child)?;
+    }
+    let path = cwd.join(TAR_MANIFEST);
+    if path.exists() {
+        let manifest: Manifest = serde_json::from_reader(fs::read(&path)?).map_err(|e| {
+            warn!("Manifest {path:?} cannot be read: {e}");
+            E::PackageFileInvalid(path.clone())
+        })?;
+        let mut fields = KeyHasher::default().fields();
+        for record in manifest.records {
+            let file = cwd.join(&record.file);
+            if !file.is_file() {
+                warn!(
+                    "File of record \"{}\" is missing in the archive; record is skipped",
+                    record.key
+                );
+                continue;
+            }
+            let mut meta = Meta::from_file(&file);
+            meta.tag = record.tag;
+            fields.insert(record.key, Field::restore(record.file, meta));
+        }
+        Map::new(cwd, &StorageOptions::default()).write(&fields)?;
+        remove_file(&path)?;
+    } else {
+        warn!("Manifest of {cwd:?} is missing; keys are restored from records");
+    }
+    Storage::recover(cwd, KeyResolver::Embedded)
+}
+
+impl Storage {
+    /// Packs the storage (including child storages) into a tar archive. Each record is an entry
+    /// of the archive; keys of records are listed in `manifest.json` of each storage folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path reference to the archive file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("a", &String::from("Hello")).unwrap();
+    /// let archive = temp_dir().join(format!("{}.tar", Uuid::new_v4()));
+    /// storage.pack_tar(&archive).unwrap();
+    /// // $ tar -tf storage.tar
+    /// let mut unpacked =
+    ///     Storage::unpack_tar(&archive, temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// assert_eq!(
+    ///     unpacked.get::<String, _>("a").unwrap(),
+    ///     Some(String::from("Hello"))
+    /// );
+    /// storage.destroy().unwrap();
+    /// unpacked.destroy().unwrap();
+    /// std::fs::remove_file(archive).unwrap();
+    /// ```
+    pub fn pack_tar<P: AsRef<Path>>(&mut self, path: P) -> Result<(), E> {
+        let op = op!("pack", path, path.as_ref());
+        self.flush()?;
+        let mut archive = tar::Builder::new(fs::create(&path)?);
+        write_tree(self, &mut archive, Path::new(""))?;
+        archive.into_inner()?.sync_all()?;
+        op.size(|| path.as_ref().metadata().ok().map(|m| m.len()));
+        Ok(())
+    }
+
+    /// Unpacks the storage from a tar archive written by `pack_tar()` (or assembled with other
+    /// tools). Only regular files are extracted. Records missing in the archive are skipped;
+    /// record files, which aren't listed in the manifest, are added with keys from their headers.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path reference to the archive file.
+    /// * `dest` - A path reference to the destination directory. It should not exist or should
+    ///   be empty.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the unpacked storage, or an error.
+    pub fn unpack_tar<P: AsRef<Path>, D: AsRef<Path>>(path: P, dest: D) -> Result<Storage, E> {
+        let op = op!("unpack", path, path.as_ref());
+        op.size(|| path.as_ref().metadata().ok().map(|m| m.len()));
+        let dest = fs::as_path_buf(dest);
+        if dest.exists() && read_dir(&dest)?.next().is_some() {
+            return Err(E::DestinationIsNotEmpty(dest));
+        }
+        create_dir_all(&dest)?;
+        unpack_entries(fs::read(&path)?, &dest)?;
+        restore_tree(&dest)
+    }
+}
+
+/// Extracts regular files of the archive into the folder.
+fn unpack_entries<R: Read>(reader: R, dest: &Path) -> Result<(), E> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path: PathBuf = entry.path()?.into_owned();
+        if !entry.header().entry_type().is_file() {
+            if !entry.header().entry_type().is_dir() {
+                warn!("Entry {path:?} isn't a regular file; entry is skipped");
+            }
+            continue;
+        }
+        if !entry.unpack_in(dest)? {
+            warn!("Entry {path:?} is outside of the destination; entry is skipped");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{map, Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn archive() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..50u32 {
+            storage.set(format!("key_{i}"), &i)?;
+        }
+        storage.child("plugins")?.set("enabled", &true)?;
+        let archive = temp_dir().join(format!("{}.tar", Uuid::new_v4()));
+        storage.pack_tar(&archive)?;
+        let mut unpacked =
+            Storage::unpack_tar(&archive, temp_dir().join(Uuid::new_v4().to_string()))?;
+        assert_eq!(unpacked.len(), 50);
+        assert_eq!(unpacked.get::<u32, _>("key_7")?, Some(7));
+        assert_eq!(unpacked.to_map::<u32>()?.len(), 50);
+        assert_eq!(
+            unpacked.child("plugins")?.get::<bool, _>("enabled")?,
+            Some(true)
+        );
+        assert!(!unpacked.cwd().join(super::TAR_MANIFEST).exists());
+        unpacked.destroy()?;
+        // Without manifest keys are restored from headers of records
+        let dest = temp_dir().join(Uuid::new_v4().to_string());
+        let mut rebuilt = tar::Builder::new(Vec::new());
+        let mut source = tar::Archive::new(crate::fs::read(&archive)?);
+        for entry in source.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            if path.file_name().is_some_and(|n| n == super::TAR_MANIFEST) {
+                continue;
+            }
+            let mut header = entry.header().clone();
+            rebuilt.append_data(&mut header, path, &mut entry)?;
+        }
+        std::fs::create_dir(&dest)?;
+        super::unpack_entries(rebuilt.into_inner()?.as_slice(), &dest)?;
+        assert!(!dest.join(map::MAP_FILE_NAME).exists());
+        let mut recovered = super::restore_tree(&dest)?;
+        assert_eq!(recovered.len(), 50);
+        assert_eq!(recovered.get::<u32, _>("key_49")?, Some(49));
+        recovered.destroy()?;
+        storage.destroy()?;
+        remove_file(&archive)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+//! Asynchronous packing and unpacking of bundles (`async` feature). Record files are read
+//! (packing) or written (unpacking) concurrently with tokio, with a bounded number of files in
+//! flight, which speeds up bundles of many small records on SSDs without exhausting file
+//! descriptors. Bundles are the same as written by `Bundle::pack()`.
+
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncSeekExt, AsyncWriteExt},
+    task::{spawn_blocking, JoinError, JoinSet},
+};
+
+use crate::{
+    bundle::{
+        begin_unpack, flatten, split_record, write_map, Index, Location, Payloads, BUNDLE_MAGIC,
+        U64_SIZE, UNPACKED_EXT,
+    },
+    fs,
+    nested::children_of,
+    trace::op,
+    Meta, Operation, PackCompression, Storage, E,
+};
+
+/// Record of a storage to pack: key, path and name of the record file
+type Source = (String, PathBuf, String, Meta);
+
+fn joined(err: JoinError) -> E {
+    E::from(io::Error::other(err))
+}
+
+/// Collects records of the storage and all its child storages.
+///
+/// # Arguments
+///
+/// * `storage` - The storage.
+/// * `path` - Names of child storages leading to the storage; empty for the root.
+/// * `tree` - Collected storages.
+///
+/// # Returns
+///
+/// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+fn collect_tree(
+    storage: &Storage,
+    path: Vec<String>,
+    tree: &mut Vec<(Vec<String>, Vec<Source>)>,
+) -> Result<(), E> {
+    let sources = storage
+        .packing_order()
+        .into_iter()
+        .map(|(key, field)| {
+            (
+                key.to_owned(),
+                field.path(storage.cwd()),
+                field.file_name().to_owned(),
+                field.meta().clone(),
+            )
+        })
+        .collect::<Vec<Source>>();
+    tree.push((path.clone(), sources));
+    for (name, cwd) in children_of(storage.cwd())? {
+        let child = Storage::open_with_options(cwd, storage.options.clone())?;
+        let mut path = path.clone();
+        path.push(name);
+        collect_tree(&child, path, tree)?;
+    }
+    Ok(())
+}
+
+/// Puts records of the storage into the index of the bundle.
+fn insert(index: &mut Index, path: &[String], records: Vec<Location>) {
+    let Some((name, rest)) = path.split_first() else {
+        index.records = records;
+        return;
+    };
+    let position = match index.children.iter().position(|(child, _)| child == name) {
+        Some(position) => position,
+        None => {
+            index.children.push((name.to_owned(), Index::default()));
+            index.children.len() - 1
+        }
+    };
+    insert(&mut index.children[position].1, rest, records);
+}
+
+/// Writes records of the storage tree into the bundle, reading up to `concurrency` record files
+/// at once.
+async fn write_tree(
+    tree: Vec<(Vec<String>, Vec<Source>)>,
+    bundle: &mut tokio::fs::File,
+    concurrency: usize,
+    compression: PackCompression,
+) -> Result<Index, E> {
+    let mut index = Index::default();
+    let mut cursor = (BUNDLE_MAGIC.len() + U64_SIZE) as u64;
+    let mut payloads = Payloads::new();
+    for (path, sources) in tree {
+        let mut records = Vec::with_capacity(sources.len());
+        let mut sources = sources.into_iter();
+        let mut reads = JoinSet::new();
+        loop {
+            while reads.len() < concurrency {
+                let Some((key, path, file, meta)) = sources.next() else {
+                    break;
+                };
+                reads.spawn(async move {
+                    let content = tokio::fs::read(&path)
+                        .await
+                        .map_err(|e| E::from(e).record(Operation::Pack, &key, &path));
+                    (key, file, meta, content)
+                });
+            }
+            let Some(read) = reads.join_next().await else {
+                break;
+            };
+            let (key, file, meta, content) = read.map_err(joined)?;
+            let content = content?;
+            if content.is_empty() {
+                continue;
+            }
+            let (header, payload) = split_record(&content)?;
+            let (from, to, compressed) = match payloads.entry(Sha256::digest(payload).to_vec()) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    let (payload, compressed) = compression.encode(payload)?;
+                    bundle.write_all(&payload).await?;
+                    let from = cursor;
+                    cursor += payload.len() as u64;
+                    *entry.insert((from, cursor, compressed))
+                }
+            };
+            records.push(Location {
+                key,
+                file,
+                header,
+                from,
+                to,
+                compressed,
+                meta: Some(meta),
+            });
+        }
+        insert(&mut index, &path, records);
+    }
+    let buffer = bincode::serialize(&index)?;
+    bundle.write_all(&buffer).await?;
+    bundle
+        .seek(SeekFrom::Start(BUNDLE_MAGIC.len() as u64))
+        .await?;
+    bundle.write_all(&cursor.to_le_bytes()).await?;
+    bundle.flush().await?;
+    Ok(index)
+}
+
+impl Storage {
+    /// Packs the storage into the bundle as `Bundle::pack()` does, reading record files
+    /// concurrently.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    /// * `concurrency` - Maximum number of record files read at once.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub async fn pack_async<P: AsRef<Path>>(
+        &mut self,
+        bundle: P,
+        concurrency: usize,
+    ) -> Result<(), E> {
+        let path = fs::as_path_buf(bundle);
+        let _op = op!("pack", path, path);
+        let mut tree = Vec::new();
+        collect_tree(self, Vec::new(), &mut tree)?;
+        let mut bundle = tokio::fs::File::create(&path)
+            .await
+            .map_err(|e| E::io(e, &path))?;
+        bundle.write_all(&BUNDLE_MAGIC).await?;
+        bundle.write_all(&0u64.to_le_bytes()).await?;
+        if let Err(err) = write_tree(
+            tree,
+            &mut bundle,
+            concurrency.max(1),
+            self.options.pack_compression,
+        )
+        .await
+        {
+            drop(bundle);
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                log::warn!("Fail to remove partial bundle {path:?}: {err}");
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Unpacks the storage from the bundle as `Bundle::unpack()` does (including resuming of
+    /// interrupted unpacking), writing record files concurrently.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    /// * `concurrency` - Maximum number of record files written at once.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the unpacked `Storage` instance or an error.
+    pub async fn unpack_async<P: AsRef<Path>>(bundle: P, concurrency: usize) -> Result<Storage, E> {
+        let bundle = fs::as_path_buf(bundle);
+        let _op = op!("unpack", path, bundle);
+        let cwd = bundle.with_extension(UNPACKED_EXT);
+        let (file, index, mut progress) = spawn_blocking({
+            let cwd = cwd.clone();
+            move || begin_unpack(&bundle, &cwd)
+        })
+        .await
+        .map_err(joined)??;
+        let file = Arc::new(file);
+        for (folder, prefix, records) in flatten(index, &cwd) {
+            tokio::fs::create_dir_all(&folder)
+                .await
+                .map_err(|e| E::io(e, &folder))?;
+            let mut map = HashMap::new();
+            let mut writes = JoinSet::new();
+            let mut records = records.into_iter().filter(Location::is_valid);
+            let mut restored = Vec::new();
+            loop {
+                while writes.len() < concurrency.max(1) {
+                    let Some(location) = records.next() else {
+                        break;
+                    };
+                    map.insert(
+                        location.key.to_owned(),
+                        (location.file.to_owned(), location.meta.clone()),
+                    );
+                    let relative = format!("{prefix}{}", location.file);
+                    if progress.is_done(&relative) {
+                        continue;
+                    }
+                    let (file, path) = (file.clone(), folder.join(&location.file));
+                    writes.spawn_blocking(move || {
+                        location
+                            .read(&file)
+                            .and_then(|content| {
+                                std::io::Write::write_all(&mut fs::create(&path)?, &content)
+                            })
+                            .map(|_| relative)
+                            .map_err(|e| E::from(e).record(Operation::Unpack, &location.key, &path))
+                    });
+                }
+                let Some(written) = writes.join_next().await else {
+                    break;
+                };
+                restored.push(written.map_err(joined)??);
+                if restored.len() >= concurrency {
+                    progress.restored(std::mem::take(&mut restored))?;
+                }
+            }
+            progress.restored(restored)?;
+            write_map(&folder, &map)?;
+        }
+        progress.finish(&cwd)?;
+        Storage::open(cwd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bundle, Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn bundle() -> Result<(), E> {
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..200u32 {
+            storage.set(format!("key_{i}"), &(i % 10))?;
+        }
+        storage.child("plugins/foo")?.set("enabled", &true)?;
+        storage.child("empty")?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        runtime.block_on(storage.pack_async(&bundle, 8))?;
+        // The bundle is the same as written by `pack()`
+        let packed = Storage::open_bundle(&bundle)?;
+        assert_eq!(packed.len(), 200);
+        assert_eq!(packed.children(), vec!["empty", "plugins"]);
+        let mut unpacked = runtime.block_on(Storage::unpack_async(&bundle, 8))?;
+        for i in 0..200u32 {
+            assert_eq!(unpacked.get::<u32, _>(format!("key_{i}"))?, Some(i % 10));
+        }
+        let foo = unpacked.child("plugins/foo")?;
+        assert_eq!(foo.get::<bool, _>("enabled")?, Some(true));
+        drop(foo);
+        unpacked.destroy()?;
+        // Bundles written by `pack()` are unpacked as well
+        let other = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&other)?;
+        let mut unpacked = runtime.block_on(Storage::unpack_async(&other, 3))?;
+        assert_eq!(unpacked.get::<u32, _>("key_7")?, Some(7));
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        remove_file(&other)?;
+        Ok(())
+    }
+}
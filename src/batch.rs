@@ -0,0 +1,189 @@
+use serde::Deserialize;
+use std::{path::Path, sync::atomic::AtomicBool};
+
+use crate::{cancel, fs, Field, Storage, E};
+
+/// Settings of batched reads used by `Search` methods, which read all records of a type
+/// (`filter`, `fold`, `group_by`). Instead of interleaving opening, reading and deserializing of
+/// each record, files are read in batches sorted by file names; optionally the OS is asked to
+/// prefetch all files of a batch first. It improves throughput on spinning disks and network
+/// filesystems.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchReads {
+    /// Batched reads are used if at least this number of records have to be read.
+    pub min_records: usize,
+    /// Number of files read in one batch (and kept open at the same time).
+    pub chunk: usize,
+    /// Asks the OS to prefetch files of the batch before reading them (Linux only).
+    pub readahead: bool,
+}
+
+impl Default for BatchReads {
+    fn default() -> Self {
+        Self {
+            min_records: 32,
+            chunk: 64,
+            readahead: true,
+        }
+    }
+}
+
+/// Reads files of the batch of fields.
+///
+/// # Arguments
+///
+/// * `cwd` - A path reference to the storage folder.
+/// * `chunk` - Fields to read.
+/// * `readahead` - True to ask the OS to prefetch files.
+///
+/// # Returns
+///
+/// * `Vec<Result<Vec<u8>, E>>` - Contents of files in the order of fields.
+pub(crate) fn read_chunk(
+    cwd: &Path,
+    chunk: &[(&String, &Field)],
+    readahead: bool,
+) -> Vec<Result<Vec<u8>, E>> {
+    let mut files = Vec::with_capacity(chunk.len());
+    let mut contents = chunk
+        .iter()
+        .map(|(_, field)| match fs::read(field.path(cwd)) {
+            Ok(file) => {
+                files.push(file);
+                None
+            }
+            Err(err) => Some(Err(err.into())),
+        })
+        .collect::<Vec<Option<Result<Vec<u8>, E>>>>();
+    if readahead {
+        files.iter().for_each(fs::will_need);
+    }
+    let mut read = fs::read_many(&files).into_iter();
+    for content in contents.iter_mut().filter(|content| content.is_none()) {
+        *content = read.next().map(|r| r.map_err(E::from));
+    }
+    contents
+        .into_iter()
+        .map(|content| content.unwrap_or(Err(E::Unknown)))
+        .collect()
+}
+
+impl Storage {
+    /// Reads all records of the type `V` and passes them into the closure. Broken records are
+    /// handled according to `CorruptionPolicy` in the same way as with `get()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cancel` - Stops reading with `E::Cancelled` when set.
+    /// * `f` - A closure that takes the key and the value of a record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub(crate) fn read_all<V: for<'a> Deserialize<'a> + 'static, F: FnMut(&String, V)>(
+        &self,
+        cancel: &AtomicBool,
+        mut f: F,
+    ) -> Result<(), E> {
+        let mut fields = self
+            .keys_of::<V>()
+            .filter(|key| !self.expired(key))
+            .filter_map(|key| self.fields.get_key_value(key))
+            .collect::<Vec<(&String, &Field)>>();
+        let batch = &self.options.batch;
+        if fields.len() < batch.min_records || self.options.fs.is_some() {
+            for (key, _) in fields {
+                cancel::check(cancel)?;
+                if let Some(v) = self.get_listed::<V>(key)? {
+                    f(key, v);
+                }
+            }
+            return Ok(());
+        }
+        // Files are read in order of their names, unless keys should be iterated in sorted order
+        if !self.options.sorted {
+            fields.sort_by(|(_, a), (_, b)| a.file_name().cmp(b.file_name()));
+        }
+        for chunk in fields.chunks(batch.chunk.max(1)) {
+            cancel::check(cancel)?;
+            let contents = read_chunk(&self.cwd, chunk, batch.readahead);
+            for ((key, field), content) in chunk.iter().zip(contents) {
+                self.accessed(field);
+                let value = match self.sealed(|| {
+                    content
+                        .and_then(|content| self.decoded(content))
+                        .and_then(|content| Field::value::<V>(&content))
+                }) {
+                    Ok(value) => value,
+                    Err(err) => self.skipped(key, field, err)?,
+                };
+                if let Some(v) = value {
+                    f(key, v);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BatchReads, Search, Storage, StorageOptions, E};
+    use std::{env::temp_dir, time::Duration};
+    use uuid::Uuid;
+
+    #[test]
+    fn batch() -> Result<(), E> {
+        let options = StorageOptions {
+            batch: BatchReads {
+                min_records: 1,
+                chunk: 7,
+                readahead: true,
+            },
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        for i in 0..100u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        storage.set("name", &String::from("bstorage"))?;
+        let path = storage
+            .fields
+            .get("99")
+            .expect("Field exists")
+            .path(&storage.cwd);
+        let mut content = std::fs::read(&path)?;
+        *content.last_mut().expect("Not empty") ^= 0xff;
+        std::fs::write(&path, content)?;
+        let mut found = storage.filter(|v: &u32| *v >= 50)?;
+        found.sort_by_key(|(_, v)| *v);
+        assert_eq!(found.len(), 49);
+        assert_eq!(found[48], (String::from("98"), 98));
+        assert_eq!(
+            storage.fold(0, |acc, _, v: &u32| acc + v)?,
+            (0..99).sum::<u32>()
+        );
+        assert_eq!(storage.take_corruptions().len(), 2);
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn expired() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let count = storage.options.batch.min_records as u32 + 40;
+        for i in 0..count {
+            if i % 2 == 0 {
+                storage.set(i.to_string(), &i)?;
+            } else {
+                storage.set_with_ttl(i.to_string(), &i, Duration::ZERO)?;
+            }
+        }
+        let found = storage.filter(|_: &u32| true)?;
+        assert_eq!(found.len(), count as usize / 2);
+        assert!(found.iter().all(|(_, v)| v % 2 == 0));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::{io::Read, path::Path};
+
+use crate::{fnv1a, fs, Durability, E};
+
+pub(crate) const BLOOM_FILE_NAME: &str = "map.bloom";
+
+/// Settings of the bloom filter of keys. The filter is persisted next to the map file and rebuilt
+/// with each write of the map. It answers "the key definitely doesn't exist" without reading the
+/// map, which matters for storages opened lazily (see `Storage::open_lazy()`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomOptions {
+    /// Number of bits per key. 10 bits give about 1% of false positives.
+    pub bits_per_key: usize,
+}
+
+impl Default for BloomOptions {
+    fn default() -> Self {
+        Self { bits_per_key: 10 }
+    }
+}
+
+/// Bloom filter of keys of the storage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bloom {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl Bloom {
+    /// Builds the filter for the given keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Keys to put into the filter.
+    /// * `options` - Settings of the filter.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns the filter.
+    pub fn build<'a, I: ExactSizeIterator<Item = &'a String>>(
+        keys: I,
+        options: &BloomOptions,
+    ) -> Self {
+        let len = (keys.len() * options.bits_per_key.max(1)).max(64);
+        // Optimal number of hashes is bits_per_key * ln(2)
+        let hashes = ((options.bits_per_key as f64 * 0.69).round() as u32).clamp(1, 16);
+        let mut bloom = Self {
+            bits: vec![0; len.div_ceil(64)],
+            hashes,
+        };
+        for key in keys {
+            for bit in bloom.positions(key) {
+                bloom.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        bloom
+    }
+
+    /// Returns positions of bits of the key (double hashing).
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let hash = fnv1a(key.as_bytes());
+        let (h1, h2) = (hash as u32 as u64, (hash >> 32) | 1);
+        let len = (self.bits.len() * 64) as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    /// Checks if the key may exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns false if the key definitely doesn't exist.
+    pub fn contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Writes the filter into the storage folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    /// * `durability` - Defines whether the file is synced.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn write(&self, cwd: &Path, durability: Durability) -> Result<(), E> {
+        fs::replace(
+            cwd.join(BLOOM_FILE_NAME),
+            &bincode::serialize(self)?,
+            durability,
+        )?;
+        Ok(())
+    }
+
+    /// Reads the filter from the storage folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Self>, E>` - Returns the filter, None if it doesn't exist, or an error.
+    pub fn read(cwd: &Path) -> Result<Option<Self>, E> {
+        let path = cwd.join(BLOOM_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut buffer = Vec::new();
+        fs::read(&path)?.read_to_end(&mut buffer)?;
+        let bloom: Bloom = bincode::deserialize(&buffer)?;
+        if bloom.bits.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(bloom))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bloom, BloomOptions, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn bloom() -> Result<(), E> {
+        let options = StorageOptions {
+            bloom: Some(BloomOptions::default()),
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        for i in 0..1000u32 {
+            storage.set(format!("key_{i}"), &i)?;
+        }
+        let bloom = Bloom::read(storage.cwd())?.expect("Filter is written");
+        assert!((0..1000).all(|i| bloom.contains(&format!("key_{i}"))));
+        let false_positives = (0..1000)
+            .filter(|i| bloom.contains(&format!("absent_{i}")))
+            .count();
+        assert!(false_positives < 50);
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    fs, manifest, sensitive::sealed_with, sensitive::Wiped, transform::Transformers, type_tag,
+    Durability, Field, FileSystem, Header, Layout, Operation, Storage, StorageOptions, E,
+};
+
+/// Options of the initial import of records with `Storage::bulk_create_with_options()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BulkLoad {
+    /// Record files are written without syncing them one by one; all of them are synced at once
+    /// before the map is written. It makes a large import with `Durability::Fsync` (or stronger)
+    /// much faster, but records written before a crash are lost, because the map isn't written
+    /// until the end anyway. Disabled by default.
+    pub deferred_sync: bool,
+    /// Number of threads serializing values and writing record files in parallel; the map is
+    /// still written once at the end. With 0 or 1 records are written by the calling thread.
+    /// Ignored with `Layout::ContentAddressed`, which compares values one by one. Disabled by
+    /// default.
+    pub workers: usize,
+}
+
+/// Record read from the source of the import, with its position in the source
+type Job<V> = (usize, String, V);
+
+/// Record file written by a worker: the position of the record in the source, the key, and the
+/// field with the size of the serialized value
+type Written = (usize, String, Result<(Field, u64), E>);
+
+/// Serializes the value and writes it into a new record file.
+fn write_record<V: Serialize>(
+    fs: &dyn FileSystem,
+    cwd: &Path,
+    options: &StorageOptions,
+    transformers: &Transformers,
+    key: &str,
+    tag: u64,
+    value: &V,
+) -> Result<(Field, u64), E> {
+    let encryption = options.encryption.clone();
+    let buffer = Wiped(sealed_with(encryption, || Ok(bincode::serialize(value)?))?);
+    let transformed = transformers.encode(key, &buffer)?;
+    let codec = options.codecs.of(key);
+    let payload = codec.encode(&transformed, options.encryption.as_ref())?;
+    let header = Header::new(key, tag, &payload).encoded(codec, transformers.names());
+    let content = Wiped(Field::encode_with(header, &payload)?);
+    let mut field = Field::create();
+    field
+        .store(fs, cwd, tag, &content, options.durability)
+        .map_err(|e| e.record(Operation::Set, key, &field.path(cwd)))?;
+    field.meta_mut().codec = codec;
+    field.meta_mut().transformers = transformers.names();
+    field.meta_mut().digest = options.digests.then(|| manifest::digest(&buffer));
+    Ok((field, buffer.len() as u64))
+}
+
+impl Storage {
+    /// Creates the storage and writes all records of the iterator into it. Record files are
+    /// written one after another and the map is written once at the end, instead of after each
+    /// record as with `set()`, so it's the fastest way to fill a new storage. Records are consumed
+    /// from the iterator one by one, so the source doesn't have to fit into memory.
+    ///
+    /// The storage is created with default options (see `Storage::bulk_create_with_options()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `records` - Keys and values of records.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with written records, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::bulk_create(
+    ///     temp_dir().join(Uuid::new_v4().to_string()),
+    ///     (0..1000u32).map(|n| (format!("item/{n}"), n)),
+    /// )
+    /// .unwrap();
+    /// assert_eq!(storage.len(), 1000);
+    /// assert_eq!(storage.get::<u32, _>("item/42").unwrap(), Some(42));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn bulk_create<
+        P: AsRef<Path>,
+        V: Serialize + Send + 'static,
+        I: IntoIterator<Item = (String, V)>,
+    >(
+        cwd: P,
+        records: I,
+    ) -> Result<Self, E> {
+        Storage::bulk_create_with_options(
+            cwd,
+            StorageOptions::default(),
+            BulkLoad::default(),
+            records,
+        )
+    }
+
+    /// Creates the storage with options and writes all records of the iterator into it (see
+    /// `Storage::bulk_create()`). Keys are normalized and checked against the key policy; limits
+    /// and eviction are applied as with `set()`. Imported records aren't published to replicas
+    /// and aren't logged as changes. If the import fails, the storage folder is left with
+    /// written record files, but without the map of them; remove the folder and start again.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `options` - Options of the storage.
+    /// * `bulk` - Options of the import.
+    /// * `records` - Keys and values of records.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with written records, or an error.
+    pub fn bulk_create_with_options<
+        P: AsRef<Path>,
+        V: Serialize + Send + 'static,
+        I: IntoIterator<Item = (String, V)>,
+    >(
+        cwd: P,
+        options: StorageOptions,
+        bulk: BulkLoad,
+        records: I,
+    ) -> Result<Self, E> {
+        let mut storage = Storage::create_with_options(cwd, options)?;
+        let durability = storage.options.durability;
+        let deferred = bulk.deferred_sync && durability >= Durability::Fsync;
+        if deferred {
+            storage.options.durability = Durability::Flush;
+        }
+        let result = if bulk.workers > 1 && storage.options.layout != Layout::ContentAddressed {
+            storage.load_parallel(records, bulk.workers)
+        } else {
+            storage.load_sequential(records)
+        };
+        storage.options.durability = durability;
+        result?;
+        if deferred {
+            storage.sync_records()?;
+        }
+        storage.evict(&[])?;
+        storage.map.write(&storage.fields)?;
+        storage.save_deadlines()?;
+        Ok(storage)
+    }
+
+    /// Writes records one by one.
+    fn load_sequential<V: Serialize + 'static, I: IntoIterator<Item = (String, V)>>(
+        &mut self,
+        records: I,
+    ) -> Result<(), E> {
+        let tag = type_tag::<V>();
+        records.into_iter().try_for_each(|(key, value)| {
+            let key = self.normalized(key.into()).into_owned();
+            self.check_key(&key)?;
+            self.validators.check(&key, &value)?;
+            let buffer = Wiped(self.sealed(|| Ok(bincode::serialize(&value)?))?);
+            self.put(&key, tag, &buffer)
+        })
+    }
+
+    /// Writes records with the pool of workers. Keys are checked by the calling thread, values
+    /// are serialized and written into new record files by workers; written files are added to
+    /// the storage in the order of the source, so the last value of a repeated key wins.
+    fn load_parallel<V: Serialize + Send + 'static, I: IntoIterator<Item = (String, V)>>(
+        &mut self,
+        records: I,
+        workers: usize,
+    ) -> Result<(), E> {
+        self.map.check()?;
+        let tag = type_tag::<V>();
+        let (fs, cwd, options) = (&*self.fs, self.cwd.as_path(), &self.options);
+        let transformers = &self.transformers;
+        let failed = AtomicBool::new(false);
+        let (jobs, queue) = mpsc::sync_channel::<Job<V>>(workers * 2);
+        // Each worker owns the queue, so it's dropped (and sending fails) once all of them exit
+        let queue = Arc::new(Mutex::new(queue));
+        let (done, results) = mpsc::channel::<Written>();
+        let (fed, mut written) = thread::scope(|scope| {
+            for _ in 0..workers {
+                let (done, queue, failed) = (done.clone(), queue.clone(), &failed);
+                scope.spawn(move || {
+                    while let Ok(Ok((n, key, value))) = queue.lock().map(|queue| queue.recv()) {
+                        if failed.load(Ordering::Relaxed) {
+                            continue;
+                        }
+                        let result =
+                            write_record(fs, cwd, options, transformers, &key, tag, &value);
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        if done.send((n, key, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop((done, queue));
+            let fed = records
+                .into_iter()
+                .enumerate()
+                .take_while(|_| !failed.load(Ordering::Relaxed))
+                .try_for_each(|(n, (key, value))| {
+                    let key = self.normalized(key.into()).into_owned();
+                    self.check_key(&key)?;
+                    self.validators.check(&key, &value)?;
+                    // Sending fails only if all workers have panicked; the scope resumes the panic
+                    if jobs.send((n, key, value)).is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    Ok::<(), E>(())
+                });
+            drop(jobs);
+            (fed, results.iter().collect::<Vec<Written>>())
+        });
+        written.sort_by_key(|(n, _, _)| *n);
+        let mut error = fed.err();
+        for (_, key, result) in written {
+            match (result, error.is_some()) {
+                (Ok((field, _)), true) => {
+                    let _ = field.remove(&*self.fs, &self.cwd);
+                }
+                (Ok((field, size)), false) => {
+                    if let Err(err) = self.adopt(&key, field, size) {
+                        error = Some(err);
+                    }
+                }
+                (Err(err), _) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Adds the record file written by a worker to the storage, replacing the previous record of
+    /// the key. The file is removed, if the record doesn't fit `StorageOptions::limits`.
+    fn adopt(&mut self, key: &str, mut field: Field, size: u64) -> Result<(), E> {
+        if let Err(err) = self.admit(key, size, field.meta().size) {
+            let _ = field.remove(&*self.fs, &self.cwd);
+            return Err(err);
+        }
+        // Pins and tags of the record are kept, even if its file is replaced
+        if let Some(previous) = self.fields.get(key) {
+            field.meta_mut().pinned = previous.meta().pinned;
+            field.meta_mut().tags = previous.meta().tags.clone();
+        }
+        self.discard(key)?;
+        self.fields.insert(key.to_owned(), field);
+        self.index_key(key);
+        Ok(())
+    }
+
+    /// Syncs all record files and their folders to the disk. Files can be synced with the
+    /// default file system only; with a custom one only folders are synced.
+    fn sync_records(&self) -> Result<(), E> {
+        let mut dirs = HashSet::new();
+        for field in self.fields.values() {
+            let path = field.path(&self.cwd);
+            if self.options.fs.is_none() {
+                File::open(fs::long_path(&path))
+                    .and_then(|file| file.sync_all())
+                    .map_err(|e| E::io(e, &path))?;
+            }
+            if let Some(dir) = path.parent() {
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+        for dir in dirs {
+            self.fs.sync_dir(&dir).map_err(|e| E::io(e, &dir))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BulkLoad, Durability, Limits, Storage, StorageOptions, E};
+    use serde::{Serialize, Serializer};
+    use std::{env::temp_dir, panic};
+    use uuid::Uuid;
+
+    #[test]
+    fn bulk_create() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            durability: Durability::Fsync,
+            ..Default::default()
+        };
+        let storage = Storage::bulk_create_with_options(
+            &storage_path,
+            options.clone(),
+            BulkLoad {
+                deferred_sync: true,
+                ..Default::default()
+            },
+            (0..200u32).map(|n| (format!("key/{n}"), format!("value {n}"))),
+        )?;
+        assert_eq!(storage.len(), 200);
+        drop(storage);
+        // The map is written at the end
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert_eq!(storage.len(), 200);
+        assert_eq!(
+            storage.get::<String, _>("key/199")?,
+            Some(String::from("value 199"))
+        );
+        storage.destroy()?;
+        // Workers write records in parallel; the last value of a repeated key wins
+        let limits = Limits {
+            max_record_size: Some(64),
+            ..Default::default()
+        };
+        let bulk = BulkLoad {
+            workers: 4,
+            ..Default::default()
+        };
+        let records = (0..500u32)
+            .map(|n| (format!("key/{}", n % 300), vec![n; 4]))
+            .collect::<Vec<(String, Vec<u32>)>>();
+        let mut storage = Storage::bulk_create_with_options(
+            &storage_path,
+            StorageOptions {
+                limits: limits.clone(),
+                ..Default::default()
+            },
+            bulk.clone(),
+            records,
+        )?;
+        assert_eq!(storage.len(), 300);
+        assert_eq!(storage.get::<Vec<u32>, _>("key/10")?, Some(vec![310; 4]));
+        assert_eq!(storage.get::<Vec<u32>, _>("key/299")?, Some(vec![299; 4]));
+        storage.destroy()?;
+        // The map isn't written, if the import fails
+        let mut records = (0..100u32)
+            .map(|n| (n.to_string(), vec![n; 4]))
+            .collect::<Vec<_>>();
+        records.push((String::from("large"), vec![0; 64]));
+        assert!(matches!(
+            Storage::bulk_create_with_options(
+                &storage_path,
+                StorageOptions {
+                    limits,
+                    ..Default::default()
+                },
+                bulk,
+                records,
+            ),
+            Err(E::QuotaExceeded(_))
+        ));
+        assert_eq!(Storage::open(&storage_path)?.len(), 0);
+        std::fs::remove_dir_all(&storage_path)?;
+        Ok(())
+    }
+
+    /// Fails to be serialized
+    struct Panicking;
+
+    impl Serialize for Panicking {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            panic!("value cannot be serialized");
+        }
+    }
+
+    #[test]
+    fn panicked_workers() {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        // Feeding stops, when all workers have panicked, and the panic is resumed
+        let result = panic::catch_unwind(|| {
+            Storage::bulk_create_with_options(
+                &storage_path,
+                StorageOptions::default(),
+                BulkLoad {
+                    workers: 2,
+                    ..Default::default()
+                },
+                (0..100u32).map(|n| (n.to_string(), Panicking)),
+            )
+        });
+        assert!(result.is_err());
+        let _ = std::fs::remove_dir_all(&storage_path);
+    }
+}
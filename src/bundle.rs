@@ -1,17 +1,840 @@
-use log::warn;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
-    fs::create_dir,
-    io::{Read, Seek, SeekFrom, Write},
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    env::temp_dir,
+    fs::{
+        create_dir, create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, File,
+        OpenOptions,
+    },
+    io::{self, Read, Seek, SeekFrom, Write},
     mem,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
 };
 
-use crate::{fs, map, Field, Storage, E};
+use crate::{
+    batch::read_chunk,
+    cancel, fs,
+    header::Header,
+    map,
+    nested::{children_of, CHILDREN_DIR},
+    trace::op,
+    BatchReads, Field, KeyHasher, Lock, Meta, Operation, Storage, StorageOptions, E,
+};
+use uuid::Uuid;
 
 /// Default extention of bundle file
-const UNPACKED_EXT: &str = "unpacked";
-const U64_SIZE: usize = mem::size_of::<u64>();
+pub(crate) const UNPACKED_EXT: &str = "unpacked";
+pub(crate) const U64_SIZE: usize = mem::size_of::<u64>();
+/// Marks bundles, which include child storages, keep identical payloads once and keep metadata of
+/// records (pins, tags, etc.). Bundles without it are written by previous versions of `bstorage`:
+/// they start with the position of the list of records, with `BUNDLE_MAGIC_V2` (bundles with
+/// child storages, but without deduplication) or with `BUNDLE_MAGIC_V3` (bundles without
+/// metadata of records).
+pub(crate) const BUNDLE_MAGIC: [u8; 8] = *b"BSBNDL\x00\x04";
+const BUNDLE_MAGIC_V2: [u8; 8] = *b"BSBNDL\x00\x02";
+const BUNDLE_MAGIC_V3: [u8; 8] = *b"BSBNDL\x00\x03";
+
+/// Progress of unpacking (see `Progress`), kept in the unpacked folder until all records are
+/// restored
+pub(crate) const PARTIAL_FILE_NAME: &str = "unpack.partial";
+/// Marks folders created by unpacking (see `Storage::clean_unpacked()`): keeps the path of the
+/// bundle and, for folders of `Storage::unpack_temp()`, the line `temporary`
+pub(crate) const SOURCE_FILE_NAME: &str = "unpack.source";
+const TEMPORARY: &str = "temporary";
+
+/// Record in the bundle. The header of the record file (which keeps the key) is kept in the
+/// index, while the payload is written into the bundle once: records with identical payloads
+/// refer to the same bytes of the bundle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Location {
+    pub key: String,
+    pub file: String,
+    /// Header of the record file; empty for legacy (headerless) records
+    pub header: Vec<u8>,
+    /// Start and end of the payload in the bundle
+    pub from: u64,
+    pub to: u64,
+    /// The payload is compressed with deflate (see `PackCompression`)
+    pub compressed: bool,
+    /// Metadata of the record in the map of the storage; None for bundles written by previous
+    /// versions of `bstorage`, so it's taken from the record file on unpacking
+    pub meta: Option<Meta>,
+}
+
+impl Location {
+    /// Returns true if the position of the payload is valid.
+    pub fn is_valid(&self) -> bool {
+        self.to >= self.from
+    }
+
+    /// Reads the payload as it's written in the bundle (compressed, if it's compressed).
+    pub fn read_raw(&self, bundle: &File) -> io::Result<Vec<u8>> {
+        let mut payload = vec![0u8; (self.to - self.from) as usize];
+        fs::read_exact_at(bundle, &mut payload, self.from)?;
+        Ok(payload)
+    }
+
+    /// Reads the content of the record file from the bundle.
+    pub fn read(&self, bundle: &File) -> io::Result<Vec<u8>> {
+        let payload = self.read_raw(bundle)?;
+        let mut content = self.header.clone();
+        if self.compressed {
+            DeflateDecoder::new(payload.as_slice()).read_to_end(&mut content)?;
+        } else {
+            content.extend_from_slice(&payload);
+        }
+        Ok(content)
+    }
+}
+
+/// Defines whether payloads of records are compressed (with deflate) in bundles. The choice is
+/// made for each record and kept in the index of the bundle, so compressed and uncompressed
+/// records can be mixed in one bundle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PackCompression {
+    /// Payloads are written as they are.
+    #[default]
+    None,
+    /// Payloads, which are compressible, are compressed: the beginning of a payload is compressed
+    /// first, and if it doesn't shrink by at least 10% (e.g. images or archives), the payload is
+    /// written as it is, so no CPU is wasted on recompressing incompressible data. Payloads
+    /// smaller than `min_size` bytes aren't compressed.
+    Auto {
+        /// Minimal size of payloads to compress.
+        min_size: usize,
+    },
+    /// All payloads are compressed.
+    Always,
+}
+
+impl PackCompression {
+    /// Size of the beginning of a payload compressed to estimate its compressibility
+    const SAMPLE: usize = 16 * 1024;
+
+    /// Returns the payload as it should be written into the bundle and true if it's compressed.
+    pub(crate) fn encode<'a>(&self, payload: &'a [u8]) -> io::Result<(Cow<'a, [u8]>, bool)> {
+        let compress = match self {
+            Self::None => false,
+            Self::Always => true,
+            Self::Auto { min_size } => {
+                payload.len() >= *min_size && {
+                    let sample = &payload[..payload.len().min(Self::SAMPLE)];
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+                    encoder.write_all(sample)?;
+                    encoder.finish()?.len() * 10 < sample.len() * 9
+                }
+            }
+        };
+        if !compress {
+            return Ok((Cow::Borrowed(payload), false));
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload)?;
+        let compressed = encoder.finish()?;
+        if compressed.len() >= payload.len() && *self != Self::Always {
+            return Ok((Cow::Borrowed(payload), false));
+        }
+        Ok((Cow::Owned(compressed), true))
+    }
+}
+
+/// Index of the storage in the bundle
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Index {
+    pub records: Vec<Location>,
+    pub children: Vec<(String, Index)>,
+    /// Named storages of the bundle (see `Storage::pack_stores()`); kept in the root index only
+    pub stores: Vec<(String, Index)>,
+}
+
+/// Index of bundles written by previous versions of `bstorage`, which don't keep metadata of
+/// records (see `BUNDLE_MAGIC_V3`).
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexV3 {
+    records: Vec<LocationV3>,
+    children: Vec<(String, IndexV3)>,
+    stores: Vec<(String, IndexV3)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LocationV3 {
+    key: String,
+    file: String,
+    header: Vec<u8>,
+    from: u64,
+    to: u64,
+    compressed: bool,
+}
+
+impl From<IndexV3> for Index {
+    fn from(index: IndexV3) -> Self {
+        let convert = |indexes: Vec<(String, IndexV3)>| {
+            indexes
+                .into_iter()
+                .map(|(name, index)| (name, index.into()))
+                .collect()
+        };
+        Self {
+            records: index
+                .records
+                .into_iter()
+                .map(|location| Location {
+                    key: location.key,
+                    file: location.file,
+                    header: location.header,
+                    from: location.from,
+                    to: location.to,
+                    compressed: location.compressed,
+                    meta: None,
+                })
+                .collect(),
+            children: convert(index.children),
+            stores: convert(index.stores),
+        }
+    }
+}
+
+/// Index of bundles written by previous versions of `bstorage`, which keep records as key, file
+/// name, start and end of the content of the record file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexV2 {
+    records: Vec<(String, String, u64, u64)>,
+    children: Vec<(String, IndexV2)>,
+}
+
+impl From<IndexV2> for Index {
+    fn from(index: IndexV2) -> Self {
+        Self {
+            records: index
+                .records
+                .into_iter()
+                .map(|(key, file, from, to)| Location {
+                    key,
+                    file,
+                    header: Vec::new(),
+                    from,
+                    to,
+                    compressed: false,
+                    meta: None,
+                })
+                .collect(),
+            children: index
+                .children
+                .into_iter()
+                .map(|(name, child)| (name, child.into()))
+                .collect(),
+            stores: Vec::new(),
+        }
+    }
+}
+
+/// Reads the index of the bundle. Bundles written by previous versions of `bstorage` don't have
+/// child storages.
+///
+/// # Arguments
+///
+/// * `bundle` - The bundle file.
+/// * `path` - A path reference to the bundle file.
+///
+/// # Returns
+///
+/// * `Result<Index, E>` - Returns the index of the bundle, or an error.
+pub(crate) fn read_index(bundle: &mut File, path: &Path) -> Result<Index, E> {
+    if bundle.metadata()?.len() < U64_SIZE as u64 {
+        return Err(E::PackageFileInvalid(path.to_path_buf()));
+    }
+    let mut buffer = [0u8; U64_SIZE];
+    bundle.seek(SeekFrom::Start(0))?;
+    bundle.read_exact(&mut buffer)?;
+    if buffer == BUNDLE_MAGIC || buffer == BUNDLE_MAGIC_V3 || buffer == BUNDLE_MAGIC_V2 {
+        let magic = buffer;
+        bundle.read_exact(&mut buffer)?;
+        let index_pos = u64::from_le_bytes(buffer);
+        let mut buffer: Vec<u8> = Vec::new();
+        bundle.seek(SeekFrom::Start(index_pos))?;
+        bundle.read_to_end(&mut buffer)?;
+        if magic == BUNDLE_MAGIC {
+            Ok(bincode::deserialize(&buffer)?)
+        } else if magic == BUNDLE_MAGIC_V3 {
+            Ok(bincode::deserialize::<IndexV3>(&buffer)?.into())
+        } else {
+            Ok(bincode::deserialize::<IndexV2>(&buffer)?.into())
+        }
+    } else {
+        let map_pos = u64::from_le_bytes(buffer);
+        let mut buffer: Vec<u8> = Vec::new();
+        bundle.seek(SeekFrom::Start(map_pos))?;
+        bundle.read_to_end(&mut buffer)?;
+        Ok(IndexV2 {
+            records: bincode::deserialize(&buffer)?,
+            children: Vec::new(),
+        }
+        .into())
+    }
+}
+
+/// Progress of unpacking. The manifest starts with the fingerprint of the bundle, followed by
+/// paths (relative to the unpacked folder) of restored record files, one per line; paths are
+/// appended as soon as a batch of records is written. While the manifest exists, the folder
+/// cannot be opened as a storage. Unpacking the same bundle again skips restored records; the
+/// manifest of another bundle is discarded and unpacking starts over.
+pub(crate) struct Progress {
+    file: File,
+    done: HashSet<String>,
+}
+
+impl Progress {
+    /// Opens the manifest of the unpacked folder or starts a new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the unpacked folder.
+    /// * `fingerprint` - Fingerprint of the bundle being unpacked.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the progress, or an error.
+    pub(crate) fn open(cwd: &Path, fingerprint: &str) -> Result<Self, E> {
+        let path = cwd.join(PARTIAL_FILE_NAME);
+        let content = match read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(E::io(err, &path)),
+        };
+        let mut lines = content.lines();
+        if lines.next() == Some(fingerprint) {
+            let done = lines
+                .map(|line| line.to_owned())
+                .collect::<HashSet<String>>();
+            debug!(
+                "Unpacking of {cwd:?} is resumed: {} records restored",
+                done.len()
+            );
+            let file = OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .map_err(|e| E::io(e, &path))?;
+            return Ok(Self { file, done });
+        }
+        let mut file = fs::create(&path)?;
+        writeln!(file, "{fingerprint}")?;
+        file.flush()?;
+        Ok(Self {
+            file,
+            done: HashSet::new(),
+        })
+    }
+
+    pub(crate) fn is_done(&self, path: &str) -> bool {
+        self.done.contains(path)
+    }
+
+    /// Adds restored record files (paths relative to the unpacked folder) to the manifest.
+    pub(crate) fn restored(&mut self, paths: Vec<String>) -> Result<(), E> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let mut lines = paths.join("\n");
+        lines.push('\n');
+        self.file.write_all(lines.as_bytes())?;
+        self.file.flush()?;
+        self.done.extend(paths);
+        Ok(())
+    }
+
+    /// Removes the manifest once all records are restored.
+    pub(crate) fn finish(self, cwd: &Path) -> Result<(), E> {
+        drop(self.file);
+        let path = cwd.join(PARTIAL_FILE_NAME);
+        remove_file(&path).map_err(|e| E::io(e, &path))
+    }
+}
+
+/// Returns the fingerprint of the bundle: its size and the checksum of its index.
+pub(crate) fn fingerprint(bundle: &File, index: &Index) -> Result<String, E> {
+    Ok(format!(
+        "{} {:08x}",
+        bundle.metadata()?.len(),
+        crc32fast::hash(&bincode::serialize(index)?)
+    ))
+}
+
+/// Start, end and compression of payloads written into the bundle by their SHA-256 hashes
+pub(crate) type Payloads = HashMap<Vec<u8>, (u64, u64, bool)>;
+
+/// Names of files and metadata (if it's kept) of unpacked records by their keys
+pub(crate) type Unpacked = HashMap<String, (String, Option<Meta>)>;
+
+/// Marks the folder as created by unpacking of the bundle, if it isn't marked yet.
+///
+/// # Arguments
+///
+/// * `cwd` - A path reference to the unpacked folder.
+/// * `bundle` - A path reference to the bundle file.
+/// * `temporary` - True if the folder is removed with the storage (see `Storage::unpack_temp()`).
+///
+/// # Returns
+///
+/// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+pub(crate) fn track(cwd: &Path, bundle: &Path, temporary: bool) -> Result<(), E> {
+    let path = cwd.join(SOURCE_FILE_NAME);
+    if path.exists() {
+        return Ok(());
+    }
+    let mut file = fs::create(&path)?;
+    writeln!(file, "{}", bundle.display())?;
+    if temporary {
+        writeln!(file, "{TEMPORARY}")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Prepares unpacking of the bundle: creates the unpacked folder (if it doesn't exist yet), reads
+/// the index and opens the progress of unpacking.
+///
+/// # Arguments
+///
+/// * `bundle` - A path reference to the bundle file.
+/// * `cwd` - A path reference to the unpacked folder.
+///
+/// # Returns
+///
+/// * `Result<(File, Index, Progress), E>` - Returns the opened bundle, its index and the
+///   progress, or an error.
+pub(crate) fn begin_unpack(bundle: &Path, cwd: &Path) -> Result<(File, Index, Progress), E> {
+    if !bundle.exists() || !bundle.is_file() {
+        return Err(E::PackageFileDoesNotExist(bundle.to_path_buf()));
+    }
+    if !cwd.exists() {
+        create_dir(cwd)?;
+    }
+    track(cwd, bundle, false)?;
+    let mut file = fs::read(bundle)?;
+    let index = read_index(&mut file, bundle)?;
+    let progress = Progress::open(cwd, &fingerprint(&file, &index)?)?;
+    Ok((file, index, progress))
+}
+
+/// Flattens the index into storages of the unpacked folder.
+///
+/// # Arguments
+///
+/// * `index` - Index of the bundle.
+/// * `cwd` - A path reference to the unpacked folder.
+///
+/// # Returns
+///
+/// * `Vec<(PathBuf, String, Vec<Location>)>` - Returns the folder of each storage, its path
+///   relative to the unpacked folder (empty or ending with `/`) and its records.
+pub(crate) fn flatten(index: Index, cwd: &Path) -> Vec<(PathBuf, String, Vec<Location>)> {
+    let mut storages = Vec::new();
+    let mut queue = vec![(index, cwd.to_path_buf(), String::new())];
+    while let Some((index, cwd, prefix)) = queue.pop() {
+        for (name, child) in index.children.into_iter().rev() {
+            let prefix = format!("{prefix}{CHILDREN_DIR}/{name}/");
+            queue.push((child, cwd.join(CHILDREN_DIR).join(name), prefix));
+        }
+        for (name, store) in index.stores.into_iter().rev() {
+            let prefix = format!("{prefix}{name}/");
+            queue.push((store, cwd.join(name), prefix));
+        }
+        storages.push((cwd, prefix, index.records));
+    }
+    storages
+}
+
+/// Writes the map of the unpacked storage. Records are given with names of their files and their
+/// metadata; metadata, which isn't kept in the bundle, is taken from record files. Unpacking over
+/// an existing storage replaces its map with the next generation, so open instances of the
+/// previous storage become stale.
+pub(crate) fn write_map(cwd: &Path, records: &Unpacked) -> Result<(), E> {
+    let mut fields = KeyHasher::default().fields();
+    for (key, (file, meta)) in records {
+        let meta = meta
+            .clone()
+            .unwrap_or_else(|| Meta::from_file(cwd.join(file)));
+        fields.insert(key.to_owned(), Field::restore(file, meta));
+    }
+    let mut map = map::Map::new(cwd, &StorageOptions::default());
+    if cwd.join(map::MAP_FILE_NAME).exists() {
+        return map.replace(&fields);
+    }
+    map.write(&fields)
+}
+
+/// Splits the content of the record file into the header (empty for legacy records) and the
+/// payload.
+pub(crate) fn split_record(content: &[u8]) -> Result<(Vec<u8>, &[u8]), E> {
+    let (_, payload) = Header::decode(content)?;
+    Ok((content[..content.len() - payload.len()].to_vec(), payload))
+}
+
+/// Writes the content of the record file into the bundle. The payload is written only if the
+/// bundle doesn't have the same payload yet.
+///
+/// # Arguments
+///
+/// * `bundle` - The bundle file.
+/// * `cursor` - Current position in the bundle file.
+/// * `payloads` - Positions of written payloads by their SHA-256 hashes.
+/// * `key` - Key of the record.
+/// * `file` - Name of the record file.
+/// * `content` - Content of the record file.
+/// * `meta` - Metadata of the record.
+/// * `compression` - Defines whether the payload is compressed.
+///
+/// # Returns
+///
+/// * `Result<Location, E>` - Returns the location of the record, or an error.
+#[allow(clippy::too_many_arguments)]
+fn write_record(
+    bundle: &mut File,
+    cursor: &mut u64,
+    payloads: &mut Payloads,
+    key: &str,
+    file: &str,
+    content: &[u8],
+    meta: Option<Meta>,
+    compression: PackCompression,
+) -> Result<Location, E> {
+    let (header, payload) = split_record(content)?;
+    let hash = Sha256::digest(payload).to_vec();
+    let (from, to, compressed) = put_payload(bundle, cursor, payloads, hash, || {
+        compression.encode(payload)
+    })?;
+    Ok(Location {
+        key: key.to_owned(),
+        file: file.to_owned(),
+        header,
+        from,
+        to,
+        compressed,
+        meta,
+    })
+}
+
+/// Writes the payload into the bundle, if the bundle doesn't have it yet.
+///
+/// # Arguments
+///
+/// * `bundle` - The bundle file.
+/// * `cursor` - Current position in the bundle file.
+/// * `payloads` - Written payloads.
+/// * `hash` - Hash of the payload.
+/// * `encode` - Returns the payload as it should be written and true if it's compressed.
+///
+/// # Returns
+///
+/// * `Result<(u64, u64, bool), E>` - Returns the start and the end of the payload in the bundle
+///   and true if it's compressed, or an error.
+fn put_payload<'a, F: FnOnce() -> io::Result<(Cow<'a, [u8]>, bool)>>(
+    bundle: &mut File,
+    cursor: &mut u64,
+    payloads: &mut Payloads,
+    hash: Vec<u8>,
+    encode: F,
+) -> Result<(u64, u64, bool), E> {
+    Ok(match payloads.entry(hash) {
+        Entry::Occupied(entry) => *entry.get(),
+        Entry::Vacant(entry) => {
+            let (payload, compressed) = encode()?;
+            // Reads of the bundle may move the cursor of the file on some platforms
+            bundle.seek(SeekFrom::Start(*cursor))?;
+            bundle.write_all(&payload)?;
+            let from = *cursor;
+            *cursor += payload.len() as u64;
+            *entry.insert((from, *cursor, compressed))
+        }
+    })
+}
+
+/// Writes records of the storage and all its child storages into the bundle.
+///
+/// # Arguments
+///
+/// * `storage` - The storage to write.
+/// * `bundle` - The bundle file.
+/// * `cursor` - Current position in the bundle file.
+/// * `payloads` - Positions of written payloads by their SHA-256 hashes.
+/// * `previous` - Index of records, which are already in the bundle (see `Bundle::append()`).
+///   Unchanged records aren't written again; records missing in the storage are kept.
+/// * `cancel` - Stops writing with `E::Cancelled` when set.
+///
+/// # Returns
+///
+/// * `Result<Index, E>` - Returns the index of the written storage, or an error.
+fn write_tree(
+    storage: &Storage,
+    bundle: &mut File,
+    cursor: &mut u64,
+    payloads: &mut Payloads,
+    previous: Index,
+    cancel: &AtomicBool,
+) -> Result<Index, E> {
+    let mut index = Index::default();
+    let mut kept = previous
+        .records
+        .into_iter()
+        .map(|location| (location.key.clone(), location))
+        .collect::<HashMap<String, Location>>();
+    let mut kept_children = previous.children.into_iter().collect::<HashMap<_, _>>();
+    let fields = storage.packing_order();
+    let batch = &storage.options.batch;
+    for chunk in fields.chunks(batch.chunk.max(1)) {
+        cancel::check(cancel)?;
+        let contents = read_chunk(storage.cwd(), chunk, batch.readahead);
+        for ((key, field), buffer) in chunk.iter().zip(contents) {
+            // Records are written as they are, so encrypted values stay encrypted
+            let buffer =
+                buffer.map_err(|e| e.record(Operation::Pack, key, &field.path(storage.cwd())))?;
+            if buffer.is_empty() {
+                continue;
+            }
+            if let Some(location) = kept.remove(key.as_str()) {
+                let content = location.read(bundle)?;
+                if content == buffer {
+                    payloads
+                        .entry(Sha256::digest(&content[location.header.len()..]).to_vec())
+                        .or_insert((location.from, location.to, location.compressed));
+                    // Pins and tags may have been changed without changing the record
+                    index.records.push(Location {
+                        meta: Some(field.meta().clone()),
+                        ..location
+                    });
+                    continue;
+                }
+            }
+            index.records.push(write_record(
+                bundle,
+                cursor,
+                payloads,
+                key,
+                field.file_name(),
+                &buffer,
+                Some(field.meta().clone()),
+                storage.options.pack_compression,
+            )?);
+        }
+    }
+    index.records.extend(kept.into_values());
+    for (name, cwd) in children_of(storage.cwd())? {
+        let child = Storage::open_with_options(cwd, storage.options.clone())?;
+        let previous = kept_children.remove(&name).unwrap_or_default();
+        let written = write_tree(&child, bundle, cursor, payloads, previous, cancel)?;
+        index.children.push((name, written));
+    }
+    index.children.extend(kept_children);
+    Ok(index)
+}
+
+/// Copies records of the storage and all its child storages from one bundle into another.
+///
+/// # Arguments
+///
+/// * `input` - The source bundle.
+/// * `output` - The written bundle.
+/// * `cursor` - Current position in the written bundle.
+/// * `payloads` - Positions of written payloads by their SHA-256 hashes.
+/// * `index` - Index of the storage in the source bundle.
+/// * `path` - Path of the storage (names of child storages separated by `/`); empty for the root.
+/// * `options` - Defines which records are dropped.
+///
+/// # Returns
+///
+/// * `Result<Index, E>` - Returns the index of the storage in the written bundle, or an error.
+fn copy_tree(
+    input: &File,
+    output: &mut File,
+    cursor: &mut u64,
+    payloads: &mut Payloads,
+    index: Index,
+    path: &str,
+    options: &RepackOptions,
+) -> Result<Index, E> {
+    let mut copied = Index::default();
+    for location in index.records {
+        if !location.is_valid() || (path.is_empty() && options.drop_keys.contains(&location.key)) {
+            continue;
+        }
+        let location = match options.compression {
+            Some(compression) => write_record(
+                output,
+                cursor,
+                payloads,
+                &location.key,
+                &location.file,
+                &location.read(input)?,
+                location.meta.clone(),
+                compression,
+            )?,
+            None => {
+                // Payloads are copied as they are: compressed payloads stay compressed
+                let raw = location.read_raw(input)?;
+                let mut hash = Sha256::digest(&raw).to_vec();
+                hash.push(location.compressed as u8);
+                let (from, to, compressed) = put_payload(output, cursor, payloads, hash, || {
+                    Ok((Cow::Borrowed(raw.as_slice()), location.compressed))
+                })?;
+                Location {
+                    from,
+                    to,
+                    compressed,
+                    ..location
+                }
+            }
+        };
+        copied.records.push(location);
+    }
+    for (name, child) in index.children {
+        let path = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{path}/{name}")
+        };
+        if options.drop_children.contains(&path) {
+            continue;
+        }
+        let child = copy_tree(input, output, cursor, payloads, child, &path, options)?;
+        copied.children.push((name, child));
+    }
+    // Named storages are copied as a whole
+    let whole = RepackOptions {
+        compression: options.compression,
+        ..Default::default()
+    };
+    for (name, store) in index.stores {
+        let store = copy_tree(input, output, cursor, payloads, store, "", &whole)?;
+        copied.stores.push((name, store));
+    }
+    Ok(copied)
+}
+
+/// Restores records of the storage from the bundle and writes the map of the storage.
+///
+/// # Arguments
+///
+/// * `bundle` - The bundle file.
+/// * `cwd` - A path reference to the folder of the storage.
+/// * `prefix` - Path of the folder of the storage relative to the unpacked folder.
+/// * `records` - Positions of records in the bundle.
+/// * `progress` - Progress of unpacking; restored records are skipped.
+/// * `cancel` - Stops restoring with `E::Cancelled` when set.
+///
+/// # Returns
+///
+/// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+fn restore(
+    bundle: &mut File,
+    cwd: &Path,
+    prefix: &str,
+    records: Vec<Location>,
+    progress: &mut Progress,
+    cancel: &AtomicBool,
+) -> Result<(), E> {
+    let mut map = Unpacked::new();
+    let records = records
+        .into_iter()
+        .filter(|location| {
+            if !location.is_valid() {
+                warn!(
+                    "Record \"{}\" has invalid position. Record will be skipped",
+                    location.key
+                );
+            }
+            location.is_valid()
+        })
+        .collect::<Vec<Location>>();
+    // Records are written in batches (see `fs::write_many()`)
+    for chunk in records.chunks(BatchReads::default().chunk) {
+        cancel::check(cancel)?;
+        let (restored, chunk): (Vec<&Location>, Vec<&Location>) = chunk
+            .iter()
+            .partition(|location| progress.is_done(&format!("{prefix}{}", location.file)));
+        for location in restored {
+            map.insert(
+                location.key.to_owned(),
+                (location.file.to_owned(), location.meta.clone()),
+            );
+        }
+        let mut contents = Vec::with_capacity(chunk.len());
+        for location in chunk.iter() {
+            contents.push(location.read(bundle)?);
+        }
+        let mut files = Vec::with_capacity(chunk.len());
+        for Location { key, file, .. } in chunk.iter() {
+            let path = cwd.join(file);
+            files.push(
+                fs::create(&path).map_err(|e| E::from(e).record(Operation::Unpack, key, &path))?,
+            );
+        }
+        let files = files
+            .into_iter()
+            .zip(contents.iter().map(|c| c.as_slice()))
+            .collect::<Vec<_>>();
+        for (
+            result,
+            Location {
+                key, file, meta, ..
+            },
+        ) in fs::write_many(&files).into_iter().zip(chunk.iter())
+        {
+            result.map_err(|e| E::from(e).record(Operation::Unpack, key, &cwd.join(file)))?;
+            map.insert(key.to_owned(), (file.to_owned(), meta.clone()));
+        }
+        progress.restored(
+            chunk
+                .iter()
+                .map(|location| format!("{prefix}{}", location.file))
+                .collect(),
+        )?;
+    }
+    write_map(cwd, &map)
+}
+
+/// Restores the storage and all its child storages (and named storages of the bundle, which are
+/// restored into folders named after them) from the bundle.
+fn restore_tree(
+    bundle: &mut File,
+    cwd: &Path,
+    prefix: &str,
+    index: Index,
+    progress: &mut Progress,
+    cancel: &AtomicBool,
+) -> Result<(), E> {
+    restore(bundle, cwd, prefix, index.records, progress, cancel)?;
+    for (name, child) in index.children {
+        let prefix = format!("{prefix}{CHILDREN_DIR}/{name}/");
+        let cwd = cwd.join(CHILDREN_DIR).join(name);
+        create_dir_all(&cwd)?;
+        restore_tree(bundle, &cwd, &prefix, child, progress, cancel)?;
+    }
+    for (name, store) in index.stores {
+        let prefix = format!("{prefix}{name}/");
+        let cwd = cwd.join(name);
+        create_dir_all(&cwd)?;
+        restore_tree(bundle, &cwd, &prefix, store, progress, cancel)?;
+    }
+    Ok(())
+}
+
+/// Settings of `Bundle::repack()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepackOptions {
+    /// Keys of the root storage, which aren't copied into the new bundle.
+    pub drop_keys: Vec<String>,
+    /// Child storages, which aren't copied into the new bundle; paths of nested child storages
+    /// are names separated by `/` (see `Storage::child()`).
+    pub drop_children: Vec<String>,
+    /// Compression of payloads in the new bundle; None to keep payloads as they are (compressed
+    /// payloads stay compressed).
+    pub compression: Option<PackCompression>,
+}
 
 /// Transferring the storage can be done by copying the entire contents of the storage directory. However,
 /// in some situations, this can be quite inconvenient, especially if the data needs to be transferred over
@@ -67,7 +890,10 @@ const U64_SIZE: usize = mem::size_of::<u64>();
 /// assert_eq!(my_record, recovered)
 /// ```
 pub trait Bundle {
-    /// Unpacks the storage from the specified bundle file.
+    /// Unpacks the storage from the specified bundle file. Unpacking, which has been interrupted
+    /// (e.g. by a crash or a cancellation), is resumed: records restored before are skipped.
+    /// Until unpacking is done, the unpacked folder cannot be opened as a storage (opening fails
+    /// with `E::PartiallyUnpacked`).
     ///
     /// # Arguments
     ///
@@ -88,6 +914,146 @@ pub trait Bundle {
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
     fn pack<P: AsRef<Path>>(&mut self, bundle: P) -> Result<(), E>;
+
+    /// Unpacks the storage as `unpack()` does; the operation can be cancelled by setting the
+    /// flag from another thread. A cancelled (or failed) unpacking keeps the partially unpacked
+    /// folder, so unpacking the bundle again resumes it.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    /// * `cancel` - Stops unpacking with `E::Cancelled` when set.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the unpacked `Storage` instance or an error.
+    fn unpack_cancellable<P: AsRef<Path>>(bundle: P, cancel: &AtomicBool) -> Result<Storage, E>;
+
+    /// Packs the storage as `pack()` does; the operation can be cancelled by setting the flag
+    /// from another thread. A cancelled (or failed) packing removes the partial bundle file.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    /// * `cancel` - Stops packing with `E::Cancelled` when set.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    fn pack_cancellable<P: AsRef<Path>>(&mut self, bundle: P, cancel: &AtomicBool)
+        -> Result<(), E>;
+
+    /// Appends records of the storage to the existing bundle: new and changed records (of the
+    /// storage and its child storages) are written after the content of the bundle, followed by
+    /// the updated index. Unchanged records aren't written again, and records, which are in the
+    /// bundle but not in the storage, are kept, so the bundle can be extended incrementally (e.g.
+    /// daily). Bytes of previous versions of changed records stay in the bundle until it's packed
+    /// again.
+    ///
+    /// The bundle stays valid if appending fails: it's switched to the new index only when the
+    /// index has been written. Bundles written by versions of `bstorage` without child storages
+    /// cannot be appended (`E::PackageFileInvalid`).
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    fn append<P: AsRef<Path>>(&mut self, bundle: P) -> Result<(), E>;
+
+    /// Rewrites the bundle into a new one without unpacking it: records are copied in the
+    /// current format of bundles (identical payloads are kept once), except records dropped
+    /// by options. Bundles written by previous versions of `bstorage` are upgraded this way, and
+    /// bytes left by `append()` are reclaimed.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - A path reference to the source bundle.
+    /// * `output` - A path reference to the new bundle; it should differ from the source one.
+    /// * `options` - Settings of repacking.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    fn repack<I: AsRef<Path>, O: AsRef<Path>>(
+        input: I,
+        output: O,
+        options: RepackOptions,
+    ) -> Result<(), E>;
+
+    /// Unpacks the storage from the bundle into a new folder in the temporary directory of the
+    /// system. The folder is locked while the storage is open and removed when the storage is
+    /// dropped; folders left by crashed processes are removed by `Storage::clean_unpacked()` of the
+    /// temporary directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the unpacked `Storage` instance or an error.
+    fn unpack_temp<P: AsRef<Path>>(bundle: P) -> Result<Storage, E>;
+
+    /// Removes orphaned folders created by unpacking (`*.unpacked` folders) in the root folder:
+    /// folders of `Storage::unpack_temp()`, which aren't locked anymore, and folders, which bundles
+    /// don't exist anymore. Folders created by other means and locked folders are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - A path reference to the folder to clean (e.g. `std::env::temp_dir()`).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<PathBuf>, E>` - Returns removed folders, or an error.
+    fn clean_unpacked<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>, E>;
+
+    /// Packs several named storages (e.g. profiles and caches of the application) into one
+    /// bundle. Child storages are packed with each storage, and identical payloads are kept once
+    /// across all storages. `unpack()` of such bundle restores each storage into the folder named
+    /// after it in the unpacked folder; `Storage::unpack_only()` restores selected storages.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    /// * `stores` - Names of storages (names of folders, see `Storage::child()`) and storages.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    fn pack_stores<P: AsRef<Path>>(bundle: P, stores: &[(&str, &Storage)]) -> Result<(), E>;
+
+    /// Returns names of storages packed with `Storage::pack_stores()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns names of storages (empty for bundles written by
+    ///   `pack()`), or an error.
+    fn stores<P: AsRef<Path>>(bundle: P) -> Result<Vec<String>, E>;
+
+    /// Unpacks selected storages of the bundle written by `Storage::pack_stores()`. Each storage
+    /// is restored into the folder named after it in the unpacked folder; other storages aren't
+    /// restored.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    /// * `names` - Names of storages to unpack.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<Storage>, E>` - Returns unpacked storages in the order of names, or
+    ///   `E::InvalidStorageName` if the bundle doesn't have some of them.
+    fn unpack_only<P: AsRef<Path>, N: AsRef<str>>(
+        bundle: P,
+        names: &[N],
+    ) -> Result<Vec<Storage>, E>;
 }
 
 impl Bundle for Storage {
@@ -95,6 +1061,7 @@ impl Bundle for Storage {
     ///
     /// This method reads the bundle file, extracts individual records, and writes them
     /// to the storage directory specified by changing the extension of the bundle file.
+    /// Child storages are restored as well. Interrupted unpacking is resumed (see `Bundle::unpack()`).
     ///
     /// # Arguments
     ///
@@ -104,49 +1071,14 @@ impl Bundle for Storage {
     ///
     /// * `Result<Self, E>` - Returns the unpacked `Storage` instance or an error.
     fn unpack<P: AsRef<Path>>(bundle: P) -> Result<Self, E> {
-        let bundle = fs::as_path_buf(bundle);
-        if !bundle.exists() || !bundle.is_file() {
-            return Err(E::PackageFileDoesNotExist(bundle));
-        }
-        let mut cwd = bundle.clone();
-        cwd.set_extension(UNPACKED_EXT);
-        if !cwd.exists() {
-            create_dir(&cwd)?;
-        }
-        let mut file = fs::read(&bundle)?;
-        if bundle.metadata()?.len() < U64_SIZE as u64 {
-            return Err(E::PackageFileInvalid(bundle));
-        }
-        let mut buffer = [0u8; U64_SIZE];
-        file.read_exact(&mut buffer)?;
-        let map_pos = u64::from_le_bytes(buffer) as usize;
-        let mut buffer: Vec<u8> = Vec::new();
-        file.seek(SeekFrom::Start(map_pos as u64))?;
-        file.read_to_end(&mut buffer)?;
-        let location: HashMap<String, (String, u64, u64)> = bincode::deserialize(&buffer)?;
-        let mut map: HashMap<String, String> = HashMap::new();
-        for (key, (filename, from, to)) in location {
-            if to < from {
-                warn!("Record \"{key}\" has invalid position. Record will be skipped");
-                continue;
-            }
-            let size = (to - from) as usize;
-            let mut buffer = vec![0; size];
-            file.seek(SeekFrom::Start(from))?;
-            file.read_exact(&mut buffer)?;
-            let mut record = fs::create(cwd.join(&filename))?;
-            record.write_all(&buffer)?;
-            map.insert(key, filename);
-        }
-        let mut map_file = fs::create(cwd.join(map::MAP_FILE_NAME))?;
-        let buffer = bincode::serialize(&map)?;
-        map_file.write_all(&buffer)?;
-        Self::open(cwd)
+        Self::unpack_cancellable(bundle, &cancel::NEVER)
     }
 
     /// Packs the storage into the specified bundle file.
     ///
     /// This method serializes all records into a single file for easy transfer and storage.
+    /// Child storages (see `Storage::child()`) are packed into the same file. Identical payloads
+    /// (e.g. the same default value under many keys) are written into the bundle once.
     ///
     /// # Arguments
     ///
@@ -156,29 +1088,743 @@ impl Bundle for Storage {
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
     fn pack<P: AsRef<Path>>(&mut self, bundle: P) -> Result<(), E> {
-        let mut location: Vec<(String, String, u64, u64)> = Vec::new();
-        let mut cursor = U64_SIZE as u64;
-        let fields = self.fields.iter().collect::<Vec<(&String, &Field)>>();
-        for (key, field) in fields.iter() {
-            let size = field.size()?;
-            if size == 0 {
+        self.pack_cancellable(bundle, &cancel::NEVER)
+    }
+
+    fn unpack_temp<P: AsRef<Path>>(bundle: P) -> Result<Self, E> {
+        let bundle = fs::as_path_buf(bundle);
+        let _op = op!("unpack", path, bundle);
+        let cwd = temp_dir().join(format!("bstorage-{}.{UNPACKED_EXT}", Uuid::new_v4()));
+        create_dir(&cwd)?;
+        let unpacked = (|| -> Result<Self, E> {
+            let lock = Lock::acquire(&cwd)?;
+            track(&cwd, &bundle, true)?;
+            let (mut file, index, mut progress) = begin_unpack(&bundle, &cwd)?;
+            restore_tree(&mut file, &cwd, "", index, &mut progress, &cancel::NEVER)?;
+            progress.finish(&cwd)?;
+            let mut storage = Self::open(&cwd)?;
+            storage.lock = Some(lock);
+            storage.temporary = true;
+            Ok(storage)
+        })();
+        if unpacked.is_err() {
+            if let Err(err) = remove_dir_all(&cwd) {
+                warn!("Fail to remove temporary storage {cwd:?}: {err}");
+            }
+        }
+        unpacked
+    }
+
+    fn clean_unpacked<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>, E> {
+        let root = fs::as_path_buf(root);
+        let mut removed = Vec::new();
+        for entry in read_dir(&root).map_err(|e| E::io(e, &root))? {
+            let cwd = entry?.path();
+            if !cwd.is_dir() || cwd.extension().is_none_or(|ext| ext != UNPACKED_EXT) {
                 continue;
             }
-            location.push((
-                key.to_owned().clone(),
-                field.file_name()?,
-                cursor,
-                cursor + size,
-            ));
-            cursor += size;
+            let Ok(source) = read_to_string(cwd.join(SOURCE_FILE_NAME)) else {
+                continue;
+            };
+            let mut lines = source.lines();
+            let orphaned = lines
+                .next()
+                .is_none_or(|bundle| !Path::new(bundle).exists())
+                || lines.next() == Some(TEMPORARY);
+            if !orphaned {
+                continue;
+            }
+            let _lock = match Lock::acquire(&cwd) {
+                Ok(lock) => lock,
+                Err(E::Locked(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            remove_dir_all(&cwd).map_err(|e| E::io(e, &cwd))?;
+            debug!("Orphaned unpacked folder {cwd:?} is removed");
+            removed.push(cwd);
+        }
+        Ok(removed)
+    }
+
+    fn pack_stores<P: AsRef<Path>>(bundle: P, stores: &[(&str, &Storage)]) -> Result<(), E> {
+        let op = op!("pack", path, bundle.as_ref());
+        let path = bundle.as_ref();
+        let mut names = HashSet::new();
+        for (name, _) in stores {
+            if !fs::is_portable_name(name) || !names.insert(*name) {
+                return Err(E::InvalidStorageName(name.to_string()));
+            }
+        }
+        let mut bundle = fs::create(path)?;
+        let mut cursor = (BUNDLE_MAGIC.len() + U64_SIZE) as u64;
+        let written = (|| -> Result<usize, E> {
+            bundle.write_all(&BUNDLE_MAGIC)?;
+            bundle.write_all(&0u64.to_le_bytes())?;
+            let mut index = Index::default();
+            let mut payloads = HashMap::new();
+            for (name, storage) in stores {
+                let store = write_tree(
+                    storage,
+                    &mut bundle,
+                    &mut cursor,
+                    &mut payloads,
+                    Index::default(),
+                    &cancel::NEVER,
+                )?;
+                index.stores.push((name.to_string(), store));
+            }
+            let index = bincode::serialize(&index)?;
+            bundle.write_all(&index)?;
+            bundle.seek(SeekFrom::Start(BUNDLE_MAGIC.len() as u64))?;
+            bundle.write_all(&cursor.to_le_bytes())?;
+            Ok(index.len())
+        })();
+        match written {
+            Ok(index) => {
+                op.size(|| Some(cursor + index as u64));
+                Ok(())
+            }
+            Err(err) => {
+                drop(bundle);
+                if let Err(err) = remove_file(path) {
+                    warn!("Fail to remove partial bundle {path:?}: {err}");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn stores<P: AsRef<Path>>(bundle: P) -> Result<Vec<String>, E> {
+        let path = fs::as_path_buf(bundle);
+        let index = read_index(&mut fs::read(&path)?, &path)?;
+        Ok(index.stores.into_iter().map(|(name, _)| name).collect())
+    }
+
+    fn unpack_only<P: AsRef<Path>, N: AsRef<str>>(bundle: P, names: &[N]) -> Result<Vec<Self>, E> {
+        let bundle = fs::as_path_buf(bundle);
+        let _op = op!("unpack", path, bundle);
+        let cwd = bundle.with_extension(UNPACKED_EXT);
+        let (mut file, mut index, mut progress) = begin_unpack(&bundle, &cwd)?;
+        let mut selected = Vec::with_capacity(names.len());
+        for name in names {
+            let name = name.as_ref();
+            let Some(position) = index.stores.iter().position(|(store, _)| store == name) else {
+                return Err(E::InvalidStorageName(name.to_owned()));
+            };
+            selected.push(index.stores.swap_remove(position));
+        }
+        let mut folders = Vec::with_capacity(selected.len());
+        for (name, store) in selected {
+            let folder = cwd.join(&name);
+            create_dir_all(&folder)?;
+            let prefix = format!("{name}/");
+            restore_tree(
+                &mut file,
+                &folder,
+                &prefix,
+                store,
+                &mut progress,
+                &cancel::NEVER,
+            )?;
+            folders.push(folder);
         }
-        let map = bincode::serialize(&location)?;
-        let mut bundle = fs::create(bundle)?;
-        bundle.write_all(&cursor.to_le_bytes())?;
-        for (_, field) in fields.iter() {
-            bundle.write_all(&field.extract()?)?;
+        progress.finish(&cwd)?;
+        folders.into_iter().map(Self::open).collect()
+    }
+
+    fn unpack_cancellable<P: AsRef<Path>>(bundle: P, cancel: &AtomicBool) -> Result<Self, E> {
+        let bundle = fs::as_path_buf(bundle);
+        let op = op!("unpack", path, bundle);
+        let cwd = bundle.with_extension(UNPACKED_EXT);
+        let (mut file, index, mut progress) = begin_unpack(&bundle, &cwd)?;
+        op.size(|| bundle.metadata().ok().map(|m| m.len()));
+        restore_tree(&mut file, &cwd, "", index, &mut progress, cancel)?;
+        progress.finish(&cwd)?;
+        Self::open(cwd)
+    }
+
+    fn pack_cancellable<P: AsRef<Path>>(
+        &mut self,
+        bundle: P,
+        cancel: &AtomicBool,
+    ) -> Result<(), E> {
+        let op = op!("pack", path, bundle.as_ref());
+        let path = bundle.as_ref();
+        let mut bundle = fs::create(path)?;
+        let mut cursor = (BUNDLE_MAGIC.len() + U64_SIZE) as u64;
+        let written = (|| -> Result<usize, E> {
+            bundle.write_all(&BUNDLE_MAGIC)?;
+            bundle.write_all(&0u64.to_le_bytes())?;
+            let index = bincode::serialize(&write_tree(
+                self,
+                &mut bundle,
+                &mut cursor,
+                &mut HashMap::new(),
+                Index::default(),
+                cancel,
+            )?)?;
+            bundle.write_all(&index)?;
+            bundle.seek(SeekFrom::Start(BUNDLE_MAGIC.len() as u64))?;
+            bundle.write_all(&cursor.to_le_bytes())?;
+            Ok(index.len())
+        })();
+        match written {
+            Ok(index) => {
+                op.size(|| Some(cursor + index as u64));
+                Ok(())
+            }
+            Err(err) => {
+                drop(bundle);
+                if let Err(err) = remove_file(path) {
+                    warn!("Fail to remove partial bundle {path:?}: {err}");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn append<P: AsRef<Path>>(&mut self, bundle: P) -> Result<(), E> {
+        let op = op!("append", path, bundle.as_ref());
+        let path = bundle.as_ref();
+        if !path.is_file() {
+            return Err(E::PackageFileDoesNotExist(fs::as_path_buf(path)));
+        }
+        let mut bundle = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(fs::long_path(path))
+            .map_err(|e| E::io(e, path))?;
+        let mut previous = read_index(&mut bundle, path)?;
+        // Named storages of the bundle are kept as they are
+        let stores = mem::take(&mut previous.stores);
+        let mut magic = [0u8; U64_SIZE];
+        fs::read_exact_at(&bundle, &mut magic, 0)?;
+        if ![BUNDLE_MAGIC, BUNDLE_MAGIC_V3, BUNDLE_MAGIC_V2].contains(&magic) {
+            return Err(E::PackageFileInvalid(fs::as_path_buf(path)));
+        }
+        let len = bundle.metadata()?.len();
+        let mut cursor = len;
+        let written = (|| -> Result<u64, E> {
+            let mut index = write_tree(
+                self,
+                &mut bundle,
+                &mut cursor,
+                &mut HashMap::new(),
+                previous,
+                &cancel::NEVER,
+            )?;
+            index.stores = stores;
+            let index = bincode::serialize(&index)?;
+            bundle.seek(SeekFrom::Start(cursor))?;
+            bundle.write_all(&index)?;
+            // The index should be on the disk before the bundle is switched to it
+            bundle.sync_data()?;
+            bundle.seek(SeekFrom::Start(0))?;
+            let mut header = BUNDLE_MAGIC.to_vec();
+            header.extend_from_slice(&cursor.to_le_bytes());
+            bundle.write_all(&header)?;
+            Ok(cursor + index.len() as u64)
+        })();
+        match written {
+            Ok(size) => {
+                op.size(|| Some(size));
+                Ok(())
+            }
+            Err(err) => {
+                if let Err(err) = bundle.set_len(len) {
+                    warn!("Fail to truncate bundle {path:?} after failed appending: {err}");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn repack<I: AsRef<Path>, O: AsRef<Path>>(
+        input: I,
+        output: O,
+        options: RepackOptions,
+    ) -> Result<(), E> {
+        let op = op!("repack", path, input.as_ref());
+        let (input, output) = (fs::as_path_buf(input), fs::as_path_buf(output));
+        if !input.is_file() {
+            return Err(E::PackageFileDoesNotExist(input));
         }
-        bundle.write_all(&map)?;
+        if input == output {
+            return Err(E::InvalidPath(output));
+        }
+        let mut source = fs::read(&input)?;
+        let index = read_index(&mut source, &input)?;
+        let mut bundle = fs::create(&output)?;
+        let mut cursor = (BUNDLE_MAGIC.len() + U64_SIZE) as u64;
+        let written = (|| -> Result<u64, E> {
+            bundle.write_all(&BUNDLE_MAGIC)?;
+            bundle.write_all(&0u64.to_le_bytes())?;
+            let index = copy_tree(
+                &source,
+                &mut bundle,
+                &mut cursor,
+                &mut HashMap::new(),
+                index,
+                "",
+                &options,
+            )?;
+            let index = bincode::serialize(&index)?;
+            bundle.seek(SeekFrom::Start(cursor))?;
+            bundle.write_all(&index)?;
+            bundle.seek(SeekFrom::Start(BUNDLE_MAGIC.len() as u64))?;
+            bundle.write_all(&cursor.to_le_bytes())?;
+            Ok(cursor + index.len() as u64)
+        })();
+        match written {
+            Ok(size) => {
+                op.size(|| Some(size));
+                Ok(())
+            }
+            Err(err) => {
+                drop(bundle);
+                if let Err(err) = remove_file(&output) {
+                    warn!("Fail to remove partial bundle {output:?}: {err}");
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_index, Progress, PARTIAL_FILE_NAME, UNPACKED_EXT};
+    use crate::{
+        Bundle, Field, Lock, PackCompression, RepackOptions, Search, Storage, StorageOptions, E,
+    };
+    use std::{
+        env::temp_dir,
+        fs::{create_dir, remove_dir_all, remove_file},
+        io::Write,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn legacy() -> Result<(), E> {
+        // Bundle written by previous versions: [position of records][content][records]
+        let content = Field::encode("a", 0, &bincode::serialize(&42u8)?)?;
+        let records = vec![(
+            String::from("a"),
+            String::from("a.bstorage"),
+            8u64,
+            8 + content.len() as u64,
+        )];
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        let mut file = crate::fs::create(&bundle)?;
+        file.write_all(&(8 + content.len() as u64).to_le_bytes())?;
+        file.write_all(&content)?;
+        file.write_all(&bincode::serialize(&records)?)?;
+        drop(file);
+        let mut storage = Storage::unpack(&bundle)?;
+        assert_eq!(storage.get::<u8, &str>("a")?, Some(42));
+        storage.destroy()?;
+        // Upgraded to the current format
+        let upgraded = temp_dir().join(Uuid::new_v4().to_string());
+        Storage::repack(&bundle, &upgraded, Default::default())?;
+        assert_eq!(std::fs::read(&upgraded)?[..8], super::BUNDLE_MAGIC);
+        let mut storage = Storage::unpack(&upgraded)?;
+        assert_eq!(storage.get::<u8, &str>("a")?, Some(42));
+        storage.destroy()?;
+        remove_file(&upgraded)?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn dedup() -> Result<(), E> {
+        let config = vec![String::from("default"); 1000];
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..20 {
+            storage.set(format!("config_{i}"), &config)?;
+        }
+        storage.child("plugin")?.set("config", &config)?;
+        storage.set("other", &vec![String::from("other"); 1000])?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let payload = bincode::serialize(&config)?.len() as u64;
+        // Two distinct payloads are written
+        assert!(std::fs::metadata(&bundle)?.len() < payload * 3);
+        let packed = Storage::open_bundle(&bundle)?;
+        assert_eq!(
+            packed.get::<Vec<String>, _>("config_7")?,
+            Some(config.clone())
+        );
+        let mut unpacked = Storage::unpack(&bundle)?;
+        for i in 0..20 {
+            assert_eq!(
+                unpacked.get::<Vec<String>, _>(format!("config_{i}"))?,
+                Some(config.clone())
+            );
+        }
+        assert_eq!(
+            unpacked.child("plugin")?.get::<Vec<String>, _>("config")?,
+            Some(config)
+        );
+        assert!(unpacked.verify()?.is_ok());
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn pins() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("a", &1u8)?;
+        storage.set("b", &2u8)?;
+        storage.pin("a")?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let unpacked = Storage::unpack(&bundle)?;
+        assert!(unpacked.is_pinned("a"));
+        assert!(!unpacked.is_pinned("b"));
+        // The map of the unpacked storage is written in the current format
+        assert_eq!(
+            std::fs::read(unpacked.cwd().join(crate::MAP_FILE_NAME))?[..4],
+            *b"BSMP"
+        );
+        drop(unpacked);
+        // Pins changed without changing records are appended as well
+        storage.pin("b")?;
+        storage.append(&bundle)?;
+        let mut unpacked = Storage::unpack(&bundle)?;
+        assert_eq!(unpacked.pinned_keys().len(), 2);
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn tags() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set_with_tags("a", &1u8, &["red", "round"])?;
+        storage.set("b", &2u8)?;
+        storage.tag("b", "red")?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let unpacked = Storage::unpack(&bundle)?;
+        assert_eq!(unpacked.keys_with_tag("red"), vec!["a", "b"]);
+        assert_eq!(unpacked.keys_with_tag("round"), vec!["a"]);
+        drop(unpacked);
+        // Tags changed without changing records are appended as well
+        storage.untag("a", "red")?;
+        storage.tag("b", "round")?;
+        storage.append(&bundle)?;
+        let mut unpacked = Storage::unpack(&bundle)?;
+        assert_eq!(unpacked.keys_with_tag("red"), vec!["b"]);
+        assert_eq!(unpacked.keys_with_tag("round"), vec!["a", "b"]);
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn append() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let large = vec![0u64; 10_000];
+        storage.set("large", &large)?;
+        storage.set("changed", &1u8)?;
+        storage.set("removed", &1u8)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let size = std::fs::metadata(&bundle)?.len();
+        storage.set("changed", &2u8)?;
+        storage.remove("removed")?;
+        storage.set("added", &3u8)?;
+        storage.child("nested")?.set("child", &4u8)?;
+        storage.append(&bundle)?;
+        // Unchanged payload isn't written again
+        assert!(std::fs::metadata(&bundle)?.len() < size + 1024);
+        storage.child("nested")?.set("child", &5u8)?;
+        storage.append(&bundle)?;
+        let packed = Storage::open_bundle(&bundle)?;
+        assert_eq!(packed.len(), 4);
+        assert_eq!(packed.get::<Vec<u64>, _>("large")?, Some(large));
+        assert_eq!(packed.get::<u8, _>("changed")?, Some(2));
+        assert_eq!(packed.get::<u8, _>("removed")?, Some(1));
+        assert_eq!(packed.get::<u8, _>("added")?, Some(3));
+        let nested = packed.child("nested").expect("Child is appended");
+        assert_eq!(nested.get::<u8, _>("child")?, Some(5));
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn repack() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("a", &vec![1u64; 10_000])?;
+        storage.set("b", &2u8)?;
+        storage.child("x")?.set("c", &3u8)?;
+        storage.child("y/z")?.set("d", &4u8)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        storage.set("a", &vec![2u64; 10_000])?;
+        storage.append(&bundle)?;
+        let repacked = temp_dir().join(Uuid::new_v4().to_string());
+        assert!(matches!(
+            Storage::repack(&bundle, &bundle, RepackOptions::default()),
+            Err(E::InvalidPath(_))
+        ));
+        Storage::repack(&bundle, &repacked, RepackOptions::default())?;
+        // Previous version of "a" is dropped
+        assert!(std::fs::metadata(&repacked)?.len() < 90_000);
+        assert!(std::fs::metadata(&bundle)?.len() > 160_000);
+        Storage::repack(
+            &bundle,
+            &repacked,
+            RepackOptions {
+                drop_keys: vec![String::from("b")],
+                drop_children: vec![String::from("y/z")],
+                ..Default::default()
+            },
+        )?;
+        let packed = Storage::open_bundle(&repacked)?;
+        assert_eq!(packed.get::<Vec<u64>, _>("a")?, Some(vec![2u64; 10_000]));
+        assert!(!packed.has("b"));
+        let x = packed.child("x").expect("Child is kept");
+        assert_eq!(x.get::<u8, _>("c")?, Some(3));
+        assert!(packed.child("y").is_some());
+        assert!(packed.child("y/z").is_none());
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        remove_file(&repacked)?;
+        Ok(())
+    }
+
+    #[test]
+    fn compression() -> Result<(), E> {
+        let options = StorageOptions {
+            pack_compression: PackCompression::Auto { min_size: 64 },
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        let text = "lorem ipsum dolor sit amet ".repeat(1000);
+        // Pseudo-random bytes don't compress (as images or archives)
+        let mut state = 1u64;
+        let noise = (0..20_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (state >> 56) as u8
+            })
+            .collect::<Vec<u8>>();
+        storage.set("text", &text)?;
+        storage.set("noise", &noise)?;
+        storage.set("small", &1u8)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let index = super::read_index(&mut crate::fs::read(&bundle)?, &bundle)?;
+        let compressed = |key: &str| {
+            index
+                .records
+                .iter()
+                .find(|location| location.key == key)
+                .map(|location| location.compressed)
+        };
+        assert_eq!(compressed("text"), Some(true));
+        assert_eq!(compressed("noise"), Some(false));
+        assert_eq!(compressed("small"), Some(false));
+        assert!(std::fs::metadata(&bundle)?.len() < noise.len() as u64 + 2048);
+        let packed = Storage::open_bundle(&bundle)?;
+        assert_eq!(packed.get::<String, _>("text")?, Some(text.clone()));
+        // Repacking keeps compressed payloads or recompresses them
+        let repacked = temp_dir().join(Uuid::new_v4().to_string());
+        Storage::repack(&bundle, &repacked, Default::default())?;
+        assert_eq!(
+            std::fs::metadata(&repacked)?.len(),
+            std::fs::metadata(&bundle)?.len()
+        );
+        Storage::repack(
+            &bundle,
+            &repacked,
+            RepackOptions {
+                compression: Some(PackCompression::None),
+                ..Default::default()
+            },
+        )?;
+        assert!(std::fs::metadata(&repacked)?.len() > text.len() as u64);
+        let mut unpacked = Storage::unpack(&repacked)?;
+        assert_eq!(unpacked.get::<String, _>("text")?, Some(text));
+        assert_eq!(unpacked.get::<Vec<u8>, _>("noise")?, Some(noise));
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        remove_file(&repacked)?;
+        Ok(())
+    }
+
+    #[test]
+    fn cancellation() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..10u8 {
+            storage.set(i.to_string(), &i)?;
+        }
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        let cancel = AtomicBool::new(true);
+        assert!(matches!(
+            storage.pack_cancellable(&bundle, &cancel),
+            Err(E::Cancelled)
+        ));
+        assert!(!bundle.exists());
+        assert!(matches!(
+            storage.verify_cancellable(&cancel),
+            Err(E::Cancelled)
+        ));
+        assert!(matches!(
+            storage.maintain_cancellable(&cancel),
+            Err(E::Cancelled)
+        ));
+        assert!(matches!(
+            storage.filter_cancellable(|_: &u8| true, &cancel),
+            Err(E::Cancelled)
+        ));
+        storage.pack(&bundle)?;
+        assert!(matches!(
+            Storage::unpack_cancellable(&bundle, &cancel),
+            Err(E::Cancelled)
+        ));
+        // The partially unpacked folder is kept to resume unpacking, but cannot be opened
+        assert!(matches!(
+            Storage::open(bundle.with_extension(UNPACKED_EXT)),
+            Err(E::PartiallyUnpacked(_))
+        ));
+        cancel.store(false, Ordering::Relaxed);
+        let mut unpacked = Storage::unpack_cancellable(&bundle, &cancel)?;
+        assert_eq!(unpacked.filter(|_: &u8| true)?.len(), 10);
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn resume() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("a", &1u8)?;
+        storage.set("b", &2u8)?;
+        storage.child("nested")?.set("c", &3u8)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let unpacked = Storage::unpack(&bundle)?;
+        let cwd = unpacked.cwd().to_path_buf();
+        assert!(!cwd.join(PARTIAL_FILE_NAME).exists());
+        // Interrupted unpacking: "a" is restored already, "b" and "c" aren't
+        let a = unpacked.fields["a"].path(&cwd);
+        drop(unpacked);
+        let mut file = crate::fs::read(&bundle)?;
+        let index = read_index(&mut file, &bundle)?;
+        let mut progress = Progress::open(&cwd, &super::fingerprint(&file, &index)?)?;
+        progress.restored(vec![a
+            .file_name()
+            .expect("Record file has name")
+            .to_string_lossy()
+            .to_string()])?;
+        drop(progress);
+        std::fs::write(&a, b"restored")?;
+        // Resumed unpacking skips "a"
+        let unpacked = Storage::unpack(&bundle)?;
+        assert_eq!(std::fs::read(&a)?, b"restored");
+        assert_eq!(unpacked.get::<u8, _>("b")?, Some(2));
+        assert_eq!(unpacked.child("nested")?.get::<u8, _>("c")?, Some(3));
+        // Manifest of another bundle is discarded
+        Progress::open(&cwd, "another")?.restored(vec![String::from("b")])?;
+        drop(unpacked);
+        let mut unpacked = Storage::unpack(&bundle)?;
+        assert_eq!(unpacked.get::<u8, _>("a")?, Some(1));
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn unpacked_folders() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("a", &1u8)?;
+        let root = temp_dir().join(Uuid::new_v4().to_string());
+        create_dir(&root)?;
+        let bundle = root.join("bundle.bin");
+        storage.pack(&bundle)?;
+        // Temporary folder is removed on drop
+        let temporary = Storage::unpack_temp(&bundle)?;
+        assert_eq!(temporary.get::<u8, _>("a")?, Some(1));
+        let cwd = temporary.cwd().to_path_buf();
+        assert!(cwd.starts_with(temp_dir()));
+        drop(temporary);
+        assert!(!cwd.exists());
+        // Folders, which bundles exist, are kept
+        let unpacked = Storage::unpack(&bundle)?;
+        let cwd = unpacked.cwd().to_path_buf();
+        drop(unpacked);
+        create_dir(root.join("other.unpacked"))?;
+        assert!(Storage::clean_unpacked(&root)?.is_empty());
+        // Locked folders are kept
+        remove_file(&bundle)?;
+        let lock = Lock::acquire(&cwd)?;
+        assert!(Storage::clean_unpacked(&root)?.is_empty());
+        drop(lock);
+        assert_eq!(Storage::clean_unpacked(&root)?, vec![cwd.clone()]);
+        assert!(!cwd.exists());
+        // Folders created by other means are kept
+        assert!(root.join("other.unpacked").exists());
+        remove_dir_all(&root)?;
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn stores() -> Result<(), E> {
+        let mut profile = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let config = vec![String::from("default"); 1000];
+        profile.set("config", &config)?;
+        profile.child("plugins")?.set("enabled", &true)?;
+        let mut cache = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        cache.set("config", &config)?;
+        cache.set("hits", &10u32)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        assert!(matches!(
+            Storage::pack_stores(&bundle, &[("a", &profile), ("a", &cache)]),
+            Err(E::InvalidStorageName(_))
+        ));
+        Storage::pack_stores(&bundle, &[("profile-a", &profile), ("cache", &cache)])?;
+        // Identical payloads are kept once across storages
+        assert!(std::fs::metadata(&bundle)?.len() < bincode::serialize(&config)?.len() as u64 * 2);
+        assert_eq!(Storage::stores(&bundle)?, vec!["profile-a", "cache"]);
+        let cwd = bundle.with_extension(UNPACKED_EXT);
+        let unpacked = Storage::unpack_only(&bundle, &["profile-a"])?;
+        assert_eq!(unpacked.len(), 1);
+        assert_eq!(unpacked[0].cwd(), &cwd.join("profile-a"));
+        assert_eq!(unpacked[0].get::<Vec<String>, _>("config")?, Some(config));
+        assert_eq!(
+            unpacked[0].child("plugins")?.get::<bool, _>("enabled")?,
+            Some(true)
+        );
+        assert!(!cwd.join("cache").exists());
+        drop(unpacked);
+        assert!(matches!(
+            Storage::unpack_only(&bundle, &["unknown"]),
+            Err(E::InvalidStorageName(_))
+        ));
+        // All storages are unpacked by `unpack()`; named storages survive appending
+        profile.append(&bundle)?;
+        let mut root = Storage::unpack(&bundle)?;
+        assert_eq!(root.get::<bool, _>("enabled")?, None);
+        let unpacked = Storage::open(cwd.join("cache"))?;
+        assert_eq!(unpacked.get::<u32, _>("hits")?, Some(10));
+        drop(unpacked);
+        root.destroy()?;
+        profile.destroy()?;
+        cache.destroy()?;
+        remove_file(&bundle)?;
         Ok(())
     }
 }
@@ -0,0 +1,349 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{deserialize_tagged, sensitive::Wiped, type_tag, Storage, StorageKey, E};
+
+/// `CachedStorage` is a write-back layer over `Storage`. `set` and `remove` change only the memory
+/// and return immediately; changed records are written to disk with one batch (and one write of
+/// the map) on `flush()`, when the storage is dropped or when the flush interval is elapsed.
+///
+/// Reading methods take pending changes into account, so `CachedStorage` behaves like `Storage`
+/// from the point of view of the caller: keys are checked against the key policy and validators,
+/// calls pass through middlewares (see `Storage::layer()`) and `Sensitive` fields are encrypted
+/// as soon as values are cached.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{CachedStorage, Storage};
+/// use std::{env::temp_dir, time::Duration};
+/// use uuid::Uuid;
+///
+/// let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+/// let storage = Storage::create(&storage_path).expect("Storage created");
+/// let mut cached = CachedStorage::new(storage, Some(Duration::from_secs(5)));
+/// for i in 0..100u32 {
+///     cached.set(format!("sample_{i}"), &i).expect("Sample is cached");
+/// }
+/// assert_eq!(cached.get::<u32, &str>("sample_10").unwrap(), Some(10));
+/// // Write all samples to disk
+/// cached.flush().expect("Samples are written");
+/// assert_eq!(cached.storage().len(), 100);
+/// ```
+#[derive(Debug)]
+pub struct CachedStorage {
+    storage: Storage,
+    /// Serialized values (with type tags) waiting to be written. `None` means the key should be
+    /// removed.
+    pending: HashMap<String, Option<(u64, Vec<u8>)>>,
+    /// Pending changes are written as soon as this interval is elapsed since the last flush.
+    interval: Option<Duration>,
+    flushed: Instant,
+}
+
+impl CachedStorage {
+    /// Creates a write-back layer over the given storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The storage to write changes into.
+    /// * `interval` - If defined, pending changes are written with the first modifying call
+    ///   after the interval is elapsed since the last flush.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns an instance of `CachedStorage`.
+    pub fn new(storage: Storage, interval: Option<Duration>) -> Self {
+        Self {
+            storage,
+            pending: HashMap::new(),
+            interval,
+            flushed: Instant::now(),
+        }
+    }
+
+    /// Retrieves a value associated with the specified key. Returns None of case of deserializing
+    /// error (see `Storage::get`). Pending values are read through middlewares as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        let key = self.storage.normalized(key.to_key());
+        let Some(change) = self.pending.get(key.as_ref()) else {
+            return self.storage.get(key);
+        };
+        let Some((tag, payload)) = self
+            .storage
+            .layers
+            .get(key.as_ref(), &mut |_| Ok(change.clone()))?
+        else {
+            return Ok(None);
+        };
+        let payload = Wiped(payload);
+        self.storage
+            .sealed(|| Ok(deserialize_tagged::<V>(tag, &payload).ok()))
+    }
+
+    /// Retrieves a value associated with the specified key, or returns a default value if the
+    /// key does not exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<V, E>` - Returns the value or the default value, or an error.
+    pub fn get_or_default<V: for<'a> Deserialize<'a> + 'static + Default, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<V, E> {
+        Ok(self.get(key)?.unwrap_or(V::default()))
+    }
+
+    /// Checks if the specified key exists, taking pending changes into account.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the key exists, false otherwise.
+    pub fn has<K: StorageKey>(&self, key: K) -> bool {
+        let key = self.storage.normalized(key.to_key());
+        match self.pending.get(key.as_ref()) {
+            Some(change) => change.is_some(),
+            None => self.storage.has(key),
+        }
+    }
+
+    /// Sets a value for the specified key. The key and the value are checked (and passed through
+    /// middlewares) immediately; the value is written to disk with the next flush.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `value` - A reference to the value to be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn set<V: Serialize + 'static, K: StorageKey>(
+        &mut self,
+        key: K,
+        value: &V,
+    ) -> Result<(), E> {
+        let key = self.storage.normalized(key.to_key());
+        self.storage.check_key(key.as_ref())?;
+        self.storage.validators.check(key.as_ref(), value)?;
+        let buffer = self.storage.sealed(|| Ok(bincode::serialize(value)?))?;
+        let pending = &mut self.pending;
+        self.storage.layers.set(
+            key.as_ref(),
+            type_tag::<V>(),
+            &buffer,
+            &mut |key, tag, _| {
+                pending.insert(key.to_owned(), Some((tag, buffer.clone())));
+                Ok(())
+            },
+        )?;
+        self.tick()
+    }
+
+    /// Removes the value associated with the specified key. The record file is removed with
+    /// the next flush.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key was found, false otherwise, or an error.
+    pub fn remove<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        let key = self.storage.normalized(key.to_key());
+        let found = self.has(key.as_ref());
+        let pending = &mut self.pending;
+        let found = self.storage.layers.remove(key.as_ref(), &mut |key| {
+            if found {
+                pending.insert(key.to_owned(), None);
+            }
+            Ok(found)
+        })?;
+        self.tick()?;
+        Ok(found)
+    }
+
+    /// Returns a number of changes waiting to be written.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - number of pending changes
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Writes all pending changes to disk. If writing fails, changes stay pending and are
+    /// written again with the next flush.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn flush(&mut self) -> Result<(), E> {
+        self.flushed = Instant::now();
+        if !self.pending.is_empty() {
+            // Changes are cloned one by one, as they are applied
+            self.storage.apply(
+                self.pending
+                    .iter()
+                    .map(|(key, change)| (key.to_owned(), change.clone())),
+            )?;
+            self.pending.clear();
+        }
+        self.storage.flush()
+    }
+
+    /// Returns a reference to the underlying storage. Note, pending changes are not visible
+    /// through it until `flush()` is called.
+    ///
+    /// # Returns
+    ///
+    /// * `&Storage` - A reference to the underlying storage.
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    fn tick(&mut self) -> Result<(), E> {
+        match self.interval {
+            Some(interval) if self.flushed.elapsed() >= interval => self.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for CachedStorage {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!(
+                "Fail to write pending changes into storage {:?}: {err}",
+                self.storage.cwd()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        CachedStorage, EncryptionKey, KeyViolation, Sensitive, Storage, StorageOptions, E,
+    };
+    use serde::{Deserialize, Serialize};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Account {
+        login: String,
+        token: Sensitive<String>,
+    }
+
+    #[test]
+    fn write_back() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        storage.set("removed", &0u8)?;
+        let mut cached = CachedStorage::new(storage, None);
+        for i in 0..10u8 {
+            cached.set(i.to_string(), &i)?;
+        }
+        assert!(cached.remove("removed")?);
+        assert!(!cached.has("removed"));
+        assert_eq!(cached.get::<u8, &str>("5")?, Some(5));
+        assert_eq!(cached.pending(), 11);
+        assert_eq!(Storage::open(&storage_path)?.len(), 1);
+        cached.flush()?;
+        assert_eq!(cached.pending(), 0);
+        let storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.len(), 10);
+        assert!(!storage.has("removed"));
+        drop(storage);
+        cached.set("10", &10u8)?;
+        drop(cached);
+        let mut storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.get::<u8, &str>("10")?, Some(10));
+        // Changes, which cannot be written, stay pending
+        storage.options.limits.max_keys = Some(12);
+        let mut cached = CachedStorage::new(storage, None);
+        for i in 11..15u8 {
+            cached.set(i.to_string(), &i)?;
+        }
+        assert!(matches!(cached.flush(), Err(E::QuotaExceeded(_))));
+        assert_eq!(cached.pending(), 4);
+        cached.storage.options.limits.max_keys = None;
+        cached.flush()?;
+        assert_eq!(cached.pending(), 0);
+        drop(cached);
+        let mut storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.len(), 15);
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn checked_like_storage() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let storage = Storage::create_with_options(
+            &storage_path,
+            StorageOptions {
+                encryption: Some(EncryptionKey::new([1u8; 32])),
+                ..Default::default()
+            },
+        )?;
+        let mut cached = CachedStorage::new(storage, None);
+        // Reserved keys are rejected before they are cached
+        assert!(matches!(
+            cached.set("__index/name", &1u8),
+            Err(E::KeyRejected {
+                violation: KeyViolation::Reserved(_),
+                ..
+            })
+        ));
+        assert_eq!(cached.pending(), 0);
+        // Sensitive fields are encrypted with the key of the storage
+        let account = Account {
+            login: String::from("admin"),
+            token: Sensitive::new(String::from("top-secret-token")),
+        };
+        cached.set("account", &account)?;
+        assert_eq!(
+            cached.get::<Account, _>("account")?.as_ref(),
+            Some(&account)
+        );
+        cached.flush()?;
+        let content =
+            std::fs::read(cached.storage().fields["account"].path(cached.storage().cwd()))?;
+        assert!(!content
+            .windows(b"top-secret-token".len())
+            .any(|w| w == b"top-secret-token"));
+        assert_eq!(
+            cached.storage().get::<Account, _>("account")?,
+            Some(account)
+        );
+        drop(cached);
+        Storage::open(&storage_path)?.destroy()?;
+        Ok(())
+    }
+}
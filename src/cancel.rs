@@ -0,0 +1,20 @@
+//! Cancellation of long operations (`pack`, `unpack`, `verify`, `maintain`, `filter`). Each
+//! operation has a `*_cancellable` variant taking `&AtomicBool`; setting the flag from another
+//! thread stops the operation at the next record with `E::Cancelled`, leaving the storage in a
+//! consistent state.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::E;
+
+/// Flag, which is never set; used by variants of operations without cancellation
+pub(crate) static NEVER: AtomicBool = AtomicBool::new(false);
+
+/// Returns `E::Cancelled` if the flag is set.
+pub(crate) fn check(cancel: &AtomicBool) -> Result<(), E> {
+    if cancel.load(Ordering::Relaxed) {
+        Err(E::Cancelled)
+    } else {
+        Ok(())
+    }
+}
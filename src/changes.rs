@@ -0,0 +1,349 @@
+use log::warn;
+use std::{
+    fmt,
+    fs::{read_to_string, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{fs, Durability, Storage, StorageOptions, E};
+
+/// File of the storage with the log of changes. It doesn't have the extension of records, so it
+/// isn't taken as an orphaned record.
+pub const CHANGES_FILE_NAME: &str = "map.changes";
+
+/// Keeps the log of changes of records in the file `map.changes` of the storage folder, so other
+/// processes can follow updates of the storage by reading the file (or with
+/// `Storage::read_changes()`) instead of watching the file system.
+///
+/// The file is appended with a line per change: the version of the change (increases by one with
+/// each change), the operation (`set` or `remove`) and the key, separated by tabs. Backslashes,
+/// tabs and line breaks in keys are escaped as `\\`, `\t`, `\n` and `\r`. Values aren't logged.
+///
+/// The log isn't kept with a custom file system (see `StorageOptions::fs`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeFeed {
+    /// Maximum number of changes in the file. As soon as it's exceeded, the older half of changes
+    /// is dropped; readers, which are behind, get `E::CursorIsTooOld`.
+    pub max_entries: usize,
+}
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+        }
+    }
+}
+
+/// Operation of the change of the record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    /// The record has been written
+    Set,
+    /// The record has been removed
+    Remove,
+}
+
+impl fmt::Display for ChangeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Set => write!(f, "set"),
+            Self::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+/// Change of the record read from the log of changes (see `ChangeFeed`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyChange {
+    /// Version of the change; use it as the cursor of the next `Storage::changes_since()`
+    pub version: u64,
+    /// Operation
+    pub op: ChangeOp,
+    /// Key of the record
+    pub key: String,
+}
+
+impl KeyChange {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, '\t');
+        let version = parts.next()?.parse().ok()?;
+        let op = match parts.next()? {
+            "set" => ChangeOp::Set,
+            "remove" => ChangeOp::Remove,
+            _ => return None,
+        };
+        Some(Self {
+            version,
+            op,
+            key: unescape(parts.next()?)?,
+        })
+    }
+}
+
+fn escape(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for c in key.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> Option<String> {
+    let mut key = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            key.push(c);
+            continue;
+        }
+        key.push(match chars.next()? {
+            '\\' => '\\',
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(key)
+}
+
+/// Reads complete lines of the log; the last line can be incomplete, if it's being written.
+fn read_log(path: &Path) -> Result<Vec<KeyChange>, E> {
+    let content = match read_to_string(fs::long_path(path)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(E::io(err, path)),
+    };
+    let complete = content.rfind('\n').map(|pos| &content[..pos]).unwrap_or("");
+    complete
+        .lines()
+        .map(|line| {
+            KeyChange::parse(line).ok_or_else(|| {
+                E::io(
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid entry of changes"),
+                    path,
+                )
+            })
+        })
+        .collect()
+}
+
+/// Log of changes of the storage
+#[derive(Debug)]
+pub(crate) struct Changes {
+    path: PathBuf,
+    /// Version of the last change
+    version: u64,
+    /// Number of changes in the file
+    count: usize,
+    max_entries: usize,
+    durability: Durability,
+}
+
+impl Changes {
+    /// Reads the state of the log of changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    /// * `options` - Options of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Self>, E>` - Returns the log, None if it isn't kept, or an error.
+    pub(crate) fn load(cwd: &Path, options: &StorageOptions) -> Result<Option<Self>, E> {
+        let Some(feed) = options.changes.as_ref().filter(|_| options.fs.is_none()) else {
+            return Ok(None);
+        };
+        let path = cwd.join(CHANGES_FILE_NAME);
+        let changes = read_log(&path)?;
+        Ok(Some(Self {
+            path,
+            version: changes
+                .last()
+                .map(|change| change.version)
+                .unwrap_or_default(),
+            count: changes.len(),
+            max_entries: feed.max_entries.max(1),
+            durability: options.durability,
+        }))
+    }
+
+    fn append(&mut self, key: &str, op: ChangeOp) -> Result<(), E> {
+        let line = format!("{}\t{op}\t{}\n", self.version + 1, escape(key));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(fs::long_path(&self.path))
+            .map_err(|e| E::io(e, &self.path))?;
+        file.write_all(line.as_bytes())?;
+        if self.durability >= Durability::Fsync {
+            file.sync_data()?;
+        }
+        self.version += 1;
+        self.count += 1;
+        if self.count > self.max_entries {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Drops the older half of changes.
+    fn compact(&mut self) -> Result<(), E> {
+        let changes = read_log(&self.path)?;
+        let kept = &changes[changes.len().saturating_sub(self.max_entries / 2)..];
+        let content = kept
+            .iter()
+            .map(|change| {
+                format!(
+                    "{}\t{}\t{}\n",
+                    change.version,
+                    change.op,
+                    escape(&change.key)
+                )
+            })
+            .collect::<String>();
+        fs::replace(&self.path, content.as_bytes(), self.durability)
+            .map_err(|e| E::io(e, &self.path))?;
+        self.count = kept.len();
+        Ok(())
+    }
+}
+
+impl Storage {
+    /// Appends the change to the log of changes, if `StorageOptions::changes` is used. The
+    /// change is already done, so failures are logged only.
+    pub(crate) fn log_change(&mut self, key: &str, op: ChangeOp) {
+        if let Some(changes) = self.changes.as_mut() {
+            if let Err(err) = changes.append(key, op) {
+                warn!("Change of \"{key}\" isn't logged: {err}");
+            }
+        }
+    }
+
+    /// Returns the version of the last logged change (see `ChangeFeed`), which can be used as
+    /// the cursor to follow changes from now on.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The version of the last change; 0 if there are no changes or the log isn't kept.
+    pub fn changes_cursor(&self) -> u64 {
+        self.changes
+            .as_ref()
+            .map(|changes| changes.version)
+            .unwrap_or_default()
+    }
+
+    /// Returns changes of records done after the cursor (see `ChangeFeed`).
+    ///
+    /// # Arguments
+    ///
+    /// * `cursor` - Version of the last seen change; 0 to read all kept changes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<KeyChange>, E>` - Returns changes in the order they have been done, or
+    ///   `E::CursorIsTooOld` if some of changes after the cursor aren't kept anymore.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{ChangeFeed, ChangeOp, Storage, StorageOptions};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let options = StorageOptions {
+    ///     changes: Some(ChangeFeed::default()),
+    ///     ..Default::default()
+    /// };
+    /// let mut storage =
+    ///     Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)
+    ///         .unwrap();
+    /// storage.set("a", &1u8).unwrap();
+    /// let cursor = storage.changes_cursor();
+    /// storage.set("b", &2u8).unwrap();
+    /// storage.remove("a").unwrap();
+    /// let changes = storage.changes_since(cursor).unwrap();
+    /// assert_eq!(changes.len(), 2);
+    /// assert_eq!((changes[1].op, changes[1].key.as_str()), (ChangeOp::Remove, "a"));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn changes_since(&self, cursor: u64) -> Result<Vec<KeyChange>, E> {
+        Storage::read_changes(&self.cwd, cursor)
+    }
+
+    /// Reads changes of records done after the cursor from the log of changes of the storage
+    /// folder without opening the storage, e.g. by another process (see `changes_since()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    /// * `cursor` - Version of the last seen change; 0 to read all kept changes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<KeyChange>, E>` - Returns changes in the order they have been done, or
+    ///   `E::CursorIsTooOld` if some of changes after the cursor aren't kept anymore.
+    pub fn read_changes<P: AsRef<Path>>(cwd: P, cursor: u64) -> Result<Vec<KeyChange>, E> {
+        let changes = read_log(&cwd.as_ref().join(CHANGES_FILE_NAME))?;
+        if let Some(oldest) = changes.first().map(|change| change.version) {
+            if cursor > 0 && cursor + 1 < oldest {
+                return Err(E::CursorIsTooOld { cursor, oldest });
+            }
+        }
+        Ok(changes
+            .into_iter()
+            .filter(|change| change.version > cursor)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ChangeFeed, ChangeOp, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn changes() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            changes: Some(ChangeFeed { max_entries: 4 }),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        storage.set("a", &1u8)?;
+        storage.set("line\tbreak\n", &2u8)?;
+        let changes = Storage::read_changes(&storage_path, 0)?;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].key, "line\tbreak\n");
+        assert_eq!(changes[1].version, 2);
+        // Versions continue after reopening
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert_eq!(storage.changes_cursor(), 2);
+        storage.remove("a")?;
+        let changes = storage.changes_since(2)?;
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].version, changes[0].op), (3, ChangeOp::Remove));
+        // The older half of changes is dropped, once the limit is exceeded
+        storage.set("b", &3u8)?;
+        storage.set("c", &4u8)?;
+        assert!(matches!(
+            storage.changes_since(1),
+            Err(E::CursorIsTooOld { cursor: 1, .. })
+        ));
+        assert_eq!(storage.changes_since(3)?.len(), 2);
+        assert!(storage.changes_since(storage.changes_cursor())?.is_empty());
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+};
+
+use crate::{sensitive::Wiped, EncryptionKey, Field, Header, Storage, StorageKey, E};
+
+/// Transformation of serialized values in record files. The codec, which a record has been
+/// written with, is kept in the header of the record file (and in the map), so records are read
+/// correctly even if `StorageOptions::codecs` has changed since then or the map has been rebuilt
+/// (see `Storage::recover()`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Codec {
+    /// Values are compressed with deflate
+    pub compress: bool,
+    /// Values are encrypted with ChaCha20-Poly1305 using `StorageOptions::encryption`
+    pub encrypt: bool,
+}
+
+impl Codec {
+    /// Values are written as they are
+    pub const PLAIN: Codec = Codec {
+        compress: false,
+        encrypt: false,
+    };
+
+    /// Returns true if values are written as they are.
+    pub fn is_plain(&self) -> bool {
+        *self == Self::PLAIN
+    }
+
+    /// Transforms the serialized value as it should be written into the record file.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The serialized value.
+    /// * `key` - The encryption key of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Cow<[u8]>, E>` - Returns the transformed value, or `E::Encryption` if the
+    ///   value should be encrypted, but there is no key.
+    pub(crate) fn encode<'a>(
+        &self,
+        payload: &'a [u8],
+        key: Option<&EncryptionKey>,
+    ) -> Result<Cow<'a, [u8]>, E> {
+        let compressed = if self.compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(payload)?;
+            Some(Wiped(encoder.finish()?))
+        } else {
+            None
+        };
+        let buffer = compressed.as_deref().unwrap_or(payload);
+        if self.encrypt {
+            return Ok(Cow::Owned(cipher_key(key)?.encrypt(buffer)?));
+        }
+        Ok(match compressed {
+            Some(mut compressed) => Cow::Owned(std::mem::take(&mut compressed.0)),
+            None => Cow::Borrowed(payload),
+        })
+    }
+
+    /// Restores the serialized value written with `Codec::encode()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The payload of the record file.
+    /// * `key` - The encryption key of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the serialized value, or an error.
+    pub(crate) fn decode(&self, payload: &[u8], key: Option<&EncryptionKey>) -> Result<Vec<u8>, E> {
+        let decrypted = if self.encrypt {
+            Some(Wiped(cipher_key(key)?.decrypt(payload)?))
+        } else {
+            None
+        };
+        let buffer = decrypted.as_deref().unwrap_or(payload);
+        if !self.compress {
+            return Ok(buffer.to_vec());
+        }
+        let mut decompressed = Vec::new();
+        DeflateDecoder::new(buffer).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+}
+
+fn cipher_key(key: Option<&EncryptionKey>) -> Result<&EncryptionKey, E> {
+    key.ok_or_else(|| {
+        E::Encryption(String::from(
+            "no encryption key; set StorageOptions::encryption to read or write encrypted records",
+        ))
+    })
+}
+
+/// Codecs of records (see `StorageOptions::codecs`): the storage-wide codec and its overrides
+/// for keys and prefixes of keys.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{Codec, Codecs, EncryptionKey, Storage, StorageOptions};
+/// use std::env::temp_dir;
+/// use uuid::Uuid;
+///
+/// let options = StorageOptions {
+///     encryption: Some(EncryptionKey::new([7u8; 32])),
+///     codecs: Codecs::new(Codec {
+///         compress: true,
+///         encrypt: false,
+///     })
+///     // Blobs are compressed already
+///     .with("blob:*", Codec::PLAIN)
+///     .with(
+///         "secret:*",
+///         Codec {
+///             compress: true,
+///             encrypt: true,
+///         },
+///     ),
+///     ..Default::default()
+/// };
+/// let mut storage =
+///     Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)
+///         .unwrap();
+/// storage.set("secret:token", &String::from("top-secret")).unwrap();
+/// assert!(storage.codec_of("secret:token").unwrap().encrypt);
+/// assert_eq!(
+///     storage.get::<String, _>("secret:token").unwrap(),
+///     Some(String::from("top-secret"))
+/// );
+/// storage.destroy().unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Codecs {
+    /// Codec of records, which don't match any override
+    pub default: Codec,
+    /// Patterns of keys with their codecs. A pattern ending with `*` matches keys with the
+    /// prefix before `*`; other patterns match the key itself. The exact key takes precedence
+    /// over prefixes, and the longest prefix takes precedence over shorter ones.
+    pub overrides: Vec<(String, Codec)>,
+}
+
+impl Codecs {
+    /// Creates codecs with the storage-wide codec and no overrides.
+    ///
+    /// # Arguments
+    ///
+    /// * `default` - Codec of all records.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns an instance of `Codecs`.
+    pub fn new(default: Codec) -> Self {
+        Self {
+            default,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Adds the override of the codec for keys matching the pattern (see `Codecs::overrides`).
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The key (normalized), or the prefix of keys followed by `*`.
+    /// * `codec` - Codec of matching records.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns codecs with the override.
+    pub fn with<P: Into<String>>(mut self, pattern: P, codec: Codec) -> Self {
+        self.overrides.push((pattern.into(), codec));
+        self
+    }
+
+    /// Returns the codec of the key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key (normalized).
+    ///
+    /// # Returns
+    ///
+    /// * `Codec` - The codec of the record of the key.
+    pub fn of(&self, key: &str) -> Codec {
+        let mut found: Option<(usize, Codec)> = None;
+        for (pattern, codec) in self.overrides.iter() {
+            let rank = match pattern.strip_suffix('*') {
+                Some(prefix) if key.starts_with(prefix) => prefix.len(),
+                None if pattern == key => usize::MAX,
+                _ => continue,
+            };
+            if found.is_none_or(|(found, _)| rank > found) {
+                found = Some((rank, *codec));
+            }
+        }
+        found.map(|(_, codec)| codec).unwrap_or(self.default)
+    }
+}
+
+impl Storage {
+    /// Returns the codec, which the record of the key has been written with (see `Codecs`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Codec>` - The codec of the record, or None if the key doesn't exist.
+    pub fn codec_of<K: StorageKey>(&self, key: K) -> Option<Codec> {
+        let key = self.normalized(key.to_key());
+        self.fields
+            .get(key.as_ref())
+            .map(|field| field.meta().codec)
+    }
+
+    /// Restores the serialized value in the content of the record file written with a codec or
+    /// transformers (see `Storage::transformer()`), so the content can be read as a plain
+    /// record. The codec and transformers are taken from the header of the record, so records
+    /// are decoded even if the map has been lost. Plain records are returned as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - Content of the field's file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the content with the serialized value, or an error.
+    pub(crate) fn decoded(&self, content: Vec<u8>) -> Result<Vec<u8>, E> {
+        if Header::decode(&content)?
+            .0
+            .is_none_or(|header| header.is_plain())
+        {
+            return Ok(content);
+        }
+        let (Some(header), payload) = Field::payload(&content)? else {
+            return Ok(content);
+        };
+        let payload = header
+            .codec
+            .decode(payload, self.options.encryption.as_ref())?;
+        let payload = Wiped(self.transformers.decode(
+            &header.key,
+            &header.transformers,
+            payload,
+        )?);
+        Field::encode(&header.key, header.tag, &payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        map, Bundle, Codec, Codecs, EncryptionKey, History, KeyResolver, Storage, StorageOptions, E,
+    };
+    use std::{
+        env::temp_dir,
+        thread,
+        time::{Duration, SystemTime},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn codecs() -> Result<(), E> {
+        let compressed = Codec {
+            compress: true,
+            encrypt: false,
+        };
+        let encrypted = Codec {
+            compress: false,
+            encrypt: true,
+        };
+        let codecs = Codecs::new(compressed)
+            .with("blob:*", Codec::PLAIN)
+            .with("blob:secret:*", encrypted)
+            .with("blob:index", compressed);
+        assert_eq!(codecs.of("name"), compressed);
+        assert_eq!(codecs.of("blob:image"), Codec::PLAIN);
+        assert_eq!(codecs.of("blob:secret:key"), encrypted);
+        assert_eq!(codecs.of("blob:index"), compressed);
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = |codecs: Codecs, key: Option<[u8; 32]>| StorageOptions {
+            codecs,
+            encryption: key.map(EncryptionKey::new),
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(&storage_path, options(codecs, Some([1u8; 32])))?;
+        let text = "repeated text ".repeat(100);
+        storage.set("name", &text)?;
+        storage.set("blob:image", &text)?;
+        storage.set("blob:secret:key", &String::from("top-secret-key"))?;
+        let file_size = |storage: &Storage, key: &str| storage.fields[key].meta().size;
+        assert!(file_size(&storage, "name") < file_size(&storage, "blob:image"));
+        let content = std::fs::read(storage.fields["blob:secret:key"].path(storage.cwd()))?;
+        assert!(!content.windows(10).any(|w| w == b"top-secret"));
+        // Codecs are kept with records, so records are read with other options
+        drop(storage);
+        let mut storage =
+            Storage::open_with_options(&storage_path, options(Codecs::default(), Some([1u8; 32])))?;
+        assert_eq!(storage.codec_of("name"), Some(compressed));
+        assert_eq!(storage.get::<String, _>("name")?, Some(text.clone()));
+        assert_eq!(storage.get::<String, _>("blob:image")?, Some(text.clone()));
+        assert_eq!(
+            storage.get::<String, _>("blob:secret:key")?,
+            Some(String::from("top-secret-key"))
+        );
+        assert_eq!(storage.snapshot()?.records.len(), 3);
+        // Rewritten records take the current codec
+        storage.set("name", &text)?;
+        assert_eq!(storage.codec_of("name"), Some(Codec::PLAIN));
+        drop(storage);
+        let storage = Storage::open_with_options(&storage_path, options(Codecs::default(), None))?;
+        assert!(matches!(
+            storage.get::<String, _>("blob:secret:key"),
+            Err(E::Record { source, .. }) if matches!(*source, E::Encryption(_))
+        ));
+        drop(storage);
+        // Codecs are kept in headers of records, so they survive the loss of the map
+        std::fs::remove_file(storage_path.join(map::MAP_FILE_NAME))?;
+        drop(Storage::recover(&storage_path, KeyResolver::Embedded)?);
+        let mut storage =
+            Storage::open_with_options(&storage_path, options(Codecs::default(), Some([1u8; 32])))?;
+        assert_eq!(storage.codec_of("blob:secret:key"), Some(encrypted));
+        assert_eq!(
+            storage.get::<String, _>("blob:secret:key")?,
+            Some(String::from("top-secret-key"))
+        );
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn encoded_copies() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            codecs: Codecs::new(Codec {
+                compress: false,
+                encrypt: true,
+            }),
+            encryption: Some(EncryptionKey::new([3u8; 32])),
+            history: Some(History::default()),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        storage.set("secret", &String::from("TOPSECRET:1"))?;
+        thread::sleep(Duration::from_millis(10));
+        let moment = SystemTime::now();
+        thread::sleep(Duration::from_millis(10));
+        storage.set("secret", &String::from("TOPSECRET:2"))?;
+        // Kept versions are decoded, when they are restored
+        assert_eq!(storage.restore_to(moment)?, 1);
+        assert_eq!(
+            storage.get::<String, _>("secret")?,
+            Some(String::from("TOPSECRET:1"))
+        );
+        let bundle = temp_dir().join(format!("{}.bundle", Uuid::new_v4()));
+        storage.pack(&bundle)?;
+        assert_eq!(storage.archive_older_than(Duration::ZERO)?, vec!["secret"]);
+        assert_eq!(
+            storage.get::<String, _>("secret")?,
+            Some(String::from("TOPSECRET:1"))
+        );
+        // Copies of records (kept versions, archives, bundles) stay encrypted
+        let mut files = vec![bundle.clone()];
+        let mut dirs = vec![storage_path.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        assert!(files.len() > 3);
+        for path in files.iter() {
+            let content = std::fs::read(path)?;
+            assert!(
+                !content.windows(9).any(|w| w == b"TOPSECRET"),
+                "{path:?} isn't encrypted"
+            );
+        }
+        storage.destroy()?;
+        let cwd = Storage::unpack(&bundle)?.cwd().clone();
+        let mut unpacked = Storage::open_with_options(&cwd, options)?;
+        assert_eq!(
+            unpacked.get::<String, _>("secret")?,
+            Some(String::from("TOPSECRET:1"))
+        );
+        unpacked.destroy()?;
+        std::fs::remove_file(&bundle)?;
+        Ok(())
+    }
+}
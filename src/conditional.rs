@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{fs, Field, Header, Storage, StorageKey, E};
+
+/// Version of a record returned by `Storage::get_if_modified()`: the record file, its size and
+/// the checksum of the value. It can be persisted between runs of the application.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Token {
+    file: String,
+    size: u64,
+    checksum: u32,
+}
+
+impl Storage {
+    /// Returns the token of the current version of the record and the content of the record
+    /// file, if it had to be read to get the token.
+    fn token(&self, field: &Field) -> Result<(Token, Option<Vec<u8>>), E> {
+        let token = |checksum| Token {
+            file: field.file_name().to_owned(),
+            size: field.meta().size,
+            checksum,
+        };
+        if self.options.fs.is_none() {
+            // Only the header is read
+            let header = fs::open_with(field.path(&self.cwd), fs::Access::new(&self.options))
+                .and_then(|mut file| Header::read(&mut file))?;
+            if let Some(header) = header {
+                return Ok((token(header.checksum), None));
+            }
+        }
+        let content = self.extract(field)?;
+        let checksum = match Header::decode(&content)? {
+            (Some(header), _) => header.checksum,
+            // Legacy records don't have checksums
+            (None, content) => crc32fast::hash(content),
+        };
+        Ok((token(checksum), Some(content)))
+    }
+
+    /// Reads the value of the key, if the record has been changed since the version identified
+    /// by the token. Only the header of the record file is read to check it, so polling of
+    /// large unchanged records is cheap.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `token` - The token returned by the previous call; None to read the value anyway.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<(V, Token)>, E>` - Returns the value with the token of its version, None
+    ///   if the record hasn't been changed or doesn't exist, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("report", &vec![1u64; 1024]).unwrap();
+    /// let (report, token) = storage
+    ///     .get_if_modified::<Vec<u64>, _>("report", None)
+    ///     .unwrap()
+    ///     .unwrap();
+    /// assert_eq!(report.len(), 1024);
+    /// // Not changed
+    /// assert!(storage
+    ///     .get_if_modified::<Vec<u64>, _>("report", Some(&token))
+    ///     .unwrap()
+    ///     .is_none());
+    /// storage.set("report", &vec![2u64; 1024]).unwrap();
+    /// assert!(storage
+    ///     .get_if_modified::<Vec<u64>, _>("report", Some(&token))
+    ///     .unwrap()
+    ///     .is_some());
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn get_if_modified<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+        token: Option<&Token>,
+    ) -> Result<Option<(V, Token)>, E> {
+        let key = self.normalized(key.to_key());
+        let Some(field) = self.fields.get(key.as_ref()) else {
+            return Ok(None);
+        };
+        if self.expired(key.as_ref()) {
+            return Ok(None);
+        }
+        let (current, content) = match self.token(field) {
+            Ok(token) => token,
+            Err(err) => return self.corrupted(key.as_ref(), field, err),
+        };
+        if token == Some(&current) {
+            return Ok(None);
+        }
+        self.accessed(field);
+        let content = match content {
+            Some(content) => Ok(content),
+            None => self.extract(field),
+        };
+        match self.sealed(|| content.and_then(|content| Field::value::<V>(&content))) {
+            Ok(value) => Ok(value.map(|value| (value, current))),
+            Err(err) => self.corrupted(key.as_ref(), field, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Layout, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn get_if_modified() -> Result<(), E> {
+        for layout in [Layout::PerKey, Layout::ContentAddressed] {
+            let mut storage = Storage::create_with_options(
+                temp_dir().join(Uuid::new_v4().to_string()),
+                StorageOptions {
+                    layout,
+                    ..Default::default()
+                },
+            )?;
+            assert!(storage.get_if_modified::<u8, _>("a", None)?.is_none());
+            storage.set("a", &String::from("first"))?;
+            let (value, first) = storage.get_if_modified::<String, _>("a", None)?.unwrap();
+            assert_eq!(value, "first");
+            assert!(storage
+                .get_if_modified::<String, _>("a", Some(&first))?
+                .is_none());
+            storage.set("a", &String::from("second"))?;
+            let (value, second) = storage
+                .get_if_modified::<String, _>("a", Some(&first))?
+                .unwrap();
+            assert_eq!(value, "second");
+            assert_ne!(first, second);
+            // The same value gets the same token
+            storage.set("a", &String::from("first"))?;
+            assert!(storage
+                .get_if_modified::<String, _>("a", Some(&first))?
+                .is_none());
+            storage.destroy()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+//! Checks of consistency of a storage, which can be used in tests and CI of applications: whether
+//! values of a type survive `set`/`get`, reopening of the storage and `pack`/`unpack`
+//! (`check_roundtrip()`), and whether the storage, its map and its record files agree with each
+//! other (`check_invariants()`).
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashSet,
+    env::temp_dir,
+    fmt::{self, Debug},
+    fs::{remove_dir_all, remove_file},
+    path::PathBuf,
+};
+use uuid::Uuid;
+
+use crate::{recover, Bundle, FlushMode, Header, Issue, Layout, Map, Storage, StorageOptions, E};
+
+/// Step of `check_roundtrip()`, after which a value differs from the original one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// The value has been read back from the same storage.
+    Get,
+    /// The value has been read from the reopened storage.
+    Reopen,
+    /// The value has been read from the storage packed into a bundle and unpacked.
+    Bundle,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Get => "get",
+                Self::Reopen => "reopen",
+                Self::Bundle => "pack/unpack",
+            }
+        )
+    }
+}
+
+/// Violation of an invariant of the storage found by `check_invariants()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The record file is missing, empty or broken (see `Storage::verify()`).
+    Record(Issue),
+    /// The header of the record file keeps another key.
+    KeyMismatch { key: String, found: String },
+    /// The type tag in the header of the record file differs from the tag in the map.
+    TagMismatch {
+        key: String,
+        expected: u64,
+        found: u64,
+    },
+    /// The size of the record file differs from the size in the map.
+    SizeMismatch {
+        key: String,
+        expected: u64,
+        found: u64,
+    },
+    /// The key of the storage isn't written into the map file.
+    NotPersisted(String),
+    /// The map file has a key, which the storage doesn't have.
+    Unknown(String),
+    /// The record file isn't referenced by any key.
+    Orphan(PathBuf),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Record(issue) => write!(f, "{}: {}", issue.key, issue.problem),
+            Self::KeyMismatch { key, found } => {
+                write!(f, "{key}: record file keeps key \"{found}\"")
+            }
+            Self::TagMismatch {
+                key,
+                expected,
+                found,
+            } => write!(f, "{key}: type tag {found} instead of {expected}"),
+            Self::SizeMismatch {
+                key,
+                expected,
+                found,
+            } => write!(f, "{key}: size {found} instead of {expected}"),
+            Self::NotPersisted(key) => write!(f, "{key}: isn't in the map file"),
+            Self::Unknown(key) => write!(f, "{key}: is in the map file only"),
+            Self::Orphan(path) => write!(f, "{path:?}: isn't referenced"),
+        }
+    }
+}
+
+/// Writes values into the storage and checks that each of them is read back equal to the
+/// original one: from the same storage, from the reopened storage and from the storage packed
+/// into a bundle and unpacked (the bundle and the unpacked copy are temporary). The bundle stage
+/// is skipped for storages with a custom `FileSystem`.
+///
+/// Values stay in the storage, so usually it's a storage created for the test.
+///
+/// # Arguments
+///
+/// * `storage` - The storage to write values into.
+/// * `records` - Keys and values.
+///
+/// # Returns
+///
+/// * `Result<(), E>` - Returns Ok(()) if all values survived, `E::Inconsistent` with the key and
+///   the stage of the first differing value, or an error.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{consistency, Storage};
+/// use std::env::temp_dir;
+/// use uuid::Uuid;
+///
+/// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// consistency::check_roundtrip(
+///     &mut storage,
+///     [(String::from("a"), vec![1u8, 2, 3]), (String::from("b"), vec![])],
+/// )
+/// .unwrap();
+/// storage.destroy().unwrap();
+/// ```
+pub fn check_roundtrip<V, I>(storage: &mut Storage, records: I) -> Result<(), E>
+where
+    V: Serialize + DeserializeOwned + PartialEq + Debug + 'static,
+    I: IntoIterator<Item = (String, V)>,
+{
+    let records: Vec<(String, V)> = records.into_iter().collect();
+    for (key, value) in records.iter() {
+        storage.set(key, value)?;
+    }
+    compare(storage, &records, Stage::Get)?;
+    storage.flush()?;
+    let options = StorageOptions {
+        flush: FlushMode::Sync,
+        group_commit: None,
+        eviction: None,
+        access: None,
+        maintenance: None,
+        ..storage.options.clone()
+    };
+    let reopened = Storage::open_with_options(storage.cwd(), options)?;
+    compare(&reopened, &records, Stage::Reopen)?;
+    drop(reopened);
+    if storage.options.fs.is_some() {
+        return Ok(());
+    }
+    let bundle = temp_dir().join(Uuid::new_v4().to_string());
+    storage.pack(&bundle)?;
+    let unpacked = Storage::unpack(&bundle);
+    remove_file(&bundle)?;
+    let unpacked = unpacked?;
+    let compared = compare(&unpacked, &records, Stage::Bundle);
+    remove_dir_all(unpacked.cwd())?;
+    compared
+}
+
+fn compare<V>(storage: &Storage, records: &[(String, V)], stage: Stage) -> Result<(), E>
+where
+    V: Serialize + DeserializeOwned + PartialEq + Debug + 'static,
+{
+    // The last value of a key wins
+    let mut checked = HashSet::new();
+    for (key, value) in records.iter().rev() {
+        if !checked.insert(key) {
+            continue;
+        }
+        if storage.get_sensitive::<V, _>(key)?.as_ref() != Some(value) {
+            return Err(E::Inconsistent {
+                key: key.to_owned(),
+                stage,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks invariants of the storage: every record file exists and has a valid checksum; headers
+/// of record files keep their keys and type tags; sizes of files match the map; the map file has
+/// the same keys as the storage; there are no record files, which aren't referenced by keys.
+/// Deferred changes of the map are flushed before the check.
+///
+/// Orphaned files aren't looked for in storages with a custom `FileSystem`.
+///
+/// # Arguments
+///
+/// * `storage` - The storage to check.
+///
+/// # Returns
+///
+/// * `Result<Vec<Violation>, E>` - Returns found violations (empty if the storage is
+///   consistent), or an error.
+pub fn check_invariants(storage: &mut Storage) -> Result<Vec<Violation>, E> {
+    storage.flush()?;
+    let mut violations: Vec<Violation> = storage
+        .verify()?
+        .issues
+        .into_iter()
+        .map(Violation::Record)
+        .collect();
+    let broken: HashSet<String> = violations
+        .iter()
+        .filter_map(|v| match v {
+            Violation::Record(issue) => Some(issue.key.clone()),
+            _ => None,
+        })
+        .collect();
+    let shared = storage.options.layout == Layout::ContentAddressed;
+    for (key, field) in storage.fields.iter().filter(|(k, _)| !broken.contains(*k)) {
+        let content = storage.extract_raw(field)?;
+        let found = content.len() as u64;
+        if field.meta().size != found {
+            violations.push(Violation::SizeMismatch {
+                key: key.to_owned(),
+                expected: field.meta().size,
+                found,
+            });
+        }
+        // Legacy records don't have headers
+        let (Some(header), _) = Header::decode(&content)? else {
+            continue;
+        };
+        if !shared && &header.key != key {
+            violations.push(Violation::KeyMismatch {
+                key: key.to_owned(),
+                found: header.key,
+            });
+        }
+        if field.meta().tag != 0 && field.meta().tag != header.tag {
+            violations.push(Violation::TagMismatch {
+                key: key.to_owned(),
+                expected: field.meta().tag,
+                found: header.tag,
+            });
+        }
+    }
+    let options = StorageOptions {
+        fs: storage.options.fs.clone(),
+        ..Default::default()
+    };
+    let (persisted, missing) = Map::new(storage.cwd(), &options).read()?;
+    let persisted: HashSet<&String> = persisted
+        .keys()
+        .chain(missing.iter().map(|(key, _)| key))
+        .collect();
+    let mut keys: Vec<&String> = storage.fields.keys().collect();
+    keys.sort();
+    violations.extend(
+        keys.iter()
+            .filter(|key| !persisted.contains(*key))
+            .map(|key| Violation::NotPersisted(key.to_string())),
+    );
+    let mut unknown: Vec<&&String> = persisted
+        .iter()
+        .filter(|key| !storage.fields.contains_key(key.as_str()))
+        .collect();
+    unknown.sort();
+    violations.extend(
+        unknown
+            .into_iter()
+            .map(|key| Violation::Unknown(key.to_string())),
+    );
+    if storage.options.fs.is_none() {
+        let known: HashSet<String> = storage
+            .fields
+            .values()
+            .map(|field| field.file_name().to_owned())
+            .collect();
+        violations.extend(
+            recover::orphans(storage.cwd(), &known)?
+                .into_iter()
+                .map(Violation::Orphan),
+        );
+    }
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        consistency::{check_invariants, check_roundtrip, Stage, Violation},
+        Storage, E,
+    };
+    use serde::{Deserialize, Serialize};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Good {
+        a: String,
+        b: Vec<u64>,
+    }
+
+    /// Skipped field doesn't survive serialization
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Lossy {
+        a: u8,
+        #[serde(skip)]
+        b: u8,
+    }
+
+    #[test]
+    fn consistency() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        check_roundtrip(
+            &mut storage,
+            (0..20u64).map(|i| {
+                (
+                    format!("good_{i}"),
+                    Good {
+                        a: i.to_string(),
+                        b: (0..i).collect(),
+                    },
+                )
+            }),
+        )?;
+        assert!(matches!(
+            check_roundtrip(
+                &mut storage,
+                [(String::from("lossy"), Lossy { a: 1, b: 2 })]
+            ),
+            Err(E::Inconsistent {
+                stage: Stage::Get,
+                ..
+            })
+        ));
+        assert!(check_invariants(&mut storage)?.is_empty());
+        // Damage the storage
+        let path = storage.fields.get("good_1").unwrap().path(storage.cwd());
+        std::fs::write(&path, [1, 2, 3])?;
+        let orphan = storage.cwd().join("orphan.bstorage");
+        std::fs::write(&orphan, [1, 2, 3])?;
+        let violations = check_invariants(&mut storage)?;
+        assert_eq!(violations.len(), 2);
+        assert!(violations.iter().any(
+            |v| matches!(v, Violation::SizeMismatch { key, found: 3, .. } if key == "good_1")
+        ));
+        assert!(violations.contains(&Violation::Orphan(orphan)));
+        storage.destroy()?;
+        Ok(())
+    }
+}
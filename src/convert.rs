@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+use crate::{cancel, type_tag, Storage, E};
+
+impl Storage {
+    /// Creates (or opens) the storage and writes all values of the map with a single write of the
+    /// storage's map. Existing records with the same keys are overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `values` - Values by keys.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with written values, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::{collections::HashMap, env::temp_dir};
+    /// use uuid::Uuid;
+    ///
+    /// let values = HashMap::from([
+    ///     (String::from("a"), 1u32),
+    ///     (String::from("b"), 2u32),
+    /// ]);
+    /// let mut storage =
+    ///     Storage::from_map(temp_dir().join(Uuid::new_v4().to_string()), values.clone()).unwrap();
+    /// assert_eq!(storage.to_map::<u32>().unwrap(), values);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn from_map<P: AsRef<Path>, V: Serialize + 'static>(
+        cwd: P,
+        values: HashMap<String, V>,
+    ) -> Result<Self, E> {
+        let mut storage = Storage::create(cwd)?;
+        let tag = type_tag::<V>();
+        let changes = values
+            .into_iter()
+            .map(|(key, value)| Ok((key, Some((tag, bincode::serialize(&value)?)))))
+            .collect::<Result<Vec<_>, E>>()?;
+        storage.apply(changes)?;
+        Ok(storage)
+    }
+
+    /// Collects all records of the type `V`. Records of other types are skipped; broken records
+    /// are handled according to `CorruptionPolicy` in the same way as with `get()`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<HashMap<String, V>, E>` - Returns values by keys, or an error.
+    pub fn to_map<V: for<'a> Deserialize<'a> + 'static>(&self) -> Result<HashMap<String, V>, E> {
+        let mut values = HashMap::new();
+        self.read_all::<V, _>(&cancel::NEVER, |key, value| {
+            values.insert(key.to_owned(), value);
+        })?;
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use std::{collections::HashMap, env::temp_dir};
+    use uuid::Uuid;
+
+    #[test]
+    fn convert() -> Result<(), E> {
+        let path = temp_dir().join(Uuid::new_v4().to_string());
+        let values = (0..20u32)
+            .map(|i| (format!("key_{i}"), i.to_string()))
+            .collect::<HashMap<String, String>>();
+        let mut storage = Storage::from_map(&path, values.clone())?;
+        storage.set("number", &7u64)?;
+        assert_eq!(storage.len(), 21);
+        assert_eq!(storage.to_map::<String>()?, values);
+        assert_eq!(
+            storage.to_map::<u64>()?,
+            HashMap::from([(String::from("number"), 7u64)])
+        );
+        drop(storage);
+        let mut storage = Storage::open(&path)?;
+        assert_eq!(storage.to_map::<String>()?, values);
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use log::debug;
+use std::{
+    collections::HashSet,
+    fs::{copy, create_dir_all, hard_link, read_dir},
+    path::Path,
+};
+
+use crate::{fs, map::MAP_FILE_NAME, Operation, Storage, E};
+
+impl Storage {
+    /// Clones the storage into the given directory. Record files are hard linked (or copied, if
+    /// hard links aren't supported, e.g. the destination is on another file system), so copying
+    /// takes milliseconds even for large storages. The new storage is independent: records are
+    /// never modified in place, so changes of one storage aren't visible in another one.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest` - A path reference to the destination directory. It should not exist or should
+    ///   be empty.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the new storage opened with the options of this storage,
+    ///   or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("profile", &String::from("original")).unwrap();
+    /// let mut fork = storage
+    ///     .copy_to(temp_dir().join(Uuid::new_v4().to_string()))
+    ///     .unwrap();
+    /// fork.set("profile", &String::from("forked")).unwrap();
+    /// assert_eq!(
+    ///     storage.get::<String, &str>("profile").unwrap(),
+    ///     Some(String::from("original"))
+    /// );
+    /// storage.destroy().unwrap();
+    /// fork.destroy().unwrap();
+    /// ```
+    pub fn copy_to<P: AsRef<Path>>(&mut self, dest: P) -> Result<Storage, E> {
+        let dest = fs::as_path_buf(dest);
+        if dest.exists() && read_dir(&dest)?.next().is_some() {
+            return Err(E::DestinationIsNotEmpty(dest));
+        }
+        create_dir_all(&dest)?;
+        self.flush()?;
+        let mut linked: HashSet<&str> = HashSet::new();
+        let mut copied = 0;
+        for (key, field) in self.fields.iter() {
+            if !linked.insert(field.file_name()) {
+                // Shared by several keys (see `Layout::ContentAddressed`)
+                continue;
+            }
+            let target = dest.join(field.file_name());
+            let path = field.path(&self.cwd);
+            if hard_link(&path, &target).is_err() {
+                copy(&path, &target)
+                    .map_err(|e| E::from(e).record(Operation::Write, key, &target))?;
+                copied += 1;
+            }
+        }
+        copy(self.cwd.join(MAP_FILE_NAME), dest.join(MAP_FILE_NAME))?;
+        debug!(
+            "Storage {:?} is copied to {dest:?}: {} file(s) linked, {copied} copied",
+            self.cwd,
+            linked.len() - copied
+        );
+        Storage::open_with_options(dest, self.options.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn copy_to() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..10u8 {
+            storage.set(i.to_string(), &i)?;
+        }
+        let dest = temp_dir().join(Uuid::new_v4().to_string());
+        let mut copy = storage.copy_to(&dest)?;
+        assert!(storage.copy_to(&dest).is_err());
+        assert_eq!(copy.len(), 10);
+        storage.set("0", &100u8)?;
+        storage.remove("1")?;
+        copy.set("2", &200u8)?;
+        assert_eq!(copy.get::<u8, &str>("0")?, Some(0));
+        assert_eq!(copy.get::<u8, &str>("1")?, Some(1));
+        assert_eq!(storage.get::<u8, &str>("2")?, Some(2));
+        drop(copy);
+        let mut copy = Storage::open(&dest)?;
+        assert_eq!(copy.len(), 10);
+        assert_eq!(copy.get::<u8, &str>("2")?, Some(200));
+        copy.destroy()?;
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use log::warn;
+use std::{
+    fs::{create_dir_all, remove_file, rename},
+    path::{Path, PathBuf},
+};
+
+use crate::E;
+
+/// Name of the folder (inside the storage folder) for quarantined record files
+pub(crate) const CORRUPT_DIR: &str = "corrupt";
+
+/// Defines what `get`, iteration/search and `open` do when a record file is missing or damaged
+/// (wrong checksum or header, empty file). Values, which cannot be deserialized as the requested
+/// type, aren't treated as corrupted: they are most likely values of other types.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CorruptionPolicy {
+    /// The record is treated as absent (`get` returns `None`); the problem is reported.
+    #[default]
+    Skip,
+    /// An error is returned.
+    Error,
+    /// The record file is moved into the `corrupt` subfolder of the storage and the key is
+    /// removed from the storage; the problem is reported.
+    Quarantine,
+    /// The record file is removed and the key is removed from the storage; the problem is
+    /// reported.
+    Delete,
+}
+
+/// The kind of detected problem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorruptionKind {
+    /// The record file doesn't exist.
+    Missing,
+    /// The record file is damaged. Contains the error message.
+    Invalid(String),
+}
+
+/// A report about a corrupted record. Reports are collected by the storage and can be taken
+/// with `Storage::take_corruptions()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Corruption {
+    /// Key of the record
+    pub key: String,
+    /// Path of the record file
+    pub path: PathBuf,
+    /// What's wrong with the record
+    pub kind: CorruptionKind,
+    /// Applied policy
+    pub action: CorruptionPolicy,
+    /// New location of the record file, if it has been quarantined
+    pub quarantined: Option<PathBuf>,
+}
+
+impl Corruption {
+    /// Applies the policy to the corrupted record file and returns a report. Should not be
+    /// called with `CorruptionPolicy::Error`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Policy to apply.
+    /// * `cwd` - Path to the storage folder.
+    /// * `key` - Key of the record.
+    /// * `path` - Path of the record file.
+    /// * `kind` - What's wrong with the record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Corruption, E>` - Returns the report, or an error if the file cannot be moved
+    ///   or removed.
+    pub(crate) fn handle(
+        policy: CorruptionPolicy,
+        cwd: &Path,
+        key: &str,
+        path: &Path,
+        kind: CorruptionKind,
+    ) -> Result<Self, E> {
+        let mut quarantined = None;
+        if kind != CorruptionKind::Missing {
+            match policy {
+                CorruptionPolicy::Quarantine => {
+                    let dest = cwd.join(CORRUPT_DIR);
+                    create_dir_all(&dest)?;
+                    let dest = dest.join(path.file_name().ok_or(E::InvalidPath(path.into()))?);
+                    rename(path, &dest)?;
+                    quarantined = Some(dest);
+                }
+                CorruptionPolicy::Delete => remove_file(path)?,
+                CorruptionPolicy::Skip | CorruptionPolicy::Error => {}
+            }
+        }
+        warn!("Record \"{key}\" ({path:?}) is corrupted ({kind:?}); applied policy: {policy:?}");
+        Ok(Self {
+            key: key.to_owned(),
+            path: path.to_path_buf(),
+            kind,
+            action: policy,
+            quarantined,
+        })
+    }
+
+    /// Returns true if the key has to be removed from the storage.
+    pub(crate) fn detached(&self) -> bool {
+        matches!(
+            self.action,
+            CorruptionPolicy::Quarantine | CorruptionPolicy::Delete
+        )
+    }
+}
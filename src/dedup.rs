@@ -0,0 +1,240 @@
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{
+    field::STORAGE_FILE_EXT, hasher::Fields, Codec, Field, Meta, Operation, Sha256Digest, Storage,
+    E,
+};
+
+/// Defines how records are placed into files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Each key has its own record file.
+    #[default]
+    PerKey,
+    /// Identical serialized values (of the same type) are stored once, in a file named by the
+    /// SHA-256 hash of its content; keys sharing the file are counted, and the file is removed
+    /// with the last key referencing it. Storing the same large value under many keys doesn't
+    /// multiply disk usage.
+    ///
+    /// Record files of this layout don't keep keys in their headers, so `KeyResolver::Embedded`
+    /// cannot restore them. A storage created with this layout should always be opened with it.
+    ContentAddressed,
+}
+
+/// Counts references to record files, which are shared by several keys.
+///
+/// # Arguments
+///
+/// * `fields` - Fields of the storage.
+/// * `layout` - Layout of the storage. With `Layout::ContentAddressed` all files are counted,
+///   otherwise only files referenced by more than one key.
+///
+/// # Returns
+///
+/// * `HashMap<String, usize>` - Number of references to files by file names.
+pub(crate) fn count_refs(fields: &Fields, layout: Layout) -> HashMap<String, usize> {
+    let mut refs: HashMap<String, usize> = HashMap::new();
+    for field in fields.values() {
+        *refs.entry(field.file_name().to_owned()).or_default() += 1;
+    }
+    if layout == Layout::PerKey {
+        refs.retain(|_, count| *count > 1);
+    }
+    refs
+}
+
+impl Storage {
+    /// Removes the key from the storage without removing its file.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<(Field, bool)>` - The removed field and true if no other key references its
+    ///   file, or None if the key doesn't exist.
+    pub(crate) fn forget(&mut self, key: &str) -> Option<(Field, bool)> {
+        let field = self.fields.remove(key)?;
+        self.unindex_key(key);
+        self.deadlines.cancel(key);
+        let last = match self.refs.get_mut(field.file_name()) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                self.refs.remove(field.file_name());
+                true
+            }
+            None => true,
+        };
+        if last {
+            self.release(&field);
+        }
+        Some((field, last))
+    }
+
+    /// Removes the key from the storage and its file, if no other key references it.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<u64>, E>` - Returns the number of released bytes, or None if the key
+    ///   doesn't exist, or an error.
+    pub(crate) fn discard(&mut self, key: &str) -> Result<Option<u64>, E> {
+        let Some((field, last)) = self.forget(key) else {
+            return Ok(None);
+        };
+        if !last {
+            return Ok(Some(0));
+        }
+        field
+            .remove(&*self.fs, &self.cwd)
+            .map_err(|e| e.record(Operation::Remove, key, &field.path(&self.cwd)))?;
+        Ok(Some(field.meta().size))
+    }
+
+    /// Returns true if the file of the key is referenced by other keys as well.
+    pub(crate) fn is_shared(&self, key: &str) -> bool {
+        self.fields
+            .get(key)
+            .and_then(|field| self.refs.get(field.file_name()))
+            .is_some_and(|count| *count > 1)
+    }
+
+    /// Returns the total size of record files; shared files are counted once.
+    pub(crate) fn total_size(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.fields
+            .values()
+            .filter(|field| {
+                !self.refs.contains_key(field.file_name()) || seen.insert(field.file_name())
+            })
+            .map(|field| field.meta().size)
+            .sum()
+    }
+
+    /// Writes the content of the record with `Layout::ContentAddressed`. The map isn't written.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `tag` - Type tag of the value.
+    /// * `codec` - Codec of the value in the record file.
+    /// * `transformers` - Names of transformers of the value in the record file.
+    /// * `digest` - SHA-256 digest of the serialized value, if it's computed.
+    /// * `content` - Content of the record file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub(crate) fn put_shared(
+        &mut self,
+        key: &str,
+        tag: u64,
+        codec: Codec,
+        transformers: Vec<String>,
+        digest: Option<Sha256Digest>,
+        content: &[u8],
+    ) -> Result<(), E> {
+        let file = format!("{:x}.{STORAGE_FILE_EXT}", Sha256::digest(content));
+        let path = self.cwd.join(&file);
+        if let Some(field) = self.fields.get(key) {
+            if field.file_name() == file {
+                field.touch(Duration::ZERO);
+                return Ok(());
+            }
+        }
+        let kept = self
+            .fields
+            .get(key)
+            .map(|field| field.meta().clone())
+            .unwrap_or_default();
+        self.discard(key)?;
+        let count = self.refs.get(&file).copied().unwrap_or_default();
+        let field = if count == 0 {
+            let mut field = Field::restore(
+                file.clone(),
+                Meta {
+                    pinned: kept.pinned,
+                    tags: kept.tags,
+                    codec,
+                    transformers,
+                    digest,
+                    ..Meta::default()
+                },
+            );
+            field
+                .store(&*self.fs, &self.cwd, tag, content, self.options.durability)
+                .map_err(|e| e.record(Operation::Set, key, &path))?;
+            field
+        } else {
+            let field = Field::restore(
+                file.clone(),
+                Meta {
+                    tag,
+                    pinned: kept.pinned,
+                    tags: kept.tags,
+                    codec,
+                    transformers,
+                    digest,
+                    ..Meta::from_file(&path)
+                },
+            );
+            field.touch(Duration::ZERO);
+            field
+        };
+        self.refs.insert(file, count + 1);
+        self.fields.insert(key.to_owned(), field);
+        self.index_key(key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Layout, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn content_addressed() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            layout: Layout::ContentAddressed,
+            ..Default::default()
+        };
+        let asset = vec![7u8; 1024];
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        for i in 0..10 {
+            storage.set(format!("asset_{i}"), &asset)?;
+        }
+        let size = storage.usage().bytes;
+        assert!(size > 1024 && size < 2048);
+        let path = storage
+            .fields
+            .get("asset_0")
+            .expect("Field exists")
+            .path(&storage.cwd);
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert_eq!(storage.usage().bytes, size);
+        for i in 0..9 {
+            storage.remove(format!("asset_{i}"))?;
+            assert!(path.exists());
+        }
+        assert_eq!(storage.get::<Vec<u8>, &str>("asset_9")?, Some(asset));
+        storage.set("asset_9", &vec![1u8; 10])?;
+        assert!(!path.exists());
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -1,10 +1,51 @@
-use std::{io, path::PathBuf};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
+use crate::{consistency::Stage, KeyViolation, Quota, VerifyReport};
+
+/// Operation which has been performed when an error happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Get,
+    Set,
+    Remove,
+    Read,
+    Write,
+    Pack,
+    Unpack,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Get => "get",
+                Self::Set => "set",
+                Self::Remove => "remove",
+                Self::Read => "read",
+                Self::Write => "write",
+                Self::Pack => "pack",
+                Self::Unpack => "unpack",
+            }
+        )
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum E {
     #[error("IO Error: {0}")]
-    IO(#[from] io::Error),
+    IO(#[source] io::Error),
+    #[error("No space left on device")]
+    DiskFull,
+    #[error("Permission denied: {path:?}")]
+    PermissionDenied { path: PathBuf },
+    #[error("Read-only file system")]
+    ReadOnlyFilesystem,
     #[error("Serialize/Deserialize error: {0}")]
     Bincode(bincode::ErrorKind),
     #[error("Given path isn't a folder: {0}")]
@@ -15,12 +56,110 @@ pub enum E {
     PackageFileDoesNotExist(PathBuf),
     #[error("Storage file {0} is invalid")]
     PackageFileInvalid(PathBuf),
+    #[error("Storage is partially unpacked; unpack the bundle again to resume: {0}")]
+    PartiallyUnpacked(PathBuf),
     #[error("Fail to get parent of package file")]
     NoParentOfStorageFile,
+    #[error("Fail to {op} record \"{key}\" ({path:?}): {source}")]
+    Record {
+        op: Operation,
+        key: String,
+        path: PathBuf,
+        #[source]
+        source: Box<E>,
+    },
+    #[error("Fail to {op} map {path:?}: {source}")]
+    Map {
+        op: Operation,
+        path: PathBuf,
+        #[source]
+        source: Box<E>,
+    },
+    #[error("Checksum doesn't match")]
+    ChecksumMismatch,
+    #[error("Header of the record file is damaged: {0}")]
+    InvalidHeader(String),
+    #[error("Storage verification failed: {0}")]
+    Verification(VerifyReport),
+    #[error("Storage is locked (in use): {0}")]
+    Locked(PathBuf),
+    #[error("Invalid name of the storage: {0}")]
+    InvalidStorageName(String),
+    #[error("Destination folder isn't empty: {0}")]
+    DestinationIsNotEmpty(PathBuf),
+    #[error("Map file is truncated: expected {expected} bytes, found {actual}")]
+    MapIsTruncated { expected: u64, actual: u64 },
+    #[error("Unsupported version of the map file: {0}")]
+    InvalidMapVersion(u8),
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(Quota),
+    #[error("Key \"{key}\" is rejected: {violation}")]
+    KeyRejected {
+        key: String,
+        violation: KeyViolation,
+    },
+    #[error("Value of \"{key}\" is invalid: {reason}")]
+    InvalidValue { key: String, reason: String },
+    #[error("Key isn't valid UTF-8: {0:?}")]
+    InvalidKey(Vec<u8>),
+    #[error("Fail to import data: {0}")]
+    Import(String),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(any(feature = "json", feature = "tar", feature = "http"))]
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "object-store")]
+    #[error("Object store error: {0}")]
+    ObjectStore(#[from] object_store::Error),
+    #[cfg(feature = "bstorage-server")]
+    #[error("Remote storage error: {0}")]
+    Remote(String),
+    #[error("Record \"{key}\" differs from the original value after {stage}")]
+    Inconsistent { key: String, stage: Stage },
+    #[error("History of changes isn't kept (see StorageOptions::history)")]
+    HistoryIsNotKept,
+    #[error("The moment is older than the kept history of changes")]
+    OutOfHistory,
+    #[error("Replication error: {0}")]
+    Replication(String),
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+    #[error("Storage folder is a symlink: {0}")]
+    SymlinkRejected(PathBuf),
+    #[error("Operation has been cancelled")]
+    Cancelled,
+    #[error("Background flusher of the map is stopped")]
+    FlusherIsStopped,
+    #[error("Storage has been replaced or cleared by another instance (generation {actual}, the instance has {expected}); it should be opened again")]
+    StaleHandle { expected: u64, actual: u64 },
+    #[error("Operation is rejected by a middleware: {0}")]
+    Rejected(String),
+    #[error("Record is written with the transformer \"{0}\", which isn't registered")]
+    UnknownTransformer(String),
+    #[error("Record is written with a codec or transformers; it can be read only by the storage, which has them")]
+    EncodedRecord,
+    #[error("Transformer \"{0}\" is registered already")]
+    DuplicateTransformer(String),
+    #[error(
+        "Changes after the cursor {cursor} aren't kept anymore; the oldest kept change is {oldest}"
+    )]
+    CursorIsTooOld { cursor: u64, oldest: u64 },
     #[error("unknown data store error")]
     Unknown,
 }
 
+impl From<io::Error> for E {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => E::DiskFull,
+            io::ErrorKind::ReadOnlyFilesystem => E::ReadOnlyFilesystem,
+            _ => E::IO(err),
+        }
+    }
+}
+
 impl From<bincode::ErrorKind> for E {
     fn from(err: bincode::ErrorKind) -> Self {
         E::Bincode(err)
@@ -32,3 +171,99 @@ impl From<Box<bincode::ErrorKind>> for E {
         E::Bincode(*err)
     }
 }
+
+impl E {
+    /// Converts the IO error on the given path. Unlike `From<io::Error>` it keeps the path of
+    /// denied access (`E::PermissionDenied`).
+    pub(crate) fn io(err: io::Error, path: &Path) -> Self {
+        E::from(err).at(path)
+    }
+
+    /// Binds denied access to the path.
+    fn at(self, path: &Path) -> Self {
+        match self {
+            E::IO(err) if err.kind() == io::ErrorKind::PermissionDenied => E::PermissionDenied {
+                path: path.to_path_buf(),
+            },
+            err => err,
+        }
+    }
+
+    /// Wraps the error with the context of the record.
+    pub(crate) fn record<K: AsRef<str>>(self, op: Operation, key: K, path: &Path) -> Self {
+        E::Record {
+            op,
+            key: key.as_ref().to_owned(),
+            path: path.to_path_buf(),
+            source: Box::new(self.at(path)),
+        }
+    }
+
+    /// Wraps the error with the context of the map.
+    pub(crate) fn map(self, op: Operation, path: &Path) -> Self {
+        E::Map {
+            op,
+            path: path.to_path_buf(),
+            source: Box::new(self.at(path)),
+        }
+    }
+
+    /// Returns the cause of the error without the context of the record or of the map (see
+    /// `E::Record` and `E::Map`), e.g. to react to `E::DiskFull` or `E::PermissionDenied`.
+    ///
+    /// # Returns
+    ///
+    /// * `&E` - The cause of the error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Storage, E};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// if let Err(err) = storage.set("key", &1u8) {
+    ///     match err.root() {
+    ///         E::DiskFull => eprintln!("free some space"),
+    ///         E::PermissionDenied { path } => eprintln!("check permissions of {path:?}"),
+    ///         _ => eprintln!("{err}"),
+    ///     }
+    /// }
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn root(&self) -> &E {
+        match self {
+            E::Record { source, .. } | E::Map { source, .. } => source.root(),
+            err => err,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Operation, E};
+    use std::{io, path::Path};
+
+    #[test]
+    fn classified() {
+        let path = Path::new("record.bstorage");
+        let err = E::from(io::Error::from(io::ErrorKind::StorageFull)).record(
+            Operation::Set,
+            "key",
+            path,
+        );
+        assert!(matches!(err.root(), E::DiskFull));
+        assert!(matches!(
+            E::from(io::Error::from(io::ErrorKind::ReadOnlyFilesystem)),
+            E::ReadOnlyFilesystem
+        ));
+        let err =
+            E::from(io::Error::from(io::ErrorKind::PermissionDenied)).map(Operation::Write, path);
+        assert!(matches!(err.root(), E::PermissionDenied { path: denied } if denied == path));
+        assert!(matches!(
+            E::from(io::Error::from(io::ErrorKind::NotFound)),
+            E::IO(_)
+        ));
+    }
+}
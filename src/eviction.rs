@@ -0,0 +1,117 @@
+use log::debug;
+
+use crate::{Event, Storage, Usage, E};
+
+/// Budget of the storage used as a cache. As soon as a modifying call (`set`, etc.) exceeds the
+/// budget, the least-recently-used records are removed until the storage fits the budget again.
+/// Records, which are written by the call itself, and pinned records (see `Storage::pin()`) are
+/// never evicted.
+///
+/// With eviction enabled, each read updates the time of the last access to the record. Access
+/// times are kept in the map of the storage and persisted with the next write of the map or with
+/// `Storage::flush()`.
+///
+/// Note, unlike `Limits`, the budget doesn't make modifying calls fail. If both are used, `Limits`
+/// are checked before the record is written, and eviction happens after.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Eviction {
+    /// Maximum total size of all record files in bytes
+    pub max_total_size: Option<u64>,
+    /// Maximum number of keys
+    pub max_keys: Option<usize>,
+}
+
+impl Eviction {
+    fn exceeded(&self, usage: &Usage) -> bool {
+        self.max_keys.is_some_and(|limit| usage.keys > limit)
+            || self.max_total_size.is_some_and(|limit| usage.bytes > limit)
+    }
+}
+
+impl Storage {
+    /// Removes the least-recently-used records until the storage fits the budget defined by
+    /// `StorageOptions::eviction`. The map isn't written.
+    ///
+    /// # Arguments
+    ///
+    /// * `keep` - Keys, which should not be evicted.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns evicted keys, or an error.
+    pub(crate) fn evict(&mut self, keep: &[&str]) -> Result<Vec<String>, E> {
+        let Some(eviction) = self.options.eviction.clone() else {
+            return Ok(Vec::new());
+        };
+        let mut usage = self.usage();
+        if !eviction.exceeded(&usage) {
+            return Ok(Vec::new());
+        }
+        let mut candidates = self
+            .fields
+            .iter()
+            .filter(|(key, field)| !keep.contains(&key.as_str()) && !field.meta().pinned)
+            .map(|(key, field)| (field.meta().accessed.get(), key.to_owned()))
+            .collect::<Vec<(u64, String)>>();
+        candidates.sort_unstable();
+        let mut evicted = Vec::new();
+        for (_, key) in candidates {
+            if !eviction.exceeded(&usage) {
+                break;
+            }
+            self.retain(&[&key])?;
+            let Some(released) = self.discard(&key)? else {
+                continue;
+            };
+            usage.keys -= 1;
+            usage.bytes = usage.bytes.saturating_sub(released);
+            evicted.push(key);
+        }
+        for key in evicted.iter() {
+            self.publish(key, None);
+            self.emit(Event::Evicted {
+                key: key.to_owned(),
+            });
+        }
+        debug!("{} record(s) evicted from {:?}", evicted.len(), self.cwd);
+        Ok(evicted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Eviction, Storage, StorageOptions, E};
+    use std::{env::temp_dir, thread, time::Duration};
+    use uuid::Uuid;
+
+    #[test]
+    fn lru() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            eviction: Some(Eviction {
+                max_keys: Some(3),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        for key in ["a", "b", "c"] {
+            storage.set(key, &String::from(key))?;
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(storage.get::<String, &str>("a")?, Some(String::from("a")));
+        thread::sleep(Duration::from_millis(5));
+        storage.set("d", &String::from("d"))?;
+        assert_eq!(storage.len(), 3);
+        assert!(!storage.has("b"));
+        assert!(storage.has("a"));
+        // Access times survive reopening
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        storage.set("e", &String::from("e"))?;
+        assert!(!storage.has("c"));
+        assert!(storage.has("a"));
+        storage.destroy()?;
+        Ok(())
+    }
+}
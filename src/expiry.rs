@@ -0,0 +1,363 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{FileSystem, Stamp, Storage, StorageKey, E};
+
+/// File of the storage with expiry times of keys. It doesn't have the extension of records, so
+/// it isn't taken as an orphaned record.
+const EXPIRY_FILE_NAME: &str = "map.expiry";
+
+/// Event of the storage delivered to handlers registered with `Storage::on_event()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The key has been removed, because its time to live has elapsed (see `Storage::expire()`).
+    Expired { key: String },
+    /// The key has been removed to fit the budget of `StorageOptions::eviction` or by
+    /// `Storage::sweep_older_than()`.
+    Evicted { key: String },
+}
+
+/// Handler of events registered with `Storage::on_event()`
+type Handler = Box<dyn Fn(&Event) + Send + Sync>;
+
+/// Handlers of events of the storage
+#[derive(Default)]
+pub(crate) struct Events {
+    handlers: Vec<Handler>,
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("handlers", &self.handlers.len())
+            .finish()
+    }
+}
+
+/// Expiry times of keys
+#[derive(Debug, Default)]
+pub(crate) struct Deadlines {
+    /// Time of expiry of the key in milliseconds since UNIX epoch
+    at: HashMap<String, u64>,
+    /// True if expiry times have been changed since they have been written
+    changed: bool,
+}
+
+impl Deadlines {
+    /// Reads expiry times of keys of the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns expiry times (empty if there are no keys with expiry), or
+    ///   an error.
+    pub(crate) fn load(fs: &dyn FileSystem, cwd: &Path) -> Result<Self, E> {
+        let path = cwd.join(EXPIRY_FILE_NAME);
+        if !fs.exists(&path) {
+            return Ok(Self::default());
+        }
+        Ok(Self {
+            at: bincode::deserialize(&fs.read(&path)?)?,
+            changed: false,
+        })
+    }
+
+    /// Drops the expiry of the key; called when the key is removed or overwritten.
+    pub(crate) fn cancel(&mut self, key: &str) -> bool {
+        let cancelled = self.at.remove(key).is_some();
+        self.changed |= cancelled;
+        cancelled
+    }
+
+    /// Returns true if the key has the expiry time.
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.at.contains_key(key)
+    }
+
+    /// Drops expiry times of all keys.
+    pub(crate) fn clear(&mut self) {
+        self.changed |= !self.at.is_empty();
+        self.at.clear();
+    }
+}
+
+impl Storage {
+    /// Returns true if the time to live of the key has elapsed. Expired keys are kept until the
+    /// next modifying call, but they are not visible to readers.
+    pub(crate) fn expired(&self, key: &str) -> bool {
+        self.deadlines
+            .at
+            .get(key)
+            .is_some_and(|at| *at <= Stamp::millis(SystemTime::now()))
+    }
+
+    /// Writes expiry times of keys, if they have been changed.
+    pub(crate) fn save_deadlines(&mut self) -> Result<(), E> {
+        if !self.deadlines.changed {
+            return Ok(());
+        }
+        let path = self.cwd.join(EXPIRY_FILE_NAME);
+        if self.deadlines.at.is_empty() {
+            if self.fs.exists(&path) {
+                self.fs.remove(&path).map_err(|err| E::io(err, &path))?;
+            }
+        } else {
+            let content = bincode::serialize(&self.deadlines.at)?;
+            self.fs
+                .write(&path, &content, self.options.durability)
+                .map_err(|err| E::io(err, &path))?;
+        }
+        self.deadlines.changed = false;
+        Ok(())
+    }
+
+    /// Delivers the event to registered handlers (see `on_event()`).
+    pub(crate) fn emit(&self, event: Event) {
+        for handler in self.events.handlers.iter() {
+            handler(&event);
+        }
+    }
+
+    /// Registers the handler of events of the storage: expiry and eviction of keys. Handlers are
+    /// called synchronously by the call, which removes keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler of events.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Event, Storage};
+    /// use std::{
+    ///     env::temp_dir,
+    ///     sync::{Arc, Mutex},
+    ///     thread,
+    ///     time::Duration,
+    /// };
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let events = Arc::new(Mutex::new(Vec::new()));
+    /// storage.on_event({
+    ///     let events = events.clone();
+    ///     move |event| events.lock().unwrap().push(event.clone())
+    /// });
+    /// storage
+    ///     .set_with_ttl("session", &1u8, Duration::from_millis(10))
+    ///     .unwrap();
+    /// thread::sleep(Duration::from_millis(20));
+    /// storage.purge_expired().unwrap();
+    /// assert_eq!(
+    ///     *events.lock().unwrap(),
+    ///     vec![Event::Expired {
+    ///         key: String::from("session")
+    ///     }]
+    /// );
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn on_event<F: Fn(&Event) + Send + Sync + 'static>(&mut self, handler: F) {
+        self.events.handlers.push(Box::new(handler));
+    }
+
+    /// Sets a value for the specified key, which expires after the given time. Overwriting the
+    /// key with `set()` drops the expiry.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `value` - A reference to the value to be stored.
+    /// * `ttl` - Time to live of the key.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn set_with_ttl<V: Serialize + 'static, K: StorageKey>(
+        &mut self,
+        key: K,
+        value: &V,
+        ttl: Duration,
+    ) -> Result<(), E> {
+        let key = self.normalized(key.to_key());
+        self.set(key.as_ref(), value)?;
+        self.expire(key, ttl)?;
+        Ok(())
+    }
+
+    /// Sets the time to live of the existing key. Expired keys aren't returned by `get()` and
+    /// `has()`; they are removed with the next modifying call or with `purge_expired()`, which
+    /// emit `Event::Expired` for each of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `ttl` - Time to live of the key, counted from now.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the expiry has been set, false if the key doesn't
+    ///   exist, or an error.
+    pub fn expire<K: StorageKey>(&mut self, key: K, ttl: Duration) -> Result<bool, E> {
+        let key = self.normalized(key.to_key());
+        if !self.has(key.as_ref()) {
+            return Ok(false);
+        }
+        let at = Stamp::millis(SystemTime::now() + ttl);
+        self.deadlines.at.insert(key.as_ref().to_owned(), at);
+        self.deadlines.changed = true;
+        self.save_deadlines()?;
+        Ok(true)
+    }
+
+    /// Returns the remaining time to live of the key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Duration>` - Returns the remaining time, or None if the key doesn't exist or
+    ///   doesn't expire.
+    pub fn ttl<K: StorageKey>(&self, key: K) -> Option<Duration> {
+        let key = self.normalized(key.to_key());
+        if !self.has(key.as_ref()) {
+            return None;
+        }
+        let at = self.deadlines.at.get(key.as_ref())?;
+        Some(Duration::from_millis(
+            at.saturating_sub(Stamp::millis(SystemTime::now())),
+        ))
+    }
+
+    /// Cancels the expiry of the key, so it's kept until it's removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the expiry has been cancelled, false if the key
+    ///   doesn't exist or doesn't expire, or an error.
+    pub fn persist<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        let key = self.normalized(key.to_key());
+        if !self.has(key.as_ref()) || !self.deadlines.cancel(key.as_ref()) {
+            return Ok(false);
+        }
+        self.save_deadlines()?;
+        Ok(true)
+    }
+
+    /// Removes expired keys (see `expire()`) and emits `Event::Expired` for each of them.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns removed keys, or an error.
+    pub fn purge_expired(&mut self) -> Result<Vec<String>, E> {
+        let now = Stamp::millis(SystemTime::now());
+        let mut expired = self
+            .deadlines
+            .at
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(key, _)| key.to_owned())
+            .collect::<Vec<String>>();
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+        expired.sort();
+        for key in expired.iter() {
+            self.deadlines.cancel(key);
+            if self.fields.contains_key(key) {
+                self.retain(&[key])?;
+                self.discard(key)?;
+            }
+        }
+        self.map.write(&self.fields)?;
+        self.save_deadlines()?;
+        for key in expired.iter() {
+            self.publish(key, None);
+            self.emit(Event::Expired {
+                key: key.to_owned(),
+            });
+        }
+        Ok(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Event, Eviction, Storage, StorageOptions, E};
+    use std::{
+        env::temp_dir,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn expiry() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            eviction: Some(Eviction {
+                max_keys: Some(3),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        storage.on_event({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event.clone())
+        });
+        storage.set_with_ttl("a", &1u8, Duration::from_millis(100))?;
+        storage.set_with_ttl("b", &2u8, Duration::from_secs(60))?;
+        storage.set_with_ttl("c", &3u8, Duration::from_secs(60))?;
+        assert!(storage
+            .ttl("a")
+            .is_some_and(|ttl| ttl <= Duration::from_millis(100)));
+        assert!(!storage.expire("missing", Duration::from_secs(1))?);
+        // Overwriting drops the expiry
+        storage.set("c", &3u8)?;
+        assert!(storage.ttl("c").is_none());
+        assert!(storage.persist("b")?);
+        assert!(!storage.persist("b")?);
+        thread::sleep(Duration::from_millis(150));
+        assert!(!storage.has("a"));
+        assert_eq!(storage.get::<u8, _>("a")?, None);
+        // Expiry survives reopening
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        storage.on_event({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event.clone())
+        });
+        assert!(storage.ttl("b").is_none());
+        storage.set("d", &4u8)?;
+        assert_eq!(storage.len(), 3);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![Event::Expired {
+                key: String::from("a")
+            }]
+        );
+        thread::sleep(Duration::from_millis(5));
+        storage.set("e", &5u8)?;
+        assert_eq!(events.lock().unwrap().len(), 2);
+        assert!(matches!(events.lock().unwrap()[1], Event::Evicted { .. }));
+        storage.destroy()?;
+        Ok(())
+    }
+}
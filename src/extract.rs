@@ -0,0 +1,313 @@
+//! Lazy extraction of bundles. `Storage::unpack_lazy()` writes only the maps of storages and
+//! opens the unpacked folder over a file system, which extracts a record file from the bundle
+//! the first time it's read. Records, which are never read, never hit the disk.
+//!
+//! Until all records are extracted, the folder keeps the manifest of unpacking (records, which
+//! are extracted or removed, are appended to it), so it cannot be opened with `Storage::open()`;
+//! unpacking the bundle with `Bundle::unpack()` starts over.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, read_to_string, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    bundle::{
+        fingerprint, flatten, read_index, track, write_map, Location, PARTIAL_FILE_NAME,
+        UNPACKED_EXT,
+    },
+    fs, map,
+    trace::op,
+    Durability, FileSystem, StdFs, Storage, StorageOptions, E,
+};
+
+/// `FileSystem`, which extracts record files from the bundle on the first access
+#[derive(Debug)]
+struct Extracting {
+    bundle: File,
+    /// Manifest of unpacking, which is removed once all records are extracted
+    manifest: (PathBuf, File),
+    /// Records, which aren't extracted yet, by paths of their files: the path relative to the
+    /// unpacked folder (as it's written into the manifest) and the location in the bundle
+    pending: Mutex<HashMap<PathBuf, (String, Location)>>,
+}
+
+/// Pending records of `Extracting`
+type Pending = HashMap<PathBuf, (String, Location)>;
+
+impl Extracting {
+    fn pending(&self) -> io::Result<MutexGuard<'_, Pending>> {
+        self.pending
+            .lock()
+            .map_err(|_| io::Error::other("pending records are poisoned"))
+    }
+
+    /// Removes the record from pending ones and adds it to the manifest; the manifest is
+    /// removed with the last of them.
+    fn done(&self, pending: &mut Pending, path: &Path) -> io::Result<()> {
+        let Some((relative, _)) = pending.remove(path) else {
+            return Ok(());
+        };
+        let (manifest, mut file) = (&self.manifest.0, &self.manifest.1);
+        if !pending.is_empty() {
+            return writeln!(file, "{relative}");
+        }
+        match std::fs::remove_file(manifest) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Extracts the record file, if it isn't extracted yet.
+    fn extract(&self, path: &Path) -> io::Result<()> {
+        let mut pending = self.pending()?;
+        let Some((_, location)) = pending.get(path) else {
+            return Ok(());
+        };
+        StdFs.write(path, &location.read(&self.bundle)?, Durability::Flush)?;
+        self.done(&mut pending, path)
+    }
+}
+
+impl FileSystem for Extracting {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.extract(path)?;
+        StdFs.read(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8], durability: Durability) -> io::Result<()> {
+        let mut pending = self.pending()?;
+        StdFs.write(path, content, durability)?;
+        self.done(&mut pending, path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut pending = self.pending()?;
+        if pending.contains_key(path) {
+            return self.done(&mut pending, path);
+        }
+        StdFs.remove(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        if path == self.manifest.0 {
+            return false;
+        }
+        self.pending
+            .lock()
+            .is_ok_and(|pending| pending.contains_key(path))
+            || StdFs.exists(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        if let Some((_, location)) = self.pending()?.get(path) {
+            if !location.compressed {
+                return Ok(location.header.len() as u64 + location.to - location.from);
+            }
+        }
+        self.extract(path)?;
+        StdFs.size(path)
+    }
+
+    fn allocated(&self, path: &Path) -> io::Result<u64> {
+        if self.pending()?.contains_key(path) {
+            return Ok(0);
+        }
+        StdFs.allocated(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        StdFs.create_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        StdFs.sync_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.pending()?.retain(|file, _| !file.starts_with(path));
+        StdFs.remove_dir_all(path)
+    }
+}
+
+impl Storage {
+    /// Opens the storage over the bundle without unpacking it up front: only maps of storages
+    /// are written, while record files are extracted from the bundle the first time they are
+    /// read (or replaced by writes). Opening of large bundles is instant and records, which are
+    /// never read, never hit the disk. The bundle should stay in place until all records are
+    /// extracted.
+    ///
+    /// The storage is unpacked into the same folder as `Bundle::unpack()` uses. Calling
+    /// `unpack_lazy()` again continues the lazy extraction. Until all records are extracted, the
+    /// folder cannot be opened with `Storage::open()` (`E::PartiallyUnpacked`); `Bundle::unpack()`
+    /// of the folder starts over and replaces changed records.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the opened `Storage` instance or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Bundle, Storage};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("my_record", &1u8).unwrap();
+    /// let bundle = temp_dir().join(Uuid::new_v4().to_string());
+    /// storage.pack(&bundle).unwrap();
+    /// let mut unpacked = Storage::unpack_lazy(&bundle).unwrap();
+    /// assert_eq!(unpacked.get::<u8, _>("my_record").unwrap(), Some(1));
+    /// unpacked.destroy().unwrap();
+    /// storage.destroy().unwrap();
+    /// std::fs::remove_file(bundle).unwrap();
+    /// ```
+    pub fn unpack_lazy<P: AsRef<Path>>(bundle: P) -> Result<Storage, E> {
+        let bundle = fs::as_path_buf(bundle);
+        let _op = op!("unpack", path, bundle);
+        if !bundle.is_file() {
+            return Err(E::PackageFileDoesNotExist(bundle));
+        }
+        let cwd = bundle.with_extension(UNPACKED_EXT);
+        let mut file = fs::read(&bundle)?;
+        let index = read_index(&mut file, &bundle)?;
+        let marker = format!("lazy {}", fingerprint(&file, &index)?);
+        let manifest = cwd.join(PARTIAL_FILE_NAME);
+        // The storage, which has been unpacked completely, is opened as it is
+        if cwd.exists() && !manifest.exists() {
+            return Storage::open(cwd);
+        }
+        // Extracted and removed records of the previous lazy extraction of the bundle
+        let done = read_to_string(&manifest).ok().and_then(|content| {
+            let mut lines = content.lines();
+            (lines.next() == Some(marker.as_str())).then(|| {
+                lines
+                    .map(|line| line.to_owned())
+                    .collect::<HashSet<String>>()
+            })
+        });
+        let resumed = done.is_some();
+        let done = done.unwrap_or_default();
+        let mut pending = HashMap::new();
+        for (folder, prefix, records) in flatten(index, &cwd) {
+            create_dir_all(&folder).map_err(|e| E::io(e, &folder))?;
+            let records = records
+                .into_iter()
+                .filter(Location::is_valid)
+                .collect::<Vec<Location>>();
+            if !resumed || !folder.join(map::MAP_FILE_NAME).exists() {
+                write_map(
+                    &folder,
+                    &records
+                        .iter()
+                        .map(|location| {
+                            (
+                                location.key.to_owned(),
+                                (location.file.to_owned(), location.meta.clone()),
+                            )
+                        })
+                        .collect(),
+                )?;
+            }
+            for location in records {
+                let relative = format!("{prefix}{}", location.file);
+                let path = folder.join(&location.file);
+                if !done.contains(&relative) && !path.exists() {
+                    pending.insert(path, (relative, location));
+                }
+            }
+        }
+        track(&cwd, &bundle, false)?;
+        if pending.is_empty() {
+            if manifest.exists() {
+                std::fs::remove_file(&manifest).map_err(|e| E::io(e, &manifest))?;
+            }
+            return Storage::open(cwd);
+        }
+        if !resumed {
+            writeln!(fs::create(&manifest)?, "{marker}")?;
+        }
+        let manifest_file = OpenOptions::new()
+            .append(true)
+            .open(&manifest)
+            .map_err(|e| E::io(e, &manifest))?;
+        let fs = Extracting {
+            bundle: file,
+            manifest: (manifest, manifest_file),
+            pending: Mutex::new(pending),
+        };
+        Storage::open_with_options(
+            cwd,
+            StorageOptions {
+                fs: Some(Arc::new(fs)),
+                ..Default::default()
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bundle::PARTIAL_FILE_NAME, Bundle, Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn lazy_extraction() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..20u32 {
+            storage.set(format!("key_{i}"), &i)?;
+        }
+        storage.child("plugins/foo")?.set("enabled", &true)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let mut unpacked = Storage::unpack_lazy(&bundle)?;
+        let cwd = unpacked.cwd().to_path_buf();
+        let extracted = |storage: &Storage| {
+            storage
+                .fields
+                .values()
+                .filter(|field| field.path(storage.cwd()).exists())
+                .count()
+        };
+        // Nothing is extracted up front
+        assert_eq!(unpacked.len(), 20);
+        assert_eq!(extracted(&unpacked), 0);
+        assert_eq!(unpacked.get::<u32, _>("key_3")?, Some(3));
+        assert_eq!(extracted(&unpacked), 1);
+        unpacked.set("key_4", &40u32)?;
+        unpacked.remove("key_5")?;
+        assert_eq!(extracted(&unpacked), 2);
+        // The folder cannot be opened as a storage until all records are extracted
+        assert!(matches!(Storage::open(&cwd), Err(E::PartiallyUnpacked(_))));
+        drop(unpacked);
+        // Lazy extraction is continued
+        let unpacked = Storage::unpack_lazy(&bundle)?;
+        assert_eq!(extracted(&unpacked), 2);
+        assert_eq!(unpacked.get::<u32, _>("key_4")?, Some(40));
+        assert_eq!(unpacked.get::<u32, _>("key_5")?, None);
+        let foo = unpacked.child("plugins/foo")?;
+        assert_eq!(foo.get::<bool, _>("enabled")?, Some(true));
+        drop(foo);
+        for i in (0..20u32).filter(|i| *i != 5) {
+            assert!(unpacked.get::<u32, _>(format!("key_{i}"))?.is_some());
+        }
+        // All records are extracted
+        assert!(!cwd.join(PARTIAL_FILE_NAME).exists());
+        drop(unpacked);
+        let mut unpacked = Storage::open(&cwd)?;
+        assert_eq!(unpacked.get::<u32, _>("key_4")?, Some(40));
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+}
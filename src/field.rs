@@ -1,116 +1,353 @@
-use crate::{fs, E};
-use serde::{Deserialize, Serialize};
+use crate::{
+    deserialize_tagged, is_stable, Codec, Durability, FileSystem, Header, Sha256Digest, E,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    fs::remove_file,
-    io::{Read, Write},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use uuid::Uuid;
 
-const STORAGE_FILE_EXT: &str = "bstorage";
+pub(crate) const STORAGE_FILE_EXT: &str = "bstorage";
+
+/// Timestamp in milliseconds since UNIX epoch, which can be updated via a shared reference
+/// (reading a field doesn't require mutable access to the storage).
+#[derive(Debug, Default)]
+pub struct Stamp(AtomicU64);
+
+impl Stamp {
+    pub(crate) fn millis(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+    }
+
+    /// Returns the value of the timestamp in milliseconds since UNIX epoch.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sets the timestamp to the current time.
+    pub fn touch(&self) {
+        self.0
+            .store(Stamp::millis(SystemTime::now()), Ordering::Relaxed);
+    }
+
+    /// Sets the timestamp to the current time, if the timestamp is older than the given
+    /// resolution.
+    ///
+    /// # Arguments
+    ///
+    /// * `resolution` - Minimal difference between the current value and the current time.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the timestamp has been updated.
+    pub fn touch_after(&self, resolution: Duration) -> bool {
+        let now = Stamp::millis(SystemTime::now());
+        let prev = self.get();
+        if now.saturating_sub(prev) < resolution.as_millis() as u64 {
+            return false;
+        }
+        self.0
+            .compare_exchange(prev, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Returns the time passed since the timestamp.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(Stamp::millis(SystemTime::now()).saturating_sub(self.get()))
+    }
+}
+
+impl Clone for Stamp {
+    fn clone(&self) -> Self {
+        Self(AtomicU64::new(self.get()))
+    }
+}
+
+impl PartialEq for Stamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl Eq for Stamp {}
+
+impl Serialize for Stamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.get())
+    }
+}
+
+impl<'de> Deserialize<'de> for Stamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self(AtomicU64::new(u64::deserialize(deserializer)?)))
+    }
+}
+
+/// Metadata of the field, which is persisted in the map of the storage. Fields, which aren't in
+/// the stored metadata, take default values.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Meta {
+    /// Size of the field's file in bytes
+    pub size: u64,
+    /// Time of the last access to the field (reading or writing)
+    pub accessed: Stamp,
+    /// Type tag of the stored value (see `type_tag()`); 0 if unknown
+    pub tag: u64,
+    /// The record is pinned (see `Storage::pin()`)
+    pub pinned: bool,
+    /// Tags of the record, sorted (see `Storage::tag()`)
+    pub tags: Vec<String>,
+    /// Codec of the value in the field's file (see `Codecs`)
+    pub codec: Codec,
+    /// Names of transformers of the value in the field's file (see `Storage::transformer()`)
+    pub transformers: Vec<String>,
+    /// SHA-256 digest of the serialized value, if it's computed (see `StorageOptions::digests`)
+    pub digest: Option<Sha256Digest>,
+}
+
+impl Meta {
+    /// Creates metadata of the field based on its file; used for fields, which metadata isn't
+    /// stored in the map (maps written by previous versions of `bstorage`, recovered files).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path reference to the file of the field.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns metadata of the field.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            return Self::default();
+        };
+        // Legacy records and records with damaged headers are taken as plain ones
+        let header = std::fs::File::open(&path)
+            .and_then(|mut file| Header::read(&mut file))
+            .ok()
+            .flatten();
+        Self {
+            size: metadata.len(),
+            accessed: Stamp(AtomicU64::new(
+                metadata.modified().map(Stamp::millis).unwrap_or_default(),
+            )),
+            tag: header.as_ref().map(|header| header.tag).unwrap_or_default(),
+            pinned: false,
+            tags: Vec::new(),
+            codec: header
+                .as_ref()
+                .map(|header| header.codec)
+                .unwrap_or_default(),
+            transformers: header.map(|header| header.transformers).unwrap_or_default(),
+            digest: None,
+        }
+    }
+}
+
 /// `Field` is a struct representing a single field stored in a binary file within the storage system.
+/// The field keeps only the name of its file; the folder of the storage is shared and given to
+/// methods, which access the file.
 #[derive(Debug)]
 pub struct Field {
-    path: PathBuf,
+    file: String,
+    meta: Meta,
 }
 
 impl Field {
-    /// Restores a `Field` from the specified path.
+    /// Restores a `Field` from the specified file name.
     ///
     /// # Arguments
     ///
-    /// * `path` - A path reference to the file of the field.
+    /// * `file` - Name of the file of the field in the storage folder.
+    /// * `meta` - Metadata of the field.
     ///
     /// # Returns
     ///
     /// * `Self` - Returns an instance of `Field`.
-    pub fn restore<P: AsRef<Path>>(path: P) -> Self {
+    pub fn restore<S: Into<String>>(file: S, meta: Meta) -> Self {
         Self {
-            path: fs::as_path_buf(path),
+            file: file.into(),
+            meta,
         }
     }
 
-    /// Creates a new `Field` in the specified directory.
+    /// Creates a new `Field` with a unique file name.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns a newly created instance of `Field`.
+    pub fn create() -> Self {
+        Self {
+            file: format!("{}.{STORAGE_FILE_EXT}", Uuid::new_v4()),
+            meta: Meta::default(),
+        }
+    }
+
+    /// Retrieves the value of the field. Returns error in case of case of deserializing error or if
+    /// the checksum of the record doesn't match.
     ///
     /// # Arguments
     ///
-    /// * `cwd` - A path reference to the current working directory.
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
     ///
     /// # Returns
     ///
-    /// * `Self` - Returns a newly created instance of `Field`.
-    pub fn create<P: AsRef<Path>>(cwd: P) -> Self {
-        let cwd = fs::as_path_buf(cwd);
-        let path = cwd.join(format!("{}.{STORAGE_FILE_EXT}", Uuid::new_v4()));
-        Self { path }
+    /// * `Result<Option<V>, E>` - Returns the deserialized value of the field or an error.
+    pub fn get_sensitive<V: for<'a> Deserialize<'a> + 'static>(
+        &self,
+        fs: &dyn FileSystem,
+        cwd: &Path,
+    ) -> Result<Option<V>, E> {
+        Field::value(&self.extract(fs, cwd)?)
     }
 
-    /// Retrieves the value of the field. Returns None of case of deserializing error.
+    /// Deserializes the value from the content of the field's file. Returns error in case of
+    /// deserializing error, if the checksum of the record doesn't match or if the value is
+    /// written with a codec or transformers (`E::EncodedRecord`; see `Codecs`).
     ///
     /// # Arguments
     ///
-    /// * None
+    /// * `content` - Content of the field's file.
     ///
     /// # Returns
     ///
-    /// * `Result<Option<V>, E>` - Returns the deserialized value of the field or an error.
-    pub fn get<V: for<'a> Deserialize<'a> + 'static>(&self) -> Result<Option<V>, E> {
-        let mut buffer = Vec::new();
-        fs::read(&self.path)?.read_to_end(&mut buffer)?;
-        bincode::deserialize::<V>(&buffer)
-            .map(|v| Some(v))
-            .or_else(|_| Ok(None))
+    /// * `Result<Option<V>, E>` - Returns the deserialized value or an error.
+    pub fn value<V: for<'a> Deserialize<'a> + 'static>(content: &[u8]) -> Result<Option<V>, E> {
+        let (header, payload) = Field::payload(content)?;
+        let tag = match header {
+            Some(header) if !header.is_plain() => return Err(E::EncodedRecord),
+            Some(header) => header.tag,
+            None => 0,
+        };
+        Ok(Some(deserialize_tagged::<V>(tag, payload)?))
     }
 
-    /// Retrieves the value of the field. Returns error in case of case of deserializing error.
+    /// Splits the content of the field's file into the header and the payload, checking the
+    /// checksum of the payload.
     ///
     /// # Arguments
     ///
-    /// * None
+    /// * `content` - Content of the field's file.
     ///
     /// # Returns
     ///
-    /// * `Result<Option<V>, E>` - Returns the deserialized value of the field or an error.
-    pub fn get_sensitive<V: for<'a> Deserialize<'a> + 'static>(&self) -> Result<Option<V>, E> {
-        let mut buffer = Vec::new();
-        fs::read(&self.path)?.read_to_end(&mut buffer)?;
-        Ok(Some(bincode::deserialize::<V>(&buffer)?))
+    /// * `Result<(Option<Header>, &[u8]), E>` - Returns the header (`None` for legacy records)
+    ///   and the payload, or an error if the header is damaged or the checksum doesn't match.
+    pub fn payload(content: &[u8]) -> Result<(Option<Header>, &[u8]), E> {
+        let (header, payload) = Header::decode(content)?;
+        if header.as_ref().is_some_and(|h| !h.is_valid(payload)) {
+            return Err(E::ChecksumMismatch);
+        }
+        Ok((header, payload))
     }
 
-    /// Sets the value of the field.
+    /// Encodes the content of the field's file: serialized value prefixed with the header.
     ///
     /// # Arguments
     ///
-    /// * `value` - A reference to the value to be stored.
+    /// * `key` - Key of the field.
+    /// * `tag` - Type tag of the value.
+    /// * `payload` - Serialized value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the content of the field's file, or an error.
+    pub fn encode(key: &str, tag: u64, payload: &[u8]) -> Result<Vec<u8>, E> {
+        Field::encode_with(Header::new(key, tag, payload), payload)
+    }
+
+    /// Encodes the content of the field's file: the payload prefixed with the given header
+    /// (e.g. with the codec of the payload, see `Header::encoded()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - Header of the payload.
+    /// * `payload` - Serialized value as it should be written.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the content of the field's file, or an error.
+    pub fn encode_with(header: Header, payload: &[u8]) -> Result<Vec<u8>, E> {
+        let mut buffer = header.encode()?;
+        buffer.extend_from_slice(payload);
+        Ok(buffer)
+    }
+
+    /// Writes the content of the field's file as it is (see `encode()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
+    /// * `tag` - Type tag of the value.
+    /// * `content` - Content of the field's file.
+    /// * `durability` - Defines whether the file is synced.
     ///
     /// # Returns
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
-    pub fn set<V: Serialize + 'static>(&self, value: &V) -> Result<(), E> {
-        let mut file = fs::create(&self.path)?;
-        let buffer = bincode::serialize(&value)?;
-        file.write_all(&buffer).map_err(|e| e.into())
+    pub fn store(
+        &mut self,
+        fs: &dyn FileSystem,
+        cwd: &Path,
+        tag: u64,
+        content: &[u8],
+        durability: Durability,
+    ) -> Result<(), E> {
+        fs.write(&self.path(cwd), content, durability)?;
+        self.meta.size = content.len() as u64;
+        self.meta.tag = tag;
+        self.meta.accessed.touch();
+        Ok(())
+    }
+
+    /// Updates the time of the last access to the field, if the previous access happened earlier
+    /// than the given resolution.
+    ///
+    /// # Arguments
+    ///
+    /// * `resolution` - Minimal difference between two recorded accesses.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the time of the last access has been updated.
+    pub fn touch(&self, resolution: Duration) -> bool {
+        self.meta.accessed.touch_after(resolution)
     }
 
     /// Extracts the binary content of the field.
     ///
+    /// # Arguments
+    ///
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
+    ///
     /// # Returns
     ///
     /// * `Result<Vec<u8>, E>` - Returns the binary content as a vector of bytes, or an error.
-    pub fn extract(&self) -> Result<Vec<u8>, E> {
-        let mut buffer: Vec<u8> = Vec::new();
-        fs::read(&self.path)?.read_to_end(&mut buffer)?;
-        Ok(buffer)
+    pub fn extract(&self, fs: &dyn FileSystem, cwd: &Path) -> Result<Vec<u8>, E> {
+        Ok(fs.read(&self.path(cwd))?)
     }
 
     /// Removes the field from the storage.
     ///
+    /// # Arguments
+    ///
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
+    ///
     /// # Returns
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
-    pub fn remove(&self) -> Result<(), E> {
-        if self.path.exists() {
-            remove_file(&self.path)?;
+    pub fn remove(&self, fs: &dyn FileSystem, cwd: &Path) -> Result<(), E> {
+        let path = self.path(cwd);
+        if fs.exists(&path) {
+            fs.remove(&path)?;
         }
         Ok(())
     }
@@ -119,22 +356,78 @@ impl Field {
     ///
     /// # Returns
     ///
-    /// * `Result<String, E>` - Returns the file name as a string, or an error.
-    pub fn file_name(&self) -> Result<String, E> {
-        Ok(self
-            .path
-            .file_name()
-            .ok_or(E::InvalidPath(self.path.clone()))?
-            .to_string_lossy()
-            .to_string())
+    /// * `&str` - Returns the file name.
+    pub fn file_name(&self) -> &str {
+        &self.file
     }
 
     /// Retrieves the size of the field in bytes.
     ///
+    /// # Arguments
+    ///
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
+    ///
     /// # Returns
     ///
     /// * `Result<u64, E>` - Returns the size of the field in bytes, or an error.
-    pub fn size(&self) -> Result<u64, E> {
-        Ok(self.path.metadata()?.len())
+    pub fn size(&self, fs: &dyn FileSystem, cwd: &Path) -> Result<u64, E> {
+        Ok(fs.size(&self.path(cwd))?)
+    }
+
+    /// Retrieves the space allocated for the field's file in bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, E>` - Returns the allocated size in bytes, or an error.
+    pub fn allocated(&self, fs: &dyn FileSystem, cwd: &Path) -> Result<u64, E> {
+        Ok(fs.allocated(&self.path(cwd))?)
+    }
+
+    /// Returns the path of the field's file.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `PathBuf` - The path of the field's file.
+    pub fn path(&self, cwd: &Path) -> PathBuf {
+        cwd.join(&self.file)
+    }
+
+    /// Checks if the field may keep a value of the type with the given tag. Only tags of types
+    /// with stable names (see `TypeName`) are compared: fields with other tags (or with unknown
+    /// type, written by previous versions of `bstorage`) match any type.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - Type tag (see `type_tag()`).
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns false if the field definitely keeps a value of another type.
+    pub fn is_of(&self, tag: u64) -> bool {
+        self.meta.tag == tag || !is_stable(tag) || !is_stable(self.meta.tag)
+    }
+
+    /// Returns the metadata of the field.
+    ///
+    /// # Returns
+    ///
+    /// * `&Meta` - A reference to the metadata of the field.
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    /// Returns mutable metadata of the field.
+    pub(crate) fn meta_mut(&mut self) -> &mut Meta {
+        &mut self.meta
     }
 }
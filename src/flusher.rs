@@ -0,0 +1,155 @@
+use log::{debug, error};
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        mpsc::{sync_channel, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{fs, Durability, E};
+
+enum Task {
+    /// Write given snapshot of the map
+    Write(Vec<u8>),
+    /// Report back as soon as all previous tasks are done
+    Flush(Sender<()>),
+}
+
+/// `Flusher` writes snapshots of the map in a background thread.
+///
+/// Because each snapshot contains the full state of the map, the thread writes only the newest
+/// snapshot available in the queue and drops older ones.
+#[derive(Debug)]
+pub struct Flusher {
+    tx: Option<SyncSender<Task>>,
+    handle: Option<JoinHandle<()>>,
+    /// The last error happened in the background thread. It's reported with the next call of
+    /// `write` or `flush`.
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl Flusher {
+    /// Spawns a background thread, which writes snapshots of the map into the given files.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - Paths to the map file and its copies; files are written in the given order.
+    /// * `queue` - Maximum number of snapshots waiting to be written.
+    /// * `durability` - Durability of each write; files are replaced atomically (see
+    ///   `fs::replace_with()`).
+    /// * `access` - Permissions of the map file and whether symlinks are followed.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns an instance of `Flusher`.
+    pub fn new(
+        paths: Vec<PathBuf>,
+        queue: usize,
+        durability: Durability,
+        access: fs::Access,
+    ) -> Self {
+        let (tx, rx) = sync_channel::<Task>(queue.max(1));
+        let error = Arc::new(Mutex::new(None));
+        let handle = thread::spawn({
+            let error = error.clone();
+            move || Flusher::run(paths, rx, durability, access, error)
+        });
+        Self {
+            tx: Some(tx),
+            handle: Some(handle),
+            error,
+        }
+    }
+
+    fn run(
+        paths: Vec<PathBuf>,
+        rx: Receiver<Task>,
+        durability: Durability,
+        access: fs::Access,
+        error: Arc<Mutex<Option<io::Error>>>,
+    ) {
+        let mut pending: Option<Vec<u8>> = None;
+        let mut waiting: Vec<Sender<()>> = Vec::new();
+        while let Ok(task) = rx.recv() {
+            let mut tasks = vec![task];
+            tasks.extend(rx.try_iter());
+            for task in tasks {
+                match task {
+                    Task::Write(buffer) => pending = Some(buffer),
+                    Task::Flush(tx) => waiting.push(tx),
+                }
+            }
+            if let Some(buffer) = pending.take() {
+                for path in paths.iter() {
+                    if let Err(err) = fs::replace_with(path, &buffer, durability, access) {
+                        error!("Fail to write map {path:?}: {err}");
+                        if let Ok(mut slot) = error.lock() {
+                            *slot = Some(err);
+                        }
+                        break;
+                    }
+                }
+            }
+            for tx in waiting.drain(..) {
+                let _ = tx.send(());
+            }
+        }
+        debug!("Map flusher for {paths:?} is stopped");
+    }
+
+    fn take_error(&self) -> Result<(), E> {
+        match self.error.lock() {
+            Ok(mut slot) => slot.take().map_or(Ok(()), |err| Err(err.into())),
+            Err(_) => Err(E::Unknown),
+        }
+    }
+
+    /// Puts a snapshot of the map into the queue. Blocks if the queue is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Serialized map.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error happened with a previous write.
+    pub fn write(&self, buffer: Vec<u8>) -> Result<(), E> {
+        self.take_error()?;
+        self.tx
+            .as_ref()
+            .ok_or(E::FlusherIsStopped)?
+            .send(Task::Write(buffer))
+            .map_err(|_| E::FlusherIsStopped)
+    }
+
+    /// Waits until all queued snapshots are written.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error happened with a previous write.
+    pub fn flush(&self) -> Result<(), E> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.tx
+            .as_ref()
+            .ok_or(E::FlusherIsStopped)?
+            .send(Task::Flush(tx))
+            .map_err(|_| E::FlusherIsStopped)?;
+        rx.recv().map_err(|_| E::FlusherIsStopped)?;
+        self.take_error()
+    }
+}
+
+impl Drop for Flusher {
+    fn drop(&mut self) {
+        // Closing the channel stops the thread as soon as all queued tasks are done
+        drop(self.tx.take());
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Map flusher thread panicked");
+            }
+        }
+    }
+}
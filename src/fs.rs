@@ -1,8 +1,82 @@
 use std::{
-    fs::{File, OpenOptions},
-    io,
+    borrow::Cow,
+    fs::{remove_file, rename, DirBuilder, File, OpenOptions},
+    io::{self, Write},
     path::{Path, PathBuf},
 };
+
+use crate::{Durability, StorageOptions, SymlinkPolicy};
+
+/// Paths of this length and longer exceed `MAX_PATH` of Windows API
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Files of this size and larger are preallocated before writing
+const PREALLOCATE_MIN: usize = 1024 * 1024;
+
+/// Names of devices, which cannot be used as file names on Windows (with any extension)
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Prepares the path for the file API of the platform. On Windows paths exceeding `MAX_PATH`
+/// (e.g. a storage deep inside a user profile) are converted into absolute paths with the `\\?\`
+/// prefix (`\\?\UNC\` for network shares), which lifts the limit. Other paths (and all paths on
+/// other platforms) are returned as they are.
+///
+/// # Arguments
+///
+/// * `path` - A path reference.
+///
+/// # Returns
+///
+/// * `Cow<Path>` - The path to pass into the file API.
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    {
+        let verbatim = path.as_os_str().to_string_lossy().starts_with(r"\\?\");
+        if let Some(absolute) = std::path::absolute(path)
+            .ok()
+            .filter(|absolute| !verbatim && absolute.as_os_str().len() >= MAX_PATH)
+        {
+            let absolute = absolute.to_string_lossy().to_string();
+            let prefixed = match absolute.strip_prefix(r"\\") {
+                Some(share) => format!(r"\\?\UNC\{share}"),
+                None => format!(r"\\?\{absolute}"),
+            };
+            return Cow::Owned(PathBuf::from(prefixed));
+        }
+    }
+    Cow::Borrowed(path)
+}
+
+/// Checks if the name can be used as a file name on all supported platforms: it isn't empty,
+/// doesn't have separators and characters forbidden on Windows, doesn't end with a dot or a space
+/// and isn't a name of a Windows device (`CON`, `NUL`, `COM1`, etc., with any extension).
+///
+/// # Arguments
+///
+/// * `name` - The name of a file or of a folder.
+///
+/// # Returns
+///
+/// * `bool` - Returns true if the name is portable.
+pub fn is_portable_name(name: &str) -> bool {
+    if name.is_empty()
+        || name.ends_with(['.', ' '])
+        || name.chars().any(|c| {
+            c.is_control() || matches!(c, '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*')
+        })
+    {
+        return false;
+    }
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    !RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
 /// Creates a new file or truncates an existing file and opens it for writing.
 ///
 /// # Arguments
@@ -13,42 +87,397 @@ use std::{
 ///
 /// * `io::Result<File>` - Returns a `File` handle if successful, or an error.
 pub fn create<P: AsRef<Path>>(filename: P) -> io::Result<File> {
-    OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(filename)
+    create_with(filename, Access::default())
 }
 
-/// Opens an existing file for reading.
+/// Defines how files of the storage are created and opened (see `StorageOptions::permissions`
+/// and `StorageOptions::symlinks`). Both settings are Unix only.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Access {
+    /// Permissions of created files; None to keep permissions defined by the system.
+    pub mode: Option<u32>,
+    /// Files are opened with `O_NOFOLLOW`, so a symlink in place of the file makes opening fail
+    /// instead of being followed.
+    pub nofollow: bool,
+}
+
+impl Access {
+    /// Returns the access to files defined by options of the storage.
+    pub fn new(options: &StorageOptions) -> Self {
+        Self {
+            mode: options.permissions.file,
+            nofollow: options.symlinks != SymlinkPolicy::Follow,
+        }
+    }
+
+    /// Applies flags of the access to options of opening of a file.
+    fn apply(&self, options: &mut OpenOptions) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            if let Some(mode) = self.mode {
+                options.mode(mode);
+            }
+            if self.nofollow {
+                options.custom_flags(libc::O_NOFOLLOW);
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = options;
+    }
+}
+
+/// Creates a new file or truncates an existing file and opens it for writing with the given
+/// access. Permissions are applied to existing files as well, regardless of umask.
+///
+/// # Arguments
+///
+/// * `filename` - A path reference to the file to be created or truncated.
+/// * `access` - Permissions of the file and whether symlinks are followed.
+///
+/// # Returns
+///
+/// * `io::Result<File>` - Returns a `File` handle if successful, or an error.
+pub fn create_with<P: AsRef<Path>>(filename: P, access: Access) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    access.apply(&mut options);
+    let file = options.open(long_path(filename.as_ref()))?;
+    #[cfg(unix)]
+    if let Some(mode) = access.mode {
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(mode))?;
+    }
+    Ok(file)
+}
+
+/// Opens an existing file for reading with the given access.
 ///
 /// # Arguments
 ///
 /// * `filename` - A path reference to the file to be opened.
+/// * `access` - Defines whether symlinks are followed.
 ///
 /// # Returns
 ///
 /// * `io::Result<File>` - Returns a `File` handle if successful, or an error.
-pub fn read<P: AsRef<Path>>(filename: P) -> io::Result<File> {
-    OpenOptions::new().read(true).open(filename)
+pub fn open_with<P: AsRef<Path>>(filename: P, access: Access) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    access.apply(&mut options);
+    options.open(long_path(filename.as_ref()))
+}
+
+/// Creates the folder and all missing parent folders with the given permissions. Permissions
+/// are applied to created folders only.
+///
+/// # Arguments
+///
+/// * `path` - A path reference to the folder.
+/// * `mode` - Unix permissions of created folders (e.g. `0o700`); ignored on other platforms.
+///   None to keep permissions defined by the system.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Returns Ok(()) if successful, or an error.
+pub fn create_dir(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    builder.create(long_path(path))
+}
+
+/// Replaces the content of the file. The content is written into a temporary file next to the
+/// given one, which is renamed over the given file. The file is never modified in place, so hard
+/// links to the previous version of the file keep it unchanged, and a failed write (e.g. no space
+/// left on device) leaves the previous version intact; the temporary file is removed.
+///
+/// # Arguments
+///
+/// * `filename` - A path reference to the file to be replaced.
+/// * `content` - New content of the file.
+/// * `durability` - Defines whether the file and its folder are synced.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Returns Ok(()) if successful, or an error.
+pub fn replace<P: AsRef<Path>>(
+    filename: P,
+    content: &[u8],
+    durability: Durability,
+) -> io::Result<()> {
+    replace_with(filename, content, durability, Access::default())
 }
 
-/// Opens a file for reading and writing, creating it if it doesn't exist.
+/// Replaces the content of the file as `replace()` does; the new version of the file is created
+/// with the given access. The file itself is replaced by renaming, which never follows symlinks.
 ///
 /// # Arguments
 ///
-/// * `filename` - A path reference to the file to be created or opened.
+/// * `filename` - A path reference to the file to be replaced.
+/// * `content` - New content of the file.
+/// * `durability` - Defines whether the file and its folder are synced.
+/// * `access` - Permissions of the file and whether symlinks are followed.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Returns Ok(()) if successful, or an error.
+pub fn replace_with<P: AsRef<Path>>(
+    filename: P,
+    content: &[u8],
+    durability: Durability,
+    access: Access,
+) -> io::Result<()> {
+    let mut tmp = filename.as_ref().as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let written = create_with(&tmp, access).and_then(|mut file| {
+        if content.len() >= PREALLOCATE_MIN {
+            preallocate(&file, content.len() as u64);
+        }
+        file.write_all(content)?;
+        match durability {
+            Durability::None => Ok(()),
+            Durability::Flush => file.flush(),
+            Durability::Fsync | Durability::FsyncDir => file.sync_all(),
+        }
+    });
+    if let Err(err) = written.and_then(|_| rename(long_path(&tmp), long_path(filename.as_ref()))) {
+        let _ = remove_file(long_path(&tmp));
+        return Err(err);
+    }
+    if durability == Durability::FsyncDir {
+        if let Some(parent) = filename.as_ref().parent() {
+            sync_dir(parent)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reserves space for the content of the file before writing, so large files are less
+/// fragmented: `posix_fallocate` on Linux, setting the length of the file (which allocates
+/// clusters) on Windows; no-op on other platforms. It's a hint, failures are ignored.
+///
+/// # Arguments
+///
+/// * `file` - The opened (empty) file.
+/// * `len` - Length of the content.
+pub fn preallocate(file: &File, len: u64) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: the descriptor is owned by `file` and valid during the call.
+        unsafe {
+            libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t);
+        }
+    }
+    #[cfg(windows)]
+    let _ = file.set_len(len);
+    #[cfg(not(any(target_os = "linux", windows)))]
+    let _ = (file, len);
+}
+
+/// Returns the space allocated on the disk for the file, which can be less than its length for
+/// sparse files and more for preallocated ones. The length of the file is returned on platforms,
+/// where the allocated space isn't known.
+///
+/// # Arguments
+///
+/// * `path` - A path reference to the file.
+///
+/// # Returns
+///
+/// * `io::Result<u64>` - Returns the allocated space in bytes, or an error.
+pub fn allocated(path: &Path) -> io::Result<u64> {
+    let metadata = long_path(path).metadata()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Blocks are counted in 512-byte units regardless of the block size of the file system
+        Ok(metadata.blocks() * 512)
+    }
+    #[cfg(not(unix))]
+    Ok(metadata.len())
+}
+
+/// Checks if the opened file is still the file of the path, i.e. it hasn't been replaced or
+/// removed since it has been opened. Files are compared by device and inode on Unix, and by the
+/// length and the time of modification on other platforms.
+///
+/// # Arguments
+///
+/// * `file` - The opened file.
+/// * `path` - A path reference to the file.
+///
+/// # Returns
+///
+/// * `io::Result<bool>` - Returns true if the path refers to the opened file, false if the file
+///   has been replaced or removed, or an error.
+pub fn is_same_file(file: &File, path: &Path) -> io::Result<bool> {
+    let current = match long_path(path).metadata() {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    let opened = file.metadata()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(opened.dev() == current.dev() && opened.ino() == current.ino())
+    }
+    #[cfg(not(unix))]
+    Ok(opened.len() == current.len() && opened.modified()? == current.modified()?)
+}
+
+/// Syncs the folder, so created, renamed and removed entries of it survive a power loss. It's a
+/// no-op on platforms, which don't allow to sync folders.
+///
+/// # Arguments
+///
+/// * `path` - A path reference to the folder.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Returns Ok(()) if successful, or an error.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(long_path(path))?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Opens an existing file for reading.
+///
+/// # Arguments
+///
+/// * `filename` - A path reference to the file to be opened.
 ///
 /// # Returns
 ///
 /// * `io::Result<File>` - Returns a `File` handle if successful, or an error.
-pub fn create_or_open<P: AsRef<Path>>(filename: P) -> io::Result<File> {
+pub fn read<P: AsRef<Path>>(filename: P) -> io::Result<File> {
     OpenOptions::new()
-        .write(true)
-        .create(true)
         .read(true)
-        .truncate(false)
-        .open(filename)
+        .open(long_path(filename.as_ref()))
+}
+
+/// Reads the whole content of the opened file with positional reads: the cursor of the handle
+/// isn't used, so the handle can be reused for the next reads.
+///
+/// # Arguments
+///
+/// * `file` - The opened file.
+///
+/// # Returns
+///
+/// * `io::Result<Vec<u8>>` - Returns the content of the file, or an error.
+pub fn read_all_at(file: &File) -> io::Result<Vec<u8>> {
+    let mut buffer = vec![0u8; file.metadata()?.len() as usize];
+    read_exact_at(file, &mut buffer, 0)?;
+    Ok(buffer)
+}
+
+/// Fills the buffer with the content of the file from the given position; the cursor of the handle
+/// isn't used, so the handle can be shared between readers.
+///
+/// # Arguments
+///
+/// * `file` - The opened file.
+/// * `buffer` - The buffer to fill.
+/// * `offset` - Position in the file.
+///
+/// # Returns
+///
+/// * `io::Result<()>` - Returns Ok(()) if the buffer is filled, or an error.
+pub fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(buffer, offset)?;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        let mut read = 0;
+        while read < buffer.len() {
+            match file.seek_read(&mut buffer[read..], offset + read as u64)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                len => read += len,
+            }
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        use std::io::{Read, Seek, SeekFrom};
+        let mut file = file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buffer)?;
+    }
+    Ok(())
+}
+
+/// Reads the whole content of many opened files. With the `io-uring` feature reads are queued
+/// into io_uring (if it's available) and submitted at once.
+///
+/// # Arguments
+///
+/// * `files` - Opened files.
+///
+/// # Returns
+///
+/// * `Vec<io::Result<Vec<u8>>>` - Contents of files in the same order.
+pub fn read_many(files: &[File]) -> Vec<io::Result<Vec<u8>>> {
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if let Some(contents) = crate::uring::read_all(files) {
+        return contents;
+    }
+    files.iter().map(read_all_at).collect()
+}
+
+/// Writes the content into each of many opened files. With the `io-uring` feature writes are
+/// queued into io_uring (if it's available) and submitted at once.
+///
+/// # Arguments
+///
+/// * `files` - Opened (empty) files and their content.
+///
+/// # Returns
+///
+/// * `Vec<io::Result<()>>` - Results of writes in the same order.
+pub fn write_many(files: &[(File, &[u8])]) -> Vec<io::Result<()>> {
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if let Some(results) = crate::uring::write_all(files) {
+        return results;
+    }
+    files
+        .iter()
+        .map(|(file, content)| (&*file).write_all(content))
+        .collect()
+}
+
+/// Hints the OS that the whole file is going to be read soon, so it can be prefetched. It's a
+/// no-op on platforms without such hints.
+///
+/// # Arguments
+///
+/// * `file` - The opened file.
+pub fn will_need(file: &File) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: the descriptor is owned by `file` and valid during the call; the hint doesn't
+        // change the file.
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = file;
 }
 
 /// Converts a path reference to a `PathBuf`.
@@ -63,3 +492,84 @@ pub fn create_or_open<P: AsRef<Path>>(filename: P) -> io::Result<File> {
 pub fn as_path_buf<P: AsRef<Path>>(path: P) -> PathBuf {
     path.as_ref().to_path_buf()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{fs, Durability, Storage, StorageManager, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn names_and_paths() -> Result<(), E> {
+        for name in [
+            "CON", "nul", "Com1.txt", "aux .log", "a:b", "a?", "a.", "a ", "",
+        ] {
+            assert!(!fs::is_portable_name(name), "{name}");
+        }
+        for name in ["console", "com10", "a.b", "profile_1"] {
+            assert!(fs::is_portable_name(name), "{name}");
+        }
+        let root = temp_dir().join(Uuid::new_v4().to_string());
+        let manager = StorageManager::new(&root)?;
+        assert!(matches!(manager.open("NUL"), Err(E::InvalidStorageName(_))));
+        // The path of the storage is longer than MAX_PATH of Windows
+        let mut cwd = root.clone();
+        while cwd.as_os_str().len() < 300 {
+            cwd = cwd.join(Uuid::new_v4().to_string());
+        }
+        let mut storage = Storage::create(&cwd)?;
+        storage.set("a", &1u8)?;
+        drop(storage);
+        let mut storage = Storage::open(&cwd)?;
+        assert_eq!(storage.get::<u8, _>("a")?, Some(1));
+        storage.destroy()?;
+        std::fs::remove_dir_all(fs::long_path(&root))?;
+        Ok(())
+    }
+
+    #[test]
+    fn durability() -> Result<(), E> {
+        for durability in [
+            Durability::None,
+            Durability::Flush,
+            Durability::Fsync,
+            Durability::FsyncDir,
+        ] {
+            let options = StorageOptions {
+                durability,
+                ..Default::default()
+            };
+            let parent = temp_dir().join(Uuid::new_v4().to_string());
+            let cwd = parent.join(Uuid::new_v4().to_string());
+            let mut storage = Storage::create_with_options(&cwd, options.clone())?;
+            storage.set("a", &1u8)?;
+            storage.set("a", &2u8)?;
+            drop(storage);
+            let mut storage = Storage::open_with_options(&cwd, options)?;
+            assert_eq!(storage.get::<u8, _>("a")?, Some(2));
+            storage.destroy()?;
+            std::fs::remove_dir_all(parent)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn preallocation() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        assert_eq!(storage.record_size("blob")?, None);
+        let blob = vec![7u8; 3 * 1024 * 1024 + 5];
+        storage.set("blob", &blob)?;
+        let size = storage.record_size("blob")?.expect("record exists");
+        assert_eq!(
+            size.logical,
+            std::fs::metadata(storage.fields.get("blob").unwrap().path(storage.cwd()))?.len()
+        );
+        assert!(size.logical > blob.len() as u64);
+        // Preallocated files aren't sparse
+        #[cfg(target_os = "linux")]
+        assert!(size.allocated >= size.logical);
+        assert_eq!(storage.get::<Vec<u8>, _>("blob")?, Some(blob));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{Storage, E};
+
+impl Storage {
+    /// Returns the generation of the storage. The generation is kept in the map and advanced by
+    /// destructive operations: `clear()`, `restore_to()`, `Storage::recover()` and unpacking a
+    /// bundle over the storage. An instance, which has been opened before such an operation done
+    /// by another instance (in another thread or process), is stale: its modifying calls fail with
+    /// `E::StaleHandle` instead of writing into the new state of the storage, and it should be
+    /// opened again.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - The generation, which this instance has read or written.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Storage, E};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let path = temp_dir().join(Uuid::new_v4().to_string());
+    /// let mut storage = Storage::create(&path).unwrap();
+    /// storage.set("key", &1u8).unwrap();
+    /// let mut stale = Storage::open(&path).unwrap();
+    /// storage.clear().unwrap();
+    /// assert_eq!(storage.generation(), stale.generation() + 1);
+    /// assert!(matches!(stale.set("key", &2u8), Err(E::StaleHandle { .. })));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn generation(&self) -> u64 {
+        self.map.generation()
+    }
+
+    /// Checks whether the storage has been changed by a destructive operation of another
+    /// instance since this instance has read it (see `Storage::generation()`).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns `E::StaleHandle` if this instance is outdated, or Ok(()).
+    pub fn check_generation(&self) -> Result<(), E> {
+        self.map.check()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bundle, Storage, E};
+    use std::{
+        env::temp_dir,
+        fs::{metadata, remove_file},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn generation() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        storage.set("a", &1u8)?;
+        assert_eq!(storage.generation(), 0);
+        let mut other = Storage::open(&storage_path)?;
+        // Regular changes don't advance the generation
+        other.set("b", &2u8)?;
+        storage.check_generation()?;
+        storage.clear()?;
+        assert_eq!(storage.generation(), 1);
+        assert!(matches!(
+            other.set("c", &3u8),
+            Err(E::StaleHandle {
+                expected: 0,
+                actual: 1
+            })
+        ));
+        assert!(matches!(other.clear(), Err(E::StaleHandle { .. })));
+        assert!(!Storage::open(&storage_path)?.has("c"));
+        // The generation survives reopening
+        drop(other);
+        let mut other = Storage::open(&storage_path)?;
+        assert_eq!(other.generation(), 1);
+        other.set("d", &4u8)?;
+        // Unpacking over the storage advances the generation as well
+        let bundle = storage_path.with_extension("bundle");
+        other.pack(&bundle)?;
+        let mut unpacked = Storage::unpack(&bundle)?;
+        let previous = unpacked.generation();
+        unpacked.set("e", &5u8)?;
+        let mut again = Storage::unpack(&bundle)?;
+        assert_eq!(again.generation(), previous + 1);
+        assert!(!again.has("e"));
+        assert!(matches!(
+            unpacked.set("f", &6u8),
+            Err(E::StaleHandle { .. })
+        ));
+        drop(unpacked);
+        again.destroy()?;
+        other.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+
+    #[test]
+    fn clear_keeps_size_of_map() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        storage.set("a", &1u8)?;
+        let mut other = Storage::open(&storage_path)?;
+        other.remove("a")?;
+        storage.check_generation()?;
+        let map = storage_path.join("map.bstorage");
+        let size = metadata(&map)?.len();
+        // The map is empty before and after clearing, so only its generation differs
+        other.clear()?;
+        assert_eq!(metadata(&map)?.len(), size);
+        assert!(matches!(
+            storage.set("b", &2u8),
+            Err(E::StaleHandle {
+                expected: 0,
+                actual: 1
+            })
+        ));
+        drop(storage);
+        other.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, DefaultHasher, Hasher},
+};
+
+use crate::{fnv1a_extend, Field, FNV1A_OFFSET};
+
+/// Keys of the storage bound to their fields
+pub(crate) type Fields = HashMap<String, Field, KeyHashing>;
+
+/// Defines the hash function of the in-memory map of keys of the storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyHasher {
+    /// SipHash with random keys chosen for each map (the hasher of `std::collections::HashMap`).
+    #[default]
+    Random,
+    /// SipHash-2-4 with the given keys. It resists collision attacks as long as the keys are
+    /// secret, which matters when keys of records are controlled by an attacker; unlike `Random`
+    /// the map is laid out the same way in each process.
+    Keyed(u64, u64),
+    /// FNV-1a, which is faster for short keys, but doesn't resist collision attacks; use it only
+    /// when keys of records are trusted.
+    Fast,
+}
+
+impl KeyHasher {
+    /// Creates an empty map of keys hashed by this hasher.
+    pub(crate) fn fields(&self) -> Fields {
+        HashMap::with_hasher(self.build())
+    }
+
+    fn build(&self) -> KeyHashing {
+        KeyHashing(match self {
+            KeyHasher::Random => State::Random(RandomState::new()),
+            KeyHasher::Keyed(k0, k1) => State::Keyed(*k0, *k1),
+            KeyHasher::Fast => State::Fast,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Random(RandomState),
+    Keyed(u64, u64),
+    Fast,
+}
+
+/// `BuildHasher` of the map of keys (see `KeyHasher`)
+#[derive(Debug, Clone)]
+pub(crate) struct KeyHashing(State);
+
+impl Default for KeyHashing {
+    fn default() -> Self {
+        KeyHasher::default().build()
+    }
+}
+
+impl BuildHasher for KeyHashing {
+    type Hasher = KeyHash;
+
+    fn build_hasher(&self) -> KeyHash {
+        match &self.0 {
+            State::Random(state) => KeyHash::Random(state.build_hasher()),
+            // `SipHasher` is deprecated in favour of `DefaultHasher`, which doesn't accept keys
+            #[allow(deprecated)]
+            State::Keyed(k0, k1) => KeyHash::Keyed(std::hash::SipHasher::new_with_keys(*k0, *k1)),
+            State::Fast => KeyHash::Fast(FNV1A_OFFSET),
+        }
+    }
+}
+
+/// `Hasher` of keys created by `KeyHashing`
+#[allow(deprecated)]
+pub(crate) enum KeyHash {
+    Random(DefaultHasher),
+    Keyed(std::hash::SipHasher),
+    Fast(u64),
+}
+
+impl Hasher for KeyHash {
+    fn finish(&self) -> u64 {
+        match self {
+            KeyHash::Random(hasher) => hasher.finish(),
+            KeyHash::Keyed(hasher) => hasher.finish(),
+            KeyHash::Fast(hash) => *hash,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            KeyHash::Random(hasher) => hasher.write(bytes),
+            KeyHash::Keyed(hasher) => hasher.write(bytes),
+            KeyHash::Fast(hash) => *hash = fnv1a_extend(*hash, bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bundle::read_index, fs, Bundle, KeyHasher, Search, Storage, StorageOptions, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn hashers() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for i in 0..50u32 {
+            storage.set(format!("key_{i:02}"), &i)?;
+        }
+        drop(storage);
+        for hasher in [KeyHasher::Random, KeyHasher::Keyed(7, 11), KeyHasher::Fast] {
+            let options = StorageOptions {
+                hasher,
+                sorted: true,
+                ..Default::default()
+            };
+            let mut storage = Storage::open_with_options(&storage_path, options)?;
+            assert_eq!(storage.len(), 50);
+            assert_eq!(storage.get::<u32, _>("key_07")?, Some(7));
+            storage.set("key_50", &50u32)?;
+            storage.remove("key_50")?;
+            // Keys are iterated in sorted order
+            let found = storage.filter::<u32, _>(|_| true)?;
+            assert_eq!(
+                found.into_iter().map(|(_, v)| v).collect::<Vec<u32>>(),
+                (0..50).collect::<Vec<u32>>()
+            );
+        }
+        // Records are packed in sorted order
+        let options = StorageOptions {
+            sorted: true,
+            ..Default::default()
+        };
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let index = read_index(&mut fs::read(&bundle)?, &bundle)?;
+        let keys = index
+            .records
+            .iter()
+            .map(|location| location.key.as_str())
+            .collect::<Vec<&str>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+}
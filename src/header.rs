@@ -0,0 +1,313 @@
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::{type_name, TypeId},
+    io::{self, Read},
+    mem,
+};
+
+use crate::{stable_tag, Codec, E};
+
+/// Marks record files with a header. Files without it are legacy (headerless) records.
+const MAGIC: [u8; 4] = [0xBA, 0x5E, 0xC0, 0xDE];
+/// Current version of the header
+const VERSION: u8 = 1;
+/// Size of magic, version and length of the header
+const PREFIX_SIZE: usize = MAGIC.len() + 1 + mem::size_of::<u32>();
+/// Maximum length of the header; a longer one is taken for damaged, so a broken length doesn't
+/// make readers allocate gigabytes
+const MAX_HEADER_SIZE: usize = 1024 * 1024;
+
+/// `Header` is written at the beginning of each record file and makes the record self-describing:
+///
+/// `MAGIC (4 bytes) | VERSION (1 byte) | length of header (u32, LE) | header (bincode) | payload`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    /// Key of the record
+    pub key: String,
+    /// Type tag of the stored value (see `type_tag()`)
+    pub tag: u64,
+    /// CRC32 of the payload
+    pub checksum: u32,
+    /// Codec of the payload (see `Codecs`)
+    pub codec: Codec,
+    /// Names of transformers of the payload in order of applying (see `Storage::transformer()`)
+    pub transformers: Vec<String>,
+}
+
+impl Header {
+    /// Creates a header for the given payload written as it is.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key of the record.
+    /// * `tag` - Type tag of the value.
+    /// * `payload` - Serialized value.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns an instance of `Header`.
+    pub fn new(key: &str, tag: u64, payload: &[u8]) -> Self {
+        Self {
+            key: key.to_owned(),
+            tag,
+            checksum: crc32fast::hash(payload),
+            codec: Codec::PLAIN,
+            transformers: Vec::new(),
+        }
+    }
+
+    /// Sets the codec and transformers, which the payload has been written with, so the record
+    /// can be read without the map.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - Codec of the payload.
+    /// * `transformers` - Names of transformers of the payload.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns the header with the codec and transformers.
+    pub fn encoded(mut self, codec: Codec, transformers: Vec<String>) -> Self {
+        self.codec = codec;
+        self.transformers = transformers;
+        self
+    }
+
+    /// Returns true if the payload is the serialized value as it is.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if the payload has no codec and transformers.
+    pub fn is_plain(&self) -> bool {
+        self.codec.is_plain() && self.transformers.is_empty()
+    }
+
+    /// Serializes the header together with the prefix (magic, version, length).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns bytes to be written before the payload, or an error.
+    pub fn encode(&self) -> Result<Vec<u8>, E> {
+        let header = bincode::serialize(self)?;
+        if header.len() > MAX_HEADER_SIZE {
+            return Err(E::InvalidHeader(format!(
+                "header is longer than {MAX_HEADER_SIZE} bytes"
+            )));
+        }
+        let mut buffer = Vec::with_capacity(PREFIX_SIZE + header.len());
+        buffer.extend_from_slice(&MAGIC);
+        buffer.push(VERSION);
+        buffer.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&header);
+        Ok(buffer)
+    }
+
+    /// Splits the content of the record file into the header and the payload. Legacy
+    /// (headerless) records are returned without header.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - Content of the record file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(Option<Header>, &[u8]), E>` - Returns the header (if it's present) and the
+    ///   payload, or `E::InvalidHeader` if the content starts with the magic, but the header is
+    ///   damaged.
+    pub fn decode(content: &[u8]) -> Result<(Option<Header>, &[u8]), E> {
+        if !content.starts_with(&MAGIC) {
+            return Ok((None, content));
+        }
+        let end = Header::decoded_len(content)?;
+        if end > content.len() {
+            return Err(E::InvalidHeader(String::from("header is truncated")));
+        }
+        let header = bincode::deserialize::<Header>(&content[PREFIX_SIZE..end])
+            .map_err(|err| E::InvalidHeader(err.to_string()))?;
+        Ok((Some(header), &content[end..]))
+    }
+
+    /// Reads the header from the beginning of the record file without reading the payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Reader of the record file.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<Option<Header>>` - Returns the header, None for legacy records, or an error
+    ///   (`io::ErrorKind::InvalidData` if the header is damaged).
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Option<Header>> {
+        let invalid = |err: E| io::Error::new(io::ErrorKind::InvalidData, err.to_string());
+        let mut prefix = [0u8; PREFIX_SIZE];
+        let mut read = 0;
+        while read < PREFIX_SIZE {
+            match reader.read(&mut prefix[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        if !prefix[..read].starts_with(&MAGIC) {
+            return Ok(None);
+        }
+        let end = Header::decoded_len(&prefix[..read]).map_err(invalid)?;
+        let mut content = prefix.to_vec();
+        content.resize(end, 0);
+        reader
+            .read_exact(&mut content[PREFIX_SIZE..])
+            .map_err(|err| {
+                if err.kind() == io::ErrorKind::UnexpectedEof {
+                    invalid(E::InvalidHeader(String::from("header is truncated")))
+                } else {
+                    err
+                }
+            })?;
+        Ok(Header::decode(&content).map_err(invalid)?.0)
+    }
+
+    /// Returns the length of the prefix and the header of the content, which starts with the
+    /// magic.
+    fn decoded_len(content: &[u8]) -> Result<usize, E> {
+        if content.len() < PREFIX_SIZE {
+            return Err(E::InvalidHeader(String::from("header is truncated")));
+        }
+        if content[MAGIC.len()] != VERSION {
+            return Err(E::InvalidHeader(format!(
+                "unsupported version {}",
+                content[MAGIC.len()]
+            )));
+        }
+        let mut len = [0u8; mem::size_of::<u32>()];
+        len.copy_from_slice(&content[MAGIC.len() + 1..PREFIX_SIZE]);
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_HEADER_SIZE {
+            return Err(E::InvalidHeader(format!(
+                "header is longer than {MAX_HEADER_SIZE} bytes ({len})"
+            )));
+        }
+        Ok(PREFIX_SIZE + len)
+    }
+
+    /// Checks the checksum of the payload.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - Serialized value.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if the checksum matches.
+    pub fn is_valid(&self, payload: &[u8]) -> bool {
+        self.checksum == crc32fast::hash(payload)
+    }
+}
+
+/// Returns the type tag of `V`: the FNV-1a hash of the stable name of the type (see `TypeName`),
+/// if it has been registered, otherwise of the name given by Rust. The name given by Rust isn't
+/// stable between versions of Rust and changes if the type is moved, so such a tag is a hint only:
+/// records must not be excluded by it.
+///
+/// # Returns
+///
+/// * `u64` - Type tag.
+pub(crate) fn type_tag<V: ?Sized + 'static>() -> u64 {
+    stable_tag(TypeId::of::<V>()).unwrap_or_else(|| fnv1a(type_name::<V>().as_bytes()))
+}
+
+/// Deserializes the value written with the type tag. The tag is a hint only (see `type_tag()`):
+/// the value written with another (or unknown) tag is deserialized as well, but its payload has
+/// to be consumed completely, so values of other types are unlikely to be taken for `V`.
+///
+/// # Arguments
+///
+/// * `tag` - Type tag of the written value.
+/// * `payload` - Serialized value.
+///
+/// # Returns
+///
+/// * `Result<V, E>` - Returns the value, or an error if it cannot be deserialized.
+pub(crate) fn deserialize_tagged<'a, V: Deserialize<'a> + 'static>(
+    tag: u64,
+    payload: &'a [u8],
+) -> Result<V, E> {
+    if tag == type_tag::<V>() {
+        return Ok(bincode::deserialize(payload)?);
+    }
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(payload)?)
+}
+
+/// Calculates the 64-bit FNV-1a hash. Unlike `DefaultHasher`, the result is stable between
+/// versions of Rust, so it can be persisted.
+///
+/// # Arguments
+///
+/// * `bytes` - Data to hash.
+///
+/// # Returns
+///
+/// * `u64` - Hash.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_extend(FNV1A_OFFSET, bytes)
+}
+
+/// Initial value of the FNV-1a hash
+pub(crate) const FNV1A_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Continues the FNV-1a hash `hash` with `bytes`.
+pub(crate) fn fnv1a_extend(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        header::{type_tag, Header, VERSION},
+        Codec, E,
+    };
+    use std::io;
+
+    #[test]
+    fn encode_decode() {
+        let payload = bincode::serialize(&String::from("value")).unwrap();
+        let header = Header::new("key", type_tag::<String>(), &payload).encoded(
+            Codec {
+                compress: true,
+                encrypt: false,
+            },
+            vec![String::from("signed")],
+        );
+        let mut content = header.encode().unwrap();
+        content.extend_from_slice(&payload);
+        let (decoded, body) = Header::decode(&content).unwrap();
+        assert_eq!(decoded.as_ref(), Some(&header));
+        assert_eq!(body, payload.as_slice());
+        assert!(header.is_valid(body));
+        assert_eq!(Header::read(&mut content.as_slice()).unwrap(), Some(header));
+        // Legacy record
+        let (decoded, body) = Header::decode(&payload).unwrap();
+        assert!(decoded.is_none());
+        assert_eq!(body, payload.as_slice());
+        assert_eq!(Header::read(&mut payload.as_slice()).unwrap(), None);
+        // Damaged header isn't taken for a legacy record
+        for damaged in [&content[..6], &content[..content.len() - payload.len() - 1]] {
+            assert!(matches!(Header::decode(damaged), Err(E::InvalidHeader(_))));
+            assert!(Header::read(&mut &damaged[..]).is_err());
+        }
+        // Damaged length of the header is rejected before anything is allocated
+        let mut huge = content.clone();
+        huge[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Header::decode(&huge), Err(E::InvalidHeader(_))));
+        assert_eq!(
+            Header::read(&mut huge.as_slice()).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        content[4] = VERSION + 1;
+        assert!(matches!(Header::decode(&content), Err(E::InvalidHeader(_))));
+    }
+}
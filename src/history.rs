@@ -0,0 +1,354 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+use crate::{
+    field::STORAGE_FILE_EXT, sensitive::Wiped, Field, FileSystem, Operation, Stamp, Storage,
+    StorageOptions, E,
+};
+
+/// Folder of the storage with the journal of changes and previous versions of records
+pub(crate) const HISTORY_DIR: &str = "history";
+const JOURNAL_FILE_NAME: &str = "journal.bstorage";
+
+/// Keeps previous versions of changed and removed records during the given window, so the
+/// storage can be restored as it was at any moment within the window (see
+/// `Storage::restore_to()`). Before a record is changed or removed, its current file is copied
+/// into the `history` folder of the storage and the change is written into the journal. Versions
+/// older than the window are removed with next changes.
+///
+/// The history isn't packed into bundles and isn't copied with the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct History {
+    /// How long previous versions are kept.
+    pub window: Duration,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Previous version of the record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Version {
+    /// Name of the file in the history folder
+    file: String,
+    /// Type tag of the value
+    tag: u64,
+}
+
+/// Change of a key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// Time of the change in milliseconds since UNIX epoch
+    at: u64,
+    key: String,
+    /// Version of the record before the change; None if the key didn't exist
+    previous: Option<Version>,
+}
+
+/// Journal of changes of the storage
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Journal {
+    /// Time in milliseconds since UNIX epoch, since which all changes are in the journal
+    since: u64,
+    entries: Vec<Entry>,
+    #[serde(skip)]
+    window: Duration,
+}
+
+impl Journal {
+    /// Reads the journal of the storage, if `StorageOptions::history` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `fs` - The file system of the storage.
+    /// * `cwd` - A path reference to the storage folder.
+    /// * `options` - Options of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Self>, E>` - Returns the journal (empty if it doesn't exist yet), None if
+    ///   the history isn't kept, or an error.
+    pub(crate) fn load(
+        fs: &dyn FileSystem,
+        cwd: &Path,
+        options: &StorageOptions,
+    ) -> Result<Option<Self>, E> {
+        let Some(history) = options.history.as_ref() else {
+            return Ok(None);
+        };
+        let path = cwd.join(HISTORY_DIR).join(JOURNAL_FILE_NAME);
+        let mut journal = if fs.exists(&path) {
+            bincode::deserialize::<Journal>(&fs.read(&path)?)?
+        } else {
+            Journal {
+                since: Stamp::millis(SystemTime::now()),
+                entries: Vec::new(),
+                window: Duration::ZERO,
+            }
+        };
+        journal.window = history.window;
+        Ok(Some(journal))
+    }
+}
+
+impl Storage {
+    fn history_dir(&self) -> PathBuf {
+        self.cwd.join(HISTORY_DIR)
+    }
+
+    /// Keeps current versions of records of keys before they are changed or removed, if
+    /// `StorageOptions::history` is set. Versions older than the window are removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Keys, which are going to be changed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub(crate) fn retain(&mut self, keys: &[&str]) -> Result<(), E> {
+        let Some(window) = self.journal.as_ref().map(|journal| journal.window) else {
+            return Ok(());
+        };
+        let dir = self.history_dir();
+        if !self.fs.exists(&dir) {
+            self.fs.create_dir_all(&dir)?;
+        }
+        let now = Stamp::millis(SystemTime::now());
+        let mut entries = Vec::new();
+        for key in keys {
+            let previous = match self.fields.get(*key) {
+                Some(field) => {
+                    // The record is kept as it is, so encrypted values stay encrypted
+                    let content = self
+                        .extract_raw(field)
+                        .map_err(|e| e.record(Operation::Read, key, &field.path(&self.cwd)))?;
+                    let version = Version {
+                        file: format!("{}.{STORAGE_FILE_EXT}", Uuid::new_v4()),
+                        tag: field.meta().tag,
+                    };
+                    self.fs
+                        .write(&dir.join(&version.file), &content, self.options.durability)?;
+                    Some(version)
+                }
+                None => None,
+            };
+            entries.push(Entry {
+                at: now,
+                key: key.to_string(),
+                previous,
+            });
+        }
+        let cutoff = now.saturating_sub(window.as_millis() as u64);
+        let Some(journal) = self.journal.as_mut() else {
+            return Ok(());
+        };
+        journal.entries.extend(entries);
+        let expired = journal.entries.partition_point(|entry| entry.at < cutoff);
+        let removed: Vec<Entry> = journal.entries.drain(..expired).collect();
+        if !removed.is_empty() {
+            journal.since = journal.since.max(cutoff);
+        }
+        let content = bincode::serialize(&*journal)?;
+        self.fs.write(
+            &dir.join(JOURNAL_FILE_NAME),
+            &content,
+            self.options.durability,
+        )?;
+        for version in removed.into_iter().filter_map(|entry| entry.previous) {
+            let path = dir.join(version.file);
+            if let Err(err) = self.fs.remove(&path) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Err(E::io(err, &path));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores the storage as it was at the given moment: keys changed since then get their
+    /// previous values back, created keys are removed, removed keys are created again. Requires
+    /// `StorageOptions::history`; the moment should be within its window. The restoring is a
+    /// change of the storage as well, so it can be undone with another `restore_to()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `moment` - The moment to restore the storage to.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, E>` - Returns the number of restored keys, `E::HistoryIsNotKept` if the
+    ///   history isn't kept, `E::OutOfHistory` if the moment is older than the kept history, or
+    ///   an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{History, Storage, StorageOptions};
+    /// use std::{env::temp_dir, thread, time::{Duration, SystemTime}};
+    /// use uuid::Uuid;
+    ///
+    /// let options = StorageOptions {
+    ///     history: Some(History::default()),
+    ///     ..Default::default()
+    /// };
+    /// let mut storage =
+    ///     Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)
+    ///         .unwrap();
+    /// storage.set("a", &1u8).unwrap();
+    /// thread::sleep(Duration::from_millis(5));
+    /// let good = SystemTime::now();
+    /// thread::sleep(Duration::from_millis(5));
+    /// storage.set("a", &2u8).unwrap();
+    /// storage.set("b", &3u8).unwrap();
+    /// assert_eq!(storage.restore_to(good).unwrap(), 2);
+    /// assert_eq!(storage.get::<u8, _>("a").unwrap(), Some(1));
+    /// assert!(!storage.has("b"));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn restore_to(&mut self, moment: SystemTime) -> Result<usize, E> {
+        let Some(journal) = self.journal.as_ref() else {
+            return Err(E::HistoryIsNotKept);
+        };
+        let at = Stamp::millis(moment);
+        if at < journal.since {
+            return Err(E::OutOfHistory);
+        }
+        let dir = self.history_dir();
+        let mut seen = HashSet::new();
+        let mut changes: HashMap<String, Option<(u64, Vec<u8>)>> = HashMap::new();
+        for entry in journal.entries.iter().filter(|entry| entry.at > at) {
+            if !seen.insert(entry.key.as_str()) {
+                continue;
+            }
+            let change = match entry.previous.as_ref() {
+                Some(version) => {
+                    let path = dir.join(&version.file);
+                    let content = self
+                        .fs
+                        .read(&path)
+                        .map_err(E::from)
+                        .and_then(|content| self.decoded(content))
+                        .map_err(|e| e.record(Operation::Read, &entry.key, &path))?;
+                    let content = Wiped(content);
+                    let (_, payload) = Field::payload(&content)
+                        .map_err(|e| e.record(Operation::Read, &entry.key, &path))?;
+                    Some((version.tag, payload.to_vec()))
+                }
+                None if self.fields.contains_key(&entry.key) => None,
+                None => continue,
+            };
+            changes.insert(entry.key.clone(), change);
+        }
+        let restored = changes.len();
+        if restored > 0 {
+            self.map.check()?;
+            self.map.advance();
+            self.apply(changes)?;
+        }
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{History, Storage, StorageOptions, E};
+    use std::{
+        env::temp_dir,
+        thread,
+        time::{Duration, SystemTime},
+    };
+    use uuid::Uuid;
+
+    fn moment() -> SystemTime {
+        thread::sleep(Duration::from_millis(5));
+        let moment = SystemTime::now();
+        thread::sleep(Duration::from_millis(5));
+        moment
+    }
+
+    #[test]
+    fn restore_to() -> Result<(), E> {
+        let cwd = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            history: Some(History::default()),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&cwd, options.clone())?;
+        storage.set("a", &1u8)?;
+        storage.set("b", &String::from("b"))?;
+        let first = moment();
+        storage.set("a", &2u8)?;
+        storage.remove("b")?;
+        storage.set("c", &3u8)?;
+        let second = moment();
+        storage.clear()?;
+        assert!(storage.is_empty());
+        // The journal survives reopening
+        drop(storage);
+        let mut storage = Storage::open_with_options(&cwd, options)?;
+        assert_eq!(storage.restore_to(second)?, 2);
+        assert_eq!(storage.get::<u8, _>("a")?, Some(2));
+        assert_eq!(storage.get::<u8, _>("c")?, Some(3));
+        assert!(!storage.has("b"));
+        let restored = moment();
+        assert_eq!(storage.restore_to(first)?, 3);
+        assert_eq!(storage.get::<u8, _>("a")?, Some(1));
+        assert_eq!(storage.get::<String, _>("b")?, Some(String::from("b")));
+        assert!(!storage.has("c"));
+        // Restoring is undone as any other change
+        assert_eq!(storage.restore_to(restored)?, 3);
+        assert_eq!(storage.get::<u8, _>("a")?, Some(2));
+        assert!(!storage.has("b"));
+        assert!(matches!(
+            storage.restore_to(SystemTime::UNIX_EPOCH),
+            Err(E::OutOfHistory)
+        ));
+        storage.destroy()?;
+        // History isn't kept by default
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        assert!(matches!(
+            storage.restore_to(first),
+            Err(E::HistoryIsNotKept)
+        ));
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn window() -> Result<(), E> {
+        let options = StorageOptions {
+            history: Some(History {
+                window: Duration::from_millis(20),
+            }),
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        let before = SystemTime::now();
+        for i in 0..5u8 {
+            storage.set("a", &i)?;
+        }
+        thread::sleep(Duration::from_millis(30));
+        storage.set("a", &5u8)?;
+        // Expired versions are removed
+        assert_eq!(
+            std::fs::read_dir(storage.cwd().join(super::HISTORY_DIR))?.count(),
+            2
+        );
+        assert!(matches!(storage.restore_to(before), Err(E::OutOfHistory)));
+        storage.destroy()?;
+        Ok(())
+    }
+}
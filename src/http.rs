@@ -0,0 +1,374 @@
+//! HTTP facade of the storage (`http` feature) for debugging and integrations:
+//!
+//! * `GET /records` - keys of records (JSON array);
+//! * `GET /records/{key}` - the value as JSON, if its type is registered with
+//!   `HttpServer::with_type()`, or the serialized value (`bincode`) otherwise;
+//! * `PUT /records/{key}?type={name}` - sets the value from JSON of the registered type;
+//!   without `type` the body is stored as the serialized value (`bincode`);
+//! * `DELETE /records/{key}` - removes the record;
+//! * `GET /bundle` - the storage packed into a stream (see `Storage::pack_to()`), which is
+//!   unpacked with `Storage::unpack_from()`.
+
+use log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    io,
+    net::ToSocketAddrs,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use crate::{type_tag, Storage, E};
+
+/// Converts the serialized value into JSON
+type ToJson = fn(&[u8]) -> Result<Vec<u8>, E>;
+/// Converts JSON into the serialized value
+type FromJson = fn(&[u8]) -> Result<Vec<u8>, E>;
+
+fn to_json<T: Serialize + DeserializeOwned>(payload: &[u8]) -> Result<Vec<u8>, E> {
+    Ok(serde_json::to_vec(&bincode::deserialize::<T>(payload)?)?)
+}
+
+fn from_json<T: Serialize + DeserializeOwned>(json: &[u8]) -> Result<Vec<u8>, E> {
+    Ok(bincode::serialize(&serde_json::from_slice::<T>(json)?)?)
+}
+
+/// Decodes `%XX` sequences of the URL segment.
+fn decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn reply<D: Into<Vec<u8>>>(status: u16, content_type: &str, data: D) -> ResponseBox {
+    let response = Response::from_data(data.into()).with_status_code(status);
+    match Header::from_bytes("Content-Type", content_type) {
+        Ok(header) => response.with_header(header).boxed(),
+        Err(()) => response.boxed(),
+    }
+}
+
+fn text(status: u16, message: &str) -> ResponseBox {
+    reply(status, "text/plain; charset=utf-8", message)
+}
+
+/// `HttpServer` exposes the storage over HTTP (see the module documentation). Values are
+/// transcoded to and from JSON for types registered with `with_type()`. Requests are served one
+/// by one.
+pub struct HttpServer {
+    storage: Arc<Mutex<Storage>>,
+    /// Converters of registered types by type tags
+    types: HashMap<u64, (ToJson, FromJson)>,
+    /// Type tags by names of registered types
+    names: HashMap<String, u64>,
+}
+
+impl HttpServer {
+    /// Creates the server of the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The served storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The server.
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage: Arc::new(Mutex::new(storage)),
+            types: HashMap::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    /// Registers the type of values, which are transcoded to and from JSON.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the type used in `PUT /records/{key}?type={name}`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The server.
+    pub fn with_type<T: Serialize + DeserializeOwned + 'static>(mut self, name: &str) -> Self {
+        let tag = type_tag::<T>();
+        self.types.insert(tag, (to_json::<T>, from_json::<T>));
+        self.names.insert(name.to_owned(), tag);
+        self
+    }
+
+    /// Gives access to the served storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<MutexGuard<Storage>, E>` - The storage, or an error if it's poisoned.
+    pub fn storage(&self) -> Result<MutexGuard<'_, Storage>, E> {
+        self.storage.lock().map_err(|_| E::Unknown)
+    }
+
+    /// Listens to the address and serves requests until an error of the listener.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to listen to.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns an error of the listener.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use bstorage::{HttpServer, Storage};
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Settings {
+    ///     theme: String,
+    /// }
+    ///
+    /// let storage = Storage::open("./data").unwrap();
+    /// // $ curl http://127.0.0.1:8080/records/settings
+    /// HttpServer::new(storage)
+    ///     .with_type::<Settings>("settings")
+    ///     .serve("127.0.0.1:8080")
+    ///     .unwrap();
+    /// ```
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> Result<(), E> {
+        let server = Server::http(addr).map_err(io::Error::other)?;
+        self.run(&server)
+    }
+
+    /// Serves requests of the running `tiny_http` server until an error of the server. Errors
+    /// of particular connections (e.g. the client has gone) are logged and don't stop the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `server` - The server.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns an error of the server.
+    pub fn run(&self, server: &Server) -> Result<(), E> {
+        loop {
+            if let Err(err) = self.handle(server.recv()?) {
+                warn!("Fail to respond to HTTP request: {err}");
+            }
+        }
+    }
+
+    /// Serves one request.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if the response is sent, or an error of the connection.
+    pub fn handle(&self, mut request: Request) -> Result<(), E> {
+        let response = match self.respond(&mut request) {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(
+                    "HTTP request {} {} failed: {err}",
+                    request.method(),
+                    request.url()
+                );
+                text(500, &err.to_string())
+            }
+        };
+        request.respond(response)?;
+        Ok(())
+    }
+
+    fn respond(&self, request: &mut Request) -> Result<ResponseBox, E> {
+        let url = request.url().to_owned();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments = path
+            .trim_start_matches('/')
+            .splitn(2, '/')
+            .collect::<Vec<&str>>();
+        match (request.method(), segments.as_slice()) {
+            (Method::Get, ["records"]) => {
+                let keys = self
+                    .storage()?
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<String>>();
+                Ok(reply(200, "application/json", serde_json::to_vec(&keys)?))
+            }
+            (Method::Get, ["bundle"]) => self.bundle(),
+            (method, ["records", key]) => {
+                let Some(key) = decode(key) else {
+                    return Ok(text(400, "Invalid key"));
+                };
+                match method {
+                    Method::Get => self.get(&key),
+                    Method::Put => {
+                        let name = query
+                            .split('&')
+                            .find_map(|pair| pair.strip_prefix("type="))
+                            .and_then(decode);
+                        let mut body = Vec::new();
+                        request.as_reader().read_to_end(&mut body)?;
+                        self.put(&key, name, &body)
+                    }
+                    Method::Delete => Ok(if self.storage()?.remove(&key)? {
+                        text(204, "")
+                    } else {
+                        text(404, "Record not found")
+                    }),
+                    _ => Ok(text(405, "Method not allowed")),
+                }
+            }
+            _ => Ok(text(404, "Not found")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<ResponseBox, E> {
+        let storage = self.storage()?;
+        let Some(guard) = storage.get_ref(key)? else {
+            return Ok(text(404, "Record not found"));
+        };
+        let tag = storage.fields.get(key).map(|f| f.meta().tag);
+        Ok(match tag.and_then(|tag| self.types.get(&tag)) {
+            Some((to_json, _)) => reply(200, "application/json", to_json(guard.bytes())?),
+            None => reply(200, "application/octet-stream", guard.bytes()),
+        })
+    }
+
+    fn put(&self, key: &str, name: Option<String>, body: &[u8]) -> Result<ResponseBox, E> {
+        let (tag, payload) = match name {
+            Some(name) => {
+                let Some(tag) = self.names.get(&name) else {
+                    return Ok(text(415, &format!("Unknown type \"{name}\"")));
+                };
+                let Some((_, from_json)) = self.types.get(tag) else {
+                    return Ok(text(415, &format!("Unknown type \"{name}\"")));
+                };
+                match from_json(body) {
+                    Ok(payload) => (*tag, payload),
+                    Err(err) => return Ok(text(400, &err.to_string())),
+                }
+            }
+            None => (0, body.to_vec()),
+        };
+        self.storage()?.set_bytes(key, tag, &payload)?;
+        Ok(text(204, ""))
+    }
+
+    fn bundle(&self) -> Result<ResponseBox, E> {
+        let mut stream = Vec::new();
+        self.storage()?.pack_to(&mut stream)?;
+        Ok(reply(200, "application/octet-stream", stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HttpServer, Storage, E};
+    use serde::{Deserialize, Serialize};
+    use std::{
+        env::temp_dir,
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        sync::Arc,
+        thread,
+    };
+    use uuid::Uuid;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Settings {
+        theme: String,
+        volume: u8,
+    }
+
+    /// Sends the request and returns the status code and the body of the response.
+    fn call(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).expect("Connected");
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .expect("Request is sent");
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).expect("Response is read");
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("Headers are read");
+        let status = String::from_utf8_lossy(&response[9..12])
+            .parse()
+            .expect("Status code");
+        (status, response[split + 4..].to_vec())
+    }
+
+    #[test]
+    fn http() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("raw", &7u32)?;
+        let http = Arc::new(HttpServer::new(storage).with_type::<Settings>("settings"));
+        let server = tiny_http::Server::http("127.0.0.1:0").expect("Server is started");
+        let addr = server.server_addr().to_ip().expect("TCP address");
+        let serving = http.clone();
+        let handle = thread::spawn(move || -> Result<(), E> {
+            for _ in 0..8 {
+                serving.handle(server.recv()?)?;
+            }
+            Ok(())
+        });
+        let (status, _) = call(
+            addr,
+            "PUT",
+            "/records/my%20settings?type=settings",
+            r#"{"theme":"dark","volume":3}"#,
+        );
+        assert_eq!(status, 204);
+        let (status, body) = call(addr, "GET", "/records/my%20settings", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            serde_json::from_slice::<Settings>(&body)?,
+            Settings {
+                theme: String::from("dark"),
+                volume: 3
+            }
+        );
+        let (status, body) = call(addr, "GET", "/records/raw", "");
+        assert_eq!((status, bincode::deserialize::<u32>(&body)?), (200, 7));
+        assert_eq!(call(addr, "PUT", "/records/x?type=unknown", "{}").0, 415);
+        let (status, body) = call(addr, "GET", "/records", "");
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_slice::<Vec<String>>(&body)?.len(), 2);
+        assert_eq!(call(addr, "DELETE", "/records/raw", "").0, 204);
+        assert_eq!(call(addr, "GET", "/records/raw", "").0, 404);
+        let (status, body) = call(addr, "GET", "/bundle", "");
+        assert_eq!(status, 200);
+        let mut unpacked =
+            Storage::unpack_from(body.as_slice(), temp_dir().join(Uuid::new_v4().to_string()))?;
+        assert_eq!(unpacked.len(), 1);
+        unpacked.destroy()?;
+        handle.join().expect("Server thread")?;
+        assert_eq!(
+            http.storage()?
+                .get::<Settings, _>("my settings")?
+                .map(|s| s.volume),
+            Some(3)
+        );
+        http.storage()?.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! Importers of data from other key-value stores (`sled`, `redb` and `json` features).
+
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{type_tag, Storage, E};
+
+/// Number of records written with one write of the map during import.
+const IMPORT_CHUNK: usize = 1024;
+
+/// Creates the storage and writes imported records into it in chunks, so the source isn't loaded
+/// into memory completely.
+fn import<P: AsRef<Path>, V: Serialize + 'static, I: Iterator<Item = Result<(String, V), E>>>(
+    cwd: P,
+    records: I,
+) -> Result<Storage, E> {
+    let mut storage = Storage::create(cwd)?;
+    let tag = type_tag::<V>();
+    let mut chunk = Vec::with_capacity(IMPORT_CHUNK);
+    for record in records {
+        let (key, value) = record?;
+        chunk.push((key, Some((tag, bincode::serialize(&value)?))));
+        if chunk.len() >= IMPORT_CHUNK {
+            storage.apply(chunk.drain(..))?;
+        }
+    }
+    storage.apply(chunk)?;
+    Ok(storage)
+}
+
+/// Converts a key of the source store into a string key.
+#[cfg(feature = "sled")]
+fn utf8_key(key: &[u8]) -> Result<String, E> {
+    String::from_utf8(key.to_vec()).map_err(|_| E::InvalidKey(key.to_vec()))
+}
+
+impl Storage {
+    /// Copies all records of the `sled` tree (or database) into a new storage. Keys should be valid
+    /// UTF-8; values are converted with `decode`, e.g. `|bytes| Ok(bytes.to_vec())` keeps raw bytes
+    /// (read them back with `get::<Vec<u8>>()`), and
+    /// `|bytes| Ok(bincode::deserialize::<MyType>(bytes)?)` keeps typed values.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `tree` - A reference to the source tree.
+    /// * `decode` - Converts the value of the source into the stored value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with imported records, or an error.
+    #[cfg(feature = "sled")]
+    pub fn import_from_sled<
+        P: AsRef<Path>,
+        V: Serialize + 'static,
+        F: FnMut(&[u8]) -> Result<V, E>,
+    >(
+        cwd: P,
+        tree: &sled::Tree,
+        mut decode: F,
+    ) -> Result<Self, E> {
+        import(
+            cwd,
+            tree.iter().map(|record| {
+                let (key, value) = record.map_err(|e| E::Import(e.to_string()))?;
+                Ok((utf8_key(&key)?, decode(&value)?))
+            }),
+        )
+    }
+
+    /// Copies all records of the `redb` table into a new storage. Records are converted into keys
+    /// and stored values with `convert`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `db` - A reference to the source database.
+    /// * `table` - Definition of the source table.
+    /// * `convert` - Converts the key and the value of the source into the key and the stored
+    ///   value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with imported records, or an error.
+    #[cfg(feature = "redb")]
+    pub fn import_from_redb<P, K, V, T, F>(
+        cwd: P,
+        db: &redb::Database,
+        table: redb::TableDefinition<K, V>,
+        mut convert: F,
+    ) -> Result<Self, E>
+    where
+        P: AsRef<Path>,
+        K: redb::Key + 'static,
+        V: redb::Value + 'static,
+        T: Serialize + 'static,
+        F: for<'a> FnMut(K::SelfType<'a>, V::SelfType<'a>) -> Result<(String, T), E>,
+    {
+        use redb::ReadableTable;
+        let err = |e: &dyn std::fmt::Display| E::Import(e.to_string());
+        let tx = db.begin_read().map_err(|e| err(&e))?;
+        let table = tx.open_table(table).map_err(|e| err(&e))?;
+        let records = table.iter().map_err(|e| err(&e))?;
+        import(
+            cwd,
+            records.map(|record| {
+                let (key, value) = record.map_err(|e| err(&e))?;
+                convert(key.value(), value.value())
+            }),
+        )
+    }
+
+    /// Copies JSON files of the folder into a new storage: the file name without the `.json`
+    /// extension becomes the key and the content is deserialized into `V`. Other files and nested
+    /// folders are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `dir` - A path reference to the folder with JSON files.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with imported records, or an error.
+    #[cfg(feature = "json")]
+    pub fn import_from_json_dir<
+        P: AsRef<Path>,
+        D: AsRef<Path>,
+        V: serde::de::DeserializeOwned + Serialize + 'static,
+    >(
+        cwd: P,
+        dir: D,
+    ) -> Result<Self, E> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "json") {
+                files.push(path);
+            }
+        }
+        import(
+            cwd,
+            files.into_iter().map(|path| {
+                let key = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .ok_or_else(|| E::InvalidPath(path.clone()))?
+                    .to_owned();
+                let value: V =
+                    serde_json::from_reader(std::io::BufReader::new(crate::fs::read(&path)?))
+                        .map_err(|e| E::Import(format!("{}: {e}", path.display())))?;
+                Ok((key, value))
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::E;
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn sled() -> Result<(), E> {
+        use crate::Storage;
+        use std::env::temp_dir;
+        use uuid::Uuid;
+
+        let db = sled::Config::new().temporary(true).open().expect("sled db");
+        for i in 0..3000u32 {
+            db.insert(format!("key_{i}"), i.to_le_bytes().to_vec())
+                .expect("insert");
+        }
+        let mut storage =
+            Storage::import_from_sled(temp_dir().join(Uuid::new_v4().to_string()), &db, |v| {
+                Ok(v.to_vec())
+            })?;
+        assert_eq!(storage.len(), 3000);
+        assert_eq!(
+            storage.get::<Vec<u8>, _>("key_7")?,
+            Some(7u32.to_le_bytes().to_vec())
+        );
+        storage.destroy()?;
+        db.insert(vec![0xff, 0xfe], vec![0u8]).expect("insert");
+        let path = temp_dir().join(Uuid::new_v4().to_string());
+        assert!(matches!(
+            Storage::import_from_sled(&path, &db, |v| Ok(v.to_vec())),
+            Err(E::InvalidKey(_))
+        ));
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn redb() -> Result<(), E> {
+        use crate::Storage;
+        use redb::{Database, TableDefinition};
+        use std::env::temp_dir;
+        use uuid::Uuid;
+
+        const TABLE: TableDefinition<&str, u64> = TableDefinition::new("numbers");
+        let path = temp_dir().join(format!("{}.redb", Uuid::new_v4()));
+        let db = Database::create(&path).expect("redb db");
+        let tx = db.begin_write().expect("tx");
+        {
+            let mut table = tx.open_table(TABLE).expect("table");
+            for i in 0..10u64 {
+                table
+                    .insert(format!("key_{i}").as_str(), i)
+                    .expect("insert");
+            }
+        }
+        tx.commit().expect("commit");
+        let mut storage = Storage::import_from_redb(
+            temp_dir().join(Uuid::new_v4().to_string()),
+            &db,
+            TABLE,
+            |key, value| Ok((key.to_owned(), value)),
+        )?;
+        assert_eq!(storage.len(), 10);
+        assert_eq!(storage.get::<u64, _>("key_3")?, Some(3));
+        storage.destroy()?;
+        drop(db);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() -> Result<(), E> {
+        use crate::Storage;
+        use serde::{Deserialize, Serialize};
+        use std::env::temp_dir;
+        use uuid::Uuid;
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct User {
+            name: String,
+            age: u8,
+        }
+        let dir = temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir)?;
+        std::fs::write(dir.join("alice.json"), r#"{"name":"Alice","age":30}"#)?;
+        std::fs::write(dir.join("bob.json"), r#"{"name":"Bob","age":25}"#)?;
+        std::fs::write(dir.join("notes.txt"), "ignored")?;
+        let mut storage = Storage::import_from_json_dir::<_, _, User>(
+            temp_dir().join(Uuid::new_v4().to_string()),
+            &dir,
+        )?;
+        assert_eq!(storage.len(), 2);
+        assert_eq!(
+            storage.get::<User, _>("alice")?,
+            Some(User {
+                name: String::from("Alice"),
+                age: 30
+            })
+        );
+        storage.destroy()?;
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{fs, nested::CHILDREN_DIR, Limits, Storage, E};
+
+/// Name of the child storage, which keeps internal areas of the storage. It cannot be opened
+/// with `Storage::child()` and isn't listed by `Storage::children()`.
+pub(crate) const INTERNAL_NAME: &str = "__internal";
+
+impl Storage {
+    /// Opens the internal area of the storage, creating it if it doesn't exist. Internal areas
+    /// keep records of the crate and of extensions (indexes, tables of expiry times, counters,
+    /// audit logs, etc.) apart from records of the application: they are isolated keyspaces,
+    /// which aren't visible to `get`, `has`, iteration and search of the storage and cannot
+    /// collide with its keys.
+    ///
+    /// Internal areas are packed, copied and cleared together with the storage (as child storages
+    /// are). An area is opened with the options of this storage, except `limits` and `eviction`,
+    /// so internal records neither count against limits of the application nor get evicted.
+    ///
+    /// # Arguments
+    ///
+    /// * `area` - Name of the area; each extension should use its own.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the storage of the area, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("counter", &1u32).unwrap();
+    /// let mut counters = storage.internal("counters").unwrap();
+    /// counters.set("counter", &10u32).unwrap();
+    /// assert_eq!(storage.get::<u32, _>("counter").unwrap(), Some(1));
+    /// assert_eq!(storage.len(), 1);
+    /// drop(counters);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn internal<N: AsRef<str>>(&self, area: N) -> Result<Storage, E> {
+        let area = area.as_ref();
+        if !fs::is_portable_name(area) {
+            return Err(E::InvalidStorageName(area.to_owned()));
+        }
+        let cwd = self
+            .cwd
+            .join(CHILDREN_DIR)
+            .join(INTERNAL_NAME)
+            .join(CHILDREN_DIR)
+            .join(area);
+        if !cwd.exists() {
+            fs::create_dir(&cwd, self.options.permissions.dir)?;
+        }
+        let mut options = self.options.clone();
+        options.limits = Limits::default();
+        options.eviction = None;
+        Storage::open_with_options(cwd, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{internal::INTERNAL_NAME, Bundle, Search, Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn internal() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("key", &1u32)?;
+        storage.child("plugins")?.set("key", &2u32)?;
+        let mut indexes = storage.internal("indexes")?;
+        indexes.set("key", &3u32)?;
+        drop(indexes);
+        // Internal records aren't visible to the storage
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.get::<u32, _>("key")?, Some(1));
+        assert_eq!(storage.filter(|_: &u32| true)?.len(), 1);
+        assert_eq!(storage.children()?, vec![String::from("plugins")]);
+        assert!(storage.child(INTERNAL_NAME).is_err());
+        assert!(!storage.has_child(INTERNAL_NAME));
+        assert!(storage.remove_child(INTERNAL_NAME).is_err());
+        assert!(storage.internal("../indexes").is_err());
+        // Internal areas are packed with the storage
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        let mut unpacked = Storage::unpack(&bundle)?;
+        assert_eq!(unpacked.children()?, vec![String::from("plugins")]);
+        assert_eq!(unpacked.internal("indexes")?.get::<u32, _>("key")?, Some(3));
+        unpacked.destroy()?;
+        // and cleared with it
+        storage.clear()?;
+        assert!(storage.internal("indexes")?.is_empty());
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use std::borrow::Cow;
+
+/// `StorageKey` is a key of a record. It's implemented for all string types (`&str`, `String`,
+/// etc.); applications can implement it (or derive it with the `derive` feature) for their own
+/// key types to keep formatting of keys in one place and let the compiler catch keys of a wrong
+/// domain.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{Storage, StorageKey};
+/// use std::{borrow::Cow, env::temp_dir};
+/// use uuid::Uuid;
+///
+/// struct UserId(u64);
+///
+/// impl StorageKey for UserId {
+///     fn to_key(&self) -> Cow<'_, str> {
+///         Cow::Owned(format!("user:{}", self.0))
+///     }
+/// }
+///
+/// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// storage.set(UserId(1), &String::from("Alice")).unwrap();
+/// assert!(storage.has("user:1"));
+/// assert_eq!(
+///     storage.get::<String, _>(UserId(1)).unwrap(),
+///     Some(String::from("Alice"))
+/// );
+/// storage.destroy().unwrap();
+/// ```
+pub trait StorageKey {
+    /// Returns the key as a string.
+    fn to_key(&self) -> Cow<'_, str>;
+}
+
+impl<T: AsRef<str> + ?Sized> StorageKey for T {
+    fn to_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use crate::{Storage, StorageKey, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[derive(StorageKey)]
+    #[storage_key(prefix = "user:")]
+    struct UserId(u64);
+
+    #[derive(StorageKey)]
+    #[storage_key(prefix = "settings/")]
+    enum Settings {
+        #[storage_key(rename = "theme")]
+        Theme,
+        Plugin(String),
+    }
+
+    #[test]
+    fn derive() -> Result<(), E> {
+        assert_eq!(UserId(1).to_key(), "user:1");
+        assert_eq!(Settings::Theme.to_key(), "settings/theme");
+        assert_eq!(
+            Settings::Plugin(String::from("foo")).to_key(),
+            "settings/Plugin:foo"
+        );
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set(Settings::Theme, &String::from("dark"))?;
+        storage.set(UserId(1), &String::from("Alice"))?;
+        assert_eq!(
+            storage.get::<String, _>(Settings::Theme)?,
+            Some(String::from("dark"))
+        );
+        assert!(storage.has("user:1"));
+        assert!(storage.remove(UserId(1))?);
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+//! Storage on top of an asynchronous key-value store, for targets without a file system, e.g.
+//! `wasm32` in the browser, where records are kept in IndexedDB. Records are encoded in the same
+//! way as record files of `Storage` (header with the key, the type tag and the checksum, followed
+//! by the serialized value).
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, future::Future, sync::Mutex};
+
+use crate::{type_tag, Field, StorageKey, E};
+
+/// `KvBackend` is an asynchronous key-value store, which keeps encoded records. Applications
+/// implement it on top of the store of their platform (e.g. an object store of IndexedDB).
+pub trait KvBackend {
+    /// Returns the record by its key, or None if it doesn't exist.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, E>>;
+
+    /// Writes the record, replacing the previous one.
+    fn put(&self, key: &str, record: Vec<u8>) -> impl Future<Output = Result<(), E>>;
+
+    /// Removes the record; returns true if it existed.
+    fn delete(&self, key: &str) -> impl Future<Output = Result<bool, E>>;
+
+    /// Returns keys of all records.
+    fn keys(&self) -> impl Future<Output = Result<Vec<String>, E>>;
+}
+
+/// `MemoryKv` keeps records in memory. It's useful for tests and as a reference implementation of
+/// `KvBackend`.
+#[derive(Debug, Default)]
+pub struct MemoryKv {
+    records: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl KvBackend for MemoryKv {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, E> {
+        Ok(self
+            .records
+            .lock()
+            .map_err(|_| E::Unknown)?
+            .get(key)
+            .cloned())
+    }
+
+    async fn put(&self, key: &str, record: Vec<u8>) -> Result<(), E> {
+        self.records
+            .lock()
+            .map_err(|_| E::Unknown)?
+            .insert(key.to_owned(), record);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, E> {
+        Ok(self
+            .records
+            .lock()
+            .map_err(|_| E::Unknown)?
+            .remove(key)
+            .is_some())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>, E> {
+        Ok(self
+            .records
+            .lock()
+            .map_err(|_| E::Unknown)?
+            .keys()
+            .cloned()
+            .collect())
+    }
+}
+
+/// `KvStorage` provides the API of `Storage` (`get`, `set`, `remove`, `has`) on top of a
+/// `KvBackend`. Methods are asynchronous, because stores of the browser are.
+#[derive(Debug)]
+pub struct KvStorage<B: KvBackend> {
+    backend: B,
+}
+
+impl<B: KvBackend> KvStorage<B> {
+    /// Creates the storage on top of the backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The key-value store.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The storage.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{KvStorage, MemoryKv};
+    ///
+    /// # futures::executor::block_on(async {
+    /// let mut storage = KvStorage::new(MemoryKv::default());
+    /// storage.set("theme", &String::from("dark")).await.unwrap();
+    /// assert_eq!(
+    ///     storage.get::<String, _>("theme").await.unwrap(),
+    ///     Some(String::from("dark"))
+    /// );
+    /// # });
+    /// ```
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+
+    /// Returns the backend of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `&B` - The backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Checks if the specified key exists in the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key exists, false otherwise, or an error.
+    pub async fn has<K: StorageKey>(&self, key: K) -> Result<bool, E> {
+        Ok(self.backend.get(&key.to_key()).await?.is_some())
+    }
+
+    /// Retrieves a value by key. Returns an error if the record cannot be deserialized or its
+    /// checksum doesn't match.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub async fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        match self.backend.get(&key.to_key()).await? {
+            Some(record) => Field::value::<V>(&record),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets a value for the specified key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `value` - A reference to the value to be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub async fn set<V: Serialize + 'static, K: StorageKey>(
+        &mut self,
+        key: K,
+        value: &V,
+    ) -> Result<(), E> {
+        let key = key.to_key();
+        let record = Field::encode(&key, type_tag::<V>(), &bincode::serialize(value)?)?;
+        self.backend.put(&key, record).await
+    }
+
+    /// Removes the value associated with the specified key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key was found and removed, false otherwise, or an error.
+    pub async fn remove<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        self.backend.delete(&key.to_key()).await
+    }
+
+    /// Returns keys of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns keys, or an error.
+    pub async fn keys(&self) -> Result<Vec<String>, E> {
+        self.backend.keys().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KvBackend, KvStorage, MemoryKv, E};
+    use futures::executor::block_on;
+
+    #[test]
+    fn kv() -> Result<(), E> {
+        block_on(async {
+            let mut storage = KvStorage::new(MemoryKv::default());
+            for i in 0..10u32 {
+                storage.set(format!("key_{i}"), &i).await?;
+            }
+            assert_eq!(storage.get::<u32, _>("key_3").await?, Some(3));
+            assert!(storage.has("key_9").await?);
+            assert!(storage.remove("key_9").await?);
+            assert!(!storage.remove("key_9").await?);
+            assert_eq!(storage.keys().await?.len(), 9);
+            // Records are checked
+            let mut record = storage
+                .backend()
+                .get("key_1")
+                .await?
+                .expect("Record exists");
+            let last = record.len() - 1;
+            record[last] ^= 0xff;
+            storage.backend().put("key_1", record).await?;
+            assert!(matches!(
+                storage.get::<u32, _>("key_1").await,
+                Err(E::ChecksumMismatch)
+            ));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,237 @@
+use serde::Deserialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{fs, Bloom, FlushMode, Map, Operation, StdFs, Storage, StorageKey, StorageOptions, E};
+
+/// `LazyStorage` is a storage, which map isn't read until it's really needed. Reading a single key
+/// looks it up in the map file without building the full map; the bloom filter of keys (see
+/// `StorageOptions::bloom`) answers for absent keys without touching the map at all. The full map is
+/// loaded with the first call of `storage()`, which is required for modifying calls.
+///
+/// It's useful for short-living processes (e.g. CLI tools), which usually touch a few keys of a huge
+/// storage.
+#[derive(Debug)]
+pub struct LazyStorage {
+    cwd: PathBuf,
+    options: StorageOptions,
+    /// Reader of the map used before the storage is loaded
+    map: Map,
+    bloom: Option<Bloom>,
+    storage: OnceLock<Storage>,
+}
+
+impl Storage {
+    /// Opens an existing storage lazily (see `LazyStorage`).
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<LazyStorage, E>` - Returns the lazily opened storage or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{BloomOptions, Storage, StorageOptions};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+    /// let options = StorageOptions {
+    ///     bloom: Some(BloomOptions::default()),
+    ///     ..Default::default()
+    /// };
+    /// let mut storage = Storage::create_with_options(&storage_path, options.clone()).unwrap();
+    /// storage.set("my_record", &1u8).unwrap();
+    /// drop(storage);
+    /// let mut storage = Storage::open_lazy_with_options(&storage_path, options).unwrap();
+    /// assert_eq!(storage.get::<u8, &str>("my_record").unwrap(), Some(1));
+    /// assert!(!storage.has("unknown").unwrap());
+    /// assert!(!storage.is_loaded());
+    /// storage.storage().unwrap().set("other", &2u8).unwrap();
+    /// assert!(storage.is_loaded());
+    /// storage.storage().unwrap().destroy().unwrap();
+    /// ```
+    pub fn open_lazy<P: AsRef<Path>>(cwd: P) -> Result<LazyStorage, E> {
+        Storage::open_lazy_with_options(cwd, StorageOptions::default())
+    }
+
+    /// Opens an existing storage lazily (see `LazyStorage`) with the given options.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `options` - Options of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<LazyStorage, E>` - Returns the lazily opened storage or an error.
+    pub fn open_lazy_with_options<P: AsRef<Path>>(
+        cwd: P,
+        options: StorageOptions,
+    ) -> Result<LazyStorage, E> {
+        if !cwd.as_ref().is_dir() {
+            return Err(E::PathIsNotFolder(fs::as_path_buf(cwd)));
+        }
+        let bloom = if options.bloom.is_some() {
+            Bloom::read(cwd.as_ref())?
+        } else {
+            None
+        };
+        Ok(LazyStorage {
+            cwd: fs::as_path_buf(&cwd),
+            // The map is only read here, so it doesn't need the background flusher
+            map: Map::new(
+                &cwd,
+                &StorageOptions {
+                    flush: FlushMode::Sync,
+                    ..options.clone()
+                },
+            ),
+            options,
+            bloom,
+            storage: OnceLock::new(),
+        })
+    }
+}
+
+impl LazyStorage {
+    /// Checks if the specified key exists in the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key exists, false otherwise, or an error.
+    pub fn has<K: StorageKey>(&self, key: K) -> Result<bool, E> {
+        let key = key.to_key();
+        if let Some(storage) = self.storage.get() {
+            return Ok(storage.has(key));
+        }
+        if self
+            .bloom
+            .as_ref()
+            .is_some_and(|b| !b.contains(key.as_ref()))
+        {
+            return Ok(false);
+        }
+        Ok(self.map.lookup(key.as_ref())?.is_some())
+    }
+
+    /// Retrieves a value associated with the specified key. Returns an error if the record cannot
+    /// be read or deserialized.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        let key = key.to_key();
+        if let Some(storage) = self.storage.get() {
+            return storage.get_sensitive(key);
+        }
+        if self
+            .bloom
+            .as_ref()
+            .is_some_and(|b| !b.contains(key.as_ref()))
+        {
+            return Ok(None);
+        }
+        let Some(field) = self.map.lookup(key.as_ref())? else {
+            return Ok(None);
+        };
+        field
+            .get_sensitive::<V>(&StdFs, &self.cwd)
+            .map_err(|e| e.record(Operation::Get, key, &field.path(&self.cwd)))
+    }
+
+    /// Returns true if the full map has been loaded.
+    pub fn is_loaded(&self) -> bool {
+        self.storage.get().is_some()
+    }
+
+    /// Loads the full map (if it isn't loaded yet) and returns the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<&mut Storage, E>` - Returns the storage or an error.
+    pub fn storage(&mut self) -> Result<&mut Storage, E> {
+        if self.storage.get().is_none() {
+            let storage = Storage::open_with_options(&self.cwd, self.options.clone())?;
+            let _ = self.storage.set(storage);
+        }
+        self.storage.get_mut().ok_or(E::Unknown)
+    }
+
+    /// Loads the full map (if it isn't loaded yet) and returns the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the storage or an error.
+    pub fn into_storage(mut self) -> Result<Storage, E> {
+        self.storage()?;
+        self.storage.take().ok_or(E::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, StorageOptions, E, MAP_FILE_NAME};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn lazy() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for i in 0..100u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        drop(storage);
+        let storage = Storage::open_lazy(&storage_path)?;
+        assert_eq!(storage.get::<u32, &str>("42")?, Some(42));
+        assert!(storage.has("99")?);
+        assert!(!storage.has("100")?);
+        assert!(storage.get::<u32, &str>("100")?.is_none());
+        assert!(!storage.is_loaded());
+        let mut storage = storage.into_storage()?;
+        assert_eq!(storage.len(), 100);
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn lazy_with_copy() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            map_copy: true,
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        for i in 0..10u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        drop(storage);
+        // Keys are looked up in the copy, if the map is missing
+        remove_file(storage_path.join(MAP_FILE_NAME))?;
+        let storage = Storage::open_lazy_with_options(&storage_path, options)?;
+        assert_eq!(storage.get::<u32, &str>("7")?, Some(7));
+        assert!(!storage.has("10")?);
+        assert!(!storage.is_loaded());
+        storage.into_storage()?.destroy()?;
+        Ok(())
+    }
+}
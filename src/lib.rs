@@ -1,19 +1,173 @@
 #![doc = include_str!("../README.md")]
 
+mod access;
+#[cfg(feature = "tar")]
+mod archive;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod batch;
+mod bloom;
+mod bulk;
 mod bundle;
+mod cached;
+mod cancel;
+mod changes;
+mod codec;
+mod conditional;
+pub mod consistency;
+mod convert;
+mod copy;
+mod corruption;
+mod dedup;
 mod error;
+mod eviction;
+mod expiry;
+mod extract;
 mod field;
+mod flusher;
 pub(crate) mod fs;
+mod generation;
+mod hasher;
+mod header;
+mod history;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(any(feature = "sled", feature = "redb", feature = "json"))]
+mod import;
+mod internal;
+mod key;
+mod kv;
+mod lazy;
+mod lock;
+mod maintenance;
+mod manager;
+mod manifest;
 mod map;
+mod merge;
+mod middleware;
+mod names;
+mod nested;
+mod normalize;
+#[cfg(feature = "object-store")]
+mod object;
+mod options;
+mod ordered;
+mod packed;
+mod pin;
+mod policy;
+mod pool;
+mod preload;
+mod quota;
+mod reconcile;
+mod record;
+mod recover;
+#[cfg(feature = "bstorage-server")]
+mod remote;
+mod replication;
+mod retry;
+mod rotating;
 mod search;
+mod sensitive;
+#[cfg(feature = "json")]
+mod sidecar;
+mod slot;
+mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
 mod storage;
+mod stream;
+pub mod sync;
+mod tags;
+#[cfg(feature = "testing")]
+mod testing;
+mod tiering;
+mod trace;
+mod transform;
+mod typed;
+mod upgrade;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod validation;
+mod verify;
+mod vfs;
 
+pub use access::*;
+#[cfg(feature = "tar")]
+pub use archive::*;
+pub use batch::*;
+pub use bloom::*;
+pub use bulk::*;
 pub use bundle::*;
+pub use cached::*;
+pub use changes::*;
+pub use codec::*;
+pub use conditional::*;
+pub use corruption::*;
+pub use dedup::*;
 pub use error::*;
+pub use eviction::*;
+pub use expiry::*;
 pub(crate) use field::*;
+pub(crate) use flusher::*;
+pub use hasher::*;
+pub(crate) use header::*;
+pub use history::*;
+#[cfg(feature = "http")]
+pub use http::*;
+pub use key::*;
+pub use kv::*;
+pub use lazy::*;
+pub use lock::*;
+pub use maintenance::*;
+pub use manager::*;
+pub use manifest::*;
 pub(crate) use map::*;
+pub use merge::*;
+pub use middleware::*;
+pub use names::*;
+pub use normalize::*;
+#[cfg(feature = "object-store")]
+pub use object::*;
+pub use options::*;
+pub use packed::*;
+pub use policy::*;
+pub use pool::*;
+pub use preload::*;
+pub use quota::*;
+pub use reconcile::*;
+pub use record::*;
+pub use recover::*;
+#[cfg(feature = "bstorage-server")]
+pub use remote::*;
+pub use retry::RetryPolicy;
+pub(crate) use retry::Retrying;
+pub use rotating::*;
 pub use search::*;
+pub use sensitive::*;
+#[cfg(feature = "json")]
+pub use sidecar::*;
+pub use slot::*;
+pub use snapshot::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
+pub use stats::*;
 pub use storage::*;
+#[cfg(feature = "testing")]
+pub use testing::*;
+pub use tiering::*;
+pub use transform::*;
+pub use typed::*;
+pub use upgrade::*;
+pub use verify::*;
+pub use vfs::*;
+
+#[cfg(feature = "derive")]
+pub use bstorage_derive::{Record, StorageKey};
+
+// Allows `::bstorage` paths generated by the derive macro in tests of the crate
+#[cfg(all(test, feature = "derive"))]
+extern crate self as bstorage;
 
 #[cfg(test)]
 mod test;
@@ -0,0 +1,319 @@
+use log::{debug, warn};
+use std::{
+    fs::{read_to_string, remove_file, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::{fs, Stamp, Storage, E};
+
+pub(crate) const LOCK_FILE_NAME: &str = ".lock";
+
+/// Period of updates of the heartbeat of the lock
+const HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// The lock, which heartbeat hasn't been updated for this period, is taken as abandoned
+const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Holder of the lock as it's written in the lock file: the id of the process, the name of the
+/// host and the time of the last heartbeat in milliseconds since UNIX epoch, one per line.
+#[derive(Debug, PartialEq, Eq)]
+struct Holder {
+    pid: u32,
+    host: Option<String>,
+    heartbeat: Option<u64>,
+}
+
+impl Holder {
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            host: hostname(),
+            heartbeat: Some(Stamp::millis(SystemTime::now())),
+        }
+    }
+
+    /// Parses the content of the lock file. Lock files of previous versions keep the id of the
+    /// process only.
+    fn parse(content: &str) -> Option<Self> {
+        let mut lines = content.lines();
+        let pid = lines.next()?.trim().parse().ok()?;
+        let host = lines
+            .next()
+            .map(|host| host.trim().to_owned())
+            .filter(|host| !host.is_empty());
+        let heartbeat = lines.next().and_then(|at| at.trim().parse().ok());
+        Some(Self {
+            pid,
+            host,
+            heartbeat,
+        })
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).truncate(true).open(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+
+    /// Returns true if the holder is gone: its heartbeat is outdated, or it's a process of this
+    /// host, which isn't running anymore.
+    fn is_gone(&self) -> bool {
+        let now = Stamp::millis(SystemTime::now());
+        if self
+            .heartbeat
+            .is_some_and(|at| now.saturating_sub(at) > STALE_AFTER.as_millis() as u64)
+        {
+            return true;
+        }
+        let local = match (&self.host, hostname()) {
+            (Some(holder), Some(current)) => *holder == current,
+            // Locks of previous versions don't have hosts
+            (None, _) => true,
+            (Some(_), None) => false,
+        };
+        local && is_running(self.pid) == Some(false)
+    }
+}
+
+impl std::fmt::Display for Holder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}\n{}",
+            self.pid,
+            self.host.as_deref().unwrap_or_default(),
+            self.heartbeat.unwrap_or_default()
+        )
+    }
+}
+
+/// Returns the name of the host, if it's known.
+fn hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        // SAFETY: the buffer is valid for writes of its length
+        if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0
+        {
+            return None;
+        }
+        let len = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+        String::from_utf8(buffer[..len].to_vec()).ok()
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").ok()
+    }
+}
+
+/// Checks whether the process is running; None if it cannot be checked on this platform.
+fn is_running(pid: u32) -> Option<bool> {
+    #[cfg(unix)]
+    {
+        // SAFETY: the signal 0 only checks whether the process exists
+        if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+            return Some(true);
+        }
+        // The process exists, but belongs to another user
+        Some(io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        None
+    }
+}
+
+/// `Lock` grants exclusive access to the storage folder. It's a file created in the storage folder,
+/// which exists as long as the lock is held. The file keeps the id of the owning process, the name
+/// of its host and the heartbeat, which is updated by a background thread while the lock is held.
+///
+/// A lock left by a crashed process is taken over automatically: if the owning process of this
+/// host isn't running anymore, or the heartbeat hasn't been updated for 30 seconds (e.g. the
+/// holder is on another host sharing the folder). `Storage::force_unlock()` removes the lock
+/// unconditionally.
+#[derive(Debug)]
+pub struct Lock {
+    path: Arc<Mutex<PathBuf>>,
+    /// Stops the heartbeat
+    stop: Option<Sender<()>>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl Lock {
+    /// Takes the lock of the storage folder. An abandoned lock (see `Lock`) is taken over.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the lock, or `E::Locked` if the folder is already locked.
+    pub fn acquire<P: AsRef<Path>>(cwd: P) -> Result<Self, E> {
+        let path = fs::as_path_buf(&cwd).join(LOCK_FILE_NAME);
+        let mut file = match Lock::create(&path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && Lock::take_over(&path) => {
+                Lock::create(&path)
+            }
+            created => created,
+        }
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => E::Locked(fs::as_path_buf(&cwd)),
+            _ => err.into(),
+        })?;
+        file.write_all(Holder::current().to_string().as_bytes())?;
+        let path = Arc::new(Mutex::new(path));
+        let (stop, rx) = channel::<()>();
+        let heartbeat = thread::spawn({
+            let path = path.clone();
+            move || {
+                while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(HEARTBEAT) {
+                    let Ok(path) = path.lock() else {
+                        return;
+                    };
+                    if let Err(err) = Holder::current().write(&path) {
+                        warn!("Fail to update heartbeat of lock {path:?}: {err}");
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            path,
+            stop: Some(stop),
+            heartbeat: Some(heartbeat),
+        })
+    }
+
+    fn create(path: &Path) -> io::Result<std::fs::File> {
+        OpenOptions::new().write(true).create_new(true).open(path)
+    }
+
+    /// Removes the lock, if its holder is gone.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the lock has been removed.
+    fn take_over(path: &Path) -> bool {
+        let Ok(content) = read_to_string(path) else {
+            return false;
+        };
+        if !Holder::parse(&content).is_some_and(|holder| holder.is_gone()) {
+            return false;
+        }
+        // The lock could have been taken over by another process in between
+        if read_to_string(path).ok().as_deref() != Some(content.as_str()) {
+            return false;
+        }
+        debug!("Abandoned lock {path:?} is taken over");
+        remove_file(path).is_ok()
+    }
+
+    /// Updates the location of the lock after the storage folder has been moved.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the new location of the storage folder.
+    pub fn moved<P: AsRef<Path>>(&mut self, cwd: P) {
+        if let Ok(mut path) = self.path.lock() {
+            *path = fs::as_path_buf(cwd).join(LOCK_FILE_NAME);
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(heartbeat) = self.heartbeat.take() {
+            let _ = heartbeat.join();
+        }
+        let Ok(path) = self.path.lock() else {
+            return;
+        };
+        if let Err(err) = remove_file(&*path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!("Fail to release lock {path:?}: {err}");
+            }
+        }
+    }
+}
+
+impl Storage {
+    /// Removes the lock of the storage folder (see `StorageManager`) regardless of its holder.
+    /// Abandoned locks are taken over automatically; this method is meant for cases, when the
+    /// holder cannot be checked (e.g. it's on another host, which is down, and the heartbeat
+    /// hasn't expired yet). Make sure the holder doesn't use the storage anymore.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the lock has been removed, false if the folder
+    ///   isn't locked, or an error.
+    pub fn force_unlock<P: AsRef<Path>>(cwd: P) -> Result<bool, E> {
+        let path = fs::as_path_buf(&cwd).join(LOCK_FILE_NAME);
+        match remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(E::io(err, &path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Holder, Lock, LOCK_FILE_NAME};
+    use crate::{Stamp, Storage, E};
+    use std::{
+        env::temp_dir,
+        fs::{create_dir, remove_dir_all, write},
+        process::Command,
+        time::{Duration, SystemTime},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn stale_lock() -> Result<(), E> {
+        let cwd = temp_dir().join(Uuid::new_v4().to_string());
+        create_dir(&cwd)?;
+        let lock = Lock::acquire(&cwd)?;
+        assert!(matches!(Lock::acquire(&cwd), Err(E::Locked(_))));
+        drop(lock);
+        // Process of this host, which isn't running anymore
+        let mut child = Command::new(std::env::current_exe()?)
+            .arg("--list")
+            .spawn()?;
+        child.wait()?;
+        let dead = Holder {
+            pid: child.id(),
+            ..Holder::current()
+        };
+        write(cwd.join(LOCK_FILE_NAME), dead.to_string())?;
+        drop(Lock::acquire(&cwd)?);
+        // Outdated heartbeat
+        let outdated = Holder {
+            host: Some(String::from("another-host")),
+            heartbeat: Some(Stamp::millis(SystemTime::now() - Duration::from_secs(60))),
+            ..Holder::current()
+        };
+        write(cwd.join(LOCK_FILE_NAME), outdated.to_string())?;
+        let lock = Lock::acquire(&cwd)?;
+        // The holder is alive
+        assert!(matches!(Lock::acquire(&cwd), Err(E::Locked(_))));
+        assert!(Storage::force_unlock(&cwd)?);
+        assert!(!Storage::force_unlock(&cwd)?);
+        drop(Lock::acquire(&cwd)?);
+        drop(lock);
+        remove_dir_all(&cwd)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use log::debug;
+use std::{
+    collections::HashSet, fs::remove_file, path::PathBuf, sync::atomic::AtomicBool, time::Duration,
+};
+
+use crate::{
+    cancel, recover::orphans, Corruption, CorruptionKind, CorruptionPolicy, Problem, Storage,
+    VerifyReport, E,
+};
+
+/// Tasks keeping the storage healthy. They run with `Storage::maintain()` or automatically when
+/// the storage is opened (if `on_open` is true).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Maintenance {
+    /// Runs maintenance each time the storage is opened.
+    pub on_open: bool,
+    /// Verifies all records (see `Storage::verify()`) and applies `CorruptionPolicy` to broken
+    /// ones (with `CorruptionPolicy::Error` broken records are only reported). Records, which
+    /// files are intact, but values don't match their digests or cannot be decoded, are only
+    /// reported.
+    pub verify: bool,
+    /// Removes records, which haven't been accessed for the given period (see
+    /// `Storage::sweep_older_than()`).
+    pub purge: Option<Duration>,
+    /// Removes record files, which aren't referenced by the map (left after crashes).
+    pub vacuum: bool,
+    /// Drops keys, which files don't exist anymore, and rewrites the map file.
+    pub compact: bool,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            on_open: false,
+            verify: true,
+            purge: None,
+            vacuum: true,
+            compact: true,
+        }
+    }
+}
+
+/// What has been done by maintenance of the storage.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// The result of verification, if it has been done
+    pub verified: Option<VerifyReport>,
+    /// Actions applied to broken records found by verification
+    pub corruptions: Vec<Corruption>,
+    /// Keys removed as not accessed for the period of `Maintenance::purge`
+    pub purged: Vec<String>,
+    /// Removed orphaned record files
+    pub vacuumed: Vec<PathBuf>,
+    /// Keys dropped from the map because their files don't exist
+    pub compacted: Vec<String>,
+}
+
+impl Storage {
+    /// Runs maintenance tasks defined by `StorageOptions::maintenance` (or `Maintenance::default()`
+    /// if options don't define it).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<MaintenanceReport, E>` - Returns the report of what has been done, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+    /// let mut storage = Storage::create(&storage_path).unwrap();
+    /// storage.set("my_record", &1u8).unwrap();
+    /// let report = storage.maintain().unwrap();
+    /// assert!(report.verified.unwrap().is_ok());
+    /// assert!(report.vacuumed.is_empty());
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn maintain(&mut self) -> Result<MaintenanceReport, E> {
+        self.maintain_cancellable(&cancel::NEVER)
+    }
+
+    /// Runs maintenance tasks as `maintain()` does; maintenance can be cancelled by setting the
+    /// flag from another thread. Changes made before the cancellation (e.g. detached broken
+    /// records) are persisted, so the storage stays consistent.
+    ///
+    /// # Arguments
+    ///
+    /// * `cancel` - Stops maintenance with `E::Cancelled` when set.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<MaintenanceReport, E>` - Returns the report of what has been done, or an error.
+    pub fn maintain_cancellable(&mut self, cancel: &AtomicBool) -> Result<MaintenanceReport, E> {
+        let tasks = self.options.maintenance.clone().unwrap_or_default();
+        let mut report = MaintenanceReport::default();
+        self.prune()?;
+        if tasks.verify {
+            let verified = self.verify_cancellable(cancel)?;
+            if self.options.corruption != CorruptionPolicy::Error {
+                for issue in verified.issues.iter() {
+                    let kind = match issue.problem {
+                        Problem::Missing => CorruptionKind::Missing,
+                        Problem::Empty | Problem::Checksum => {
+                            CorruptionKind::Invalid(issue.problem.to_string())
+                        }
+                        // The file is intact, so it's only reported: the value may have been
+                        // replaced on purpose, or it's read with wrong options
+                        Problem::Digest | Problem::Undecodable(_) => continue,
+                    };
+                    let corruption = Corruption::handle(
+                        self.options.corruption,
+                        &self.cwd,
+                        &issue.key,
+                        &issue.path,
+                        kind,
+                    )?;
+                    if corruption.detached() {
+                        self.forget(&issue.key);
+                    }
+                    report.corruptions.push(corruption);
+                }
+            }
+            report.verified = Some(verified);
+        }
+        if let Err(err) = cancel::check(cancel) {
+            if !report.corruptions.is_empty() {
+                self.map.write(&self.fields)?;
+            }
+            return Err(err);
+        }
+        if let Some(age) = tasks.purge {
+            report.purged = self.sweep_older_than(age)?;
+        }
+        if tasks.vacuum {
+            let known = self
+                .fields
+                .values()
+                .map(|field| field.file_name().to_owned())
+                .collect::<HashSet<String>>();
+            for path in orphans(&self.cwd, &known)? {
+                if let Err(err) = cancel::check(cancel) {
+                    if !report.corruptions.is_empty() {
+                        self.map.write(&self.fields)?;
+                    }
+                    return Err(err);
+                }
+                remove_file(&path)?;
+                report.vacuumed.push(path);
+            }
+        }
+        if tasks.compact {
+            report.compacted = self
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.path(&self.cwd).exists())
+                .map(|(key, _)| key.to_owned())
+                .collect();
+            for key in report.compacted.iter() {
+                self.forget(key);
+            }
+            self.map.write(&self.fields)?;
+            self.map.flush(&self.fields)?;
+        } else if !report.corruptions.is_empty() {
+            self.map.write(&self.fields)?;
+        }
+        debug!("Maintenance of {:?} is done: {report:?}", self.cwd);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Maintenance, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn on_open() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for key in ["a", "b"] {
+            storage.set(key, &String::from(key))?;
+        }
+        let path = storage
+            .fields
+            .get("a")
+            .expect("Field exists")
+            .path(&storage.cwd);
+        drop(storage);
+        std::fs::remove_file(&path)?;
+        let orphan = storage_path.join("orphan.bstorage");
+        std::fs::write(&orphan, [1, 2, 3])?;
+        let options = StorageOptions {
+            maintenance: Some(Maintenance {
+                on_open: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let storage = Storage::open_with_options(&storage_path, options)?;
+        let report = storage.maintenance_report().expect("Maintenance is done");
+        assert_eq!(report.vacuumed, vec![orphan.clone()]);
+        assert!(!orphan.exists());
+        assert_eq!(storage.len(), 1);
+        drop(storage);
+        // The key with the missing file has been dropped from the map
+        let mut storage = Storage::open(&storage_path)?;
+        assert!(storage.take_corruptions().is_empty());
+        assert_eq!(storage.len(), 1);
+        let path = storage
+            .fields
+            .get("b")
+            .expect("Field exists")
+            .path(&storage.cwd);
+        std::fs::remove_file(&path)?;
+        let report = storage.maintain()?;
+        assert_eq!(report.compacted, vec![String::from("b")]);
+        assert!(storage.is_empty());
+        storage.destroy()?;
+        Ok(())
+    }
+}
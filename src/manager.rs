@@ -0,0 +1,226 @@
+use std::{
+    fs::{read_dir, remove_dir_all, rename},
+    path::{Path, PathBuf},
+};
+
+use crate::{fs, Lock, Storage, StorageOptions, E};
+
+/// `StorageManager` owns a root folder with multiple named storages (profiles), each of them in
+/// its own subfolder. A storage opened via the manager is locked (see `Lock`) until it's dropped,
+/// so the same profile cannot be opened twice, deleted or renamed while it's in use (also by
+/// another process).
+#[derive(Debug)]
+pub struct StorageManager {
+    root: PathBuf,
+    options: StorageOptions,
+}
+
+impl StorageManager {
+    /// Creates the root folder if it doesn't exist and returns the manager of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - A path reference to the root folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the manager, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::StorageManager;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let manager = StorageManager::new(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let mut profile = manager.open("profile-a").unwrap();
+    /// profile.set("theme", &String::from("dark")).unwrap();
+    /// // The profile is in use
+    /// assert!(manager.open("profile-a").is_err());
+    /// drop(profile);
+    /// manager.rename("profile-a", "profile-b").unwrap();
+    /// assert_eq!(manager.list().unwrap(), vec![String::from("profile-b")]);
+    /// manager.delete("profile-b").unwrap();
+    /// ```
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self, E> {
+        StorageManager::with_options(root, StorageOptions::default())
+    }
+
+    /// Creates the root folder if it doesn't exist and returns the manager of it. Storages are
+    /// opened with the given options.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - A path reference to the root folder.
+    /// * `options` - Options of storages.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the manager, or an error.
+    pub fn with_options<P: AsRef<Path>>(root: P, options: StorageOptions) -> Result<Self, E> {
+        if !root.as_ref().exists() {
+            fs::create_dir(root.as_ref(), options.permissions.dir)?;
+        }
+        if !root.as_ref().is_dir() {
+            return Err(E::PathIsNotFolder(fs::as_path_buf(root)));
+        }
+        Ok(Self {
+            root: fs::as_path_buf(root),
+            options,
+        })
+    }
+
+    /// Returns the folder of the named storage.
+    fn path(&self, name: &str) -> Result<PathBuf, E> {
+        if name.starts_with('.') || !fs::is_portable_name(name) {
+            return Err(E::InvalidStorageName(name.to_owned()));
+        }
+        Ok(self.root.join(name))
+    }
+
+    /// Opens the named storage (creating it if it doesn't exist) and locks it until the storage
+    /// is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the storage, or `E::Locked` if it's already in use, or an
+    ///   error.
+    pub fn open<N: AsRef<str>>(&self, name: N) -> Result<Storage, E> {
+        let cwd = self.path(name.as_ref())?;
+        if !cwd.exists() {
+            fs::create_dir(&cwd, self.options.permissions.dir)?;
+        }
+        let lock = Lock::acquire(&cwd)?;
+        let mut storage = Storage::open_with_options(&cwd, self.options.clone())?;
+        storage.lock = Some(lock);
+        Ok(storage)
+    }
+
+    /// Returns names of all storages, sorted.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns names of storages, or an error.
+    pub fn list(&self) -> Result<Vec<String>, E> {
+        let mut names = Vec::new();
+        for entry in read_dir(&self.root)? {
+            let entry = entry?;
+            if !entry.path().is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if self.path(&name).is_ok() {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Checks if the named storage exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the storage exists.
+    pub fn has<N: AsRef<str>>(&self, name: N) -> bool {
+        self.path(name.as_ref()).is_ok_and(|path| path.is_dir())
+    }
+
+    /// Removes the named storage with all its records.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the storage has been removed, false if it doesn't
+    ///   exist, `E::Locked` if it's in use, or an error.
+    pub fn delete<N: AsRef<str>>(&self, name: N) -> Result<bool, E> {
+        let cwd = self.path(name.as_ref())?;
+        if !cwd.exists() {
+            return Ok(false);
+        }
+        let lock = Lock::acquire(&cwd)?;
+        remove_dir_all(&cwd)?;
+        drop(lock);
+        Ok(true)
+    }
+
+    /// Renames the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Current name of the storage.
+    /// * `to` - New name of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, `E::Locked` if the storage is in use,
+    ///   or an error.
+    pub fn rename<F: AsRef<str>, T: AsRef<str>>(&self, from: F, to: T) -> Result<(), E> {
+        let src = self.path(from.as_ref())?;
+        let dest = self.path(to.as_ref())?;
+        if !src.is_dir() {
+            return Err(E::PathIsNotFolder(src));
+        }
+        if dest.exists() {
+            return Err(E::DestinationIsNotEmpty(dest));
+        }
+        let mut lock = Lock::acquire(&src)?;
+        rename(&src, &dest)?;
+        lock.moved(&dest);
+        Ok(())
+    }
+
+    /// Returns the root folder of the manager.
+    ///
+    /// # Returns
+    ///
+    /// * `&PathBuf` - A reference to the root folder.
+    pub fn root(&self) -> &PathBuf {
+        &self.root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{StorageManager, E};
+    use std::{env::temp_dir, fs::remove_dir_all};
+    use uuid::Uuid;
+
+    #[test]
+    fn profiles() -> Result<(), E> {
+        let manager = StorageManager::new(temp_dir().join(Uuid::new_v4().to_string()))?;
+        assert!(matches!(
+            manager.open("../a"),
+            Err(E::InvalidStorageName(_))
+        ));
+        let mut a = manager.open("a")?;
+        a.set("key", &1u8)?;
+        let b = manager.open("b")?;
+        assert_eq!(manager.list()?, vec![String::from("a"), String::from("b")]);
+        assert!(matches!(manager.open("a"), Err(E::Locked(_))));
+        assert!(matches!(manager.delete("a"), Err(E::Locked(_))));
+        assert!(matches!(manager.rename("a", "c"), Err(E::Locked(_))));
+        drop(a);
+        drop(b);
+        assert!(manager.delete("b")?);
+        manager.rename("a", "c")?;
+        assert!(!manager.has("a"));
+        let c = manager.open("c")?;
+        assert_eq!(c.get::<u8, &str>("key")?, Some(1));
+        drop(c);
+        remove_dir_all(manager.root())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::{Field, Operation, Storage, E};
+
+/// SHA-256 digest of a serialized value
+pub type Sha256Digest = [u8; 32];
+
+/// Computes the digest of the serialized value.
+pub(crate) fn digest(payload: &[u8]) -> Sha256Digest {
+    Sha256::digest(payload).into()
+}
+
+/// Digests of all records of the storage (see `Storage::manifest()`), which can be published
+/// along with the storage, so the receiver can check that records haven't been changed on the
+/// way (see `Storage::verify_manifest()`). Digests are computed for serialized values, so they
+/// don't depend on codecs and transformers of records (see `Codecs` and `Transformer`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestManifest {
+    /// SHA-256 digests of serialized values by keys, as lowercase hex strings
+    pub records: BTreeMap<String, String>,
+}
+
+/// The result of checking the storage against a manifest (see `Storage::verify_manifest()`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DigestManifestReport {
+    /// Number of checked records
+    pub checked: usize,
+    /// Keys of records, which digests don't match the manifest
+    pub mismatched: Vec<String>,
+    /// Keys of the manifest, which aren't in the storage
+    pub missing: Vec<String>,
+    /// Keys of the storage, which aren't in the manifest
+    pub unexpected: Vec<String>,
+}
+
+impl DigestManifestReport {
+    /// Returns true if the storage matches the manifest.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if all records match the manifest.
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// Formats the digest as a lowercase hex string.
+fn hex(digest: &Sha256Digest) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+impl Storage {
+    /// Exports the manifest of the storage: SHA-256 digests of all records (see `DigestManifest`).
+    /// Expired records are skipped. With `StorageOptions::digests` digests are computed when
+    /// records are written, so the manifest is exported without reading records; otherwise each
+    /// record is read.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DigestManifest, E>` - Returns the manifest, or an error if some record cannot be
+    ///   read.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Storage, StorageOptions};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let options = StorageOptions {
+    ///     digests: true,
+    ///     ..Default::default()
+    /// };
+    /// let mut storage =
+    ///     Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)
+    ///         .unwrap();
+    /// storage.set("model", &vec![0.5f32; 256]).unwrap();
+    /// let manifest = storage.manifest().unwrap();
+    /// assert_eq!(manifest.records["model"].len(), 64);
+    /// // ... transfer the storage and the manifest
+    /// assert!(storage.verify_manifest(&manifest).unwrap().is_ok());
+    /// storage.set("model", &vec![1.0f32; 256]).unwrap();
+    /// assert_eq!(
+    ///     storage.verify_manifest(&manifest).unwrap().mismatched,
+    ///     vec!["model"]
+    /// );
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn manifest(&self) -> Result<DigestManifest, E> {
+        let mut manifest = DigestManifest::default();
+        for (key, field) in self.fields.iter() {
+            if self.expired(key) {
+                continue;
+            }
+            let digest = match field.meta().digest {
+                Some(digest) => digest,
+                None => self.digest_of(key, field)?,
+            };
+            manifest.records.insert(key.to_owned(), hex(&digest));
+        }
+        Ok(manifest)
+    }
+
+    /// Checks records of the storage against the manifest (see `Storage::manifest()`). Each
+    /// record is read and its digest is computed, so digests kept in the map aren't trusted.
+    /// Expired records are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `manifest` - The manifest.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<DigestManifestReport, E>` - Returns the report with found differences, or an
+    ///   error if some record cannot be read.
+    pub fn verify_manifest(&self, manifest: &DigestManifest) -> Result<DigestManifestReport, E> {
+        let mut report = DigestManifestReport::default();
+        for (key, expected) in manifest.records.iter() {
+            let Some(field) = self.fields.get(key).filter(|_| !self.expired(key)) else {
+                report.missing.push(key.to_owned());
+                continue;
+            };
+            report.checked += 1;
+            if hex(&self.digest_of(key, field)?) != expected.to_lowercase() {
+                report.mismatched.push(key.to_owned());
+            }
+        }
+        report.unexpected = self
+            .fields
+            .keys()
+            .filter(|key| !self.expired(key) && !manifest.records.contains_key(*key))
+            .cloned()
+            .collect();
+        report.unexpected.sort();
+        Ok(report)
+    }
+
+    /// Reads the record and computes the digest of its serialized value.
+    pub(crate) fn digest_of(&self, key: &str, field: &Field) -> Result<Sha256Digest, E> {
+        let content = self
+            .extract(field)
+            .map_err(|e| e.record(Operation::Read, key, &field.path(&self.cwd)))?;
+        let (_, payload) = Field::payload(&content)
+            .map_err(|e| e.record(Operation::Read, key, &field.path(&self.cwd)))?;
+        Ok(digest(payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{type_tag, CorruptionPolicy, Field, Problem, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn manifest() -> Result<(), E> {
+        let options = |digests: bool| StorageOptions {
+            digests,
+            ..Default::default()
+        };
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create_with_options(&storage_path, options(true))?;
+        for n in 0..10u32 {
+            storage.set(format!("n/{n}"), &n)?;
+        }
+        assert!(storage
+            .fields
+            .values()
+            .all(|field| field.meta().digest.is_some()));
+        let manifest = storage.manifest()?;
+        assert_eq!(manifest.records.len(), 10);
+        drop(storage);
+        // Manifests computed from records and from kept digests are the same
+        let mut copy = Storage::open_with_options(&storage_path, options(false))?;
+        assert!(copy
+            .fields
+            .values()
+            .all(|field| field.meta().digest.is_some()));
+        copy.set("n/0", &0u32)?;
+        assert!(copy.fields["n/0"].meta().digest.is_none());
+        assert_eq!(copy.manifest()?, manifest);
+        drop(copy);
+        // Changes after the transfer are found
+        let mut storage = Storage::open_with_options(&storage_path, options(true))?;
+        // The record replaced with a valid checksum is found by its digest
+        let path = storage.fields["n/1"].path(storage.cwd());
+        let content = std::fs::read(&path)?;
+        std::fs::write(
+            &path,
+            Field::encode("n/1", type_tag::<u32>(), &bincode::serialize(&10u32)?)?,
+        )?;
+        assert_eq!(storage.get::<u32, _>("n/1")?, Some(10));
+        assert!(storage
+            .verify()?
+            .issues
+            .iter()
+            .any(|issue| issue.key == "n/1" && issue.problem == Problem::Digest));
+        // Records with mismatched digests are only reported by maintenance
+        storage.options.corruption = CorruptionPolicy::Quarantine;
+        let report = storage.maintain()?;
+        assert!(report.corruptions.is_empty());
+        assert!(storage.has("n/1") && path.exists());
+        storage.options.corruption = CorruptionPolicy::Skip;
+        std::fs::write(&path, &content)?;
+        assert!(storage.verify()?.is_ok());
+        storage.set("n/2", &20u32)?;
+        storage.remove("n/3")?;
+        storage.set("other", &0u32)?;
+        let report = storage.verify_manifest(&manifest)?;
+        assert_eq!(report.checked, 9);
+        assert_eq!(report.mismatched, vec!["n/2"]);
+        assert_eq!(report.missing, vec!["n/3"]);
+        assert_eq!(report.unexpected, vec!["other"]);
+        assert!(!report.is_ok());
+        storage.destroy()?;
+        Ok(())
+    }
+}
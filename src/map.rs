@@ -1,13 +1,62 @@
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    io::{Read, Write},
+    io::{self, BufReader},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
 };
 
-use crate::{fs, Field, E};
+use crate::{
+    fs, hasher::Fields, vfs, Bloom, BloomOptions, Durability, Field, FileSystem, FlushMode,
+    Flusher, GroupCommit, KeyHasher, Meta, Operation, StorageOptions, E,
+};
 
 pub(crate) const MAP_FILE_NAME: &str = "map.bstorage";
+/// Copy of the map file (see `StorageOptions::map_copy`)
+pub(crate) const MAP_COPY_FILE_NAME: &str = "map.bstorage.1";
+/// Marks the versioned map file. Map files without it are written by previous versions of
+/// `bstorage` and contain only file names of fields.
+const MAP_MAGIC: [u8; 4] = *b"BSMP";
+/// Version of the map file
+const MAP_VERSION: u8 = 1;
+/// Length of the header of the versioned map file: magic, version, generation (u64), length (u64)
+/// and checksum (u32) of the compressed body. The length and the checksum allow to detect partial
+/// writes.
+const MAP_HEADER_LEN: usize = MAP_MAGIC.len() + 1 + 8 + 8 + 4;
+
+/// Parsed header of the versioned map file
+struct MapHeader {
+    /// Generation of the map
+    generation: u64,
+    /// Length of the compressed body
+    len: u64,
+    /// Checksum of the compressed body
+    checksum: u32,
+}
+
+/// Entry of the map file
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// File name of the field
+    file: String,
+    /// Metadata of the field
+    meta: Meta,
+}
+
+/// Fields restored from the map and keys, which files don't exist
+pub type Restored = (Fields, Vec<(String, PathBuf)>);
+
+/// Decoded entry of the map file; `meta` is None for maps written by previous versions
+struct Decoded {
+    file: String,
+    meta: Option<Meta>,
+}
+
+/// Decoded entries of the map file by keys and the generation of the map
+type Entries = (u64, Vec<(String, Decoded)>);
 
 /// `Map` is a struct representing the mapping of keys to fields within the storage.
 #[derive(Debug)]
@@ -16,6 +65,26 @@ pub struct Map {
     cwd: PathBuf,
     /// Path to map file
     path: PathBuf,
+    /// Path to the copy of the map file, if `StorageOptions::map_copy` is used
+    copy: Option<PathBuf>,
+    /// Background writer of the map, if `FlushMode::Background` is used
+    flusher: Option<Flusher>,
+    /// Settings of group commit, if it's used
+    group: Option<GroupCommit>,
+    /// Number of deferred updates and the time of the first one
+    pending: Option<(usize, Instant)>,
+    /// Settings of the bloom filter of keys, if it's used
+    bloom: Option<BloomOptions>,
+    /// File system of the storage
+    fs: Arc<dyn FileSystem>,
+    /// Defines whether the map file is synced
+    durability: Durability,
+    /// Hash function of maps of fields read from the map file
+    hasher: KeyHasher,
+    /// Generation of the map, which has been read or written by this instance
+    generation: u64,
+    /// The next write of the map advances the generation
+    advancing: bool,
 }
 
 impl Map {
@@ -24,45 +93,347 @@ impl Map {
     /// # Arguments
     ///
     /// * `cwd` - A path reference to the current working directory.
+    /// * `options` - Options of the storage.
     ///
     /// # Returns
     ///
     /// * `Self` - Returns a newly created instance of `Map`.
-    pub fn new<P: AsRef<Path>>(cwd: P) -> Self {
+    pub fn new<P: AsRef<Path>>(cwd: P, options: &StorageOptions) -> Self {
+        let path = fs::as_path_buf(&cwd).join(MAP_FILE_NAME);
+        let copy = options
+            .map_copy
+            .then(|| fs::as_path_buf(&cwd).join(MAP_COPY_FILE_NAME));
+        let flusher = match options.flush {
+            FlushMode::Sync => None,
+            FlushMode::Background { queue, fsync } => Some(Flusher::new(
+                copy.iter().chain([&path]).cloned().collect(),
+                queue,
+                if fsync {
+                    options.durability.max(Durability::Fsync)
+                } else {
+                    options.durability
+                },
+                fs::Access::new(options),
+            )),
+        };
         Self {
             cwd: fs::as_path_buf(&cwd),
-            path: fs::as_path_buf(&cwd).join(MAP_FILE_NAME),
+            path,
+            copy,
+            flusher,
+            group: options.group_commit.clone(),
+            pending: None,
+            bloom: options.bloom.clone(),
+            fs: vfs::resolve(options),
+            durability: options.durability,
+            hasher: options.hasher,
+            generation: 0,
+            advancing: false,
         }
     }
 
-    /// Reads the map file and returns a `HashMap` of keys to fields.
+    /// Reads the map file and returns a `HashMap` of keys to fields. Keys, which files don't
+    /// exist, are not included into the map of fields, but returned separately.
     ///
     /// # Returns
     ///
-    /// * `Result<HashMap<String, Field>, E>` - Returns the map of keys to fields, or an error.
-    pub fn read(&self) -> Result<HashMap<String, Field>, E> {
-        if !self.path.exists() {
+    /// * `Result<Restored, E>` - Returns the map of keys to fields and the list of keys with
+    ///   missing files, or an error.
+    pub fn read(&mut self) -> Result<Restored, E> {
+        let (generation, restored) = self
+            .load()
+            .map_err(|e| e.map(Operation::Read, &self.path))?;
+        self.generation = generation;
+        Ok(restored)
+    }
+
+    fn load(&self) -> Result<(u64, Restored), E> {
+        let mut fields = self.hasher.fields();
+        let mut missing: Vec<(String, PathBuf)> = Vec::new();
+        let copy = self.copy.as_ref().filter(|copy| self.fs.exists(copy));
+        if !self.fs.exists(&self.path) && copy.is_none() {
             debug!("Storage's map file will be created: {:?}", self.path);
+            self.fs.write(&self.path, &[], self.durability)?;
+            return Ok((0, (fields, missing)));
         }
-        let mut file = fs::create_or_open(&self.path)?;
-        let mut fields: HashMap<String, Field> = HashMap::new();
-        if file.metadata()?.len() > 0 {
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)?;
-            let decoded: HashMap<String, String> = bincode::deserialize(&buffer)?;
-            for (key, filename) in decoded.into_iter() {
-                let file_path = self.cwd.join(&filename);
-                if !file_path.exists() {
-                    warn!("File \"{filename}\" for key \"{key}\" doesn't exist");
-                    continue;
+        let read = |path: &Path| -> Result<(Vec<u8>, Entries), E> {
+            let buffer = self.fs.read(path)?;
+            // The empty map is written only for a new storage, before any copy; with a copy the
+            // empty map has been truncated
+            if buffer.is_empty() && path == self.path && copy.is_some() {
+                return Err(E::MapIsTruncated {
+                    expected: MAP_HEADER_LEN as u64,
+                    actual: 0,
+                });
+            }
+            if buffer.is_empty() {
+                return Ok((buffer, (0, Vec::new())));
+            }
+            let entries = Map::decode(&buffer)?;
+            Ok((buffer, entries))
+        };
+        let (generation, entries) = match read(&self.path) {
+            Ok((_, entries)) => entries,
+            Err(err) => {
+                let Some(copy) = copy else {
+                    return Err(err);
+                };
+                let (buffer, entries) = read(copy)?;
+                warn!(
+                    "Map {:?} cannot be read ({err}); it's restored from the copy {copy:?}",
+                    self.path
+                );
+                self.fs.write(&self.path, &buffer, self.durability)?;
+                entries
+            }
+        };
+        for (key, entry) in entries {
+            let file_path = self.cwd.join(&entry.file);
+            if !self.fs.exists(&file_path) {
+                missing.push((key, file_path));
+                continue;
+            }
+            let meta = entry.meta.unwrap_or_else(|| Meta::from_file(&file_path));
+            fields.insert(key, Field::restore(entry.file, meta));
+        }
+        Ok((generation, (fields, missing)))
+    }
+
+    /// Looks for the key in the map file without building the full map: entries are decoded one
+    /// by one until the key is found. Maps written by previous versions of `bstorage` are read
+    /// completely.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Field>, E>` - Returns the field of the key, None if the key or its file
+    ///   doesn't exist, or an error.
+    pub fn lookup(&self, key: &str) -> Result<Option<Field>, E> {
+        self.find(key)
+            .or_else(|err| {
+                // The whole map is read to fall back to its copy
+                if self.copy.is_none() {
+                    return Err(err);
                 }
-                fields.insert(key, Field::restore(&file_path));
+                let (_, (mut fields, _)) = self.load()?;
+                Ok(fields.remove(key))
+            })
+            .map_err(|e| e.map(Operation::Read, &self.path))
+    }
+
+    fn find(&self, key: &str) -> Result<Option<Field>, E> {
+        // The missing map is looked up in its copy, as `load()` does
+        let path = match self.copy.as_ref() {
+            Some(copy) if !self.fs.exists(&self.path) && self.fs.exists(copy) => copy,
+            _ => &self.path,
+        };
+        let buffer = match self.fs.read(path) {
+            Ok(buffer) => buffer,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if buffer.len() < MAP_HEADER_LEN || !buffer.starts_with(&MAP_MAGIC) {
+            let (_, (mut fields, _)) = self.load()?;
+            return Ok(fields.remove(key));
+        }
+        // The checksum isn't verified here, because the body isn't decoded completely
+        let MapHeader { len, .. } = Map::header(&buffer[..MAP_HEADER_LEN])?;
+        let expected = (MAP_HEADER_LEN as u64).saturating_add(len);
+        let actual = buffer.len() as u64;
+        if expected != actual {
+            return Err(E::MapIsTruncated { expected, actual });
+        }
+        let mut reader = BufReader::new(DeflateDecoder::new(&buffer[MAP_HEADER_LEN..]));
+        let len: u64 = bincode::deserialize_from(&mut reader)?;
+        for _ in 0..len {
+            let (candidate, entry): (String, Entry) = bincode::deserialize_from(&mut reader)?;
+            if candidate == key {
+                let exists = self.fs.exists(&self.cwd.join(&entry.file));
+                return Ok(exists.then(|| Field::restore(entry.file, entry.meta)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Parses the header of the versioned map file.
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The header (`MAP_HEADER_LEN` bytes).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<MapHeader, E>` - Returns the parsed header, or an error if the version isn't
+    ///   supported.
+    fn header(header: &[u8]) -> Result<MapHeader, E> {
+        let version = header[MAP_MAGIC.len()];
+        if version != MAP_VERSION {
+            return Err(E::InvalidMapVersion(version));
+        }
+        let mut pos = MAP_MAGIC.len() + 1;
+        let mut generation = [0u8; 8];
+        generation.copy_from_slice(&header[pos..pos + 8]);
+        pos += 8;
+        let mut len = [0u8; 8];
+        len.copy_from_slice(&header[pos..pos + 8]);
+        let mut checksum = [0u8; 4];
+        checksum.copy_from_slice(&header[pos + 8..pos + 12]);
+        Ok(MapHeader {
+            generation: u64::from_le_bytes(generation),
+            len: u64::from_le_bytes(len),
+            checksum: u32::from_le_bytes(checksum),
+        })
+    }
+
+    /// Decodes the content of the map file. Metadata isn't available for maps written by previous
+    /// versions of `bstorage`.
+    fn decode(buffer: &[u8]) -> Result<Entries, E> {
+        if buffer.starts_with(&MAP_MAGIC) {
+            if buffer.len() < MAP_HEADER_LEN {
+                return Err(E::MapIsTruncated {
+                    expected: MAP_HEADER_LEN as u64,
+                    actual: buffer.len() as u64,
+                });
+            }
+            let MapHeader {
+                generation,
+                len,
+                checksum,
+            } = Map::header(&buffer[..MAP_HEADER_LEN])?;
+            let body = &buffer[MAP_HEADER_LEN..];
+            if body.len() as u64 != len {
+                return Err(E::MapIsTruncated {
+                    expected: (MAP_HEADER_LEN as u64).saturating_add(len),
+                    actual: buffer.len() as u64,
+                });
+            }
+            if crc32fast::hash(body) != checksum {
+                return Err(E::ChecksumMismatch);
+            }
+            let entries: HashMap<String, Entry> =
+                bincode::deserialize_from(DeflateDecoder::new(body))?;
+            return Ok((
+                generation,
+                entries
+                    .into_iter()
+                    .map(|(key, entry)| {
+                        (
+                            key,
+                            Decoded {
+                                file: entry.file,
+                                meta: Some(entry.meta),
+                            },
+                        )
+                    })
+                    .collect(),
+            ));
+        }
+        let entries: HashMap<String, String> = bincode::deserialize(buffer)?;
+        Ok((
+            0,
+            entries
+                .into_iter()
+                .map(|(key, file)| (key, Decoded { file, meta: None }))
+                .collect(),
+        ))
+    }
+
+    /// Returns the generation of the map, which has been read or written by this instance.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Makes the next write of the map advance the generation. The write isn't deferred by group
+    /// commit.
+    pub fn advance(&mut self) {
+        self.advancing = true;
+    }
+
+    /// Reads the generation from the header of the map file; the body of the map isn't read.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<u64, E>` - Returns the generation (0 if the file doesn't exist or has been
+    ///   written without it).
+    fn stored_generation(&self) -> Result<u64, E> {
+        let header = match self.fs.read_head(&self.path, MAP_HEADER_LEN) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        if header.len() < MAP_HEADER_LEN || header[..MAP_MAGIC.len()] != MAP_MAGIC {
+            return Ok(0);
+        }
+        Ok(Map::header(&header)?.generation)
+    }
+
+    /// Checks whether the map file has been written with a newer generation (e.g. the storage
+    /// has been cleared by another instance), so this instance is outdated. Only the header of
+    /// the map file is read.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns `E::StaleHandle` if the map is outdated, or Ok(()).
+    pub fn check(&self) -> Result<(), E> {
+        // Pending writes of the background flusher can be older than this instance; only newer
+        // generations of the file make it stale
+        let actual = self
+            .stored_generation()
+            .map_err(|e| e.map(Operation::Read, &self.path))?;
+        if actual > self.generation {
+            return Err(E::StaleHandle {
+                expected: self.generation,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the map of fields over the map of another storage in the folder (e.g. unpacking
+    /// over it). The generation is advanced past the generation of the replaced map, so its
+    /// instances become stale.
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - A reference to the `HashMap` of fields to be written.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn replace(&mut self, fields: &Fields) -> Result<(), E> {
+        self.generation = self
+            .stored_generation()
+            .map_err(|e| e.map(Operation::Read, &self.path))?;
+        self.advancing = true;
+        self.commit(fields)
+    }
+
+    /// Writes the current map of fields to the map file. With group commit the write can be
+    /// deferred until one of the limits of `GroupCommit` is reached.
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - A reference to the `HashMap` of fields to be written.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn write(&mut self, fields: &Fields) -> Result<(), E> {
+        self.check()?;
+        if let Some(group) = self.group.as_ref().filter(|_| !self.advancing) {
+            let (count, since) = self.pending.get_or_insert_with(|| (0, Instant::now()));
+            *count += 1;
+            if *count < group.max_pending && since.elapsed() < group.max_delay {
+                return Ok(());
             }
         }
-        Ok(fields)
+        self.commit(fields)
     }
 
-    /// Writes the current map of fields to the map file.
+    /// Writes the current map of fields to the map file immediately.
     ///
     /// # Arguments
     ///
@@ -71,15 +442,71 @@ impl Map {
     /// # Returns
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
-    pub fn write(&mut self, fields: &HashMap<String, Field>) -> Result<(), E> {
-        let mut files: HashMap<String, String> = HashMap::new();
+    fn commit(&mut self, fields: &Fields) -> Result<(), E> {
+        self.pending = None;
+        if self.advancing {
+            self.generation += 1;
+            self.advancing = false;
+        }
+        self.store(fields)
+            .map_err(|e| e.map(Operation::Write, &self.path))
+    }
+
+    fn store(&self, fields: &Fields) -> Result<(), E> {
+        let mut entries: HashMap<&String, Entry> = HashMap::new();
         for (key, field) in fields.iter() {
-            let file_name = field.file_name()?;
-            files.insert(key.to_owned(), file_name);
+            entries.insert(
+                key,
+                Entry {
+                    file: field.file_name().to_owned(),
+                    meta: field.meta().clone(),
+                },
+            );
+        }
+        // File names of fields are highly repetitive, so the body is compressed
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        bincode::serialize_into(&mut encoder, &entries)?;
+        let body = encoder.finish()?;
+        let mut buffer = Vec::with_capacity(MAP_HEADER_LEN + body.len());
+        buffer.extend_from_slice(&MAP_MAGIC);
+        buffer.push(MAP_VERSION);
+        buffer.extend_from_slice(&self.generation.to_le_bytes());
+        buffer.extend_from_slice(&(body.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(&crc32fast::hash(&body).to_le_bytes());
+        buffer.extend_from_slice(&body);
+        if let Some(options) = self.bloom.as_ref() {
+            Bloom::build(fields.keys(), options).write(&self.cwd, self.durability)?;
+        }
+        if let Some(flusher) = self.flusher.as_ref() {
+            return flusher.write(buffer);
+        }
+        if let Some(copy) = self.copy.as_ref() {
+            self.fs.write(copy, &buffer, self.durability)?;
         }
-        let buffer = bincode::serialize(&files)?;
-        let mut map = fs::create(&self.path)?;
-        map.write_all(&buffer)?;
+        self.fs.write(&self.path, &buffer, self.durability)?;
         Ok(())
     }
+
+    /// Commits deferred updates (group commit) and waits until all pending writes of the map
+    /// are done.
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - A reference to the `HashMap` of fields to be written.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn flush(&mut self, fields: &Fields) -> Result<(), E> {
+        if self.pending.is_some() {
+            self.commit(fields)?;
+        }
+        if let Some(flusher) = self.flusher.as_ref() {
+            flusher
+                .flush()
+                .map_err(|e| e.map(Operation::Write, &self.path))
+        } else {
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,407 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    time::SystemTime,
+};
+
+use crate::{type_tag, Field, Operation, Stamp, Storage, E};
+
+/// Values, which can be merged semantically instead of picking one of them (CRDT). `merge()`
+/// should be commutative, associative and idempotent: merging the same values in any order and
+/// any number of times gives the same result.
+///
+/// Types are declared as mergeable with `Mergers::register()`; then `Storage::merge_from()` and
+/// `sync::bidirectional()` merge records of these types changed in both storages.
+pub trait Merge: Serialize + DeserializeOwned + 'static {
+    /// Merges the other value into this one.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The other version of the value.
+    fn merge(&mut self, other: Self);
+}
+
+/// Counter, which can be incremented and decremented on several devices (PN-counter): each
+/// device counts its own increments and decrements, merging takes the maximum for each device.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Counter {
+    counts: BTreeMap<String, (u64, u64)>,
+}
+
+impl Counter {
+    /// Increments the counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - Identifier of the device (or process), which changes the counter.
+    /// * `n` - Increment.
+    pub fn increment(&mut self, device: &str, n: u64) {
+        self.counts.entry(device.to_owned()).or_default().0 += n;
+    }
+
+    /// Decrements the counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - Identifier of the device (or process), which changes the counter.
+    /// * `n` - Decrement.
+    pub fn decrement(&mut self, device: &str, n: u64) {
+        self.counts.entry(device.to_owned()).or_default().1 += n;
+    }
+
+    /// Returns the value of the counter.
+    ///
+    /// # Returns
+    ///
+    /// * `i64` - Sum of increments minus sum of decrements of all devices.
+    pub fn value(&self) -> i64 {
+        self.counts
+            .values()
+            .map(|(inc, dec)| *inc as i64 - *dec as i64)
+            .sum()
+    }
+}
+
+impl Merge for Counter {
+    fn merge(&mut self, other: Self) {
+        for (device, (inc, dec)) in other.counts {
+            let counts = self.counts.entry(device).or_default();
+            counts.0 = counts.0.max(inc);
+            counts.1 = counts.1.max(dec);
+        }
+    }
+}
+
+/// Set, which elements can only be added (G-Set); merging is the union of sets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrowSet<T: Ord> {
+    items: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for GrowSet<T> {
+    fn default() -> Self {
+        Self {
+            items: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord> GrowSet<T> {
+    /// Adds the element.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - The element to add.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the element is new.
+    pub fn insert(&mut self, item: T) -> bool {
+        self.items.insert(item)
+    }
+
+    /// Returns elements of the set.
+    ///
+    /// # Returns
+    ///
+    /// * `&BTreeSet<T>` - Elements.
+    pub fn items(&self) -> &BTreeSet<T> {
+        &self.items
+    }
+}
+
+impl<T: Ord + Serialize + DeserializeOwned + 'static> Merge for GrowSet<T> {
+    fn merge(&mut self, other: Self) {
+        self.items.extend(other.items);
+    }
+}
+
+/// Last-writer-wins register: keeps the value together with the time it has been set; merging
+/// keeps the value set later. Values set at the same millisecond are ordered by their
+/// serialized form, so all devices pick the same one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lww<T> {
+    value: T,
+    /// Time of the change in milliseconds since UNIX epoch
+    at: u64,
+}
+
+impl<T> Lww<T> {
+    /// Creates the register with the value set now.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The register.
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            at: Stamp::millis(SystemTime::now()),
+        }
+    }
+
+    /// Sets the value.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The new value.
+    pub fn set(&mut self, value: T) {
+        self.value = value;
+        self.at = Stamp::millis(SystemTime::now()).max(self.at + 1);
+    }
+
+    /// Returns the value.
+    ///
+    /// # Returns
+    ///
+    /// * `&T` - The value.
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + 'static> Merge for Lww<T> {
+    fn merge(&mut self, other: Self) {
+        let later = match other.at.cmp(&self.at) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Equal => {
+                bincode::serialize(&other.value).ok() > bincode::serialize(&self.value).ok()
+            }
+        };
+        if later {
+            *self = other;
+        }
+    }
+}
+
+/// Merges two serialized values of a type
+type MergeFn = fn(&[u8], &[u8]) -> Result<Vec<u8>, E>;
+
+fn merge_bytes<V: Merge>(ours: &[u8], theirs: &[u8]) -> Result<Vec<u8>, E> {
+    let mut value: V = bincode::deserialize(ours)?;
+    value.merge(bincode::deserialize(theirs)?);
+    Ok(bincode::serialize(&value)?)
+}
+
+/// Types of records, which are merged (see `Merge`); passed with `StorageOptions::merge`.
+#[derive(Clone, Default)]
+pub struct Mergers {
+    mergers: HashMap<u64, MergeFn>,
+}
+
+impl fmt::Debug for Mergers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mergers")
+            .field("types", &self.mergers.len())
+            .finish()
+    }
+}
+
+impl Mergers {
+    /// Declares records of the type `V` as mergeable.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The registry with the type.
+    pub fn register<V: Merge>(mut self) -> Self {
+        self.mergers.insert(type_tag::<V>(), merge_bytes::<V>);
+        self
+    }
+
+    /// Merges two serialized values with the given type tag.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - Type tag of both values.
+    /// * `ours` - The value to merge into.
+    /// * `theirs` - The other value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Vec<u8>>, E>` - Returns the merged value, None if the type isn't
+    ///   mergeable, or an error.
+    pub(crate) fn merge(&self, tag: u64, ours: &[u8], theirs: &[u8]) -> Result<Option<Vec<u8>>, E> {
+        match self.mergers.get(&tag) {
+            Some(merge) => merge(ours, theirs).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Storage {
+    /// Reads the type tag and the serialized value of the key.
+    pub(crate) fn payload_of(&self, key: &str) -> Result<Option<(u64, Vec<u8>)>, E> {
+        let Some(field) = self.fields.get(key) else {
+            return Ok(None);
+        };
+        let path = field.path(&self.cwd);
+        let content = self
+            .extract(field)
+            .map_err(|e| e.record(Operation::Read, key, &path))?;
+        let (_, payload) =
+            Field::payload(&content).map_err(|e| e.record(Operation::Read, key, &path))?;
+        Ok(Some((field.meta().tag, payload.to_vec())))
+    }
+
+    /// Merges records of the other storage into this one: missing keys are copied, records of
+    /// mergeable types (see `StorageOptions::merge`) are merged with the records of this
+    /// storage; other records of this storage are left as they are. The other storage isn't
+    /// changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The storage to merge records from.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, E>` - Returns the number of changed keys, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Counter, Mergers, Storage, StorageOptions};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let options = StorageOptions {
+    ///     merge: Mergers::default().register::<Counter>(),
+    ///     ..Default::default()
+    /// };
+    /// let create = || {
+    ///     Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options.clone())
+    ///         .unwrap()
+    /// };
+    /// let (mut desktop, mut laptop) = (create(), create());
+    /// let mut visits = Counter::default();
+    /// visits.increment("desktop", 2);
+    /// desktop.set("visits", &visits).unwrap();
+    /// let mut visits = Counter::default();
+    /// visits.increment("laptop", 3);
+    /// laptop.set("visits", &visits).unwrap();
+    /// desktop.merge_from(&laptop).unwrap();
+    /// assert_eq!(desktop.get::<Counter, _>("visits").unwrap().unwrap().value(), 5);
+    /// desktop.destroy().unwrap();
+    /// laptop.destroy().unwrap();
+    /// ```
+    pub fn merge_from(&mut self, other: &Storage) -> Result<usize, E> {
+        self.prune()?;
+        let mut keys: Vec<&String> = other.fields.keys().collect();
+        keys.sort();
+        let mut changes = Vec::new();
+        for key in keys {
+            let Some((tag, theirs)) = other.payload_of(key)? else {
+                continue;
+            };
+            let value = match self.payload_of(key)? {
+                None => theirs,
+                Some((our_tag, ours)) if our_tag == tag => {
+                    match self.options.merge.merge(tag, &ours, &theirs)? {
+                        Some(merged) if merged != ours => merged,
+                        _ => continue,
+                    }
+                }
+                Some(_) => continue,
+            };
+            changes.push((key.to_owned(), Some((tag, value))));
+        }
+        let changed = changes.len();
+        if changed > 0 {
+            self.apply(changes)?;
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        sync::{bidirectional, ConflictPolicy},
+        Counter, GrowSet, Lww, Merge, Mergers, Storage, StorageOptions, E,
+    };
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn merge() -> Result<(), E> {
+        let options = StorageOptions {
+            merge: Mergers::default()
+                .register::<Counter>()
+                .register::<GrowSet<String>>()
+                .register::<Lww<String>>(),
+            ..Default::default()
+        };
+        let create = || {
+            Storage::create_with_options(
+                temp_dir().join(Uuid::new_v4().to_string()),
+                options.clone(),
+            )
+        };
+        let (mut a, mut b) = (create()?, create()?);
+        let mut counter = Counter::default();
+        counter.increment("a", 1);
+        a.set("counter", &counter)?;
+        b.set("counter", &counter)?;
+        let mut set = GrowSet::default();
+        set.insert(String::from("x"));
+        a.set("set", &set)?;
+        b.set("set", &set)?;
+        let name = Lww::new(String::from("initial"));
+        a.set("name", &name)?;
+        b.set("name", &name)?;
+        a.set("plain", &1u8)?;
+        b.set("plain", &1u8)?;
+        bidirectional(&mut a, &mut b, ConflictPolicy::PreferA)?;
+        // Concurrent changes
+        counter.increment("a", 2);
+        a.set("counter", &counter)?;
+        let mut other = b.get::<Counter, _>("counter")?.unwrap();
+        other.decrement("b", 1);
+        b.set("counter", &other)?;
+        set.insert(String::from("y"));
+        a.set("set", &set)?;
+        let mut other = b.get::<GrowSet<String>, _>("set")?.unwrap();
+        other.insert(String::from("z"));
+        b.set("set", &other)?;
+        let mut name = a.get::<Lww<String>, _>("name")?.unwrap();
+        name.set(String::from("first"));
+        a.set("name", &name)?;
+        let mut later = b.get::<Lww<String>, _>("name")?.unwrap();
+        later.set(String::from("second"));
+        later.set(String::from("second"));
+        b.set("name", &later)?;
+        a.set("plain", &2u8)?;
+        b.set("plain", &3u8)?;
+        let report = bidirectional(&mut a, &mut b, ConflictPolicy::PreferA)?;
+        assert_eq!(report.conflicts, vec![String::from("plain")]);
+        assert_eq!(report.merged.len(), 3);
+        for storage in [&a, &b] {
+            assert_eq!(storage.get::<Counter, _>("counter")?.unwrap().value(), 2);
+            assert_eq!(
+                storage
+                    .get::<GrowSet<String>, _>("set")?
+                    .unwrap()
+                    .items()
+                    .len(),
+                3
+            );
+            assert_eq!(
+                storage.get::<Lww<String>, _>("name")?.unwrap().get(),
+                "second"
+            );
+            assert_eq!(storage.get::<u8, _>("plain")?, Some(2));
+        }
+        // Merging is idempotent
+        assert_eq!(a.merge_from(&b)?, 0);
+        let mut merged = counter.clone();
+        merged.merge(counter.clone());
+        assert_eq!(merged, counter);
+        a.destroy()?;
+        b.destroy()?;
+        Ok(())
+    }
+}
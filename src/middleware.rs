@@ -0,0 +1,325 @@
+use std::{fmt, sync::Arc};
+
+use crate::{sensitive::Wiped, Field, Storage, E};
+
+/// Continues `set` with the next middleware or with the storage: takes the key, the type tag and
+/// the serialized value.
+pub type NextSet<'a> = &'a mut dyn FnMut(&str, u64, &[u8]) -> Result<(), E>;
+
+/// Continues `get` with the next middleware or with the storage: takes the key and returns the
+/// type tag and the serialized value, or None if the key doesn't exist.
+pub type NextGet<'a> = &'a mut dyn FnMut(&str) -> Result<Option<(u64, Vec<u8>)>, E>;
+
+/// Continues `remove` with the next middleware or with the storage: takes the key and returns
+/// true if the record has been removed.
+pub type NextRemove<'a> = &'a mut dyn FnMut(&str) -> Result<bool, E>;
+
+/// Interceptor of `Storage::set()`, `Storage::get()` and `Storage::remove()` registered with
+/// `Storage::layer()`. Each method gets the call and the continuation of it (`next`); the
+/// middleware can inspect the call, reject it with an error (e.g. `E::Rejected`), or do
+/// something before and after the continuation (metrics, logging, publishing changes).
+///
+/// Middlewares must not change values: bulk operations read records as they are stored, so a
+/// transformed value would be taken for a damaged one there. A changed value is rejected by `set`
+/// with `E::Rejected`; use `Storage::transformer()` or `Codecs` to transform stored values.
+///
+/// Middlewares are composed as layers: the first registered one is the outermost, it gets the
+/// call first and the result last. All methods pass calls through by default.
+///
+/// Only `set` (and methods based on it, like `set_with_ttl`), `get`, `get_sensitive` and
+/// `remove` (and the same methods of `CachedStorage`) go through middlewares. Bulk operations
+/// work with records as they are stored and bypass middlewares: searches (`find`, `filter`,
+/// `scan`, etc.), batched reads, iteration, packing, and batches of changes (imports, merges,
+/// syncs, restoring snapshots and flushes of `CachedStorage`, which have passed middlewares
+/// already).
+pub trait Middleware: Send + Sync {
+    /// Intercepts writing of the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key (normalized and checked against the key policy).
+    /// * `tag` - Type tag of the value (see `type_tag()`).
+    /// * `payload` - The serialized value.
+    /// * `next` - The continuation of the call.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    fn set(&self, key: &str, tag: u64, payload: &[u8], next: NextSet<'_>) -> Result<(), E> {
+        next(key, tag, payload)
+    }
+
+    /// Intercepts reading of the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key (normalized).
+    /// * `next` - The continuation of the call.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<(u64, Vec<u8>)>, E>` - Returns the type tag and the serialized value,
+    ///   None if the key doesn't exist, or an error.
+    fn get(&self, key: &str, next: NextGet<'_>) -> Result<Option<(u64, Vec<u8>)>, E> {
+        next(key)
+    }
+
+    /// Intercepts removal of the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key (normalized).
+    /// * `next` - The continuation of the call.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the record has been removed, or an error.
+    fn remove(&self, key: &str, next: NextRemove<'_>) -> Result<bool, E> {
+        next(key)
+    }
+}
+
+/// Middlewares registered with `Storage::layer()`, from the outermost one
+#[derive(Default, Clone)]
+pub(crate) struct Layers(Vec<Arc<dyn Middleware>>);
+
+impl fmt::Debug for Layers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Layers").field(&self.0.len()).finish()
+    }
+}
+
+impl Layers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Passes the write through middlewares to `inner`. Fails with `E::Rejected` if some
+    /// middleware has changed the value.
+    pub(crate) fn set(
+        &self,
+        key: &str,
+        tag: u64,
+        payload: &[u8],
+        inner: NextSet<'_>,
+    ) -> Result<(), E> {
+        run_set(&self.0, key, tag, payload, &mut |key, tag, changed| {
+            if changed != payload {
+                return Err(E::Rejected(format!(
+                    "middleware has changed the value of \"{key}\""
+                )));
+            }
+            inner(key, tag, payload)
+        })
+    }
+
+    /// Passes the read through middlewares to `inner`.
+    pub(crate) fn get(&self, key: &str, inner: NextGet<'_>) -> Result<Option<(u64, Vec<u8>)>, E> {
+        run_get(&self.0, key, inner)
+    }
+
+    /// Passes the removal through middlewares to `inner`.
+    pub(crate) fn remove(&self, key: &str, inner: NextRemove<'_>) -> Result<bool, E> {
+        run_remove(&self.0, key, inner)
+    }
+}
+
+fn run_set(
+    layers: &[Arc<dyn Middleware>],
+    key: &str,
+    tag: u64,
+    payload: &[u8],
+    inner: NextSet<'_>,
+) -> Result<(), E> {
+    match layers.split_first() {
+        Some((layer, rest)) => layer.set(key, tag, payload, &mut |key, tag, payload| {
+            run_set(rest, key, tag, payload, inner)
+        }),
+        None => inner(key, tag, payload),
+    }
+}
+
+fn run_get(
+    layers: &[Arc<dyn Middleware>],
+    key: &str,
+    inner: NextGet<'_>,
+) -> Result<Option<(u64, Vec<u8>)>, E> {
+    match layers.split_first() {
+        Some((layer, rest)) => layer.get(key, &mut |key| run_get(rest, key, inner)),
+        None => inner(key),
+    }
+}
+
+fn run_remove(layers: &[Arc<dyn Middleware>], key: &str, inner: NextRemove<'_>) -> Result<bool, E> {
+    match layers.split_first() {
+        Some((layer, rest)) => layer.remove(key, &mut |key| run_remove(rest, key, inner)),
+        None => inner(key),
+    }
+}
+
+impl Storage {
+    /// Registers the middleware, which intercepts `set`, `get` and `remove` of the storage (see
+    /// `Middleware`). Middlewares registered earlier wrap the ones registered later.
+    ///
+    /// # Arguments
+    ///
+    /// * `middleware` - The middleware.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Middleware, NextSet, Storage, E};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// struct Limit;
+    ///
+    /// impl Middleware for Limit {
+    ///     fn set(&self, key: &str, tag: u64, payload: &[u8], next: NextSet<'_>) -> Result<(), E> {
+    ///         if payload.len() > 16 {
+    ///             return Err(E::Rejected(format!("\"{key}\" is too large")));
+    ///         }
+    ///         next(key, tag, payload)
+    ///     }
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.layer(Limit);
+    /// storage.set("small", &1u8).unwrap();
+    /// assert!(storage.set("large", &vec![0u8; 64]).is_err());
+    /// assert!(!storage.has("large"));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn layer<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.layers.0.push(Arc::new(middleware));
+    }
+
+    /// Writes the serialized value through middlewares. Fails with `E::Rejected` if some
+    /// middleware has changed the value.
+    pub(crate) fn set_through(&mut self, key: &str, tag: u64, payload: &[u8]) -> Result<(), E> {
+        if self.layers.is_empty() {
+            return self.set_bytes(key, tag, payload);
+        }
+        let layers = self.layers.clone();
+        layers.set(key, tag, payload, &mut |key, tag, payload| {
+            self.set_bytes(key, tag, payload)
+        })
+    }
+
+    /// Reads the serialized value through middlewares. Records, which cannot be read, are
+    /// handled according to `CorruptionPolicy`.
+    pub(crate) fn get_through(&self, key: &str) -> Result<Option<(u64, Vec<u8>)>, E> {
+        self.layers.get(key, &mut |key| {
+            let Some(field) = self.fields.get(key) else {
+                return self.archived_payload(key);
+            };
+            if self.expired(key) {
+                return Ok(None);
+            }
+            self.accessed(field);
+            let read = self.extract(field).and_then(|content| {
+                let content = Wiped(content);
+                let (_, payload) = Field::payload(&content)?;
+                Ok(payload.to_vec())
+            });
+            match read {
+                Ok(payload) => Ok(Some((field.meta().tag, payload))),
+                Err(err) => self.corrupted(key, field, err),
+            }
+        })
+    }
+
+    /// Removes the record through middlewares.
+    pub(crate) fn remove_through(&mut self, key: &str) -> Result<bool, E> {
+        if self.layers.is_empty() {
+            return self.remove_key(key);
+        }
+        let layers = self.layers.clone();
+        layers.remove(key, &mut |key| self.remove_key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Middleware, NextGet, NextRemove, NextSet, Search, Storage, E};
+    use std::{
+        env::temp_dir,
+        sync::{Arc, Mutex},
+    };
+    use uuid::Uuid;
+
+    /// Tries to invert bytes of values
+    struct Invert;
+
+    impl Middleware for Invert {
+        fn set(&self, key: &str, tag: u64, payload: &[u8], next: NextSet<'_>) -> Result<(), E> {
+            next(key, tag, &payload.iter().map(|b| !b).collect::<Vec<u8>>())
+        }
+
+        fn get(&self, key: &str, next: NextGet<'_>) -> Result<Option<(u64, Vec<u8>)>, E> {
+            Ok(next(key)?.map(|(tag, payload)| (tag, payload.iter().map(|b| !b).collect())))
+        }
+    }
+
+    /// Records calls and rejects removal of protected keys
+    struct Audit(Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Audit {
+        fn set(&self, key: &str, tag: u64, payload: &[u8], next: NextSet<'_>) -> Result<(), E> {
+            self.0.lock().unwrap().push(format!("set {key}"));
+            next(key, tag, payload)
+        }
+
+        fn get(&self, key: &str, next: NextGet<'_>) -> Result<Option<(u64, Vec<u8>)>, E> {
+            let value = next(key)?;
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("get {key} {}", value.is_some()));
+            Ok(value)
+        }
+
+        fn remove(&self, key: &str, next: NextRemove<'_>) -> Result<bool, E> {
+            if key.starts_with("protected/") {
+                return Err(E::Rejected(format!("{key} is protected")));
+            }
+            self.0.lock().unwrap().push(format!("remove {key}"));
+            next(key)
+        }
+    }
+
+    #[test]
+    fn middleware() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        storage.layer(Audit(calls.clone()));
+        storage.set("a", &String::from("value"))?;
+        storage.set("protected/b", &1u8)?;
+        assert_eq!(storage.get::<String, _>("a")?, Some(String::from("value")));
+        assert!(storage.get::<String, _>("missing")?.is_none());
+        assert!(matches!(storage.remove("protected/b"), Err(E::Rejected(_))));
+        assert!(storage.has("protected/b"));
+        assert_eq!(storage.get_sensitive::<u8, _>("protected/b")?, Some(1));
+        // Searches bypass middlewares
+        assert_eq!(storage.filter(|_: &String| true)?.len(), 1);
+        assert_eq!(storage.scan::<u8>().filter_map(Result::ok).count(), 1);
+        assert!(storage.remove("a")?);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "set a",
+                "set protected/b",
+                "get a true",
+                "get missing false",
+                "get protected/b true",
+                "remove a"
+            ]
+        );
+        // Middlewares cannot change values
+        storage.layer(Invert);
+        assert!(matches!(storage.set("c", &1u8), Err(E::Rejected(_))));
+        assert!(!storage.has("c"));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    sync::{OnceLock, RwLock},
+};
+
+use crate::fnv1a;
+
+/// `TypeName` gives the type a stable name. By default records are tagged with the hash of the
+/// name of the type given by Rust, which isn't stable between versions of Rust and changes if the
+/// type is moved, so searches can't rely on it and read each record. Records of the type with a
+/// stable name (registered with `register_type()`) are tagged with the hash of this name
+/// instead, and typed searches (`find`, `filter`, `scan`, etc.) of another registered type skip
+/// them without reading their files.
+pub trait TypeName: 'static {
+    /// Stable name of the type. It should be unique within the storage and shouldn't be changed
+    /// while records of the type are stored.
+    const NAME: &'static str;
+}
+
+#[derive(Default)]
+struct Names {
+    /// Stable tags of registered types
+    types: HashMap<TypeId, u64>,
+    /// All stable tags
+    tags: HashSet<u64>,
+}
+
+fn names() -> &'static RwLock<Names> {
+    static NAMES: OnceLock<RwLock<Names>> = OnceLock::new();
+    NAMES.get_or_init(Default::default)
+}
+
+/// Registers the stable name of the type `V` (see `TypeName`). Records of the type written after
+/// the registration are tagged with the stable name; records written before are read by searches
+/// as usual. Types should be registered before storages are opened, in each process, which uses
+/// the storage.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{register_type, Search, Storage, TypeName};
+/// use serde::{Deserialize, Serialize};
+/// use std::env::temp_dir;
+/// use uuid::Uuid;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct User {
+///     name: String,
+/// }
+///
+/// impl TypeName for User {
+///     const NAME: &'static str = "app::User";
+/// }
+///
+/// register_type::<User>();
+/// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// storage
+///     .set("user:1", &User { name: String::from("Ann") })
+///     .unwrap();
+/// assert_eq!(storage.filter(|_: &User| true).unwrap().len(), 1);
+/// storage.destroy().unwrap();
+/// ```
+pub fn register_type<V: TypeName>() {
+    let tag = fnv1a(V::NAME.as_bytes());
+    let mut names = names().write().unwrap_or_else(|e| e.into_inner());
+    names.types.insert(TypeId::of::<V>(), tag);
+    names.tags.insert(tag);
+}
+
+/// Returns the stable tag of the type, if the type has been registered with `register_type()`.
+pub(crate) fn stable_tag(id: TypeId) -> Option<u64> {
+    names()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .types
+        .get(&id)
+        .copied()
+}
+
+/// Checks if the tag is the stable tag of a registered type.
+pub(crate) fn is_stable(tag: u64) -> bool {
+    names()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .tags
+        .contains(&tag)
+}
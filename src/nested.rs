@@ -0,0 +1,190 @@
+use std::{
+    fs::{read_dir, remove_dir_all},
+    path::{Path, PathBuf},
+};
+
+use crate::{fs, internal::INTERNAL_NAME, Storage, E};
+
+/// Folder inside of the storage folder, which contains child storages
+pub(crate) const CHILDREN_DIR: &str = "children";
+
+/// Returns folders of child storages of the storage in the given folder.
+///
+/// # Arguments
+///
+/// * `cwd` - A path reference to the storage folder.
+///
+/// # Returns
+///
+/// * `Result<Vec<(String, PathBuf)>, E>` - Returns names and folders of child storages, sorted by
+///   name, or an error.
+pub(crate) fn children_of(cwd: &Path) -> Result<Vec<(String, PathBuf)>, E> {
+    let dir = cwd.join(CHILDREN_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut children = Vec::new();
+    for entry in read_dir(&dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        if let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) {
+            children.push((name, path));
+        }
+    }
+    children.sort();
+    Ok(children)
+}
+
+impl Storage {
+    /// Returns the folder of the child storage. Each segment of the path is a level of nesting:
+    /// `"plugins/foo"` is the child `foo` of the child `plugins`.
+    fn child_path(&self, path: &str) -> Result<PathBuf, E> {
+        let mut cwd = self.cwd.clone();
+        for name in path.split('/') {
+            if !fs::is_portable_name(name) || name == INTERNAL_NAME {
+                return Err(E::InvalidStorageName(path.to_owned()));
+            }
+            cwd = cwd.join(CHILDREN_DIR).join(name);
+        }
+        Ok(cwd)
+    }
+
+    /// Opens the child storage, creating it if it doesn't exist. Child storages are stored in
+    /// subfolders of the storage and have isolated keyspaces; they are packed together with the
+    /// storage (see `Bundle`), removed by `clear()` and `destroy()` of the storage. The child
+    /// storage is opened with the options of this storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the child storage; segments separated by `/` are levels of nesting.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the child storage, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let mut plugin = storage.child("plugins/foo").unwrap();
+    /// plugin.set("enabled", &true).unwrap();
+    /// assert!(!storage.has("enabled"));
+    /// assert_eq!(storage.children().unwrap(), vec![String::from("plugins")]);
+    /// drop(plugin);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn child<P: AsRef<str>>(&self, path: P) -> Result<Storage, E> {
+        let cwd = self.child_path(path.as_ref())?;
+        if !cwd.exists() {
+            fs::create_dir(&cwd, self.options.permissions.dir)?;
+        }
+        Storage::open_with_options(cwd, self.options.clone())
+    }
+
+    /// Checks if the child storage exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the child storage; segments separated by `/` are levels of nesting.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the child storage exists.
+    pub fn has_child<P: AsRef<str>>(&self, path: P) -> bool {
+        self.child_path(path.as_ref()).is_ok_and(|cwd| cwd.is_dir())
+    }
+
+    /// Returns names of direct child storages, sorted. Internal areas (see `Storage::internal()`)
+    /// aren't listed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns names of child storages, or an error.
+    pub fn children(&self) -> Result<Vec<String>, E> {
+        Ok(children_of(&self.cwd)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name != INTERNAL_NAME)
+            .collect())
+    }
+
+    /// Removes the child storage with all its records and nested storages. The child storage
+    /// should not be used after this call.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the child storage; segments separated by `/` are levels of nesting.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the child storage has been removed, false if it
+    ///   doesn't exist, or an error.
+    pub fn remove_child<P: AsRef<str>>(&self, path: P) -> Result<bool, E> {
+        let cwd = self.child_path(path.as_ref())?;
+        if !cwd.exists() {
+            return Ok(false);
+        }
+        remove_dir_all(cwd)?;
+        Ok(true)
+    }
+
+    /// Removes all child storages.
+    pub(crate) fn clear_children(&self) -> Result<(), E> {
+        let dir = self.cwd.join(CHILDREN_DIR);
+        if dir.exists() {
+            remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bundle, Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn nested() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("root", &0u8)?;
+        let mut foo = storage.child("plugins/foo")?;
+        foo.set("key", &1u8)?;
+        let mut bar = storage.child("plugins/bar")?;
+        bar.set("key", &2u8)?;
+        drop(foo);
+        drop(bar);
+        assert!(storage.child("plugins/../x").is_err());
+        assert!(storage.has_child("plugins/foo"));
+        assert_eq!(
+            storage.child("plugins")?.children()?,
+            vec![String::from("bar"), String::from("foo")]
+        );
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        storage.clear()?;
+        assert!(!storage.has_child("plugins"));
+        let mut unpacked = Storage::unpack(&bundle)?;
+        assert_eq!(unpacked.get::<u8, &str>("root")?, Some(0));
+        assert_eq!(
+            unpacked.child("plugins/foo")?.get::<u8, &str>("key")?,
+            Some(1)
+        );
+        assert_eq!(
+            unpacked.child("plugins/bar")?.get::<u8, &str>("key")?,
+            Some(2)
+        );
+        assert!(unpacked.remove_child("plugins/bar")?);
+        assert!(!unpacked.has_child("plugins/bar"));
+        unpacked.destroy()?;
+        storage.destroy()?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+}
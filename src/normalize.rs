@@ -0,0 +1,118 @@
+use std::borrow::Cow;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::Storage;
+
+/// Defines how keys of records are normalized before they are used by `get`, `set`, `has`,
+/// `remove` and other methods taking keys, so keys like "Theme" and "theme" cannot become two
+/// records on some platforms and one on others. Keys aren't normalized by default.
+///
+/// Keys written before the normalizer has been enabled are kept as they are; such records
+/// aren't reachable with keys, which are normalized differently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyNormalizer {
+    /// Removes leading and trailing whitespaces.
+    pub trim: bool,
+    /// Brings keys to Unicode Normalization Form C, so composed and decomposed forms of the same
+    /// characters (e.g. "é" as one code point or as "e" with a combining accent) are one key.
+    pub nfc: bool,
+    /// Converts keys to lowercase.
+    pub lowercase: bool,
+}
+
+impl KeyNormalizer {
+    /// Returns true if keys are changed by the normalizer.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if at least one of normalizations is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.trim || self.nfc || self.lowercase
+    }
+
+    /// Normalizes the key. The key is copied only if it's changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Cow<str>` - The normalized key.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::KeyNormalizer;
+    ///
+    /// let normalizer = KeyNormalizer {
+    ///     trim: true,
+    ///     lowercase: true,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(normalizer.normalize(" Theme ".into()), "theme");
+    /// ```
+    pub fn normalize<'a>(&self, key: Cow<'a, str>) -> Cow<'a, str> {
+        let mut key = key;
+        if self.trim && key.trim() != key.as_ref() {
+            key = Cow::Owned(key.trim().to_owned());
+        }
+        if self.nfc && !is_nfc(&key) {
+            key = Cow::Owned(key.nfc().collect());
+        }
+        if self.lowercase && key.chars().any(|c| c.to_lowercase().ne([c])) {
+            key = Cow::Owned(key.to_lowercase());
+        }
+        key
+    }
+}
+
+impl Storage {
+    /// Normalizes the key according to `StorageOptions::normalizer`.
+    pub(crate) fn normalized<'a>(&self, key: Cow<'a, str>) -> Cow<'a, str> {
+        self.options.normalizer.normalize(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KeyNormalizer, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn normalizer() -> Result<(), E> {
+        let options = StorageOptions {
+            normalizer: KeyNormalizer {
+                trim: true,
+                nfc: true,
+                lowercase: true,
+            },
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        storage.set("Theme", &String::from("dark"))?;
+        storage.set(" theme ", &String::from("light"))?;
+        assert_eq!(storage.len(), 1);
+        assert!(storage.has("THEME"));
+        assert_eq!(
+            storage.get::<String, _>("theme")?,
+            Some(String::from("light"))
+        );
+        // Composed and decomposed forms of "é"
+        storage.set("caf\u{e9}", &1u8)?;
+        assert_eq!(storage.get::<u8, _>("cafe\u{301}")?, Some(1));
+        assert_eq!(storage.len(), 2);
+        assert!(storage.remove("Café")?);
+        assert!(storage.remove("THEME ")?);
+        assert!(storage.is_empty());
+        storage.destroy()?;
+        // Keys aren't normalized by default
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("Theme", &1u8)?;
+        assert!(!storage.has("theme"));
+        storage.destroy()?;
+        Ok(())
+    }
+}
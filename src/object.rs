@@ -0,0 +1,334 @@
+//! Storage kept in an object store (S3, GCS, Azure, etc. via the `object_store` crate;
+//! `object-store` feature). The layout of the bucket is the same as the layout of the storage
+//! folder: the map object and an object per record file under the given prefix.
+
+use log::warn;
+use object_store::{path::Path as ObjectPath, ObjectStore};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::remove_file,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    fs, map::MAP_FILE_NAME, recover::orphans, Durability, Map, Meta, Storage, StorageKey,
+    StorageOptions, E,
+};
+
+/// Version of the record file: size, time of the last write and type tag
+fn version(meta: &Meta) -> (u64, u64, u64) {
+    (meta.size, meta.accessed.get(), meta.tag)
+}
+
+/// `ObjectStorage` is a storage, which lives in an object store. Records are cached in a local
+/// folder: reads are served from the cache, writes go to the cache and are uploaded immediately
+/// (write-through). Changes made by other clients are downloaded with `refresh()`; only record
+/// files, which aren't cached yet, are downloaded.
+///
+/// Calls are blocking: requests to the object store are executed on an internal runtime, so
+/// methods should not be called from async code (use `spawn_blocking`).
+pub struct ObjectStorage {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+    /// Local cache of the storage
+    local: Storage,
+    runtime: Runtime,
+}
+
+impl ObjectStorage {
+    /// Opens the storage kept in the object store under the given prefix; the storage is created
+    /// if nothing is stored under the prefix yet. The cached copy is updated from the object store.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The object store.
+    /// * `prefix` - Prefix of objects of the storage, e.g. `"configs/fleet"`.
+    /// * `cache` - A path reference to the folder of the local cache.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the opened storage, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::ObjectStorage;
+    /// use object_store::memory::InMemory;
+    /// use std::{env::temp_dir, sync::Arc};
+    /// use uuid::Uuid;
+    ///
+    /// // Usually it's `AmazonS3Builder`, `GoogleCloudStorageBuilder`, etc.
+    /// let bucket = Arc::new(InMemory::new());
+    /// let mut a = ObjectStorage::open(bucket.clone(), "configs", temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let mut b = ObjectStorage::open(bucket, "configs", temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// a.set("timeout", &30u32).unwrap();
+    /// assert_eq!(b.get::<u32, _>("timeout").unwrap(), None);
+    /// b.refresh().unwrap();
+    /// assert_eq!(b.get::<u32, _>("timeout").unwrap(), Some(30));
+    /// a.local().destroy().unwrap();
+    /// b.local().destroy().unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(
+        store: Arc<dyn ObjectStore>,
+        prefix: &str,
+        cache: P,
+    ) -> Result<Self, E> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let cache = fs::as_path_buf(cache);
+        let local = Storage::create(&cache)?;
+        let mut storage = Self {
+            store,
+            prefix: ObjectPath::from(prefix),
+            local,
+            runtime,
+        };
+        storage.refresh()?;
+        Ok(storage)
+    }
+
+    /// Returns the object of the file of the storage.
+    fn object(&self, file: &str) -> ObjectPath {
+        self.prefix.child(file)
+    }
+
+    fn download(&self, file: &str, dest: &Path) -> Result<bool, E> {
+        let object = self.object(file);
+        let content = self.runtime.block_on(async {
+            match self.store.get(&object).await {
+                Ok(result) => result.bytes().await.map(Some),
+                Err(object_store::Error::NotFound { .. }) => Ok(None),
+                Err(err) => Err(err),
+            }
+        })?;
+        let Some(content) = content else {
+            return Ok(false);
+        };
+        fs::replace(dest, &content, Durability::Flush)?;
+        Ok(true)
+    }
+
+    fn upload(&self, file: &str, content: Vec<u8>) -> Result<(), E> {
+        let object = self.object(file);
+        self.runtime
+            .block_on(self.store.put(&object, content.into()))?;
+        Ok(())
+    }
+
+    fn delete(&self, file: &str) -> Result<(), E> {
+        let object = self.object(file);
+        match self.runtime.block_on(self.store.delete(&object)) {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Downloads changes made by other clients: the map and record files, which are changed or
+    /// aren't cached yet. Cached files, which aren't used anymore, are removed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn refresh(&mut self) -> Result<(), E> {
+        let cwd = self.local.cwd().clone();
+        self.local.flush()?;
+        // Records are replaced in place, so changed records are detected by metadata
+        let cached = self
+            .local
+            .fields
+            .values()
+            .map(|field| (field.file_name().to_owned(), version(field.meta())))
+            .collect::<HashMap<String, (u64, u64, u64)>>();
+        if !self.download(MAP_FILE_NAME, &cwd.join(MAP_FILE_NAME))? {
+            // Nothing is uploaded yet
+            return Ok(());
+        }
+        let (fields, missing) = Map::new(&cwd, &StorageOptions::default()).read()?;
+        let changed = fields
+            .iter()
+            .filter(|(_, field)| cached.get(field.file_name()) != Some(&version(field.meta())))
+            .map(|(key, field)| (key.to_owned(), field.path(&cwd)));
+        for (key, path) in changed.chain(missing) {
+            let Some(file) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                continue;
+            };
+            if !self.download(&file, &path)? {
+                warn!("Object of record \"{key}\" is missing in the object store");
+            }
+        }
+        self.local = Storage::open_with_options(&cwd, self.local.options.clone())?;
+        let known = self
+            .local
+            .fields
+            .values()
+            .map(|field| field.file_name().to_owned())
+            .collect::<HashSet<String>>();
+        for path in orphans(&cwd, &known)? {
+            remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the map and the record file of the key (if it exists) and deletes the previous
+    /// file of the record, if no other key uses it.
+    fn sync(&mut self, key: &str, previous: Option<String>) -> Result<(), E> {
+        self.local.flush()?;
+        if let Some(field) = self.local.fields.get(key) {
+            self.upload(field.file_name(), self.local.extract_raw(field)?)?;
+        }
+        let map = std::fs::read(self.local.cwd().join(MAP_FILE_NAME))?;
+        self.upload(MAP_FILE_NAME, map)?;
+        if let Some(previous) = previous {
+            let used = self
+                .local
+                .fields
+                .values()
+                .any(|field| field.file_name() == previous);
+            if !used {
+                self.delete(&previous)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the file name of the record of the key.
+    fn file_of(&self, key: &str) -> Option<String> {
+        self.local
+            .fields
+            .get(key)
+            .map(|field| field.file_name().to_owned())
+    }
+
+    /// Checks if the specified key exists in the cached storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the key exists, false otherwise.
+    pub fn has<K: StorageKey>(&self, key: K) -> bool {
+        self.local.has(key)
+    }
+
+    /// Retrieves a value by key from the cached storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        self.local.get(key)
+    }
+
+    /// Sets a value for the specified key and uploads it to the object store.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `value` - A reference to the value to be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn set<V: Serialize + 'static, K: StorageKey>(
+        &mut self,
+        key: K,
+        value: &V,
+    ) -> Result<(), E> {
+        let key = key.to_key();
+        let previous = self.file_of(&key);
+        self.local.set(key.as_ref(), value)?;
+        self.sync(&key, previous)
+    }
+
+    /// Removes the value associated with the specified key from the cache and the object store.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key was found and removed, false otherwise, or an error.
+    pub fn remove<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        let key = key.to_key();
+        let previous = self.file_of(&key);
+        if !self.local.remove(key.as_ref())? {
+            return Ok(false);
+        }
+        self.sync(&key, previous)?;
+        Ok(true)
+    }
+
+    /// Returns the local cache of the storage. Changes made directly in the cache aren't
+    /// uploaded to the object store.
+    ///
+    /// # Returns
+    ///
+    /// * `&mut Storage` - The cached storage.
+    pub fn local(&mut self) -> &mut Storage {
+        &mut self.local
+    }
+
+    /// Returns the folder of the local cache.
+    ///
+    /// # Returns
+    ///
+    /// * `&PathBuf` - The folder of the cache.
+    pub fn cache(&self) -> &PathBuf {
+        self.local.cwd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ObjectStorage, E};
+    use object_store::{memory::InMemory, ObjectStore};
+    use std::{env::temp_dir, sync::Arc};
+    use uuid::Uuid;
+
+    #[test]
+    fn object_store() -> Result<(), E> {
+        let bucket = Arc::new(InMemory::new());
+        let cache = || temp_dir().join(Uuid::new_v4().to_string());
+        let mut a = ObjectStorage::open(bucket.clone(), "fleet", cache())?;
+        for i in 0..10u32 {
+            a.set(format!("key_{i}"), &i)?;
+        }
+        let mut b = ObjectStorage::open(bucket.clone(), "fleet", cache())?;
+        assert_eq!(b.get::<u32, _>("key_3")?, Some(3));
+        a.set("key_3", &33u32)?;
+        assert!(a.remove("key_4")?);
+        assert!(!a.remove("key_4")?);
+        b.refresh()?;
+        assert_eq!(b.get::<u32, _>("key_3")?, Some(33));
+        assert!(!b.has("key_4"));
+        assert_eq!(b.local().len(), 9);
+        // Replaced and removed records are deleted from the object store
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+        let objects = runtime.block_on(async {
+            use futures::TryStreamExt;
+            bucket
+                .list(Some(&"fleet".into()))
+                .try_collect::<Vec<_>>()
+                .await
+        })?;
+        assert_eq!(objects.len(), 10);
+        // Files of removed records are removed from the cache
+        let cached = std::fs::read_dir(b.cache())?.count();
+        assert_eq!(cached, 10);
+        a.local().destroy()?;
+        b.local().destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    AccessTracking, BatchReads, BloomOptions, ChangeFeed, Codecs, CorruptionPolicy, EncryptionKey,
+    Eviction, FileSystem, HandlePoolOptions, History, KeyHasher, KeyNormalizer, KeyPolicy, Layout,
+    Limits, Maintenance, Mergers, PackCompression, RetryPolicy, Tiering,
+};
+
+/// Defines how the map of the storage (the file which binds keys to record files) is persisted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FlushMode {
+    /// The map is written synchronously as part of each modifying call (`set`, `remove`, etc.).
+    #[default]
+    Sync,
+    /// The map is written by a background thread. Modifying calls only put a snapshot of the map
+    /// into a bounded queue and return immediately. If the queue is full, the caller is blocked
+    /// until the background thread takes the next snapshot.
+    ///
+    /// `Storage::flush()` waits until all queued snapshots are written; dropping the storage
+    /// joins the background thread.
+    Background {
+        /// Maximum number of map snapshots waiting to be written.
+        queue: usize,
+        /// Calls `sync_all` on the map file after each write.
+        fsync: bool,
+    },
+}
+
+/// Defines what is done to make written records and maps survive a crash of the process or a
+/// power loss. Stronger levels make writes slower.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability {
+    /// Nothing is flushed explicitly; a custom `FileSystem` may keep written data in its buffers.
+    None,
+    /// Written data is handed over to the OS: it survives a crash of the process, but may be
+    /// lost with a power loss.
+    #[default]
+    Flush,
+    /// Written files are synced to the disk (`sync_all`) before they replace previous versions.
+    Fsync,
+    /// In addition to `Fsync` the folder is synced after a file has been created or renamed in
+    /// it, so the new name of the file survives a power loss as well (Unix only; on other
+    /// platforms it's the same as `Fsync`).
+    FsyncDir,
+}
+
+/// Permissions of files and folders created by the storage (Unix modes, e.g. `0o600` for files
+/// and `0o700` for folders, so records are readable by the owner only). Permissions are ignored
+/// on other platforms and with a custom `FileSystem`. By default permissions are defined by the
+/// system (umask).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    /// Mode of record files, the map and other files of the storage.
+    pub file: Option<u32>,
+    /// Mode of the storage folder and its subfolders.
+    pub dir: Option<u32>,
+}
+
+/// Defines how symlinks in place of the storage folder and of files of the storage are treated.
+/// Policies other than `Follow` keep a swapped symlink from redirecting reads and writes of the
+/// storage outside of its folder in multi-user environments. Symlinks are handled on Unix only
+/// and aren't checked with a custom `FileSystem`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Symlinks are followed.
+    #[default]
+    Follow,
+    /// The symlinked storage folder is resolved once, when the storage is opened, so swapping the
+    /// symlink later doesn't redirect the storage. Record files and the map are opened with
+    /// `O_NOFOLLOW`: a symlink in place of a file makes reading it fail.
+    NoFollow,
+    /// Opening a storage, which folder is a symlink, fails with `E::SymlinkRejected`. Files are
+    /// opened as with `NoFollow`.
+    Reject,
+}
+
+/// Coalesces writes of the map produced by bursts of modifying calls into one commit. The map is
+/// committed as soon as one of the limits is reached, with `Storage::flush()` or when the storage
+/// is dropped. Records themselves are always written immediately; only the map update is deferred.
+///
+/// Note, the delay is checked on each modifying call; there is no timer committing the map if the
+/// burst stopped. Call `Storage::flush()` to be sure all changes are persisted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupCommit {
+    /// Maximum number of deferred map updates.
+    pub max_pending: usize,
+    /// Maximum time since the first deferred map update.
+    pub max_delay: Duration,
+}
+
+impl Default for GroupCommit {
+    fn default() -> Self {
+        Self {
+            max_pending: 64,
+            max_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// `StorageOptions` allows tuning the behaviour of `Storage`. Options are passed into
+/// `Storage::create_with_options` or `Storage::open_with_options`; `Storage::create` and
+/// `Storage::open` use `StorageOptions::default()`.
+#[derive(Debug, Clone, Default)]
+pub struct StorageOptions {
+    /// Defines how the map of the storage is persisted.
+    pub flush: FlushMode,
+    /// Coalesces map writes of bursts of modifying calls. Disabled by default.
+    pub group_commit: Option<GroupCommit>,
+    /// Defines what to do with missing or broken record files. `CorruptionPolicy::Skip` by
+    /// default.
+    pub corruption: CorruptionPolicy,
+    /// Limits of the storage (total size, number of keys, size of a record). No limits by
+    /// default.
+    pub limits: Limits,
+    /// Turns the storage into a cache, which evicts least-recently-used records as soon as the
+    /// budget is exceeded. Disabled by default.
+    pub eviction: Option<Eviction>,
+    /// Tracks the time of the last read of each record (see `Storage::sweep_older_than()`).
+    /// Disabled by default; always enabled with `eviction`.
+    pub access: Option<AccessTracking>,
+    /// Maintenance tasks run by `Storage::maintain()` and, optionally, when the storage is
+    /// opened. `Maintenance::default()` is used by `Storage::maintain()` if not set.
+    pub maintenance: Option<Maintenance>,
+    /// Defines how records are placed into files. `Layout::PerKey` by default.
+    pub layout: Layout,
+    /// Keeps the bloom filter of keys next to the map file. Disabled by default.
+    pub bloom: Option<BloomOptions>,
+    /// Keeps recently read record files open (see `HandlePoolOptions`). Disabled by default.
+    pub handles: Option<HandlePoolOptions>,
+    /// Defines how `Search` methods read many records at once (see `BatchReads`).
+    pub batch: BatchReads,
+    /// File primitives used by the core of the storage (see `FileSystem`). `StdFs` is used if
+    /// not set.
+    pub fs: Option<Arc<dyn FileSystem>>,
+    /// Defines whether writes of records and of the map are synced to the disk.
+    /// `Durability::Flush` by default.
+    pub durability: Durability,
+    /// Retries reads, writes and removals of records and of the map failed with transient
+    /// errors (see `RetryPolicy`). Disabled by default.
+    pub retry: Option<RetryPolicy>,
+    /// Keeps previous versions of changed records, so the storage can be restored as it was at
+    /// a moment in the past (see `History`). Disabled by default.
+    pub history: Option<History>,
+    /// Types of records, which are merged by `Storage::merge_from()` and `sync::bidirectional()`
+    /// instead of picking one of versions (see `Merge`). No types by default.
+    pub merge: Mergers,
+    /// Key used to encrypt `Sensitive` values of records. Without the key such values can be
+    /// neither written nor read. None by default.
+    pub encryption: Option<EncryptionKey>,
+    /// Compression and encryption of values in record files, storage-wide and per key or prefix
+    /// of keys (see `Codecs`). Values are written as they are by default.
+    pub codecs: Codecs,
+    /// Computes the SHA-256 digest of each written value and keeps it in the map, so
+    /// `Storage::manifest()` doesn't read records and `Storage::verify()` checks digests along
+    /// with checksums. Disabled by default.
+    pub digests: bool,
+    /// Permissions of created files and folders (see `Permissions`). Defined by the system by
+    /// default.
+    pub permissions: Permissions,
+    /// Treatment of symlinks in place of the storage folder and its files (see `SymlinkPolicy`).
+    /// Symlinks are followed by default.
+    pub symlinks: SymlinkPolicy,
+    /// Compression of payloads of records in bundles written by `Bundle::pack()` (see
+    /// `PackCompression`). Payloads aren't compressed by default.
+    pub pack_compression: PackCompression,
+    /// Keeps a copy of the map (`map.bstorage.1`), which is written along with the map; if the
+    /// map is damaged (e.g. by a bad sector), the storage is opened with the copy. Disabled by
+    /// default.
+    pub map_copy: bool,
+    /// Reconciles the map with the folder of the storage when it's opened (see
+    /// `Storage::reconcile()`); the report is available with `Storage::reconciled()`. Disabled
+    /// by default.
+    pub reconcile: bool,
+    /// Hash function of the in-memory map of keys (see `KeyHasher`). `KeyHasher::Random` by
+    /// default.
+    pub hasher: KeyHasher,
+    /// Iterates records in sorted order of keys (searches, packing), so results and bundles are
+    /// reproducible. Disabled by default: records are iterated in arbitrary order and packed in
+    /// order of their files.
+    pub sorted: bool,
+    /// Keeps an ordered index of keys in memory, so `Storage::keys_in_range()` and
+    /// `Storage::sorted_keys()` don't sort all keys on each call. Disabled by default.
+    pub ordered: bool,
+    /// Normalizes keys of records (see `KeyNormalizer`). Keys aren't normalized by default.
+    pub normalizer: KeyNormalizer,
+    /// Rules, which keys of records have to follow (see `KeyPolicy`). Only `INDEX_PREFIX` is
+    /// reserved by default.
+    pub key_policy: KeyPolicy,
+    /// Keeps the log of changes of records, which other processes can follow (see
+    /// `ChangeFeed`). Disabled by default.
+    pub changes: Option<ChangeFeed>,
+    /// Moves rarely used records into the archive with `Storage::archive_cold()` (see
+    /// `Tiering`). Disabled by default.
+    pub tiering: Option<Tiering>,
+}
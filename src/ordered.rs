@@ -0,0 +1,156 @@
+use std::{
+    collections::BTreeSet,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::Storage;
+
+impl Storage {
+    /// Adds the key to the ordered index, if `StorageOptions::ordered` is used.
+    pub(crate) fn index_key(&mut self, key: &str) {
+        if let Some(ordered) = self.ordered.as_mut() {
+            if !ordered.contains(key) {
+                ordered.insert(key.to_owned());
+            }
+        }
+    }
+
+    /// Removes the key from the ordered index, if `StorageOptions::ordered` is used.
+    pub(crate) fn unindex_key(&mut self, key: &str) {
+        if let Some(ordered) = self.ordered.as_mut() {
+            ordered.remove(key);
+        }
+    }
+
+    /// Returns keys within the range, sorted. With `StorageOptions::ordered` keys are taken from
+    /// the ordered index; otherwise all keys are filtered and sorted. Expired keys are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - A range of keys, e.g. `"2024-01-01".."2024-02-01"`.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<&String>` - Keys within the range in ascending order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Storage, StorageOptions};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let options = StorageOptions {
+    ///     ordered: true,
+    ///     ..Default::default()
+    /// };
+    /// let mut storage =
+    ///     Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)
+    ///         .unwrap();
+    /// storage.set("2024-01-15/report", &1u8).unwrap();
+    /// storage.set("2024-02-03/report", &2u8).unwrap();
+    /// assert_eq!(
+    ///     storage.keys_in_range("2024-01-01".."2024-02-01"),
+    ///     vec!["2024-01-15/report"]
+    /// );
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn keys_in_range<'a, R: RangeBounds<&'a str>>(&self, range: R) -> Vec<&String> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        // `BTreeSet::range()` panics on such ranges; nothing is within them anyway
+        if let (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) = bounds
+        {
+            let excluded = matches!(bounds, (Bound::Excluded(_), Bound::Excluded(_)));
+            if start > end || (start == end && excluded) {
+                return Vec::new();
+            }
+        }
+        let keys = match self.ordered.as_ref() {
+            Some(ordered) => ordered.range::<str, _>(bounds).collect::<Vec<&String>>(),
+            None => {
+                let mut keys = self
+                    .fields
+                    .keys()
+                    .filter(|key| RangeBounds::<str>::contains(&bounds, key.as_str()))
+                    .collect::<Vec<&String>>();
+                keys.sort();
+                keys
+            }
+        };
+        keys.into_iter().filter(|key| !self.expired(key)).collect()
+    }
+
+    /// Returns all keys of the storage, sorted (see `Storage::keys_in_range()`).
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<&String>` - Keys in ascending order.
+    pub fn sorted_keys(&self) -> Vec<&String> {
+        self.keys_in_range(..)
+    }
+
+    /// Builds the ordered index of keys, if `StorageOptions::ordered` is used.
+    pub(crate) fn build_ordered(&mut self) {
+        self.ordered = self
+            .options
+            .ordered
+            .then(|| self.fields.keys().cloned().collect::<BTreeSet<String>>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn ordered() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            ordered: true,
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        for month in (1..=12u8).rev() {
+            for day in [1u8, 15] {
+                storage.set(format!("2024-{month:02}-{day:02}"), &(month, day))?;
+            }
+        }
+        let january = vec!["2024-01-01", "2024-01-15"];
+        assert_eq!(storage.keys_in_range("2024-01-01".."2024-02-01"), january);
+        assert_eq!(storage.keys_in_range("2024-12-01"..).len(), 2);
+        assert_eq!(storage.keys_in_range(..="2024-01-01"), vec!["2024-01-01"]);
+        assert!(storage.keys_in_range("2024-02-01".."2024-01-01").is_empty());
+        assert_eq!(storage.sorted_keys().len(), 24);
+        assert!(storage
+            .sorted_keys()
+            .windows(2)
+            .all(|keys| keys[0] < keys[1]));
+        // The index follows changes of the storage
+        storage.remove("2024-01-15")?;
+        storage.set("2024-01-20", &(1u8, 20u8))?;
+        assert_eq!(
+            storage.keys_in_range("2024-01-01".."2024-02-01"),
+            vec!["2024-01-01", "2024-01-20"]
+        );
+        drop(storage);
+        // The index is built when the storage is opened; without it keys are sorted on demand
+        let reopened = Storage::open_with_options(&storage_path, options)?;
+        let unordered = Storage::open(&storage_path)?;
+        assert!(unordered.ordered.is_none());
+        assert_eq!(
+            reopened.keys_in_range("2024-03-01".."2024-06-01"),
+            unordered.keys_in_range("2024-03-01".."2024-06-01")
+        );
+        drop(reopened);
+        let mut storage = unordered;
+        storage.clear()?;
+        assert!(storage.sorted_keys().is_empty());
+        storage.destroy()?;
+        Ok(())
+    }
+}
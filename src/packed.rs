@@ -0,0 +1,233 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    bundle::{read_index, Index, Location},
+    fs,
+    internal::INTERNAL_NAME,
+    Field, Operation, Storage, StorageKey, E,
+};
+
+/// `PackedStorage` is a read-only storage opened directly from a bundle (see `Bundle`). Records
+/// are read straight out of the bundle file, so it isn't extracted anywhere. It's useful for
+/// datasets shipped with an application, which are never changed.
+#[derive(Debug)]
+pub struct PackedStorage {
+    /// Path to the bundle file
+    path: PathBuf,
+    /// Bundle file shared with child storages
+    bundle: Arc<File>,
+    /// Positions of records in the bundle
+    records: HashMap<String, Location>,
+    /// Indexes of child storages
+    children: HashMap<String, Index>,
+}
+
+impl PackedStorage {
+    fn new(path: PathBuf, bundle: Arc<File>, index: Index) -> Self {
+        Self {
+            path,
+            bundle,
+            records: index
+                .records
+                .into_iter()
+                .filter(|location| location.is_valid())
+                .map(|location| (location.key.clone(), location))
+                .collect(),
+            children: index.children.into_iter().collect(),
+        }
+    }
+
+    /// Checks if the specified key exists in the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the key exists, false otherwise.
+    pub fn has<K: StorageKey>(&self, key: K) -> bool {
+        self.records.contains_key(key.to_key().as_ref())
+    }
+
+    /// Retrieves a value by key. The content of the record is read from the bundle and its
+    /// checksum is verified. Records are kept in bundles as they have been written, so records
+    /// written with a codec or transformers (see `Codecs`) aren't read (`E::EncodedRecord`);
+    /// unpack the bundle and open the storage with its options to read them.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        let key = key.to_key();
+        let Some(content) = self.content_of(&key)? else {
+            return Ok(None);
+        };
+        Field::value::<V>(&content).map_err(|e| e.record(Operation::Get, &key, &self.path))
+    }
+
+    /// Reads the content of the record file of the key from the bundle.
+    pub(crate) fn content_of(&self, key: &str) -> Result<Option<Vec<u8>>, E> {
+        let Some(location) = self.records.get(key) else {
+            return Ok(None);
+        };
+        location
+            .read(&self.bundle)
+            .map(Some)
+            .map_err(|e| E::from(e).record(Operation::Get, key, &self.path))
+    }
+
+    /// Returns keys of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = &String>` - Keys of records.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.records.keys()
+    }
+
+    /// Returns a number of records in the storage
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - number of records in the storage
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if the storage doesn't have any records
+    ///
+    /// # Returns
+    ///
+    /// * `true` - if no records in the storage
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns names of child storages packed into the bundle.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<String>` - Names of child storages, sorted; internal areas aren't listed.
+    pub fn children(&self) -> Vec<String> {
+        let mut names = self
+            .children
+            .keys()
+            .filter(|name| *name != INTERNAL_NAME)
+            .cloned()
+            .collect::<Vec<String>>();
+        names.sort();
+        names
+    }
+
+    /// Opens the child storage packed into the bundle (see `Storage::child()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the child storage; segments separated by `/` are levels of nesting.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<PackedStorage>` - Returns the child storage, or None if it isn't in the bundle.
+    pub fn child<P: AsRef<str>>(&self, path: P) -> Option<PackedStorage> {
+        let (name, rest) = match path.as_ref().split_once('/') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (path.as_ref(), None),
+        };
+        let index = self.children.get(name)?;
+        let child = PackedStorage::new(self.path.clone(), self.bundle.clone(), index.clone());
+        match rest {
+            Some(rest) => child.child(rest),
+            None => Some(child),
+        }
+    }
+}
+
+impl Storage {
+    /// Opens the bundle (see `Bundle`) as a read-only storage without unpacking it.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<PackedStorage, E>` - Returns the read-only storage, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Bundle, Storage};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("dataset", &vec![1u32, 2, 3]).unwrap();
+    /// let bundle = temp_dir().join(Uuid::new_v4().to_string());
+    /// storage.pack(&bundle).unwrap();
+    /// storage.destroy().unwrap();
+    /// let packed = Storage::open_bundle(&bundle).unwrap();
+    /// assert_eq!(
+    ///     packed.get::<Vec<u32>, _>("dataset").unwrap(),
+    ///     Some(vec![1, 2, 3])
+    /// );
+    /// std::fs::remove_file(bundle).unwrap();
+    /// ```
+    pub fn open_bundle<P: AsRef<Path>>(bundle: P) -> Result<PackedStorage, E> {
+        let path = fs::as_path_buf(bundle);
+        if !path.is_file() {
+            return Err(E::PackageFileDoesNotExist(path));
+        }
+        let mut file = fs::read(&path)?;
+        let index = read_index(&mut file, &path)?;
+        Ok(PackedStorage::new(path, Arc::new(file), index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bundle, Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn packed() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..100u32 {
+            storage.set(format!("key_{i}"), &i)?;
+        }
+        storage.child("plugins/foo")?.set("enabled", &true)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        storage.pack(&bundle)?;
+        storage.destroy()?;
+        let packed = Storage::open_bundle(&bundle)?;
+        assert_eq!(packed.len(), 100);
+        assert!(packed.has("key_99"));
+        assert_eq!(packed.get::<u32, _>("key_42")?, Some(42));
+        assert_eq!(packed.get::<u32, _>("unknown")?, None);
+        assert_eq!(packed.children(), vec![String::from("plugins")]);
+        let foo = packed.child("plugins/foo").expect("Child is packed");
+        assert_eq!(foo.get::<bool, _>("enabled")?, Some(true));
+        assert!(packed.child("plugins/bar").is_none());
+        // Nothing is extracted
+        let mut unpacked = bundle.clone();
+        unpacked.set_extension("unpacked");
+        assert!(!unpacked.exists());
+        remove_file(&bundle)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{Storage, StorageKey, E};
+
+impl Storage {
+    /// Pins the record, so `clear()`, `clear_where()`, eviction (see `StorageOptions::eviction`)
+    /// and `sweep_older_than()` never remove it. Pinned records can still be removed with
+    /// `remove()` and expire, if the time to live is set. The pin is kept in the map of the
+    /// storage and survives overwriting of the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the record has been pinned, false if the key doesn't
+    ///   exist, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("settings", &1u8).unwrap();
+    /// storage.set("cache", &2u8).unwrap();
+    /// storage.pin("settings").unwrap();
+    /// storage.clear().unwrap();
+    /// assert!(storage.has("settings"));
+    /// assert!(!storage.has("cache"));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn pin<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        self.set_pinned(key, true)
+    }
+
+    /// Unpins the record (see `Storage::pin()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the record has been unpinned, false if the key
+    ///   doesn't exist, or an error.
+    pub fn unpin<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        self.set_pinned(key, false)
+    }
+
+    /// Returns true if the record is pinned (see `Storage::pin()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if the key exists and is pinned.
+    pub fn is_pinned<K: StorageKey>(&self, key: K) -> bool {
+        let key = self.normalized(key.to_key());
+        self.fields
+            .get(key.as_ref())
+            .is_some_and(|field| field.meta().pinned)
+    }
+
+    /// Returns keys of pinned records, sorted.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<&String>` - Keys of pinned records in ascending order.
+    pub fn pinned_keys(&self) -> Vec<&String> {
+        let mut keys = self
+            .fields
+            .iter()
+            .filter(|(_, field)| field.meta().pinned)
+            .map(|(key, _)| key)
+            .collect::<Vec<&String>>();
+        keys.sort();
+        keys
+    }
+
+    fn set_pinned<K: StorageKey>(&mut self, key: K, pinned: bool) -> Result<bool, E> {
+        let key = self.normalized(key.to_key());
+        if !self.has(key.as_ref()) {
+            return Ok(false);
+        }
+        let Some(field) = self.fields.get_mut(key.as_ref()) else {
+            return Ok(false);
+        };
+        if field.meta().pinned != pinned {
+            field.meta_mut().pinned = pinned;
+            self.map.write(&self.fields)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Eviction, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn pinned() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            eviction: Some(Eviction {
+                max_keys: Some(2),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        storage.set("settings", &1u8)?;
+        assert!(storage.pin("settings")?);
+        assert!(!storage.pin("missing")?);
+        // The oldest record is pinned, so the next one is evicted
+        storage.set("a", &2u8)?;
+        storage.set("b", &3u8)?;
+        assert!(storage.has("settings"));
+        assert!(!storage.has("a"));
+        // The pin survives overwriting and reopening
+        storage.set("settings", &4u8)?;
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert!(storage.is_pinned("settings"));
+        assert_eq!(storage.pinned_keys(), vec!["settings"]);
+        storage.clear()?;
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.get::<u8, _>("settings")?, Some(4));
+        assert!(storage.unpin("settings")?);
+        storage.clear()?;
+        assert!(storage.is_empty());
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use std::{borrow::Cow, collections::HashSet, fmt};
+
+use crate::{Storage, E};
+
+/// Prefix of keys reserved for secondary indexes of the storage
+pub const INDEX_PREFIX: &str = "__index/";
+
+/// Characters allowed in keys of records (see `KeyPolicy`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyCharset {
+    /// Any characters.
+    #[default]
+    Any,
+    /// Any characters except control ones (new lines, tabs, etc.).
+    Printable,
+    /// Printable ASCII characters.
+    Ascii,
+    /// ASCII letters, digits and `.`, `_`, `-`, `/`, `:`.
+    Portable,
+}
+
+impl KeyCharset {
+    /// Checks whether the character is allowed.
+    fn allows(&self, c: char) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Printable => !c.is_control(),
+            Self::Ascii => c.is_ascii_graphic() || c == ' ',
+            Self::Portable => c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/' | ':'),
+        }
+    }
+}
+
+/// Rules, which keys of records have to follow. `Storage::set()` fails with `E::KeyRejected`
+/// if the key breaks one of them; the storage isn't changed in this case. By default only
+/// `INDEX_PREFIX` is reserved.
+///
+/// Keys written before the policy has been changed are kept as they are; they can be found with
+/// `Storage::nonconforming_keys()` and renamed with `Storage::migrate_keys()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPolicy {
+    /// Maximum length of a key in bytes
+    pub max_len: Option<usize>,
+    /// Characters allowed in keys
+    pub charset: KeyCharset,
+    /// Prefixes of keys, which are reserved for internal records and cannot be set
+    pub reserved: Vec<String>,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        Self {
+            max_len: None,
+            charset: KeyCharset::Any,
+            reserved: vec![INDEX_PREFIX.to_owned()],
+        }
+    }
+}
+
+impl KeyPolicy {
+    /// Checks whether the key follows the policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), KeyViolation>` - Returns Ok(()) if the key follows the policy, or the
+    ///   broken rule.
+    pub fn check(&self, key: &str) -> Result<(), KeyViolation> {
+        if let Some(limit) = self.max_len.filter(|limit| key.len() > *limit) {
+            return Err(KeyViolation::TooLong {
+                limit,
+                len: key.len(),
+            });
+        }
+        if let Some(c) = key.chars().find(|c| !self.charset.allows(*c)) {
+            return Err(KeyViolation::Char(c));
+        }
+        if let Some(prefix) = self.reserved.iter().find(|prefix| key.starts_with(*prefix)) {
+            return Err(KeyViolation::Reserved(prefix.to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// Describes which rule of `KeyPolicy` the key breaks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyViolation {
+    /// The key is longer than `KeyPolicy::max_len`
+    TooLong { limit: usize, len: usize },
+    /// The key has a character, which isn't allowed by `KeyPolicy::charset`
+    Char(char),
+    /// The key starts with a reserved prefix
+    Reserved(String),
+    /// The key is already used by another record (see `Storage::migrate_keys()`)
+    Taken,
+}
+
+impl fmt::Display for KeyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong { limit, len } => write!(f, "{len} bytes; limit {limit} bytes"),
+            Self::Char(c) => write!(f, "character {c:?} isn't allowed"),
+            Self::Reserved(prefix) => write!(f, "prefix \"{prefix}\" is reserved"),
+            Self::Taken => write!(f, "key is already used"),
+        }
+    }
+}
+
+impl Storage {
+    /// Checks the key against `StorageOptions::key_policy`.
+    pub(crate) fn check_key(&self, key: &str) -> Result<(), E> {
+        self.options
+            .key_policy
+            .check(key)
+            .map_err(|violation| E::KeyRejected {
+                key: key.to_owned(),
+                violation,
+            })
+    }
+
+    /// Returns keys, which don't follow `StorageOptions::key_policy` (e.g. written before the
+    /// policy has been changed), with broken rules.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(String, KeyViolation)>` - Keys and broken rules, sorted by keys.
+    pub fn nonconforming_keys(&self) -> Vec<(String, KeyViolation)> {
+        let mut keys = self
+            .fields
+            .keys()
+            .filter_map(|key| {
+                self.options
+                    .key_policy
+                    .check(key)
+                    .err()
+                    .map(|violation| (key.to_owned(), violation))
+            })
+            .collect::<Vec<(String, KeyViolation)>>();
+        keys.sort_by(|(a, _), (b, _)| a.cmp(b));
+        keys
+    }
+
+    /// Renames records, which keys don't follow `StorageOptions::key_policy`. New keys are
+    /// normalized (see `StorageOptions::normalizer`) and checked before anything is changed: the
+    /// migration fails with `E::KeyRejected` if a new key doesn't follow the policy as well, or
+    /// if it's already used.
+    ///
+    /// # Arguments
+    ///
+    /// * `rename` - A closure, which takes the key and the broken rule and returns the new key,
+    ///   or None to remove the record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<(String, Option<String>)>, E>` - Returns migrated keys with their new keys
+    ///   (None for removed records), or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{KeyCharset, KeyPolicy, Storage, StorageOptions};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let path = temp_dir().join(Uuid::new_v4().to_string());
+    /// let mut storage = Storage::create(&path).unwrap();
+    /// storage.set("my record", &1u8).unwrap();
+    /// drop(storage);
+    /// let options = StorageOptions {
+    ///     key_policy: KeyPolicy {
+    ///         charset: KeyCharset::Portable,
+    ///         ..Default::default()
+    ///     },
+    ///     ..Default::default()
+    /// };
+    /// let mut storage = Storage::open_with_options(&path, options).unwrap();
+    /// assert_eq!(storage.nonconforming_keys().len(), 1);
+    /// storage
+    ///     .migrate_keys(|key, _| Some(key.replace(' ', "_")))
+    ///     .unwrap();
+    /// assert_eq!(storage.get::<u8, _>("my_record").unwrap(), Some(1));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn migrate_keys<F: Fn(&str, &KeyViolation) -> Option<String>>(
+        &mut self,
+        rename: F,
+    ) -> Result<Vec<(String, Option<String>)>, E> {
+        let mut migrations = Vec::new();
+        let mut taken = HashSet::new();
+        for (key, violation) in self.nonconforming_keys() {
+            let renamed = rename(&key, &violation)
+                .map(|renamed| self.normalized(Cow::Owned(renamed)).into_owned());
+            if let Some(renamed) = renamed.as_ref() {
+                self.check_key(renamed)?;
+                if self.fields.contains_key(renamed) || !taken.insert(renamed.to_owned()) {
+                    return Err(E::KeyRejected {
+                        key: renamed.to_owned(),
+                        violation: KeyViolation::Taken,
+                    });
+                }
+            }
+            migrations.push((key, renamed));
+        }
+        for (key, renamed) in migrations.iter() {
+            if let Some(renamed) = renamed {
+                if let Some((tag, payload)) = self.payload_of(key)? {
+                    self.set_bytes(renamed, tag, &payload)?;
+                }
+            }
+            self.remove_key(key)?;
+        }
+        Ok(migrations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KeyCharset, KeyPolicy, KeyViolation, Storage, StorageOptions, E, INDEX_PREFIX};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn key_policy() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        // Reserved prefix is rejected by default
+        assert!(matches!(
+            storage.set(format!("{INDEX_PREFIX}name"), &1u8),
+            Err(E::KeyRejected {
+                violation: KeyViolation::Reserved(_),
+                ..
+            })
+        ));
+        // Batches of changes (imports, merges, syncs, etc.) are checked as well
+        assert!(matches!(
+            storage.apply([(
+                format!("{INDEX_PREFIX}name"),
+                Some((0, bincode::serialize(&1u8)?))
+            )]),
+            Err(E::KeyRejected {
+                violation: KeyViolation::Reserved(_),
+                ..
+            })
+        ));
+        assert!(storage.is_empty());
+        storage.set("long_key_of_record", &1u8)?;
+        storage.set("bad key", &2u8)?;
+        storage.set("bad_key", &3u8)?;
+        storage.set("good", &4u8)?;
+        drop(storage);
+        let options = StorageOptions {
+            key_policy: KeyPolicy {
+                max_len: Some(8),
+                charset: KeyCharset::Portable,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert!(matches!(
+            storage.set("very_long_key", &1u8),
+            Err(E::KeyRejected {
+                violation: KeyViolation::TooLong { limit: 8, len: 13 },
+                ..
+            })
+        ));
+        assert!(matches!(
+            storage.set("a b", &1u8),
+            Err(E::KeyRejected {
+                violation: KeyViolation::Char(' '),
+                ..
+            })
+        ));
+        assert!(!storage.has("a b"));
+        assert_eq!(
+            storage.nonconforming_keys(),
+            vec![
+                (String::from("bad key"), KeyViolation::Char(' ')),
+                (
+                    String::from("long_key_of_record"),
+                    KeyViolation::TooLong { limit: 8, len: 18 }
+                ),
+            ]
+        );
+        // The new key is already used; nothing is changed
+        assert!(matches!(
+            storage.migrate_keys(|key, _| (key == "bad key").then(|| String::from("bad_key"))),
+            Err(E::KeyRejected {
+                violation: KeyViolation::Taken,
+                ..
+            })
+        ));
+        assert_eq!(storage.len(), 4);
+        let migrated = storage.migrate_keys(|key, violation| match violation {
+            KeyViolation::Char(_) => Some(key.replace(' ', "-")),
+            _ => None,
+        })?;
+        assert_eq!(
+            migrated,
+            vec![
+                (String::from("bad key"), Some(String::from("bad-key"))),
+                (String::from("long_key_of_record"), None),
+            ]
+        );
+        assert_eq!(storage.get::<u8, _>("bad-key")?, Some(2));
+        assert!(!storage.has("long_key_of_record"));
+        assert!(storage.nonconforming_keys().is_empty());
+        storage.destroy()?;
+        Ok(())
+    }
+}
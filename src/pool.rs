@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{fs, Field, Storage, E};
+
+/// Settings of the pool of open record files. Reading a record via the pool doesn't open and
+/// close its file each time: recently read files are kept open and read with positional reads.
+/// Writes still replace files atomically (see `Field::store()`), so the handle of a written
+/// record is closed and reopened with the next read. Before each read the open file is checked
+/// against its path, so files replaced or removed by other handles or processes aren't read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlePoolOptions {
+    /// Maximum number of files kept open. The least recently used file is closed to open a new
+    /// one.
+    pub capacity: usize,
+}
+
+impl Default for HandlePoolOptions {
+    fn default() -> Self {
+        Self { capacity: 64 }
+    }
+}
+
+/// Pool of open record files.
+#[derive(Debug)]
+pub(crate) struct HandlePool {
+    capacity: usize,
+    /// Open files by file names with the time (value of `clock`) of the last use
+    handles: Mutex<HashMap<String, (File, u64)>>,
+    clock: AtomicU64,
+    /// Access to record files (see `StorageOptions::symlinks`)
+    access: fs::Access,
+}
+
+impl HandlePool {
+    pub fn new(options: &HandlePoolOptions, access: fs::Access) -> Self {
+        Self {
+            access,
+            capacity: options.capacity.max(1),
+            handles: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the content of the file, opening it if it isn't open yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    /// * `file` - Name of the file in the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the content of the file, or an error.
+    pub fn read(&self, cwd: &Path, file: &str) -> Result<Vec<u8>, E> {
+        let used = self.clock.fetch_add(1, Ordering::Relaxed);
+        let path = cwd.join(file);
+        let mut handles = self.handles.lock().map_err(|_| E::Unknown)?;
+        if let Some((handle, last)) = handles.get_mut(file) {
+            if fs::is_same_file(handle, &path)? {
+                *last = used;
+                return Ok(fs::read_all_at(handle)?);
+            }
+            // The file has been replaced or removed by another handle or process
+            handles.remove(file);
+        }
+        let handle = fs::open_with(&path, self.access)?;
+        let content = fs::read_all_at(&handle)?;
+        if handles.len() >= self.capacity {
+            let lru = handles
+                .iter()
+                .min_by_key(|(_, (_, last))| *last)
+                .map(|(name, _)| name.to_owned());
+            if let Some(lru) = lru {
+                handles.remove(&lru);
+            }
+        }
+        handles.insert(file.to_owned(), (handle, used));
+        Ok(content)
+    }
+
+    /// Closes the file, if it's open.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - Name of the file in the storage folder.
+    pub fn release(&self, file: &str) {
+        if let Ok(mut handles) = self.handles.lock() {
+            handles.remove(file);
+        }
+    }
+
+    /// Closes all files.
+    pub fn clear(&self) {
+        if let Ok(mut handles) = self.handles.lock() {
+            handles.clear();
+        }
+    }
+}
+
+impl Storage {
+    /// Reads the content of the field's file (see `Storage::extract_raw()`) with the value
+    /// decoded, if the record has been written with a codec (see `Codecs`).
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - The field to read.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the content of the field's file, or an error.
+    pub(crate) fn extract(&self, field: &Field) -> Result<Vec<u8>, E> {
+        self.decoded(self.extract_raw(field)?)
+    }
+
+    /// Reads the content of the field's file as it is via the pool of open files (see
+    /// `StorageOptions::handles`), if it's used, or takes its content read by
+    /// `Storage::preload()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - The field to read.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the content of the field's file, or an error.
+    pub(crate) fn extract_raw(&self, field: &Field) -> Result<Vec<u8>, E> {
+        if let Some(content) = self.preloaded.take(field.file_name()) {
+            return Ok(content);
+        }
+        match self.handles.as_ref() {
+            Some(pool) => pool.read(&self.cwd, field.file_name()),
+            None => field.extract(&*self.fs, &self.cwd),
+        }
+    }
+
+    /// Closes the field's file in the pool of open files and drops its preloaded content before
+    /// the file is replaced or removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `field` - The field, which file is going to be changed.
+    pub(crate) fn release(&self, field: &Field) {
+        self.preloaded.release(field.file_name());
+        if let Some(pool) = self.handles.as_ref() {
+            pool.release(field.file_name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CorruptionKind, HandlePoolOptions, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn handles() -> Result<(), E> {
+        let options = StorageOptions {
+            handles: Some(HandlePoolOptions { capacity: 4 }),
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        for i in 0..10u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        for _ in 0..2 {
+            for i in 0..10u32 {
+                assert_eq!(storage.get::<u32, String>(i.to_string())?, Some(i));
+            }
+        }
+        let open = |storage: &Storage| {
+            storage
+                .handles
+                .as_ref()
+                .and_then(|pool| pool.handles.lock().ok().map(|handles| handles.len()))
+        };
+        assert_eq!(open(&storage), Some(4));
+        // Written records are read from the new files
+        storage.set("9", &90u32)?;
+        assert_eq!(storage.get::<u32, &str>("9")?, Some(90));
+        storage.remove("8")?;
+        assert_eq!(storage.get::<u32, &str>("8")?, None);
+        // Records changed by another handle aren't read from the open files
+        assert_eq!(storage.get::<u32, &str>("7")?, Some(7));
+        let mut other = Storage::open(storage.cwd())?;
+        other.set("7", &70u32)?;
+        assert_eq!(storage.get::<u32, &str>("7")?, Some(70));
+        std::fs::remove_file(storage.fields["7"].path(storage.cwd()))?;
+        assert_eq!(storage.get::<u32, &str>("7")?, None);
+        assert_eq!(storage.take_corruptions()[0].kind, CorruptionKind::Missing);
+        drop(other);
+        storage.clear()?;
+        assert_eq!(open(&storage), Some(0));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use crate::{type_tag, Storage, StorageKey, E};
+
+#[derive(Debug, Default)]
+struct State {
+    /// Contents of preloaded record files by file names
+    contents: HashMap<String, Vec<u8>>,
+    /// Number of running preloads
+    running: usize,
+    /// Increased each time all contents are dropped; preloads, which have started before,
+    /// don't keep their contents
+    epoch: u64,
+    /// Files, which have been changed or removed since running preloads have started; their
+    /// contents read by preloads are outdated
+    released: HashSet<String>,
+}
+
+/// Contents of record files read in advance by `Storage::preload()`. Each content is taken by the
+/// first read of the record, so the memory is released as soon as the record has been read.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Preloaded {
+    state: Arc<Mutex<State>>,
+}
+
+impl Preloaded {
+    /// Takes the preloaded content of the file.
+    pub(crate) fn take(&self, file: &str) -> Option<Vec<u8>> {
+        self.state.lock().ok()?.contents.remove(file)
+    }
+
+    /// Drops the preloaded content of the file before the file is changed or removed.
+    pub(crate) fn release(&self, file: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.contents.remove(file);
+            if state.running > 0 {
+                state.released.insert(file.to_owned());
+            }
+        }
+    }
+
+    /// Drops all preloaded contents.
+    pub(crate) fn clear(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.contents.clear();
+            state.epoch += 1;
+        }
+    }
+
+    /// Registers the running preload and returns the current epoch.
+    fn started(&self) -> u64 {
+        match self.state.lock() {
+            Ok(mut state) => {
+                state.running += 1;
+                state.epoch
+            }
+            Err(_) => 0,
+        }
+    }
+
+    fn finished(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.running -= 1;
+            if state.running == 0 {
+                state.released.clear();
+            }
+        }
+    }
+
+    /// Keeps the content read by the preload, unless the file has been changed since then.
+    fn keep(&self, file: String, content: Vec<u8>, epoch: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            if state.epoch == epoch && !state.released.contains(&file) {
+                state.contents.insert(file, content);
+            }
+        }
+    }
+}
+
+/// Running preload of records started with `Storage::preload()` or `Storage::preload_all()`.
+/// The preload keeps running, if the handle is dropped.
+#[derive(Debug)]
+pub struct Preload {
+    handle: JoinHandle<Result<usize, E>>,
+}
+
+impl Preload {
+    /// Returns true if all records have been read.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the preload to finish.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, E>` - Returns the number of preloaded records, or the first error of
+    ///   reading.
+    pub fn wait(self) -> Result<usize, E> {
+        self.handle.join().map_err(|_| E::Unknown)?
+    }
+}
+
+impl Storage {
+    /// Reads records of the keys in a background thread, so following `get()` of them doesn't
+    /// wait for the disk; e.g. an interactive application can preload the records it needs while
+    /// the splash screen is shown. A preloaded record is kept in memory until it's read for the
+    /// first time, changed or removed. Missing and expired keys are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Keys of records to read.
+    ///
+    /// # Returns
+    ///
+    /// * `Preload` - The handle of the running preload.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("theme", &String::from("dark")).unwrap();
+    /// storage.set("recent", &vec![1u32, 2, 3]).unwrap();
+    /// let preload = storage.preload(["theme", "recent", "missing"]);
+    /// // ... show the splash screen
+    /// assert_eq!(preload.wait().unwrap(), 2);
+    /// assert_eq!(storage.get::<String, _>("theme").unwrap(), Some(String::from("dark")));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn preload<K: StorageKey, I: IntoIterator<Item = K>>(&self, keys: I) -> Preload {
+        let files = keys
+            .into_iter()
+            .filter_map(|key| {
+                let key = self.normalized(key.to_key());
+                if self.expired(key.as_ref()) {
+                    return None;
+                }
+                self.fields
+                    .get(key.as_ref())
+                    .map(|field| field.file_name().to_owned())
+            })
+            .collect();
+        self.preload_files(files)
+    }
+
+    /// Reads records for values of the type `V` in a background thread (see
+    /// `Storage::preload()`). If `V` has a stable name (see `TypeName`), records of other types
+    /// with stable names are skipped; other records, which haven't expired, are read.
+    ///
+    /// # Returns
+    ///
+    /// * `Preload` - The handle of the running preload.
+    pub fn preload_all<V: 'static>(&self) -> Preload {
+        let tag = type_tag::<V>();
+        let files = self
+            .fields
+            .iter()
+            .filter(|(key, field)| field.is_of(tag) && !self.expired(key))
+            .map(|(_, field)| field.file_name().to_owned())
+            .collect();
+        self.preload_files(files)
+    }
+
+    fn preload_files(&self, mut files: Vec<String>) -> Preload {
+        files.sort();
+        files.dedup();
+        let fs = self.fs.clone();
+        let cwd: PathBuf = self.cwd.clone();
+        let preloaded = self.preloaded.clone();
+        let epoch = preloaded.started();
+        let handle = thread::spawn(move || {
+            let mut result = Ok(0);
+            for file in files {
+                let path = cwd.join(&file);
+                match fs.read(&path) {
+                    Ok(content) => {
+                        preloaded.keep(file, content, epoch);
+                        result = result.map(|count| count + 1);
+                    }
+                    Err(err) => {
+                        if result.is_ok() {
+                            result = Err(E::io(err, &path));
+                        }
+                    }
+                }
+            }
+            preloaded.finished();
+            result
+        });
+        Preload { handle }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn preload() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for n in 0..10u32 {
+            storage.set(format!("n/{n}"), &n)?;
+        }
+        storage.set("name", &String::from("value"))?;
+        assert_eq!(storage.preload_all::<u32>().wait()?, 11);
+        // Preloaded records are read from memory once
+        remove_file(storage.fields["n/1"].path(storage.cwd()))?;
+        assert_eq!(storage.get::<u32, _>("n/1")?, Some(1));
+        assert!(storage.get::<u32, _>("n/1")?.is_none());
+        // Changed records aren't read from the outdated preloaded content
+        storage.set("n/2", &20u32)?;
+        assert_eq!(storage.get::<u32, _>("n/2")?, Some(20));
+        assert_eq!(storage.preload(["name", "missing"]).wait()?, 1);
+        storage.clear()?;
+        assert!(storage.get::<String, _>("name")?.is_none());
+        storage.destroy()?;
+        Ok(())
+    }
+}
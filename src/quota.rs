@@ -0,0 +1,116 @@
+use std::fmt;
+
+/// Limits of the storage. Modifying calls (`set`, etc.) fail with `E::QuotaExceeded` if the change
+/// would exceed one of the limits; the storage isn't changed in this case. No limits by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum total size of all record files in bytes
+    pub max_total_size: Option<u64>,
+    /// Maximum number of keys
+    pub max_keys: Option<usize>,
+    /// Maximum size of a single serialized value in bytes
+    pub max_record_size: Option<u64>,
+    /// Size of a single serialized value in bytes, above which a warning is logged. Unlike
+    /// `max_record_size` the value is written. Large values are better split into several keys,
+    /// because each of them is read and written as a whole.
+    pub warn_record_size: Option<u64>,
+}
+
+/// Describes which limit has been exceeded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Quota {
+    /// Total size of record files would exceed `Limits::max_total_size`
+    TotalSize { limit: u64, requested: u64 },
+    /// Number of keys would exceed `Limits::max_keys`
+    Keys { limit: usize, requested: usize },
+    /// Size of the serialized value exceeds `Limits::max_record_size`
+    RecordSize { limit: u64, requested: u64 },
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TotalSize { limit, requested } => {
+                write!(f, "total size {requested} bytes; limit {limit} bytes")
+            }
+            Self::Keys { limit, requested } => {
+                write!(f, "number of keys {requested}; limit {limit}")
+            }
+            Self::RecordSize { limit, requested } => {
+                write!(f, "record size {requested} bytes; limit {limit} bytes")
+            }
+        }
+    }
+}
+
+/// Current usage of the storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Number of keys
+    pub keys: usize,
+    /// Total size of record files in bytes
+    pub bytes: u64,
+}
+
+/// Size of a record file: the length of its content and the space allocated for it on the disk.
+/// The allocated size is less than the logical one for sparse files and greater for preallocated
+/// files and files, which don't fill their last cluster.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordSize {
+    /// Length of the record file in bytes
+    pub logical: u64,
+    /// Space allocated for the record file in bytes
+    pub allocated: u64,
+}
+
+impl Limits {
+    /// Checks whether the storage with the given usage can accept the change of a record.
+    ///
+    /// # Arguments
+    ///
+    /// * `usage` - Returns the current usage of the storage; it's called only if the number of
+    ///   keys or the total size is limited, because counting the size takes a pass over all
+    ///   records.
+    /// * `replaced` - Size of the record file being replaced, or None if the key is new.
+    /// * `record` - Size of the serialized value.
+    /// * `file` - Size of the new record file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Quota>` - Returns Ok(()) if the change is accepted, or the exceeded limit.
+    pub(crate) fn check<U: FnOnce() -> Usage>(
+        &self,
+        usage: U,
+        replaced: Option<u64>,
+        record: u64,
+        file: u64,
+    ) -> Result<(), Quota> {
+        if let Some(limit) = self.max_record_size {
+            if record > limit {
+                return Err(Quota::RecordSize {
+                    limit,
+                    requested: record,
+                });
+            }
+        }
+        if self.max_keys.is_none() && self.max_total_size.is_none() {
+            return Ok(());
+        }
+        let usage = usage();
+        if let (Some(limit), None) = (self.max_keys, replaced) {
+            if usage.keys + 1 > limit {
+                return Err(Quota::Keys {
+                    limit,
+                    requested: usage.keys + 1,
+                });
+            }
+        }
+        if let Some(limit) = self.max_total_size {
+            let requested = usage.bytes.saturating_sub(replaced.unwrap_or_default()) + file;
+            if requested > limit {
+                return Err(Quota::TotalSize { limit, requested });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::{recover::orphans, Storage, E};
+
+/// Record file, which size differs from the size kept in the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeMismatch {
+    /// Key of the record
+    pub key: String,
+    /// Size of the record file kept in the map
+    pub expected: u64,
+    /// Actual size of the record file
+    pub found: u64,
+}
+
+/// The result of reconciliation of the map with the folder of the storage (see
+/// `Storage::reconcile()` and `StorageOptions::reconcile`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// Keys of the map, which record files don't exist, and paths of the files
+    pub missing: Vec<(String, PathBuf)>,
+    /// Record files, which aren't referenced by the map (left after crashes). They aren't looked
+    /// for in storages with a custom `FileSystem`.
+    pub unexpected: Vec<PathBuf>,
+    /// Record files, which sizes differ from sizes kept in the map
+    pub size_mismatches: Vec<SizeMismatch>,
+}
+
+impl ReconcileReport {
+    /// Returns true if the map and the folder agree with each other.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if no differences have been found.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.size_mismatches.is_empty()
+    }
+}
+
+impl Storage {
+    /// Reconciles the map with the folder of the storage: looks for keys, which record files are
+    /// missing, record files, which aren't referenced by the map, and record files, which sizes
+    /// differ from the map. Only metadata of files is read. Nothing is changed; see
+    /// `Storage::maintain()` to fix found problems.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ReconcileReport, E>` - Returns found differences, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("my_record", &1u8).unwrap();
+    /// assert!(storage.reconcile().unwrap().is_ok());
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn reconcile(&self) -> Result<ReconcileReport, E> {
+        self.reconcile_with(Vec::new())
+    }
+
+    /// Reconciles the map with the folder as `reconcile()` does; `missing` are keys of the map
+    /// detached when the storage has been opened.
+    pub(crate) fn reconcile_with(
+        &self,
+        mut missing: Vec<(String, PathBuf)>,
+    ) -> Result<ReconcileReport, E> {
+        let mut report = ReconcileReport::default();
+        let mut keys = self.fields.keys().collect::<Vec<&String>>();
+        keys.sort();
+        for key in keys {
+            let field = &self.fields[key];
+            let path = field.path(&self.cwd);
+            if !self.fs.exists(&path) {
+                missing.push((key.to_owned(), path));
+                continue;
+            }
+            let found = self.fs.size(&path).map_err(|e| E::io(e, &path))?;
+            if found != field.meta().size {
+                report.size_mismatches.push(SizeMismatch {
+                    key: key.to_owned(),
+                    expected: field.meta().size,
+                    found,
+                });
+            }
+        }
+        missing.sort();
+        report.missing = missing;
+        if self.options.fs.is_none() {
+            let known: HashSet<String> = self
+                .fields
+                .values()
+                .map(|field| field.file_name().to_owned())
+                .collect();
+            report.unexpected = orphans(&self.cwd, &known)?;
+            report.unexpected.sort();
+        }
+        Ok(report)
+    }
+
+    /// Returns the report of reconciliation done when the storage has been opened (see
+    /// `StorageOptions::reconcile`).
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&ReconcileReport>` - The report, or None if reconciliation hasn't been done.
+    pub fn reconciled(&self) -> Option<&ReconcileReport> {
+        self.reconciled.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn reconcile() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for i in 0..10u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        assert!(storage.reconcile()?.is_ok());
+        assert!(storage.reconciled().is_none());
+        let missing = storage.fields["1"].path(storage.cwd());
+        let resized = storage.fields["2"].path(storage.cwd());
+        drop(storage);
+        std::fs::remove_file(&missing)?;
+        let mut content = std::fs::read(&resized)?;
+        content.push(0);
+        std::fs::write(&resized, &content)?;
+        let orphan = storage_path.join("orphan.bstorage");
+        std::fs::write(&orphan, [1, 2, 3])?;
+        let options = StorageOptions {
+            reconcile: true,
+            ..Default::default()
+        };
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        let report = storage.reconciled().expect("Storage is reconciled on open");
+        assert!(!report.is_ok());
+        assert_eq!(report.missing, vec![(String::from("1"), missing)]);
+        assert_eq!(report.unexpected, vec![orphan]);
+        assert_eq!(report.size_mismatches.len(), 1);
+        assert_eq!(report.size_mismatches[0].key, "2");
+        assert_eq!(
+            report.size_mismatches[0].found,
+            report.size_mismatches[0].expected + 1
+        );
+        storage.destroy()?;
+        Ok(())
+    }
+}
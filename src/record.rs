@@ -0,0 +1,138 @@
+use serde::Deserialize;
+
+use crate::{trace::op, Field, Operation, Storage, StorageKey, E};
+
+/// `RecordGuard` holds the content of a record read by `Storage::get_ref()`. Values are
+/// deserialized from the held buffer, so `&str` and `&[u8]` fields of the value borrow from the
+/// guard instead of being copied.
+#[derive(Debug)]
+pub struct RecordGuard {
+    content: Vec<u8>,
+    /// Position of the payload (serialized value) in the content
+    start: usize,
+}
+
+impl RecordGuard {
+    /// Deserializes the value, which may borrow from the guard.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<V, E>` - Returns the value, or an error if it cannot be deserialized.
+    pub fn value<'a, V: Deserialize<'a>>(&'a self) -> Result<V, E> {
+        Ok(bincode::deserialize::<V>(self.bytes())?)
+    }
+
+    /// Returns the serialized value (`bincode`).
+    ///
+    /// # Returns
+    ///
+    /// * `&[u8]` - Serialized value.
+    pub fn bytes(&self) -> &[u8] {
+        &self.content[self.start..]
+    }
+}
+
+impl Storage {
+    /// Reads the record without deserializing it. The value is deserialized with
+    /// `RecordGuard::value()` and may borrow from the guard (zero-copy deserialization). Returns an
+    /// error if the record cannot be read or its checksum doesn't match. Expired records aren't
+    /// returned; archived records are read from the archive (see `Storage::archive_older_than()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<RecordGuard>, E>` - Returns the guard of the record if found, or None if
+    ///   not found, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use serde::{Deserialize, Serialize};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Document<'a> {
+    ///     title: &'a str,
+    ///     body: &'a str,
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage
+    ///     .set("doc", &Document { title: "Hello", body: "World" })
+    ///     .unwrap();
+    /// let guard = storage.get_ref("doc").unwrap().expect("Record exists");
+    /// let doc: Document = guard.value().unwrap();
+    /// assert_eq!(doc.title, "Hello");
+    /// assert_eq!(doc.body, "World");
+    /// drop(guard);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn get_ref<K: StorageKey>(&self, key: K) -> Result<Option<RecordGuard>, E> {
+        let key = self.normalized(key.to_key());
+        let op = op!("get", key, key.as_ref());
+        let Some(field) = self.fields.get(key.as_ref()) else {
+            return Ok(self
+                .archived_payload(key.as_ref())?
+                .map(|(_, content)| RecordGuard { content, start: 0 }));
+        };
+        if self.expired(key.as_ref()) {
+            return Ok(None);
+        }
+        self.accessed(field);
+        let content = self
+            .extract(field)
+            .map_err(|e| e.record(Operation::Get, &key, &field.path(&self.cwd)))?;
+        op.size(|| Some(content.len() as u64));
+        let start = Field::payload(&content)
+            .map(|(_, payload)| content.len() - payload.len())
+            .map_err(|e| e.record(Operation::Get, &key, &field.path(&self.cwd)))?;
+        Ok(Some(RecordGuard { content, start }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use serde::{Deserialize, Serialize};
+    use std::{env::temp_dir, time::Duration};
+    use uuid::Uuid;
+
+    #[derive(Serialize, Deserialize)]
+    struct Borrowed<'a> {
+        id: u32,
+        name: &'a str,
+    }
+
+    #[test]
+    fn zero_copy() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set(
+            "a",
+            &Borrowed {
+                id: 1,
+                name: "bstorage",
+            },
+        )?;
+        let guard = storage.get_ref("a")?.expect("Record exists");
+        let value: Borrowed = guard.value()?;
+        assert_eq!(value.id, 1);
+        assert_eq!(value.name, "bstorage");
+        // The value borrows from the guard
+        let bytes = guard.bytes().as_ptr_range();
+        assert!(bytes.contains(&value.name.as_ptr()));
+        assert!(storage.get_ref("b")?.is_none());
+        storage.set_with_ttl("b", &1u32, Duration::ZERO)?;
+        assert!(storage.get_ref("b")?.is_none());
+        storage.archive_older_than(Duration::ZERO)?;
+        assert!(storage.is_archived("a"));
+        let guard = storage.get_ref("a")?.expect("Record is archived");
+        assert_eq!(guard.value::<Borrowed>()?.name, "bstorage");
+        storage.destroy()?;
+        Ok(())
+    }
+}
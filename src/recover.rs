@@ -0,0 +1,211 @@
+use log::warn;
+use std::{
+    collections::HashSet,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    field::STORAGE_FILE_EXT, fs, map, Field, Header, KeyHasher, Map, Meta, StdFs, Storage,
+    StorageOptions, E,
+};
+
+/// A callback resolving the key of the record file by its path and content.
+pub type ResolveKey = Box<dyn Fn(&Path, &[u8]) -> Option<String>>;
+
+/// Defines how keys of orphaned record files are restored by `Storage::recover()`.
+pub enum KeyResolver {
+    /// The name of the record file (without extension) is used as the key.
+    Generated,
+    /// The key is taken from the header of the record file. Legacy record files (written by
+    /// versions of `bstorage` without headers) are skipped.
+    Embedded,
+    /// The key is defined by the callback, which gets the path of the record file and the
+    /// payload of the record (the serialized value, if the record has been written without a
+    /// codec and transformers). If the callback returns `None`, the file is skipped.
+    Callback(ResolveKey),
+}
+
+impl KeyResolver {
+    fn resolve(&self, cwd: &Path, field: &Field) -> Result<Option<String>, E> {
+        let path = field.path(cwd);
+        Ok(match self {
+            Self::Generated => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()),
+            Self::Embedded => {
+                let content = field.extract(&StdFs, cwd)?;
+                let (header, _) = Header::decode(&content)?;
+                header.map(|header| header.key)
+            }
+            Self::Callback(cb) => {
+                let content = field.extract(&StdFs, cwd)?;
+                let (_, payload) = Header::decode(&content)?;
+                cb(&path, payload)
+            }
+        })
+    }
+}
+
+/// Finds record files in the storage directory, which aren't referenced by the map.
+///
+/// # Arguments
+///
+/// * `cwd` - A path reference to the storage directory.
+/// * `known` - File names of records referenced by the map.
+///
+/// # Returns
+///
+/// * `Result<Vec<PathBuf>, E>` - Returns paths of orphaned record files, or an error.
+pub(crate) fn orphans(cwd: &Path, known: &HashSet<String>) -> Result<Vec<PathBuf>, E> {
+    let mut orphans = Vec::new();
+    for entry in read_dir(cwd)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        if !path.is_file()
+            || file_name == map::MAP_FILE_NAME
+            || known.contains(&file_name)
+            || path.extension().is_none_or(|ext| ext != STORAGE_FILE_EXT)
+        {
+            continue;
+        }
+        orphans.push(path);
+    }
+    Ok(orphans)
+}
+
+impl Storage {
+    /// Rebuilds the map of the storage. Records, which are referenced by the map (if it's still
+    /// readable), keep their keys; keys of orphaned record files (`*.bstorage` files, which aren't
+    /// referenced by the map) are restored with the given `KeyResolver`. Since each record file
+    /// keeps its key in the header, `KeyResolver::Embedded` restores original keys.
+    ///
+    /// This method should be used if the map file is missing or broken, which makes records
+    /// inaccessible via `Storage::open`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `resolver` - Defines how keys of orphaned files are restored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the recovered `Storage` instance or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{KeyResolver, Storage};
+    /// use std::{env::temp_dir, fs::remove_file};
+    /// use uuid::Uuid;
+    ///
+    /// let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+    /// let mut storage = Storage::create(&storage_path).expect("Storage created");
+    /// storage.set("my_record", &String::from("Hello World!")).expect("Record is saved");
+    /// drop(storage);
+    /// // Lose the map
+    /// remove_file(storage_path.join("map.bstorage")).expect("Map removed");
+    /// assert!(Storage::open(&storage_path).unwrap().is_empty());
+    /// // Restore keys from the content of records
+    /// let storage = Storage::recover(
+    ///     &storage_path,
+    ///     KeyResolver::Callback(Box::new(|_path, content| {
+    ///         bincode::deserialize::<String>(content)
+    ///             .ok()
+    ///             .map(|_| String::from("my_record"))
+    ///     })),
+    /// )
+    /// .expect("Storage recovered");
+    /// assert_eq!(
+    ///     storage.get::<String, &str>("my_record").unwrap(),
+    ///     Some(String::from("Hello World!"))
+    /// );
+    /// ```
+    pub fn recover<P: AsRef<Path>>(cwd: P, resolver: KeyResolver) -> Result<Self, E> {
+        if !cwd.as_ref().is_dir() {
+            return Err(E::PathIsNotFolder(fs::as_path_buf(cwd)));
+        }
+        let mut map = Map::new(&cwd, &StorageOptions::default());
+        let mut fields = match map.read() {
+            Ok((fields, _)) => fields,
+            Err(err) => {
+                warn!("Map of storage {:?} cannot be read: {err}", cwd.as_ref());
+                KeyHasher::default().fields()
+            }
+        };
+        let known = fields
+            .values()
+            .map(|field| field.file_name().to_owned())
+            .collect::<HashSet<String>>();
+        for path in orphans(cwd.as_ref(), &known)? {
+            let Some(file_name) = path.file_name() else {
+                continue;
+            };
+            let field = Field::restore(file_name.to_string_lossy(), Meta::from_file(&path));
+            let Some(key) = resolver.resolve(cwd.as_ref(), &field)? else {
+                warn!("Key for {path:?} isn't resolved; file is skipped");
+                continue;
+            };
+            if fields.contains_key(&key) {
+                warn!("Key \"{key}\" for {path:?} is already used; file is skipped");
+                continue;
+            }
+            fields.insert(key, field);
+        }
+        // The map is rebuilt, so instances, which have read the previous one, become stale
+        map.replace(&fields)?;
+        drop(map);
+        Storage::open(cwd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{map, KeyResolver, Storage, E};
+    use std::{env::temp_dir, fs::write};
+    use uuid::Uuid;
+
+    #[test]
+    fn recover() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for i in 0..10u8 {
+            storage.set(i.to_string(), &i)?;
+        }
+        drop(storage);
+        write(storage_path.join(map::MAP_FILE_NAME), [1, 2, 3])?;
+        assert!(Storage::open(&storage_path).is_err());
+        let storage = Storage::recover(&storage_path, KeyResolver::Generated)?;
+        assert_eq!(storage.len(), 10);
+        let mut values = storage
+            .into_iter()
+            .map(|key| storage.get::<u8, &String>(key))
+            .collect::<Result<Vec<Option<u8>>, E>>()?;
+        values.sort();
+        assert_eq!(values, (0..10u8).map(Some).collect::<Vec<Option<u8>>>());
+        let mut storage = Storage::recover(&storage_path, KeyResolver::Generated)?;
+        assert_eq!(storage.len(), 10);
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn recover_embedded() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for i in 0..10u8 {
+            storage.set(format!("key_{i}"), &i)?;
+        }
+        drop(storage);
+        std::fs::remove_file(storage_path.join(map::MAP_FILE_NAME))?;
+        let mut storage = Storage::recover(&storage_path, KeyResolver::Embedded)?;
+        assert_eq!(storage.len(), 10);
+        for i in 0..10u8 {
+            assert_eq!(storage.get::<u8, String>(format!("key_{i}"))?, Some(i));
+        }
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,501 @@
+//! Access to a storage owned by another process (`bstorage-server` feature). `StorageServer` owns
+//! the storage and serves requests of `RemoteStorage` clients over TCP or Unix sockets. Requests
+//! and responses are frames: the length of the body (u32, little endian) and the body serialized
+//! with `bincode`. Values are serialized by clients, so the server never deserializes them.
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use crate::{deserialize_tagged, type_tag, Search, Storage, StorageKey, E};
+
+/// Maximum size of a frame; larger frames are treated as broken.
+const MAX_FRAME: u32 = 256 * 1024 * 1024;
+
+/// Request of the client
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    Get(String),
+    Has(String),
+    Set(String, u64, Vec<u8>),
+    Remove(String),
+    Keys,
+    /// Records, which may keep values with the type tag (see `Field::is_of()`)
+    Scan(u64),
+}
+
+/// Response of the server
+#[derive(Debug, Serialize, Deserialize)]
+enum Response {
+    Value(Option<Vec<u8>>),
+    Bool(bool),
+    Keys(Vec<String>),
+    /// Keys, type tags and serialized values of records
+    Records(Vec<(String, u64, Vec<u8>)>),
+    Error(String),
+}
+
+/// Writes the frame with the message.
+fn send<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<(), E> {
+    let body = bincode::serialize(message)?;
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME)
+        .ok_or_else(|| E::Remote(format!("frame is too large: {} bytes", body.len())))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the frame with the message; returns None if the connection is closed.
+fn receive<R: Read, T: for<'a> Deserialize<'a>>(reader: &mut R) -> Result<Option<T>, E> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME {
+        return Err(E::Remote(format!("frame is too large: {len} bytes")));
+    }
+    let mut body = vec![0u8; len as usize];
+    reader.read_exact(&mut body)?;
+    Ok(Some(bincode::deserialize(&body)?))
+}
+
+/// `StorageServer` owns the storage and serves requests of `RemoteStorage` clients. Each
+/// connection is served by its own thread; requests are applied to the storage one by one.
+pub struct StorageServer {
+    storage: Arc<Mutex<Storage>>,
+}
+
+impl StorageServer {
+    /// Creates the server of the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The served storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The server.
+    pub fn new(storage: Storage) -> Self {
+        Self {
+            storage: Arc::new(Mutex::new(storage)),
+        }
+    }
+
+    /// Gives access to the served storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<MutexGuard<Storage>, E>` - The storage, or an error if it's poisoned.
+    pub fn storage(&self) -> Result<MutexGuard<'_, Storage>, E> {
+        self.storage.lock().map_err(|_| E::Unknown)
+    }
+
+    /// Listens to the address and serves connections until an error of the listener.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to listen to.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns an error of the listener.
+    pub fn serve_tcp<A: ToSocketAddrs>(&self, addr: A) -> Result<(), E> {
+        self.serve(TcpListener::bind(addr)?)
+    }
+
+    /// Serves connections of the TCP listener until an error of the listener.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - The listener.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns an error of the listener.
+    pub fn serve(&self, listener: TcpListener) -> Result<(), E> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            self.spawn(stream.try_clone()?, stream);
+        }
+        Ok(())
+    }
+
+    /// Serves connections of the Unix socket listener until an error of the listener.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - The listener.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns an error of the listener.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: std::os::unix::net::UnixListener) -> Result<(), E> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            self.spawn(stream.try_clone()?, stream);
+        }
+        Ok(())
+    }
+
+    fn spawn<R: Read + Send + 'static, W: Write + Send + 'static>(&self, reader: R, writer: W) {
+        let server = Self {
+            storage: self.storage.clone(),
+        };
+        thread::spawn(move || {
+            if let Err(err) = server.handle(reader, writer) {
+                warn!("Connection of remote storage is closed with error: {err}");
+            }
+        });
+    }
+
+    /// Serves requests of one connection until it's closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Reading half of the connection.
+    /// * `writer` - Writing half of the connection.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) when the connection is closed, or an error of the
+    ///   connection.
+    pub fn handle<R: Read, W: Write>(&self, reader: R, writer: W) -> Result<(), E> {
+        let mut reader = BufReader::new(reader);
+        let mut writer = BufWriter::new(writer);
+        while let Some(request) = receive::<_, Request>(&mut reader)? {
+            let response = self.apply(request).unwrap_or_else(|err| {
+                debug!("Request of remote storage failed: {err}");
+                Response::Error(err.to_string())
+            });
+            send(&mut writer, &response)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, request: Request) -> Result<Response, E> {
+        let mut storage = self.storage()?;
+        Ok(match request {
+            Request::Get(key) => {
+                Response::Value(storage.get_ref(key)?.map(|guard| guard.bytes().to_vec()))
+            }
+            Request::Has(key) => Response::Bool(storage.has(key)),
+            Request::Set(key, tag, payload) => {
+                storage.set_bytes(key, tag, &payload)?;
+                Response::Bool(true)
+            }
+            Request::Remove(key) => Response::Bool(storage.remove(key)?),
+            Request::Keys => Response::Keys(storage.into_iter().cloned().collect()),
+            Request::Scan(tag) => {
+                let mut records = Vec::new();
+                for (key, field) in storage.fields.iter().filter(|(_, f)| f.is_of(tag)) {
+                    if let Some(guard) = storage.get_ref(key)? {
+                        records.push((key.to_owned(), field.meta().tag, guard.bytes().to_vec()));
+                    }
+                }
+                Response::Records(records)
+            }
+        })
+    }
+}
+
+/// `RemoteStorage` is a client of the storage served by `StorageServer` in another process. It
+/// provides the same `get`/`set`/`remove` and `Search` API as `Storage`. Searching transfers all
+/// records of the searched type and evaluates conditions on the client.
+pub struct RemoteStorage {
+    connection: Mutex<(Box<dyn Read + Send>, Box<dyn Write + Send>)>,
+}
+
+impl RemoteStorage {
+    /// Connects to the server over TCP.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the server.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the client, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{RemoteStorage, Storage, StorageServer};
+    /// use std::{env::temp_dir, net::TcpListener, thread};
+    /// use uuid::Uuid;
+    ///
+    /// let storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let addr = listener.local_addr().unwrap();
+    /// thread::spawn(move || StorageServer::new(storage).serve(listener));
+    ///
+    /// let mut remote = RemoteStorage::connect(addr).unwrap();
+    /// remote.set("timeout", &30u32).unwrap();
+    /// assert_eq!(remote.get::<u32, _>("timeout").unwrap(), Some(30));
+    /// assert!(remote.remove("timeout").unwrap());
+    /// ```
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, E> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::with_stream(stream.try_clone()?, stream))
+    }
+
+    /// Connects to the server over the Unix socket.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path reference to the socket.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the client, or an error.
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> Result<Self, E> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        Ok(Self::with_stream(stream.try_clone()?, stream))
+    }
+
+    fn with_stream<R: Read + Send + 'static, W: Write + Send + 'static>(
+        reader: R,
+        writer: W,
+    ) -> Self {
+        Self {
+            connection: Mutex::new((
+                Box::new(BufReader::new(reader)),
+                Box::new(BufWriter::new(writer)),
+            )),
+        }
+    }
+
+    fn request(&self, request: &Request) -> Result<Response, E> {
+        let mut connection = self.connection.lock().map_err(|_| E::Unknown)?;
+        let (reader, writer) = &mut *connection;
+        send(writer, request)?;
+        match receive::<_, Response>(reader)? {
+            Some(Response::Error(err)) => Err(E::Remote(err)),
+            Some(response) => Ok(response),
+            None => Err(E::Remote(String::from(
+                "connection is closed by the server",
+            ))),
+        }
+    }
+
+    fn unexpected(response: Response) -> E {
+        E::Remote(format!("unexpected response: {response:?}"))
+    }
+
+    /// Checks if the specified key exists in the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key exists, false otherwise, or an error.
+    pub fn has<K: StorageKey>(&self, key: K) -> Result<bool, E> {
+        match self.request(&Request::Has(key.to_key().into_owned()))? {
+            Response::Bool(exists) => Ok(exists),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    /// Retrieves a value by key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        match self.request(&Request::Get(key.to_key().into_owned()))? {
+            Response::Value(Some(payload)) => Ok(Some(bincode::deserialize(&payload)?)),
+            Response::Value(None) => Ok(None),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    /// Sets a value for the specified key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `value` - A reference to the value to be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn set<V: Serialize + 'static, K: StorageKey>(
+        &mut self,
+        key: K,
+        value: &V,
+    ) -> Result<(), E> {
+        let request = Request::Set(
+            key.to_key().into_owned(),
+            type_tag::<V>(),
+            bincode::serialize(value)?,
+        );
+        match self.request(&request)? {
+            Response::Bool(_) => Ok(()),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    /// Removes the value associated with the specified key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key was found and removed, false otherwise, or an error.
+    pub fn remove<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        match self.request(&Request::Remove(key.to_key().into_owned()))? {
+            Response::Bool(removed) => Ok(removed),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    /// Returns keys of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns keys, or an error.
+    pub fn keys(&self) -> Result<Vec<String>, E> {
+        match self.request(&Request::Keys)? {
+            Response::Keys(keys) => Ok(keys),
+            response => Err(Self::unexpected(response)),
+        }
+    }
+
+    /// Reads all records of the type `V`. Records, which cannot be deserialized into `V`, are
+    /// skipped.
+    fn records<V: for<'a> Deserialize<'a> + 'static>(&self) -> Result<Vec<(String, V)>, E> {
+        let records = match self.request(&Request::Scan(type_tag::<V>()))? {
+            Response::Records(records) => records,
+            response => return Err(Self::unexpected(response)),
+        };
+        let mut values = Vec::with_capacity(records.len());
+        for (key, tag, payload) in records {
+            if let Ok(value) = deserialize_tagged::<V>(tag, &payload) {
+                values.push((key, value));
+            }
+        }
+        Ok(values)
+    }
+}
+
+impl Search for RemoteStorage {
+    fn find<V: for<'a> Deserialize<'a> + 'static, F: Fn(&V) -> bool>(
+        &self,
+        condition: F,
+    ) -> Result<Option<(String, V)>, E> {
+        Ok(self.records::<V>()?.into_iter().find(|(_, v)| condition(v)))
+    }
+
+    fn filter<V: for<'a> Deserialize<'a> + 'static, F: Fn(&V) -> bool>(
+        &self,
+        condition: F,
+    ) -> Result<Vec<(String, V)>, E> {
+        Ok(self
+            .records::<V>()?
+            .into_iter()
+            .filter(|(_, v)| condition(v))
+            .collect())
+    }
+
+    fn find_map<V: for<'a> Deserialize<'a> + 'static, T, F: Fn(&str, &V) -> Option<T>>(
+        &self,
+        f: F,
+    ) -> Result<Option<T>, E> {
+        Ok(self
+            .records::<V>()?
+            .into_iter()
+            .find_map(|(key, v)| f(&key, &v)))
+    }
+
+    fn fold<V: for<'a> Deserialize<'a> + 'static, Acc, F: FnMut(Acc, &str, &V) -> Acc>(
+        &self,
+        init: Acc,
+        mut f: F,
+    ) -> Result<Acc, E> {
+        Ok(self
+            .records::<V>()?
+            .into_iter()
+            .fold(init, |acc, (key, v)| f(acc, &key, &v)))
+    }
+
+    fn group_by<V: for<'a> Deserialize<'a> + 'static, G: Eq + Hash, F: Fn(&V) -> G>(
+        &self,
+        f: F,
+        limit: Option<usize>,
+    ) -> Result<HashMap<G, Vec<(String, V)>>, E> {
+        let mut groups: HashMap<G, Vec<(String, V)>> = HashMap::new();
+        for (key, v) in self.records::<V>()? {
+            let group = groups.entry(f(&v)).or_default();
+            if limit.is_none_or(|limit| group.len() < limit) {
+                group.push((key, v));
+            }
+        }
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RemoteStorage, Search, Storage, StorageServer, E};
+    use std::{env::temp_dir, net::TcpListener, sync::Arc, thread};
+    use uuid::Uuid;
+
+    #[test]
+    fn remote() -> Result<(), E> {
+        let storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let server = Arc::new(StorageServer::new(storage));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let serving = server.clone();
+        let handle = thread::spawn(move || -> Result<(), E> {
+            let (stream, _) = listener.accept()?;
+            serving.handle(stream.try_clone()?, stream)
+        });
+        let mut remote = RemoteStorage::connect(addr)?;
+        for i in 0..10u32 {
+            remote.set(format!("n{i}"), &i)?;
+        }
+        remote.set("s", &String::from("text"))?;
+        assert_eq!(remote.get::<u32, _>("n3")?, Some(3));
+        assert_eq!(remote.get::<u32, _>("unknown")?, None);
+        assert!(remote.has("s")?);
+        assert_eq!(remote.keys()?.len(), 11);
+        assert_eq!(remote.filter(|v: &u32| *v < 5)?.len(), 5);
+        assert_eq!(remote.fold(0, |acc, _, v: &u32| acc + v)?, 45);
+        assert_eq!(remote.get::<String, _>("s")?, Some(String::from("text")));
+        assert!(remote.remove("s")?);
+        assert!(!remote.remove("s")?);
+        drop(remote);
+        handle.join().expect("Server thread")?;
+        assert_eq!(server.storage()?.len(), 10);
+        server.storage()?.destroy()?;
+        Ok(())
+    }
+}
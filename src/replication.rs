@@ -0,0 +1,306 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    sync::Mutex,
+};
+
+use crate::{ChangeOp, Field, Operation, Storage, E};
+
+/// Change of a key sent from the primary storage to replicas
+#[derive(Debug, Serialize, Deserialize)]
+struct Change {
+    /// Number of the change; increases by one with each change of the primary storage
+    seq: u64,
+    key: String,
+    /// Type tag and serialized value; None if the key has been removed
+    value: Option<(u64, Vec<u8>)>,
+}
+
+/// Streams of changes of the primary storage
+#[derive(Default)]
+pub(crate) struct Replicas {
+    /// Number of the last sent change
+    seq: u64,
+    writers: Mutex<Vec<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for Replicas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replicas")
+            .field("seq", &self.seq)
+            .field(
+                "writers",
+                &self.writers.lock().map(|w| w.len()).unwrap_or_default(),
+            )
+            .finish()
+    }
+}
+
+/// Writes the change as a frame: length of the change (u64, LE) and the change (bincode).
+fn write_frame<W: Write + ?Sized>(writer: &mut W, change: &Change) -> Result<(), E> {
+    let frame = bincode::serialize(change)?;
+    writer.write_all(&(frame.len() as u64).to_le_bytes())?;
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the next frame; returns None if the stream is ended.
+fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Change>, E> {
+    let mut len = [0u8; 8];
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let mut frame = vec![0u8; u64::from_le_bytes(len) as usize];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(bincode::deserialize(&frame)?))
+}
+
+impl Storage {
+    /// Makes this storage the primary one for a replica: records of the storage are written into
+    /// the writer, then each change of the storage (written and removed keys) is written into it
+    /// as soon as the change is done. The writer can be any transport (a pipe, a socket, a
+    /// file); the other side applies changes with `apply_changes()`. Changes are written in the
+    /// order they are done.
+    ///
+    /// If writing into the stream fails (e.g. the replica is disconnected), the stream is
+    /// dropped and the error is logged; changes of the storage aren't affected.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The stream of changes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if the current records have been written, or an
+    ///   error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::{env::temp_dir, fs::File};
+    /// use uuid::Uuid;
+    ///
+    /// let mut primary = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let mut replica = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let stream = temp_dir().join(Uuid::new_v4().to_string());
+    /// primary.set("a", &1u8).unwrap();
+    /// primary.replicate_to(File::create(&stream).unwrap()).unwrap();
+    /// primary.set("b", &2u8).unwrap();
+    /// primary.remove("a").unwrap();
+    /// assert_eq!(replica.apply_changes(File::open(&stream).unwrap()).unwrap(), 3);
+    /// assert_eq!(replica.get::<u8, _>("b").unwrap(), Some(2));
+    /// assert!(!replica.has("a"));
+    /// # std::fs::remove_file(stream).unwrap();
+    /// # primary.destroy().unwrap();
+    /// # replica.destroy().unwrap();
+    /// ```
+    pub fn replicate_to<W: Write + Send + 'static>(&mut self, mut writer: W) -> Result<(), E> {
+        self.prune()?;
+        let mut keys: Vec<&String> = self.fields.keys().collect();
+        keys.sort();
+        for key in keys {
+            let field = &self.fields[key];
+            let path = field.path(&self.cwd);
+            let content = self
+                .extract(field)
+                .map_err(|e| e.record(Operation::Read, key, &path))?;
+            let (_, payload) =
+                Field::payload(&content).map_err(|e| e.record(Operation::Read, key, &path))?;
+            self.replicas.seq += 1;
+            write_frame(
+                &mut writer,
+                &Change {
+                    seq: self.replicas.seq,
+                    key: key.to_owned(),
+                    value: Some((field.meta().tag, payload.to_vec())),
+                },
+            )?;
+        }
+        self.replicas
+            .writers
+            .get_mut()
+            .map_err(|_| E::Replication(String::from("streams of changes are poisoned")))?
+            .push(Box::new(writer));
+        Ok(())
+    }
+
+    /// Sends the change of the key to replicas (see `replicate_to()`) and to the log of changes
+    /// (see `ChangeFeed`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The changed key.
+    /// * `value` - Type tag and serialized value; None if the key has been removed.
+    pub(crate) fn publish(&mut self, key: &str, value: Option<(u64, &[u8])>) {
+        self.obsolete_archived(key);
+        self.log_change(
+            key,
+            if value.is_some() {
+                ChangeOp::Set
+            } else {
+                ChangeOp::Remove
+            },
+        );
+        let Ok(writers) = self.replicas.writers.get_mut() else {
+            return;
+        };
+        if writers.is_empty() {
+            return;
+        }
+        self.replicas.seq += 1;
+        let change = Change {
+            seq: self.replicas.seq,
+            key: key.to_owned(),
+            value: value.map(|(tag, payload)| (tag, payload.to_vec())),
+        };
+        writers.retain_mut(|writer| match write_frame(writer, &change) {
+            Ok(()) => true,
+            Err(err) => {
+                log::warn!("Stream of changes is dropped: {err}");
+                false
+            }
+        });
+    }
+
+    /// Applies changes of the primary storage (see `replicate_to()`) read from the reader, until
+    /// the stream is ended. Each change is applied as a separate modifying call, so readers of
+    /// this storage see the state of the primary storage as of one of its changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream of changes.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, E>` - Returns the number of applied changes, `E::Replication` if changes
+    ///   are out of order, or an error.
+    pub fn apply_changes<R: Read>(&mut self, mut reader: R) -> Result<usize, E> {
+        let mut applied = 0;
+        let mut last = None;
+        while let Some(change) = read_frame(&mut reader)? {
+            if last.is_some_and(|last| change.seq <= last) {
+                return Err(E::Replication(format!(
+                    "change #{} follows change #{}",
+                    change.seq,
+                    last.unwrap_or_default()
+                )));
+            }
+            last = Some(change.seq);
+            match change.value {
+                Some((tag, payload)) => self.set_bytes(&change.key, tag, &payload)?,
+                None => {
+                    self.remove(&change.key)?;
+                }
+            }
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use std::{
+        env::temp_dir,
+        io::{self, Write},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+    };
+    use uuid::Uuid;
+
+    /// Sends written bytes through the channel
+    struct Pipe(mpsc::Sender<Vec<u8>>);
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reads bytes received from the channel
+    struct Receiver {
+        rx: mpsc::Receiver<Vec<u8>>,
+        buffer: Vec<u8>,
+    }
+
+    impl io::Read for Receiver {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.buffer.is_empty() {
+                match self.rx.recv() {
+                    Ok(bytes) => self.buffer = bytes,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.buffer.len());
+            buf[..n].copy_from_slice(&self.buffer[..n]);
+            self.buffer.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn replication() -> Result<(), E> {
+        let mut primary = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let replica = Arc::new(Mutex::new(Storage::create(
+            temp_dir().join(Uuid::new_v4().to_string()),
+        )?));
+        primary.set("existing", &String::from("value"))?;
+        let (tx, rx) = mpsc::channel();
+        primary.replicate_to(Pipe(tx))?;
+        let applier = thread::spawn({
+            let replica = replica.clone();
+            move || -> Result<usize, E> {
+                let mut reader = Receiver {
+                    rx,
+                    buffer: Vec::new(),
+                };
+                let mut replica = replica.lock().unwrap();
+                replica.apply_changes(&mut reader)
+            }
+        });
+        for i in 0..10u32 {
+            primary.set(i.to_string(), &i)?;
+        }
+        primary.remove("0")?;
+        primary.set("1", &100u32)?;
+        primary.set("2", &200u32)?;
+        // Closes the stream
+        drop(primary.replicas.writers.get_mut().unwrap().pop());
+        assert_eq!(applier.join().unwrap()?, 14);
+        let mut replica = replica.lock().unwrap();
+        assert_eq!(replica.len(), 10);
+        assert!(!replica.has("0"));
+        assert_eq!(replica.get::<u32, _>("2")?, Some(200));
+        assert_eq!(
+            replica.get::<String, _>("existing")?,
+            Some(String::from("value"))
+        );
+        // Broken streams are dropped
+        let (tx, rx) = mpsc::channel();
+        primary.replicate_to(Pipe(tx))?;
+        drop(rx);
+        primary.set("a", &1u8)?;
+        assert!(primary.replicas.writers.get_mut().unwrap().is_empty());
+        primary.destroy()?;
+        replica.destroy()?;
+        Ok(())
+    }
+}
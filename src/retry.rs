@@ -0,0 +1,228 @@
+use std::{io, path::Path, sync::Arc, thread, time::Duration};
+
+use crate::{Durability, FileSystem};
+
+/// Windows: the file is used by another process (e.g. opened by an antivirus scanner)
+const ERROR_SHARING_VIOLATION: i32 = 32;
+/// Windows: a part of the file is locked by another process
+const ERROR_LOCK_VIOLATION: i32 = 33;
+
+/// Retries file operations of the storage (reading and writing of records and of the map,
+/// removing of records), which failed with a transient error: the file is busy (`EBUSY`, sharing
+/// and lock violations on Windows, which are usually caused by antivirus scanners or indexers),
+/// or the call has been interrupted (`EINTR`). Other errors are returned immediately.
+///
+/// The delay before the next attempt starts with `backoff` and is doubled after each attempt,
+/// but doesn't exceed `max_backoff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts (including the first one).
+    pub attempts: u32,
+    /// Delay before the second attempt.
+    pub backoff: Duration,
+    /// Maximum delay between attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Checks if the operation, which failed with the error, can succeed if it's repeated.
+    ///
+    /// # Arguments
+    ///
+    /// * `err` - The error of the operation.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true for transient errors.
+    pub fn is_transient(err: &io::Error) -> bool {
+        matches!(
+            err.kind(),
+            io::ErrorKind::Interrupted | io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock
+        ) || (cfg!(windows)
+            && matches!(
+                err.raw_os_error(),
+                Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+            ))
+    }
+
+    /// Runs the operation and repeats it while it fails with a transient error and attempts
+    /// aren't exhausted.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The operation.
+    ///
+    /// # Returns
+    ///
+    /// * `io::Result<T>` - The result of the last attempt.
+    pub fn run<T, F: FnMut() -> io::Result<T>>(&self, mut op: F) -> io::Result<T> {
+        let mut delay = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(err) if attempt < self.attempts && RetryPolicy::is_transient(&err) => {
+                    log::debug!("Attempt {attempt} failed with transient error: {err}");
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// File system, which retries operations of the wrapped one according to `RetryPolicy`.
+#[derive(Debug)]
+pub(crate) struct Retrying {
+    pub(crate) inner: Arc<dyn FileSystem>,
+    pub(crate) policy: RetryPolicy,
+}
+
+impl FileSystem for Retrying {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.policy.run(|| self.inner.read(path))
+    }
+
+    fn read_head(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        self.policy.run(|| self.inner.read_head(path, len))
+    }
+
+    fn write(&self, path: &Path, content: &[u8], durability: Durability) -> io::Result<()> {
+        self.policy
+            .run(|| self.inner.write(path, content, durability))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.policy.run(|| self.inner.remove(path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.policy.run(|| self.inner.size(path))
+    }
+
+    fn allocated(&self, path: &Path) -> io::Result<u64> {
+        self.policy.run(|| self.inner.allocated(path))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.policy.run(|| self.inner.create_dir_all(path))
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.policy.run(|| self.inner.sync_dir(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.policy.run(|| self.inner.remove_dir_all(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Durability, FileSystem, RetryPolicy, StdFs, Storage, StorageOptions, E};
+    use std::{
+        env::temp_dir,
+        io,
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use uuid::Uuid;
+
+    /// Fails the given number of next operations with `ResourceBusy`
+    #[derive(Debug, Default)]
+    struct Busy {
+        failures: AtomicUsize,
+    }
+
+    impl Busy {
+        fn check(&self) -> io::Result<()> {
+            match self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            {
+                Ok(_) => Err(io::ErrorKind::ResourceBusy.into()),
+                Err(_) => Ok(()),
+            }
+        }
+    }
+
+    impl FileSystem for Busy {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.check()?;
+            StdFs.read(path)
+        }
+
+        fn write(&self, path: &Path, content: &[u8], durability: Durability) -> io::Result<()> {
+            self.check()?;
+            StdFs.write(path, content, durability)
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            self.check()?;
+            StdFs.remove(path)
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            StdFs.exists(path)
+        }
+
+        fn size(&self, path: &Path) -> io::Result<u64> {
+            StdFs.size(path)
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            StdFs.create_dir_all(path)
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            StdFs.remove_dir_all(path)
+        }
+    }
+
+    #[test]
+    fn retry() -> Result<(), E> {
+        let busy = Arc::new(Busy::default());
+        let options = StorageOptions {
+            fs: Some(busy.clone()),
+            retry: Some(RetryPolicy {
+                attempts: 3,
+                backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(2),
+            }),
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        busy.failures.store(2, Ordering::SeqCst);
+        storage.set("a", &1u8)?;
+        busy.failures.store(2, Ordering::SeqCst);
+        assert_eq!(storage.get::<u8, _>("a")?, Some(1));
+        busy.failures.store(2, Ordering::SeqCst);
+        assert!(storage.remove("a")?);
+        // Attempts are exhausted
+        busy.failures.store(3, Ordering::SeqCst);
+        assert!(storage.set("b", &2u8).is_err());
+        assert!(!RetryPolicy::is_transient(&io::ErrorKind::NotFound.into()));
+        storage.destroy()?;
+        Ok(())
+    }
+}
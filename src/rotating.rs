@@ -0,0 +1,419 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{create_dir_all, read_dir, remove_file},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    fs, sensitive::Wiped, tiering::pack_records, Field, Operation, PackedStorage, Storage,
+    StorageKey, E,
+};
+
+/// Folder in the storage folder with archives of rotated records
+pub(crate) const ROTATED_DIR: &str = "rotated";
+/// Extension of archives of rotated records
+const ROTATED_FILE_EXT: &str = "bundle";
+
+/// Thresholds of `RotatingStorage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rotation {
+    /// Records are rotated as soon as the total size of record files exceeds this size in bytes
+    pub max_size: u64,
+    /// Oldest records are rotated until the total size of record files is at most this size in
+    /// bytes; it should be less than `max_size`, so records aren't rotated with each write
+    pub target_size: u64,
+    /// Maximum number of kept archives; the oldest archives are removed. None keeps all
+    /// archives.
+    pub max_archives: Option<usize>,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            max_size: 64 * 1024 * 1024,
+            target_size: 32 * 1024 * 1024,
+            max_archives: None,
+        }
+    }
+}
+
+/// Archive of records rotated out of `RotatingStorage`
+#[derive(Debug)]
+pub struct RotatedArchive {
+    /// Time of the rotation
+    created: SystemTime,
+    path: PathBuf,
+    packed: PackedStorage,
+}
+
+impl RotatedArchive {
+    fn open(path: PathBuf) -> Result<Option<Self>, E> {
+        let Some(millis) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            created: UNIX_EPOCH + Duration::from_millis(millis),
+            packed: Storage::open_bundle(&path)?,
+            path,
+        }))
+    }
+
+    /// Returns the time of the rotation.
+    pub fn created(&self) -> SystemTime {
+        self.created
+    }
+
+    /// Returns the path to the bundle of the archive.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns records of the archive. Records are kept as they have been written, so records
+    /// written with a codec or transformers (see `Codecs`) cannot be read from here; use
+    /// `RotatingStorage::find()` to read them.
+    pub fn records(&self) -> &PackedStorage {
+        &self.packed
+    }
+}
+
+/// `RotatingStorage` keeps the size of `Storage` bounded like log rotation does: as soon as the
+/// total size of record files exceeds `Rotation::max_size`, the oldest records (by the time of
+/// the last write, or the last access with `StorageOptions::access`) are packed into the
+/// timestamped archive and removed from the storage. Archives are bundles in the folder
+/// `rotated` of the storage, so they are read without unpacking (see `PackedStorage`). Pinned
+/// records are never rotated.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{RotatingStorage, Rotation, Storage};
+/// use std::env::temp_dir;
+/// use uuid::Uuid;
+///
+/// let storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// let rotation = Rotation {
+///     max_size: 64 * 1024,
+///     target_size: 32 * 1024,
+///     max_archives: Some(10),
+/// };
+/// let mut telemetry = RotatingStorage::new(storage, rotation).unwrap();
+/// for n in 0..64u32 {
+///     telemetry.set(format!("sample/{n:04}"), &vec![n; 1024]).unwrap();
+/// }
+/// assert!(telemetry.storage().usage().bytes <= 64 * 1024);
+/// assert!(!telemetry.archives().is_empty());
+/// // Rotated samples are still available
+/// assert_eq!(
+///     telemetry.find::<Vec<u32>, _>("sample/0000").unwrap(),
+///     Some(vec![0; 1024])
+/// );
+/// telemetry.into_inner().destroy().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct RotatingStorage {
+    storage: Storage,
+    rotation: Rotation,
+    /// Archives sorted from the oldest one
+    archives: Vec<RotatedArchive>,
+}
+
+impl RotatingStorage {
+    /// Creates the rotating layer over the given storage and opens archives of previous
+    /// rotations.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - The storage.
+    /// * `rotation` - Thresholds of the rotation.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns an instance of `RotatingStorage`, or an error if some
+    ///   archive cannot be opened.
+    pub fn new(storage: Storage, rotation: Rotation) -> Result<Self, E> {
+        let dir = storage.cwd.join(ROTATED_DIR);
+        let mut paths = Vec::new();
+        if fs::long_path(&dir).is_dir() {
+            for entry in read_dir(fs::long_path(&dir)).map_err(|e| E::io(e, &dir))? {
+                let path = entry.map_err(|e| E::io(e, &dir))?.path();
+                if path.extension().is_some_and(|ext| ext == ROTATED_FILE_EXT) {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+        let mut archives = Vec::new();
+        for path in paths {
+            if let Some(archive) = RotatedArchive::open(path)? {
+                archives.push(archive);
+            }
+        }
+        Ok(Self {
+            storage,
+            rotation,
+            archives,
+        })
+    }
+
+    /// Retrieves a value of the key from the storage. Rotated records aren't looked up (see
+    /// `RotatingStorage::find()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        self.storage.get(key)
+    }
+
+    /// Checks if the key exists in the storage. Rotated records aren't looked up.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the key exists, false otherwise.
+    pub fn has<K: StorageKey>(&self, key: K) -> bool {
+        self.storage.has(key)
+    }
+
+    /// Sets a value for the key and rotates the oldest records, if the storage has exceeded
+    /// `Rotation::max_size`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    /// * `value` - A reference to the value to be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn set<V: Serialize + 'static, K: StorageKey>(
+        &mut self,
+        key: K,
+        value: &V,
+    ) -> Result<(), E> {
+        self.storage.set(key, value)?;
+        if self.storage.usage().bytes > self.rotation.max_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Removes the key from the storage. Rotated records aren't changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the key was found, false otherwise, or an error.
+    pub fn remove<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        self.storage.remove(key)
+    }
+
+    /// Packs the oldest records into a new archive until the total size of record files is at
+    /// most `Rotation::target_size`, and removes the oldest archives beyond
+    /// `Rotation::max_archives`. It's called by `set()`, but can be called at any time (e.g. on
+    /// shutdown).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<&RotatedArchive>, E>` - Returns the new archive, None if nothing has
+    ///   been rotated, or an error.
+    pub fn rotate(&mut self) -> Result<Option<&RotatedArchive>, E> {
+        self.storage.map.check()?;
+        if self.storage.prune()? {
+            self.storage.map.write(&self.storage.fields)?;
+        }
+        let mut oldest = self
+            .storage
+            .fields
+            .iter()
+            .filter(|(_, field)| !field.meta().pinned)
+            .map(|(key, field)| (field.meta().accessed.get(), key, field.meta().size))
+            .collect::<Vec<(u64, &String, u64)>>();
+        oldest.sort();
+        let mut size = self.storage.usage().bytes;
+        let mut rotated = Vec::new();
+        for (_, key, record) in oldest {
+            if size <= self.rotation.target_size {
+                break;
+            }
+            size = size.saturating_sub(record);
+            rotated.push(key.to_owned());
+        }
+        if rotated.is_empty() {
+            return Ok(None);
+        }
+        let mut records = Vec::with_capacity(rotated.len());
+        for key in rotated.iter() {
+            if let Some(field) = self.storage.fields.get(key) {
+                let content = self
+                    .storage
+                    .extract_raw(field)
+                    .map_err(|e| e.record(Operation::Read, key, &field.path(&self.storage.cwd)))?;
+                records.push((key.to_owned(), content));
+            }
+        }
+        let dir = self.storage.cwd.join(ROTATED_DIR);
+        create_dir_all(fs::long_path(&dir)).map_err(|e| E::io(e, &dir))?;
+        let mut millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        if let Some(last) = self.archives.last() {
+            let last = last
+                .created
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            millis = millis.max(last + 1);
+        }
+        let path = dir.join(format!("{millis:020}.{ROTATED_FILE_EXT}"));
+        pack_records(&path, records)?;
+        for key in rotated.iter() {
+            self.storage.discard(key)?;
+        }
+        self.storage.map.write(&self.storage.fields)?;
+        if let Some(archive) = RotatedArchive::open(path)? {
+            self.archives.push(archive);
+        }
+        if let Some(max) = self.rotation.max_archives {
+            let excess = self.archives.len().saturating_sub(max);
+            for archive in self.archives.drain(..excess) {
+                remove_file(fs::long_path(&archive.path)).map_err(|e| E::io(e, &archive.path))?;
+            }
+        }
+        Ok(self.archives.last())
+    }
+
+    /// Returns archives of rotated records sorted from the oldest one.
+    ///
+    /// # Returns
+    ///
+    /// * `&[RotatedArchive]` - Archives of rotated records.
+    pub fn archives(&self) -> &[RotatedArchive] {
+        &self.archives
+    }
+
+    /// Retrieves the latest value of the key: from the storage or, if the key has been rotated,
+    /// from the newest archive with the key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    pub fn find<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
+        &self,
+        key: K,
+    ) -> Result<Option<V>, E> {
+        let key = self.storage.normalized(key.to_key());
+        if self.storage.has(key.as_ref()) {
+            return self.storage.get(key);
+        }
+        for archive in self.archives.iter().rev() {
+            let Some(content) = archive.packed.content_of(key.as_ref())? else {
+                continue;
+            };
+            // Archived records are kept as they have been written, so they are decoded with
+            // the codec and transformers of the storage
+            return self
+                .storage
+                .sealed(|| {
+                    self.storage
+                        .decoded(content)
+                        .and_then(|content| Field::value::<V>(&Wiped(content)))
+                })
+                .map_err(|e| e.record(Operation::Get, key.as_ref(), &archive.path));
+        }
+        Ok(None)
+    }
+
+    /// Returns a reference to the underlying storage.
+    ///
+    /// # Returns
+    ///
+    /// * `&Storage` - A reference to the underlying storage.
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    /// Returns the underlying storage; archives stay in its folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Storage` - The underlying storage.
+    pub fn into_inner(self) -> Storage {
+        self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RotatingStorage, Rotation, Storage, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn rotation() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let rotation = Rotation {
+            max_size: 16 * 1024,
+            target_size: 8 * 1024,
+            max_archives: Some(3),
+        };
+        let mut storage = Storage::create(&storage_path)?;
+        storage.set("config", &vec![0u8; 4096])?;
+        storage.pin("config")?;
+        let mut rotating = RotatingStorage::new(storage, rotation.clone())?;
+        for n in 0..64u32 {
+            rotating.set(format!("sample/{n:04}"), &vec![n; 256])?;
+            assert!(rotating.storage().usage().bytes <= rotation.max_size);
+        }
+        assert_eq!(rotating.archives().len(), 3);
+        assert!(rotating.has("config"));
+        assert!(rotating.has("sample/0063"));
+        assert!(!rotating.has("sample/0000"));
+        // Archives are sorted from the oldest one
+        let created = rotating
+            .archives()
+            .iter()
+            .map(|archive| archive.created())
+            .collect::<Vec<_>>();
+        assert!(created.windows(2).all(|pair| pair[0] < pair[1]));
+        // The oldest archives are removed, so the first samples are gone
+        assert!(rotating.find::<Vec<u32>, _>("sample/0000")?.is_none());
+        let rotated = rotating.archives()[0]
+            .records()
+            .keys()
+            .next()
+            .cloned()
+            .expect("Archive isn't empty");
+        assert!(rotating.find::<Vec<u32>, _>(rotated.as_str())?.is_some());
+        // Archives are found after reopening
+        drop(rotating);
+        let rotating = RotatingStorage::new(Storage::open(&storage_path)?, rotation)?;
+        assert_eq!(rotating.archives().len(), 3);
+        assert!(rotating.find::<Vec<u32>, _>(rotated.as_str())?.is_some());
+        rotating.into_inner().destroy()?;
+        Ok(())
+    }
+}
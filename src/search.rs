@@ -1,7 +1,12 @@
-use crate::{Storage, E};
+use crate::{cancel, Storage, E};
 use serde::Deserialize;
+use std::{collections::HashMap, hash::Hash, sync::atomic::AtomicBool};
 
 /// The `Search` trait provides methods for searching records in the storage.
+///
+/// Searches work on a snapshot of keys taken when the search starts. Records removed by another
+/// handle of the storage folder (another `Storage` opened on the same folder or another process)
+/// while the search is running are skipped; they aren't reported as errors or corruptions.
 pub trait Search {
     /// Finds the first record that matches the specified condition.
     ///
@@ -30,6 +35,53 @@ pub trait Search {
         &self,
         condition: F,
     ) -> Result<Vec<(String, V)>, E>;
+
+    /// Applies the closure to records and returns the first non-None result.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - A closure that takes the key and a reference to a value and returns an optional result.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<T>, E>` - Returns the first non-None result of the closure, or None, or an error.
+    fn find_map<V: for<'a> Deserialize<'a> + 'static, T, F: Fn(&str, &V) -> Option<T>>(
+        &self,
+        f: F,
+    ) -> Result<Option<T>, E>;
+
+    /// Folds all records of the type `V` into an accumulator in one pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `init` - Initial value of the accumulator.
+    /// * `f` - A closure that takes the accumulator, the key and a reference to a value and returns the
+    ///   next value of the accumulator.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Acc, E>` - Returns the final value of the accumulator, or an error.
+    fn fold<V: for<'a> Deserialize<'a> + 'static, Acc, F: FnMut(Acc, &str, &V) -> Acc>(
+        &self,
+        init: Acc,
+        f: F,
+    ) -> Result<Acc, E>;
+
+    /// Groups records of the type `V` by the key returned by the closure. Each record is read once.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - A closure that takes a reference to a value and returns the key of its group.
+    /// * `limit` - Maximum number of records in each group; None for no limit.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<HashMap<G, Vec<(String, V)>>, E>` - Returns records (keys and values) by groups, or an error.
+    fn group_by<V: for<'a> Deserialize<'a> + 'static, G: Eq + Hash, F: Fn(&V) -> G>(
+        &self,
+        f: F,
+        limit: Option<usize>,
+    ) -> Result<HashMap<G, Vec<(String, V)>>, E>;
 }
 
 impl Search for Storage {
@@ -112,8 +164,8 @@ impl Search for Storage {
         &self,
         condition: F,
     ) -> Result<Option<(String, V)>, E> {
-        for key in self.into_iter() {
-            let Some(v) = self.get::<V, &String>(key)? else {
+        for key in self.keys_of::<V>() {
+            let Some(v) = self.get_listed::<V>(key)? else {
                 continue;
             };
             if condition(&v) {
@@ -210,13 +262,305 @@ impl Search for Storage {
         condition: F,
     ) -> Result<Vec<(String, V)>, E> {
         let mut filtered = Vec::new();
-        for key in self.into_iter() {
-            let Some(v) = self.get::<V, &String>(key)? else {
+        self.read_all(&cancel::NEVER, |key, v: V| {
+            if condition(&v) {
+                filtered.push((key.to_owned(), v));
+            }
+        })?;
+        Ok(filtered)
+    }
+
+    /// Applies the closure to records and returns the first non-None result.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - A closure that takes the key and a reference to a value and returns an optional result.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<T>, E>` - Returns the first non-None result of the closure, or None, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bstorage::{Search, Storage};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("name", &String::from("bstorage")).unwrap();
+    /// let len = storage.find_map(|_key, v: &String| Some(v.len())).unwrap();
+    /// assert_eq!(len, Some(8));
+    /// storage.destroy().unwrap();
+    /// ```
+    fn find_map<V: for<'a> Deserialize<'a> + 'static, T, F: Fn(&str, &V) -> Option<T>>(
+        &self,
+        f: F,
+    ) -> Result<Option<T>, E> {
+        for key in self.keys_of::<V>() {
+            let Some(v) = self.get_listed::<V>(key)? else {
                 continue;
             };
+            if let Some(found) = f(key, &v) {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Folds all records of the type `V` into an accumulator in one pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `init` - Initial value of the accumulator.
+    /// * `f` - A closure that takes the accumulator, the key and a reference to a value and returns the
+    ///   next value of the accumulator.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Acc, E>` - Returns the final value of the accumulator, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bstorage::{Search, Storage};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// for i in 1..=4u32 {
+    ///     storage.set(i.to_string(), &i).unwrap();
+    /// }
+    /// let sum = storage.fold(0, |acc, _key, v: &u32| acc + v).unwrap();
+    /// assert_eq!(sum, 10);
+    /// storage.destroy().unwrap();
+    /// ```
+    fn fold<V: for<'a> Deserialize<'a> + 'static, Acc, F: FnMut(Acc, &str, &V) -> Acc>(
+        &self,
+        init: Acc,
+        mut f: F,
+    ) -> Result<Acc, E> {
+        let mut acc = Some(init);
+        self.read_all(&cancel::NEVER, |key, v: V| {
+            acc = acc.take().map(|acc| f(acc, key, &v));
+        })?;
+        acc.ok_or(E::Unknown)
+    }
+
+    /// Groups records of the type `V` by the key returned by the closure. Each record is read once.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - A closure that takes a reference to a value and returns the key of its group.
+    /// * `limit` - Maximum number of records in each group; None for no limit.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<HashMap<G, Vec<(String, V)>>, E>` - Returns records (keys and values) by groups, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bstorage::{Search, Storage};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// for i in 0..10u32 {
+    ///     storage.set(i.to_string(), &i).unwrap();
+    /// }
+    /// let groups = storage.group_by(|v: &u32| v % 2 == 0, Some(3)).unwrap();
+    /// assert_eq!(groups.len(), 2);
+    /// assert_eq!(groups[&true].len(), 3);
+    /// assert!(groups[&false].iter().all(|(_key, v)| v % 2 == 1));
+    /// storage.destroy().unwrap();
+    /// ```
+    fn group_by<V: for<'a> Deserialize<'a> + 'static, G: Eq + Hash, F: Fn(&V) -> G>(
+        &self,
+        f: F,
+        limit: Option<usize>,
+    ) -> Result<HashMap<G, Vec<(String, V)>>, E> {
+        let mut groups: HashMap<G, Vec<(String, V)>> = HashMap::new();
+        self.read_all(&cancel::NEVER, |key, v: V| {
+            let group = groups.entry(f(&v)).or_default();
+            if limit.is_none_or(|limit| group.len() < limit) {
+                group.push((key.to_owned(), v));
+            }
+        })?;
+        Ok(groups)
+    }
+}
+
+impl Storage {
+    /// Filters the records as `Search::filter()` does; the search can be cancelled by setting the
+    /// flag from another thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `condition` - A closure that takes a reference to a value and returns a boolean indicating if the value matches the condition.
+    /// * `cancel` - Stops the search with `E::Cancelled` when set.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<(String, V)>, E>` - Returns all matching records, or an error.
+    pub fn filter_cancellable<V: for<'a> Deserialize<'a> + 'static, F: Fn(&V) -> bool>(
+        &self,
+        condition: F,
+        cancel: &AtomicBool,
+    ) -> Result<Vec<(String, V)>, E> {
+        let mut filtered = Vec::new();
+        self.read_all(cancel, |key, v: V| {
             if condition(&v) {
                 filtered.push((key.to_owned(), v));
             }
+        })?;
+        Ok(filtered)
+    }
+
+    /// Returns a lazy iterator over records of the type `V`. Record files are read only when the
+    /// iterator is advanced, so `take(n)` or a short-circuiting `find` don't read records they never
+    /// look at. Unlike `Search` methods, errors (including deserializing errors) aren't swallowed but
+    /// returned as items of the iterator. As with `Search` methods, keys are taken when the
+    /// iterator is created, and records removed concurrently by another handle are skipped.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = Result<(String, V), E>>` - Iterator over keys and values of records.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// for i in 0..100u32 {
+    ///     storage.set(i.to_string(), &i).unwrap();
+    /// }
+    /// let first = storage
+    ///     .scan::<u32>()
+    ///     .take(5)
+    ///     .collect::<Result<Vec<(String, u32)>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(first.len(), 5);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn scan<V: for<'a> Deserialize<'a> + 'static>(
+        &self,
+    ) -> impl Iterator<Item = Result<(String, V), E>> + '_ {
+        self.keys_of::<V>()
+            .filter_map(move |key| match self.get_stored::<V>(key) {
+                Ok(Some(v)) => Some(Ok((key.to_owned(), v))),
+                Ok(None) => None,
+                Err(err)
+                    if self
+                        .fields
+                        .get(key)
+                        .is_some_and(|field| self.vanished(field, &err)) =>
+                {
+                    None
+                }
+                Err(err) => Some(Err(err)),
+            })
+    }
+}
+
+/// `MultiSearch` runs searches across a set of storages (e.g. all profiles of `StorageManager`).
+/// Found records are tagged with the name of the storage they come from.
+///
+/// # Example
+///
+/// ```
+/// use bstorage::{MultiSearch, Storage};
+/// use std::env::temp_dir;
+/// use uuid::Uuid;
+///
+/// let mut a = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// let mut b = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// a.set("volume", &10u8).unwrap();
+/// b.set("volume", &90u8).unwrap();
+/// let search = MultiSearch::new().with("a", &a).with("b", &b);
+/// let loud = search.filter(|v: &u8| *v > 50).unwrap();
+/// assert_eq!(loud, vec![(String::from("b"), String::from("volume"), 90)]);
+/// a.destroy().unwrap();
+/// b.destroy().unwrap();
+/// ```
+#[derive(Default)]
+pub struct MultiSearch<'a> {
+    storages: Vec<(String, &'a Storage)>,
+}
+
+impl<'a> MultiSearch<'a> {
+    /// Creates an empty set of storages.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns an instance of `MultiSearch`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the storage to the set.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the storage, which is used to tag found records.
+    /// * `storage` - A reference to the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - Returns the set with the added storage.
+    pub fn with<N: AsRef<str>>(mut self, name: N, storage: &'a Storage) -> Self {
+        self.storages.push((name.as_ref().to_owned(), storage));
+        self
+    }
+
+    /// Finds the first record that matches the specified condition. Storages are searched in the
+    /// order they have been added.
+    ///
+    /// # Arguments
+    ///
+    /// * `condition` - A closure that takes a reference to a value and returns a boolean indicating if the value matches the condition.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<(String, String, V)>, E>` - Returns the name of the storage, the key and the value of the first
+    ///   matching record, or None if no match is found, or an error.
+    pub fn find<V: for<'b> Deserialize<'b> + 'static, F: Fn(&V) -> bool>(
+        &self,
+        condition: F,
+    ) -> Result<Option<(String, String, V)>, E> {
+        for (name, storage) in self.storages.iter() {
+            if let Some((key, v)) = storage.find(&condition)? {
+                return Ok(Some((name.to_owned(), key, v)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Filters the records of all storages and returns all that match the specified condition.
+    ///
+    /// # Arguments
+    ///
+    /// * `condition` - A closure that takes a reference to a value and returns a boolean indicating if the value matches the condition.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<(String, String, V)>, E>` - Returns the name of the storage, the key and the value of each matching
+    ///   record, or an error.
+    pub fn filter<V: for<'b> Deserialize<'b> + 'static, F: Fn(&V) -> bool>(
+        &self,
+        condition: F,
+    ) -> Result<Vec<(String, String, V)>, E> {
+        let mut filtered = Vec::new();
+        for (name, storage) in self.storages.iter() {
+            filtered.extend(
+                storage
+                    .filter(&condition)?
+                    .into_iter()
+                    .map(|(key, v)| (name.to_owned(), key, v)),
+            );
         }
         Ok(filtered)
     }
@@ -224,9 +568,14 @@ impl Search for Storage {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Search, Storage, E};
+    use crate::{
+        register_type, BatchReads, CorruptionPolicy, Search, Storage, StorageOptions, TypeName, E,
+    };
     use serde::{Deserialize, Serialize};
-    use std::{env::temp_dir, fs::remove_dir_all};
+    use std::{
+        env::temp_dir,
+        fs::{remove_dir_all, write},
+    };
     use uuid::Uuid;
 
     #[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -241,6 +590,44 @@ mod tests {
         d: Option<bool>,
     }
 
+    #[test]
+    fn concurrent_removal() -> Result<(), E> {
+        for min_records in [usize::MAX, 1] {
+            let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+            let options = StorageOptions {
+                corruption: CorruptionPolicy::Error,
+                batch: BatchReads {
+                    min_records,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut writer = Storage::create_with_options(&storage_path, options.clone())?;
+            for i in 0..100u32 {
+                writer.set(i.to_string(), &i)?;
+            }
+            let reader = Storage::open_with_options(&storage_path, options)?;
+            // Records are removed by another handle after the reader has taken its keys
+            for i in (0..100u32).step_by(2) {
+                writer.remove(i.to_string())?;
+            }
+            let found = reader.filter(|v: &u32| *v < 10)?;
+            assert_eq!(found.len(), 5);
+            assert_eq!(reader.fold(0, |n, _, _: &u32| n + 1)?, 50);
+            assert!(reader.find(|v: &u32| *v == 0)?.is_none());
+            assert_eq!(
+                reader.scan::<u32>().collect::<Result<Vec<_>, E>>()?.len(),
+                50
+            );
+            assert!(reader.take_corruptions().is_empty());
+            // The snapshot of keys isn't changed by removals
+            assert_eq!((&reader).into_iter().count(), 100);
+            drop(reader);
+            writer.destroy()?;
+        }
+        Ok(())
+    }
+
     #[test]
     fn find() -> Result<(), E> {
         let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
@@ -346,4 +733,140 @@ mod tests {
         remove_dir_all(storage.cwd())?;
         Ok(())
     }
+
+    #[test]
+    fn aggregate() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..10u8 {
+            storage.set(
+                i.to_string(),
+                &A {
+                    a: i,
+                    b: i.to_string(),
+                },
+            )?;
+        }
+        storage.set("b", &B { c: 100, d: None })?;
+        let max = storage.fold(None, |acc: Option<u8>, _key, v: &A| {
+            Some(acc.map_or(v.a, |acc| acc.max(v.a)))
+        })?;
+        assert_eq!(max, Some(9));
+        let found = storage.find_map(|key, v: &A| (v.a == 5).then(|| key.to_owned()))?;
+        assert_eq!(found, Some(String::from("5")));
+        assert!(storage
+            .find_map(|_key, v: &A| (v.a > 254).then_some(()))?
+            .is_none());
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn typed_scan() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..10u8 {
+            storage.set(
+                format!("a{i}"),
+                &A {
+                    a: i,
+                    b: i.to_string(),
+                },
+            )?;
+            storage.set(
+                format!("b{i}"),
+                &B {
+                    c: i as u32,
+                    d: None,
+                },
+            )?;
+        }
+        // The record, which type has another tag (e.g. the type has been moved), is still read
+        storage.set_bytes(
+            "moved",
+            0xdead,
+            &bincode::serialize(&A {
+                a: 100,
+                b: String::from("moved"),
+            })?,
+        )?;
+        assert_eq!(storage.filter(|_: &A| true)?.len(), 11);
+        assert_eq!(
+            storage.find(|v: &A| v.a == 100)?.map(|(key, _)| key),
+            Some(String::from("moved"))
+        );
+        // Values of other types aren't reported as corrupted
+        assert!(storage.take_corruptions().is_empty());
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn stable_names() -> Result<(), E> {
+        #[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+        struct C(u32);
+        #[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+        struct D(u32);
+        impl TypeName for C {
+            const NAME: &'static str = "tests::C";
+        }
+        impl TypeName for D {
+            const NAME: &'static str = "tests::D";
+        }
+        register_type::<C>();
+        register_type::<D>();
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..10u32 {
+            storage.set(format!("c{i}"), &C(i))?;
+            storage.set(format!("d{i}"), &D(i))?;
+        }
+        // Files of records of other types aren't read: damaging them goes unnoticed
+        for i in 0..10u32 {
+            write(
+                storage.fields[&format!("d{i}")].path(storage.cwd()),
+                b"damaged",
+            )?;
+        }
+        assert_eq!(storage.filter(|_: &C| true)?.len(), 10);
+        assert_eq!(
+            storage.scan::<C>().collect::<Result<Vec<_>, E>>()?.len(),
+            10
+        );
+        assert!(storage.take_corruptions().is_empty());
+        // Records of unregistered types are still read
+        storage.set(
+            "a",
+            &A {
+                a: 1,
+                b: String::new(),
+            },
+        )?;
+        assert_eq!(storage.keys_of::<A>().count(), 21);
+        assert_eq!(storage.keys_of::<C>().count(), 11);
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn scan() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..10u8 {
+            storage.set(
+                i.to_string(),
+                &A {
+                    a: i,
+                    b: i.to_string(),
+                },
+            )?;
+        }
+        assert_eq!(storage.scan::<A>().count(), 10);
+        let path = storage
+            .fields
+            .get("3")
+            .expect("Field exists")
+            .path(&storage.cwd);
+        std::fs::write(&path, [1, 2, 3])?;
+        let errors = storage.scan::<A>().filter(|r| r.is_err()).count();
+        assert_eq!(errors, 1);
+        storage.destroy()?;
+        Ok(())
+    }
 }
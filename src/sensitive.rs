@@ -0,0 +1,332 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cell::RefCell,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{Storage, E};
+
+/// Length of the nonce, which precedes the encrypted value
+const NONCE_LEN: usize = 12;
+
+thread_local! {
+    /// Key of the storage, which is reading or writing a record in this thread, and the error
+    /// of encryption or decryption of `Sensitive` values of the record.
+    static CONTEXT: RefCell<(Option<EncryptionKey>, Option<String>)> = const { RefCell::new((None, None)) };
+}
+
+/// Key used to encrypt `Sensitive` values of records (see `StorageOptions::encryption`).
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Creates the key of ChaCha20-Poly1305 from 32 bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The encryption key.
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl EncryptionKey {
+    /// Encrypts the buffer: a random nonce followed by the buffer encrypted with
+    /// ChaCha20-Poly1305.
+    pub(crate) fn encrypt(&self, plain: &[u8]) -> Result<Vec<u8>, E> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = ChaCha20Poly1305::new(&self.0.into())
+            .encrypt(&nonce, plain)
+            .map_err(|_| E::Encryption(String::from("fail to encrypt record")))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + encrypted.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&encrypted);
+        Ok(sealed)
+    }
+
+    /// Decrypts the buffer written by `EncryptionKey::encrypt()`.
+    pub(crate) fn decrypt(&self, sealed: &[u8]) -> Result<Vec<u8>, E> {
+        if sealed.len() < NONCE_LEN {
+            return Err(E::Encryption(String::from("encrypted record is truncated")));
+        }
+        let (nonce, encrypted) = sealed.split_at(NONCE_LEN);
+        ChaCha20Poly1305::new(&self.0.into())
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| {
+                E::Encryption(String::from(
+                    "fail to decrypt record: wrong key or damaged record",
+                ))
+            })
+    }
+}
+
+impl From<[u8; 32]> for EncryptionKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for EncryptionKey {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.0);
+    }
+}
+
+/// Buffer with a serialized value or the content of a record. If the `zeroize` feature is
+/// enabled, the buffer is wiped when it's dropped, so no plaintext copies of values are left in
+/// freed memory.
+pub(crate) struct Wiped(pub(crate) Vec<u8>);
+
+impl Deref for Wiped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for Wiped {
+    fn drop(&mut self) {
+        #[cfg(feature = "zeroize")]
+        zeroize::Zeroize::zeroize(&mut self.0);
+    }
+}
+
+/// Records the error of encryption or decryption, so the storage reports `E::Encryption`
+/// instead of a serialization error.
+fn failed(msg: &str) -> String {
+    CONTEXT.with(|context| context.borrow_mut().1 = Some(msg.to_owned()));
+    msg.to_owned()
+}
+
+/// Returns the cipher of the storage, which is reading or writing a record in this thread.
+fn cipher() -> Result<ChaCha20Poly1305, String> {
+    match CONTEXT.with(|context| context.borrow().0.clone()) {
+        Some(key) => Ok(ChaCha20Poly1305::new(&key.0.into())),
+        None => Err(failed(
+            "no encryption key; set StorageOptions::encryption to read or write Sensitive values",
+        )),
+    }
+}
+
+/// Value, which is encrypted within an otherwise plaintext record. The rest of the record stays
+/// readable by tools inspecting the storage, while the value is kept as a random nonce followed
+/// by the value encrypted with ChaCha20-Poly1305.
+///
+/// The value is encrypted with the key of the storage (see `StorageOptions::encryption`), which
+/// writes the record, and decrypted by the storage, which reads it. Serializing or deserializing
+/// the value outside of the storage fails. Reading the record with a wrong key fails with
+/// `E::Encryption`; such records aren't taken as corrupted. With the `zeroize` feature, plaintext
+/// buffers of the value are wiped after encryption and decryption.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{EncryptionKey, Sensitive, Storage, StorageOptions};
+/// use serde::{Deserialize, Serialize};
+/// use std::env::temp_dir;
+/// use uuid::Uuid;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Account {
+///     login: String,
+///     password: Sensitive<String>,
+/// }
+///
+/// let options = StorageOptions {
+///     encryption: Some(EncryptionKey::new([7u8; 32])),
+///     ..Default::default()
+/// };
+/// let mut storage =
+///     Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)
+///         .unwrap();
+/// storage
+///     .set(
+///         "account",
+///         &Account {
+///             login: String::from("admin"),
+///             password: Sensitive::new(String::from("secret")),
+///         },
+///     )
+///     .unwrap();
+/// let account = storage.get::<Account, _>("account").unwrap().unwrap();
+/// assert_eq!(account.password.as_str(), "secret");
+/// storage.destroy().unwrap();
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Sensitive<T>(T);
+
+impl<T> Sensitive<T> {
+    /// Wraps the value.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Sensitive<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for Sensitive<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Sensitive<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sensitive(..)")
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T: zeroize::Zeroize> zeroize::Zeroize for Sensitive<T> {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Serialize> Serialize for Sensitive<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let cipher = cipher().map_err(ser::Error::custom)?;
+        let plain = Wiped(bincode::serialize(&self.0).map_err(ser::Error::custom)?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = cipher
+            .encrypt(&nonce, &*plain)
+            .map_err(|_| ser::Error::custom(failed("fail to encrypt sensitive value")))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + encrypted.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&encrypted);
+        serializer.serialize_bytes(&sealed)
+    }
+}
+
+impl<'de, T: for<'a> Deserialize<'a>> Deserialize<'de> for Sensitive<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let sealed = <Vec<u8>>::deserialize(deserializer)?;
+        let cipher = cipher().map_err(de::Error::custom)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(de::Error::custom(failed("sensitive value is truncated")));
+        }
+        let (nonce, encrypted) = sealed.split_at(NONCE_LEN);
+        let plain = Wiped(
+            cipher
+                .decrypt(Nonce::from_slice(nonce), encrypted)
+                .map_err(|_| {
+                    de::Error::custom(failed(
+                        "fail to decrypt sensitive value: wrong key or damaged value",
+                    ))
+                })?,
+        );
+        bincode::deserialize(&plain)
+            .map(Self)
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Storage {
+    /// Runs the serialization or deserialization of records with the key of the storage, so
+    /// `Sensitive` values are encrypted and decrypted with it. Errors of encryption are reported
+    /// as `E::Encryption`.
+    pub(crate) fn sealed<T, F: FnOnce() -> Result<T, E>>(&self, f: F) -> Result<T, E> {
+        sealed_with(self.options.encryption.clone(), f)
+    }
+}
+
+/// Runs the serialization or deserialization of records with the given key (see
+/// `Storage::sealed()`); used by threads, which don't have access to the storage.
+pub(crate) fn sealed_with<T, F: FnOnce() -> Result<T, E>>(
+    key: Option<EncryptionKey>,
+    f: F,
+) -> Result<T, E> {
+    let previous = CONTEXT.with(|context| context.replace((key, None)).0);
+    let result = f();
+    let (_, failure) = CONTEXT.with(|context| context.replace((previous, None)));
+    match (result, failure) {
+        (Err(_), Some(msg)) => Err(E::Encryption(msg)),
+        (result, _) => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EncryptionKey, Sensitive, Storage, StorageOptions, E};
+    use serde::{Deserialize, Serialize};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Account {
+        login: String,
+        token: Sensitive<String>,
+    }
+
+    #[test]
+    fn sensitive() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = |key: Option<[u8; 32]>| StorageOptions {
+            encryption: key.map(EncryptionKey::new),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options(Some([1u8; 32])))?;
+        let account = Account {
+            login: String::from("admin"),
+            token: Sensitive::new(String::from("top-secret-token")),
+        };
+        storage.set("account", &account)?;
+        assert_eq!(storage.get::<Account, _>("account")?, Some(account));
+        // The rest of the record is plaintext
+        let content = std::fs::read(storage.fields["account"].path(storage.cwd()))?;
+        let contains = |needle: &[u8]| content.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"admin"));
+        assert!(!contains(b"top-secret-token"));
+        drop(storage);
+        // Wrong key and no key
+        for key in [Some([2u8; 32]), None] {
+            let storage = Storage::open_with_options(&storage_path, options(key))?;
+            assert!(matches!(
+                storage.get::<Account, _>("account"),
+                Err(E::Record { source, .. }) if matches!(*source, E::Encryption(_))
+            ));
+            assert!(storage.take_corruptions().is_empty());
+        }
+        let mut storage = Storage::open_with_options(&storage_path, options(None))?;
+        assert!(matches!(
+            storage.set("other", &Sensitive::new(1u8)),
+            Err(E::Encryption(_))
+        ));
+        storage.destroy()?;
+        Ok(())
+    }
+}
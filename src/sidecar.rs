@@ -0,0 +1,253 @@
+//! JSON manifest written next to the bundle (`json` feature). The manifest describes records of
+//! the bundle (keys, sizes, type tags and checksums), the time of packing and the version of the
+//! application, so a receiver can check whether the bundle is compatible before unpacking it.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    bundle::{fingerprint, read_index, Index},
+    fs, type_tag, Bundle, Header, Stamp, Storage, E,
+};
+
+/// Version of the format of the manifest
+const MANIFEST_VERSION: u32 = 1;
+
+/// Record of the bundle described by `BundleManifest`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the storage: names of child storages separated by `/`, empty for the root storage
+    pub storage: String,
+    /// Key of the record
+    pub key: String,
+    /// Size of the record file in bytes
+    pub size: u64,
+    /// Type tag of the value; 0 if unknown (e.g. records written by previous versions)
+    pub tag: u64,
+    /// CRC32 of the serialized value
+    pub checksum: u32,
+}
+
+impl ManifestEntry {
+    /// Checks whether the record keeps a value of the type `V`.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Returns true if the type tag of the record is the tag of `V`.
+    pub fn is<V: ?Sized + 'static>(&self) -> bool {
+        self.tag == type_tag::<V>()
+    }
+}
+
+/// `BundleManifest` is the JSON file written next to the bundle by `Storage::pack_with_manifest()`
+/// (the name of the bundle with `.json` appended).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleManifest {
+    /// Version of the format of the manifest
+    pub version: u32,
+    /// Version of the application, which has packed the bundle
+    pub app_version: String,
+    /// Time of packing in milliseconds since UNIX epoch
+    pub created: u64,
+    /// Identifies the bundle described by the manifest: its size and the checksum of its index
+    pub fingerprint: String,
+    /// Records of the bundle
+    pub records: Vec<ManifestEntry>,
+}
+
+impl BundleManifest {
+    /// Returns the path of the manifest of the bundle.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    ///
+    /// # Returns
+    ///
+    /// * `PathBuf` - The path of the manifest.
+    pub fn path<P: AsRef<Path>>(bundle: P) -> PathBuf {
+        let mut path = OsString::from(fs::as_path_buf(bundle));
+        path.push(".json");
+        PathBuf::from(path)
+    }
+
+    /// Reads the manifest of the bundle.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file (not to the manifest).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the manifest, or an error.
+    pub fn read<P: AsRef<Path>>(bundle: P) -> Result<Self, E> {
+        let path = Self::path(bundle);
+        Ok(serde_json::from_reader(fs::read(&path)?)?)
+    }
+
+    /// Checks whether the bundle is the one described by the manifest.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if the bundle matches the manifest, or
+    ///   `E::PackageFileInvalid`.
+    pub fn check<P: AsRef<Path>>(&self, bundle: P) -> Result<(), E> {
+        let bundle = fs::as_path_buf(bundle);
+        let mut file = fs::read(&bundle)?;
+        let index = read_index(&mut file, &bundle)?;
+        if fingerprint(&file, &index)? != self.fingerprint {
+            return Err(E::PackageFileInvalid(bundle));
+        }
+        Ok(())
+    }
+}
+
+/// Collects records of the index and its children.
+fn describe(
+    bundle: &std::fs::File,
+    index: &Index,
+    storage: &str,
+    records: &mut Vec<ManifestEntry>,
+) -> Result<(), E> {
+    for location in index.records.iter().filter(|location| location.is_valid()) {
+        let size = if location.compressed {
+            location.read(bundle)?.len() as u64
+        } else {
+            location.header.len() as u64 + location.to - location.from
+        };
+        let header = Header::decode(&location.header)?.0;
+        records.push(ManifestEntry {
+            storage: storage.to_owned(),
+            key: location.key.to_owned(),
+            size,
+            tag: header.as_ref().map(|header| header.tag).unwrap_or_default(),
+            checksum: header
+                .as_ref()
+                .map(|header| header.checksum)
+                .unwrap_or_default(),
+        });
+    }
+    for (name, child) in index.children.iter() {
+        let path = if storage.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{storage}/{name}")
+        };
+        describe(bundle, child, &path, records)?;
+    }
+    Ok(())
+}
+
+impl Storage {
+    /// Packs the storage into the bundle (see `Bundle::pack()`) and writes `BundleManifest`
+    /// next to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `bundle` - A path reference to the bundle file.
+    /// * `app_version` - Version of the application, which is written into the manifest.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<BundleManifest, E>` - Returns the written manifest, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{BundleManifest, Storage};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("my_record", &1u8).unwrap();
+    /// let bundle = temp_dir().join(Uuid::new_v4().to_string());
+    /// storage.pack_with_manifest(&bundle, "1.0.0").unwrap();
+    /// // Receiver of the bundle
+    /// let manifest = BundleManifest::read(&bundle).unwrap();
+    /// assert_eq!(manifest.app_version, "1.0.0");
+    /// assert!(manifest.records[0].is::<u8>());
+    /// manifest.check(&bundle).unwrap();
+    /// storage.destroy().unwrap();
+    /// std::fs::remove_file(BundleManifest::path(&bundle)).unwrap();
+    /// std::fs::remove_file(bundle).unwrap();
+    /// ```
+    pub fn pack_with_manifest<P: AsRef<Path>>(
+        &mut self,
+        bundle: P,
+        app_version: &str,
+    ) -> Result<BundleManifest, E> {
+        let bundle = fs::as_path_buf(bundle);
+        self.pack(&bundle)?;
+        let mut file = fs::read(&bundle)?;
+        let index = read_index(&mut file, &bundle)?;
+        let mut records = Vec::new();
+        describe(&file, &index, "", &mut records)?;
+        let manifest = BundleManifest {
+            version: MANIFEST_VERSION,
+            app_version: app_version.to_owned(),
+            created: Stamp::millis(SystemTime::now()),
+            fingerprint: fingerprint(&file, &index)?,
+            records,
+        };
+        let path = BundleManifest::path(&bundle);
+        std::fs::write(&path, serde_json::to_vec_pretty(&manifest)?)
+            .map_err(|e| E::io(e, &path))?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Bundle, BundleManifest, Storage, E};
+    use std::{env::temp_dir, fs::remove_file};
+    use uuid::Uuid;
+
+    #[test]
+    fn manifest() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("a", &1u8)?;
+        storage.set("b", &String::from("value"))?;
+        storage.child("plugins/foo")?.set("enabled", &true)?;
+        let bundle = temp_dir().join(Uuid::new_v4().to_string());
+        let written = storage.pack_with_manifest(&bundle, "2.1.0")?;
+        let manifest = BundleManifest::read(&bundle)?;
+        assert_eq!(manifest, written);
+        assert_eq!(manifest.app_version, "2.1.0");
+        assert_eq!(manifest.records.len(), 3);
+        let entry = |key: &str| {
+            manifest
+                .records
+                .iter()
+                .find(|entry| entry.key == key)
+                .expect("Record is described")
+        };
+        assert!(entry("a").is::<u8>());
+        assert!(entry("b").is::<String>());
+        assert!(!entry("b").is::<u8>());
+        assert_eq!(entry("enabled").storage, "plugins/foo");
+        assert_eq!(
+            entry("a").size,
+            std::fs::metadata(storage.fields["a"].path(storage.cwd()))?.len()
+        );
+        manifest.check(&bundle)?;
+        // The manifest doesn't describe another bundle
+        storage.set("c", &3u8)?;
+        storage.pack(&bundle)?;
+        assert!(matches!(
+            manifest.check(&bundle),
+            Err(E::PackageFileInvalid(_))
+        ));
+        storage.destroy()?;
+        remove_file(BundleManifest::path(&bundle))?;
+        remove_file(&bundle)?;
+        Ok(())
+    }
+}
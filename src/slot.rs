@@ -0,0 +1,194 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Storage, E};
+
+/// Listener of changes of a `ConfigSlot`
+type Listener<'a, T> = Box<dyn FnMut(&T) + 'a>;
+
+/// `ConfigSlot` is a single value (usually settings of an application) stored under one key.
+/// A missing value is loaded as `T::default()`. Listeners registered with `on_change()` are
+/// called each time the value is saved through the slot.
+pub struct ConfigSlot<'a, T> {
+    storage: &'a mut Storage,
+    key: String,
+    listeners: Vec<Listener<'a, T>>,
+}
+
+impl<'a, T: Serialize + DeserializeOwned + Default + 'static> ConfigSlot<'a, T> {
+    /// Returns the key of the slot.
+    ///
+    /// # Returns
+    ///
+    /// * `&str` - The key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Loads the value of the slot.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, E>` - Returns the stored value, or the default value if nothing is stored
+    ///   yet, or an error.
+    pub fn load(&self) -> Result<T, E> {
+        Ok(self.storage.get::<T, _>(&self.key)?.unwrap_or_default())
+    }
+
+    /// Saves the value of the slot and notifies listeners.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - A reference to the value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn save(&mut self, value: &T) -> Result<(), E> {
+        self.storage.set(&self.key, value)?;
+        self.listeners
+            .iter_mut()
+            .for_each(|listener| listener(value));
+        Ok(())
+    }
+
+    /// Loads the value, changes it with the given closure and saves it.
+    ///
+    /// # Arguments
+    ///
+    /// * `change` - Closure, which changes the value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, E>` - Returns the saved value, or an error.
+    pub fn update<F: FnOnce(&mut T)>(&mut self, change: F) -> Result<T, E> {
+        let mut value = self.load()?;
+        change(&mut value);
+        self.save(&value)?;
+        Ok(value)
+    }
+
+    /// Removes the stored value, so the next `load()` returns the default value. Listeners are
+    /// notified with the default value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn reset(&mut self) -> Result<(), E> {
+        self.storage.remove(&self.key)?;
+        let value = T::default();
+        self.listeners
+            .iter_mut()
+            .for_each(|listener| listener(&value));
+        Ok(())
+    }
+
+    /// Registers a listener, which is called with the new value after each change made through
+    /// the slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - Closure called with the new value.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The slot with the listener.
+    pub fn on_change<F: FnMut(&T) + 'a>(mut self, listener: F) -> Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+}
+
+impl Storage {
+    /// Returns the slot of a single value stored under the key, e.g. settings of an application.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `ConfigSlot<T>` - The slot of the value.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use serde::{Deserialize, Serialize};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// #[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+    /// struct Settings {
+    ///     theme: String,
+    ///     font_size: u8,
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let mut slot = storage.slot::<Settings>("settings");
+    /// assert_eq!(slot.load().unwrap(), Settings::default());
+    /// slot.update(|s| s.font_size = 14).unwrap();
+    /// assert_eq!(slot.load().unwrap().font_size, 14);
+    /// drop(slot);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn slot<T: Serialize + DeserializeOwned + Default + 'static>(
+        &mut self,
+        key: &str,
+    ) -> ConfigSlot<'_, T> {
+        ConfigSlot {
+            storage: self,
+            key: key.to_owned(),
+            listeners: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use serde::{Deserialize, Serialize};
+    use std::{cell::RefCell, env::temp_dir};
+    use uuid::Uuid;
+
+    #[derive(Serialize, Deserialize, Default, Debug, PartialEq, Clone)]
+    struct Settings {
+        theme: String,
+        volume: u8,
+    }
+
+    #[test]
+    fn slot() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let changes: RefCell<Vec<Settings>> = RefCell::new(Vec::new());
+        {
+            let mut slot = storage
+                .slot::<Settings>("settings")
+                .on_change(|s| changes.borrow_mut().push(s.clone()));
+            assert_eq!(slot.key(), "settings");
+            assert_eq!(slot.load()?, Settings::default());
+            slot.save(&Settings {
+                theme: String::from("dark"),
+                volume: 5,
+            })?;
+            let updated = slot.update(|s| s.volume += 1)?;
+            assert_eq!(updated.volume, 6);
+            assert_eq!(slot.load()?, updated);
+        }
+        assert_eq!(changes.borrow().len(), 2);
+        assert_eq!(
+            storage.get::<Settings, _>("settings")?.map(|s| s.volume),
+            Some(6)
+        );
+        {
+            let mut slot = storage
+                .slot::<Settings>("settings")
+                .on_change(|s| changes.borrow_mut().push(s.clone()));
+            slot.reset()?;
+            assert_eq!(slot.load()?, Settings::default());
+        }
+        assert_eq!(changes.borrow().last(), Some(&Settings::default()));
+        assert!(!storage.has("settings"));
+        storage.destroy()?;
+        Ok(())
+    }
+}
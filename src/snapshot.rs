@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{Storage, E};
+
+/// Record of `StorageSnapshot`: the key with the type tag and the serialized value as they are
+/// stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotRecord {
+    /// Key of the record
+    pub key: String,
+    /// Type tag of the value (see `type_tag()`); 0 if unknown
+    pub tag: u64,
+    /// Value serialized with `bincode` (encrypted, if it has been stored with encryption)
+    pub payload: Vec<u8>,
+}
+
+impl SnapshotRecord {
+    /// Deserializes the value of the record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<V, E>` - Returns the value, or an error if it isn't a value of the type `V`.
+    pub fn value<V: for<'a> Deserialize<'a>>(&self) -> Result<V, E> {
+        Ok(bincode::deserialize::<V>(&self.payload)?)
+    }
+}
+
+/// Serializable view of all records of the storage, so the whole storage can be embedded into
+/// another serde document (e.g. a diagnostic report) and restored with
+/// `Storage::from_snapshot()`. Only records are kept; options, expiry times, pins and tags
+/// aren't.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageSnapshot {
+    /// Records sorted by keys (`get()` relies on the order)
+    pub records: Vec<SnapshotRecord>,
+}
+
+impl StorageSnapshot {
+    /// Returns the record of the key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&SnapshotRecord>` - The record, or None if the key doesn't exist.
+    pub fn get<K: AsRef<str>>(&self, key: K) -> Option<&SnapshotRecord> {
+        self.records
+            .binary_search_by(|record| record.key.as_str().cmp(key.as_ref()))
+            .ok()
+            .map(|pos| &self.records[pos])
+    }
+}
+
+impl Storage {
+    /// Takes the snapshot of all records of the storage (see `StorageSnapshot`). Expired records
+    /// are skipped.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<StorageSnapshot, E>` - Returns the snapshot, or an error if some record cannot be
+    ///   read.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Storage, StorageSnapshot};
+    /// use serde::{Deserialize, Serialize};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Diagnostic {
+    ///     version: String,
+    ///     storage: StorageSnapshot,
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("threads", &4u32).unwrap();
+    /// let report = bincode::serialize(&Diagnostic {
+    ///     version: String::from("1.0"),
+    ///     storage: storage.snapshot().unwrap(),
+    /// })
+    /// .unwrap();
+    /// let report: Diagnostic = bincode::deserialize(&report).unwrap();
+    /// assert_eq!(report.storage.get("threads").unwrap().value::<u32>().unwrap(), 4);
+    /// let mut restored =
+    ///     Storage::from_snapshot(temp_dir().join(Uuid::new_v4().to_string()), report.storage)
+    ///         .unwrap();
+    /// assert_eq!(restored.get::<u32, _>("threads").unwrap(), Some(4));
+    /// storage.destroy().unwrap();
+    /// restored.destroy().unwrap();
+    /// ```
+    pub fn snapshot(&self) -> Result<StorageSnapshot, E> {
+        let mut keys = self
+            .fields
+            .keys()
+            .filter(|key| !self.expired(key))
+            .collect::<Vec<&String>>();
+        keys.sort();
+        let mut records = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some((tag, payload)) = self.payload_of(key)? {
+                records.push(SnapshotRecord {
+                    key: key.to_owned(),
+                    tag,
+                    payload,
+                });
+            }
+        }
+        Ok(StorageSnapshot { records })
+    }
+
+    /// Creates the storage with records of the snapshot (see `Storage::snapshot()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `snapshot` - The snapshot.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with records of the snapshot, or an error.
+    pub fn from_snapshot<P: AsRef<Path>>(cwd: P, snapshot: StorageSnapshot) -> Result<Self, E> {
+        let mut storage = Storage::create(cwd)?;
+        storage.apply(
+            snapshot
+                .records
+                .into_iter()
+                .map(|record| (record.key, Some((record.tag, record.payload)))),
+        )?;
+        Ok(storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, StorageSnapshot, E};
+    use std::{env::temp_dir, time::Duration};
+    use uuid::Uuid;
+
+    #[test]
+    fn snapshot() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("b", &String::from("value"))?;
+        storage.set("a", &vec![1u64, 2, 3])?;
+        storage.set_with_ttl("expired", &1u8, Duration::ZERO)?;
+        let snapshot = storage.snapshot()?;
+        assert_eq!(
+            snapshot
+                .records
+                .iter()
+                .map(|record| record.key.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["a", "b"]
+        );
+        let snapshot: StorageSnapshot = bincode::deserialize(&bincode::serialize(&snapshot)?)?;
+        assert!(snapshot.get("missing").is_none());
+        assert_eq!(
+            snapshot
+                .get("b")
+                .map(|record| record.value::<String>())
+                .transpose()?,
+            Some(String::from("value"))
+        );
+        let mut restored =
+            Storage::from_snapshot(temp_dir().join(Uuid::new_v4().to_string()), snapshot)?;
+        assert_eq!(restored.get::<Vec<u64>, _>("a")?, Some(vec![1, 2, 3]));
+        assert_eq!(restored.get::<String, _>("b")?, Some(String::from("value")));
+        assert_eq!(restored.len(), 2);
+        // The snapshot of the restored storage is the same
+        assert_eq!(restored.snapshot()?, storage.snapshot()?);
+        storage.destroy()?;
+        restored.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! Export of the storage into a SQLite database and import back (`sqlite` feature). Records are
+//! written into the table `records`:
+//!
+//! | column     | type    | content                                                      |
+//! |------------|---------|--------------------------------------------------------------|
+//! | `key`      | TEXT    | key of the record                                            |
+//! | `payload`  | BLOB    | serialized value (`bincode`)                                 |
+//! | `tag`      | INTEGER | type tag of the value; 0 if unknown                          |
+//! | `size`     | INTEGER | size of the record's file in bytes                           |
+//! | `accessed` | INTEGER | time of the last access in milliseconds since UNIX epoch     |
+
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+use crate::{Field, Storage, E};
+
+/// Name of the table with records
+pub const SQLITE_TABLE: &str = "records";
+
+/// Number of records written with one write of the map during import.
+const SQLITE_IMPORT_CHUNK: usize = 1024;
+
+impl Storage {
+    /// Writes all records into the SQLite database (the table `records`, see the module
+    /// documentation). The database is created if it doesn't exist; records with the same keys
+    /// are replaced. Broken records are handled according to `CorruptionPolicy` in the same way as
+    /// with `get()`.
+    ///
+    /// Payloads are exported as serialized values, so the database can be read without
+    /// `bstorage`: records written with a codec or transformers (see `Codecs`) are decoded, and
+    /// encrypted records are exported unencrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A path reference to the database file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, E>` - Returns the number of exported records, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("a", &String::from("Hello")).unwrap();
+    /// let db = temp_dir().join(format!("{}.sqlite", Uuid::new_v4()));
+    /// assert_eq!(storage.export_sqlite(&db).unwrap(), 1);
+    /// // SELECT key, tag, size FROM records;
+    /// let mut restored = Storage::import_sqlite(temp_dir().join(Uuid::new_v4().to_string()), &db).unwrap();
+    /// assert_eq!(restored.get::<String, _>("a").unwrap(), Some(String::from("Hello")));
+    /// storage.destroy().unwrap();
+    /// restored.destroy().unwrap();
+    /// std::fs::remove_file(db).unwrap();
+    /// ```
+    pub fn export_sqlite<P: AsRef<Path>>(&self, path: P) -> Result<usize, E> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {SQLITE_TABLE} (
+                key TEXT PRIMARY KEY NOT NULL,
+                payload BLOB NOT NULL,
+                tag INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                accessed INTEGER NOT NULL
+            )"
+        ))?;
+        let tx = conn.transaction()?;
+        let mut exported = 0;
+        {
+            let mut insert = tx.prepare(&format!(
+                "INSERT OR REPLACE INTO {SQLITE_TABLE} (key, payload, tag, size, accessed)
+                 VALUES (?1, ?2, ?3, ?4, ?5)"
+            ))?;
+            for (key, field) in self.fields.iter() {
+                let content = match self.extract(field) {
+                    Ok(content) => content,
+                    Err(err) => {
+                        self.corrupted::<()>(key, field, err)?;
+                        continue;
+                    }
+                };
+                let payload = match Field::payload(&content) {
+                    Ok((_, payload)) => payload,
+                    Err(err) => {
+                        self.corrupted::<()>(key, field, err)?;
+                        continue;
+                    }
+                };
+                let meta = field.meta();
+                // SQLite doesn't have unsigned integers: values are stored as i64 with the same bits
+                insert.execute(params![
+                    key,
+                    payload,
+                    meta.tag as i64,
+                    meta.size as i64,
+                    meta.accessed.get() as i64
+                ])?;
+                exported += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(exported)
+    }
+
+    /// Creates (or opens) the storage and writes into it records of the SQLite database exported
+    /// with `export_sqlite()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `path` - A path reference to the database file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the storage with imported records, or an error.
+    pub fn import_sqlite<P: AsRef<Path>, D: AsRef<Path>>(cwd: P, path: D) -> Result<Self, E> {
+        let conn = Connection::open(path)?;
+        let mut select = conn.prepare(&format!("SELECT key, tag, payload FROM {SQLITE_TABLE}"))?;
+        let rows = select.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)? as u64,
+                row.get::<_, Vec<u8>>(2)?,
+            ))
+        })?;
+        let mut storage = Storage::create(cwd)?;
+        let mut chunk = Vec::with_capacity(SQLITE_IMPORT_CHUNK);
+        for row in rows {
+            let (key, tag, payload) = row?;
+            chunk.push((key, Some((tag, payload))));
+            if chunk.len() >= SQLITE_IMPORT_CHUNK {
+                storage.apply(chunk.drain(..))?;
+            }
+        }
+        storage.apply(chunk)?;
+        Ok(storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use rusqlite::Connection;
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn sqlite() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        for i in 0..2000u32 {
+            storage.set(format!("n{i}"), &i)?;
+        }
+        storage.set("s", &String::from("text"))?;
+        let db = temp_dir().join(format!("{}.sqlite", Uuid::new_v4()));
+        assert_eq!(storage.export_sqlite(&db)?, 2001);
+        let conn = Connection::open(&db)?;
+        let payload: Vec<u8> =
+            conn.query_row("SELECT payload FROM records WHERE key = 'n7'", [], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(bincode::deserialize::<u32>(&payload)?, 7);
+        drop(conn);
+        // Records are replaced on the next export
+        assert_eq!(storage.export_sqlite(&db)?, 2001);
+        let mut restored =
+            Storage::import_sqlite(temp_dir().join(Uuid::new_v4().to_string()), &db)?;
+        assert_eq!(restored.len(), 2001);
+        assert_eq!(restored.get::<u32, _>("n1999")?, Some(1999));
+        assert_eq!(restored.get::<String, _>("s")?, Some(String::from("text")));
+        // Type tags are kept
+        assert_eq!(restored.to_map::<u32>()?.len(), 2000);
+        storage.destroy()?;
+        restored.destroy()?;
+        std::fs::remove_file(db)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::{map::MAP_FILE_NAME, recover, Storage, E};
+
+/// Number of largest records listed in `Stats::largest`
+const LARGEST: usize = 10;
+
+/// Statistics of the storage returned by `Storage::stats()`. It's serializable, so it can be
+/// exported into monitoring as is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    /// Number of keys
+    pub records: usize,
+    /// Total size of record files in bytes; shared files are counted once
+    pub bytes: u64,
+    /// Average size of a record file in bytes
+    pub average: u64,
+    /// Keys and sizes of the largest record files (largest first)
+    pub largest: Vec<(String, u64)>,
+    /// Number of record files larger than `Limits::warn_record_size`
+    pub large: usize,
+    /// Number of record files in the storage folder, which aren't referenced by keys
+    pub orphans: usize,
+    /// Size of the map file in bytes
+    pub map: u64,
+    /// Space allocated for record files on the disk in bytes
+    pub allocated: u64,
+    /// Estimate of fragmentation: the share of the allocated space, which isn't used by the
+    /// content of record files (0.0 - 1.0)
+    pub fragmentation: f64,
+}
+
+impl Storage {
+    /// Collects statistics of the storage. Sizes of files on the disk are read, so the call is
+    /// proportional to the number of records. Orphaned files aren't counted with a custom
+    /// `FileSystem`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Stats, E>` - Returns statistics of the storage, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("a", &vec![0u8; 128]).unwrap();
+    /// let stats = storage.stats().unwrap();
+    /// assert_eq!(stats.records, 1);
+    /// assert_eq!(stats.largest[0].0, "a");
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn stats(&self) -> Result<Stats, E> {
+        let bytes = self.total_size();
+        let mut largest: Vec<(String, u64)> = self
+            .fields
+            .iter()
+            .map(|(key, field)| (key.to_owned(), field.meta().size))
+            .collect();
+        largest.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+        largest.truncate(LARGEST);
+        let mut seen = HashSet::new();
+        let mut allocated = 0;
+        let mut used = 0;
+        for field in self.fields.values() {
+            if !seen.insert(field.file_name()) {
+                continue;
+            }
+            allocated += field.allocated(&*self.fs, &self.cwd)?;
+            used += field.meta().size;
+        }
+        let map = self.cwd.join(MAP_FILE_NAME);
+        let orphans = if self.options.fs.is_none() {
+            let known = seen.into_iter().map(String::from).collect();
+            recover::orphans(&self.cwd, &known)?.len()
+        } else {
+            0
+        };
+        Ok(Stats {
+            records: self.fields.len(),
+            bytes,
+            average: bytes
+                .checked_div(self.fields.len() as u64)
+                .unwrap_or_default(),
+            largest,
+            large: self.large_records().len(),
+            orphans,
+            map: if self.fs.exists(&map) {
+                self.fs.size(&map)?
+            } else {
+                0
+            },
+            allocated,
+            fragmentation: if allocated > used {
+                (allocated - used) as f64 / allocated as f64
+            } else {
+                0.0
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Limits, Storage, StorageOptions, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn stats() -> Result<(), E> {
+        let options = StorageOptions {
+            limits: Limits {
+                warn_record_size: Some(1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut storage =
+            Storage::create_with_options(temp_dir().join(Uuid::new_v4().to_string()), options)?;
+        assert_eq!(storage.stats()?.records, 0);
+        for i in 0..20usize {
+            storage.set(i.to_string(), &vec![0u8; i * 100])?;
+        }
+        std::fs::write(storage.cwd().join("orphan.bstorage"), [1, 2, 3])?;
+        let stats = storage.stats()?;
+        assert_eq!(stats.records, 20);
+        assert_eq!(stats.bytes, storage.usage().bytes);
+        assert_eq!(stats.average, stats.bytes / 20);
+        assert_eq!(stats.largest.len(), 10);
+        assert_eq!(stats.largest[0].0, "19");
+        assert_eq!(stats.large, 10);
+        assert_eq!(stats.orphans, 1);
+        assert!(stats.map > 0);
+        assert!((0.0..=1.0).contains(&stats.fragmentation));
+        storage.destroy()?;
+        Ok(())
+    }
+}
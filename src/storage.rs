@@ -1,11 +1,39 @@
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    fs::{create_dir_all, remove_dir_all},
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    io,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 
-use crate::{fs, Field, Map, E};
+use crate::{
+    bundle::PARTIAL_FILE_NAME,
+    cancel,
+    changes::Changes,
+    count_refs, deserialize_tagged,
+    expiry::{Deadlines, Events},
+    fs,
+    hasher::Fields,
+    history::Journal,
+    manifest,
+    middleware::Layers,
+    preload::Preloaded,
+    replication::Replicas,
+    sensitive::Wiped,
+    tiering::Archive,
+    trace::op,
+    transform::Transformers,
+    type_tag,
+    validation::Validators,
+    vfs, Corruption, CorruptionKind, CorruptionPolicy, Durability, Field, FileSystem, HandlePool,
+    Header, Issue, Layout, Lock, MaintenanceReport, Map, Operation, Problem, ReconcileReport,
+    RecordSize, StorageKey, StorageOptions, SymlinkPolicy, Usage, VerifyReport, E,
+};
+use log::{debug, error};
 
 /// `Storage` is a struct for managing binary data storage. It utilizes the `bincode` crate for
 /// serialization and deserialization of data. Each record is stored as a separate file within a specified directory.
@@ -23,7 +51,51 @@ use crate::{fs, Field, Map, E};
 pub struct Storage {
     pub(crate) map: Map,
     pub(crate) cwd: PathBuf,
-    pub(crate) fields: HashMap<String, Field>,
+    pub(crate) fields: Fields,
+    /// Ordered index of keys, if `StorageOptions::ordered` is used
+    pub(crate) ordered: Option<BTreeSet<String>>,
+    pub(crate) options: StorageOptions,
+    /// Reports about corrupted records, which haven't been taken yet
+    pub(crate) corruptions: Mutex<Vec<Corruption>>,
+    /// Keys which have to be removed from the map with the next modifying call, because their
+    /// files have been quarantined or removed according to `CorruptionPolicy`
+    pub(crate) detached: Mutex<Vec<String>>,
+    /// True if access times of fields have been changed since the map has been written
+    pub(crate) touched: AtomicBool,
+    /// Report of the maintenance done when the storage has been opened
+    pub(crate) maintained: Option<MaintenanceReport>,
+    /// Report of the reconciliation done when the storage has been opened
+    pub(crate) reconciled: Option<ReconcileReport>,
+    /// Number of references to record files shared by several keys (see `Layout`)
+    pub(crate) refs: HashMap<String, usize>,
+    /// Exclusive access to the storage folder, if the storage is opened via `StorageManager`
+    pub(crate) lock: Option<Lock>,
+    /// Open record files, if `StorageOptions::handles` is used
+    pub(crate) handles: Option<HandlePool>,
+    /// File system of the storage (see `StorageOptions::fs`)
+    pub(crate) fs: Arc<dyn FileSystem>,
+    /// Journal of changes, if `StorageOptions::history` is used
+    pub(crate) journal: Option<Journal>,
+    /// Streams of changes to replicas (see `Storage::replicate_to()`)
+    pub(crate) replicas: Replicas,
+    /// Expiry times of keys (see `Storage::expire()`)
+    pub(crate) deadlines: Deadlines,
+    /// Handlers of events (see `Storage::on_event()`)
+    pub(crate) events: Events,
+    /// Log of changes, if `StorageOptions::changes` is used
+    pub(crate) changes: Option<Changes>,
+    /// Middlewares of `set()`, `get()` and `remove()` (see `Storage::layer()`)
+    pub(crate) layers: Layers,
+    /// Validators of values (see `Storage::validator()`)
+    pub(crate) validators: Validators,
+    /// Transformers of values (see `Storage::transformer()`)
+    pub(crate) transformers: Transformers,
+    /// Records read in advance (see `Storage::preload()`)
+    pub(crate) preloaded: Preloaded,
+    /// Archive of rarely used records, if it exists (see `Storage::archive_older_than()`)
+    pub(crate) archive: Option<Archive>,
+    /// True if the folder is removed on drop (see `Storage::unpack_temp()`)
+    pub(crate) temporary: bool,
 }
 
 impl Storage {
@@ -71,10 +143,31 @@ impl Storage {
     /// assert_eq!(my_record, recovered)
     /// ```
     pub fn create<P: AsRef<Path>>(cwd: P) -> Result<Self, E> {
-        if !cwd.as_ref().exists() {
-            create_dir_all(&cwd)?;
+        Storage::create_with_options(cwd, StorageOptions::default())
+    }
+
+    /// Creates a new storage if it does not exist and opens the storage with the given options.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `options` - Options of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the created `Storage` instance or an error.
+    pub fn create_with_options<P: AsRef<Path>>(cwd: P, options: StorageOptions) -> Result<Self, E> {
+        let fs = vfs::resolve(&options);
+        if !fs.exists(cwd.as_ref()) {
+            fs.create_dir_all(cwd.as_ref())
+                .map_err(|e| E::io(e, cwd.as_ref()))?;
+            if let (Durability::FsyncDir, Some(parent)) =
+                (options.durability, cwd.as_ref().parent())
+            {
+                fs.sync_dir(parent)?;
+            }
         }
-        Storage::open(cwd)
+        Storage::open_with_options(cwd, options)
     }
 
     /// Opens an existing storage.
@@ -87,16 +180,203 @@ impl Storage {
     ///
     /// * `Result<Self, E>` - Returns the opened `Storage` instance or an error.
     pub fn open<P: AsRef<Path>>(cwd: P) -> Result<Self, E> {
-        if !cwd.as_ref().exists() {
+        Storage::open_with_options(cwd, StorageOptions::default())
+    }
+
+    /// Opens an existing storage with the given options.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    /// * `options` - Options of the storage.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the opened `Storage` instance or an error.
+    pub fn open_with_options<P: AsRef<Path>>(cwd: P, options: StorageOptions) -> Result<Self, E> {
+        let fs = vfs::resolve(&options);
+        if !fs.exists(cwd.as_ref()) {
             return Err(E::PathIsNotFolder(fs::as_path_buf(cwd)));
         }
-        let map = Map::new(&cwd);
-        let fields = map.read()?;
-        Ok(Self {
+        let cwd = match options.symlinks {
+            _ if options.fs.is_some() => fs::as_path_buf(cwd),
+            SymlinkPolicy::Follow => fs::as_path_buf(cwd),
+            SymlinkPolicy::NoFollow => std::fs::canonicalize(fs::long_path(cwd.as_ref()))
+                .map_err(|e| E::io(e, cwd.as_ref()))?,
+            SymlinkPolicy::Reject => {
+                let cwd = fs::as_path_buf(cwd);
+                if fs::long_path(&cwd)
+                    .symlink_metadata()
+                    .is_ok_and(|meta| meta.file_type().is_symlink())
+                {
+                    return Err(E::SymlinkRejected(cwd));
+                }
+                cwd
+            }
+        };
+        if fs.exists(&cwd.join(PARTIAL_FILE_NAME)) {
+            return Err(E::PartiallyUnpacked(cwd));
+        }
+        let mut map = Map::new(&cwd, &options);
+        let (fields, missing) = map.read()?;
+        let detached = if options.reconcile {
+            missing.clone()
+        } else {
+            Vec::new()
+        };
+        let mut corruptions = Vec::new();
+        for (key, path) in missing {
+            if options.corruption == CorruptionPolicy::Error {
+                return Err(E::from(io::Error::from(io::ErrorKind::NotFound)).record(
+                    Operation::Read,
+                    key,
+                    &path,
+                ));
+            }
+            corruptions.push(Corruption::handle(
+                options.corruption,
+                cwd.as_ref(),
+                &key,
+                &path,
+                CorruptionKind::Missing,
+            )?);
+        }
+        let on_open = options.maintenance.as_ref().is_some_and(|m| m.on_open);
+        let refs = count_refs(&fields, options.layout);
+        // Open files of the pool are `std::fs` files, so the pool isn't used with a custom file
+        // system
+        let handles = options
+            .handles
+            .as_ref()
+            .filter(|_| options.fs.is_none())
+            .map(|pool| HandlePool::new(pool, fs::Access::new(&options)));
+        let journal = Journal::load(&*fs, cwd.as_ref(), &options)?;
+        let deadlines = Deadlines::load(&*fs, cwd.as_ref())?;
+        let changes = Changes::load(cwd.as_ref(), &options)?;
+        let archive = Archive::load(cwd.as_ref())?;
+        let mut storage = Self {
             map,
+            refs,
             fields,
+            ordered: None,
             cwd: fs::as_path_buf(cwd),
-        })
+            options,
+            corruptions: Mutex::new(corruptions),
+            detached: Mutex::new(Vec::new()),
+            touched: AtomicBool::new(false),
+            maintained: None,
+            reconciled: None,
+            lock: None,
+            handles,
+            fs,
+            journal,
+            replicas: Replicas::default(),
+            deadlines,
+            events: Events::default(),
+            changes,
+            layers: Layers::default(),
+            validators: Validators::default(),
+            transformers: Transformers::default(),
+            preloaded: Preloaded::default(),
+            archive,
+            temporary: false,
+        };
+        storage.build_ordered();
+        if storage.options.reconcile {
+            storage.reconciled = Some(storage.reconcile_with(detached)?);
+        }
+        if on_open {
+            storage.maintained = Some(storage.maintain()?);
+        }
+        Ok(storage)
+    }
+
+    /// Returns the report of the maintenance done when the storage has been opened (see
+    /// `Maintenance::on_open`).
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&MaintenanceReport>` - The report, or None if maintenance hasn't been done.
+    pub fn maintenance_report(&self) -> Option<&MaintenanceReport> {
+        self.maintained.as_ref()
+    }
+
+    /// Opens an existing storage and verifies it (see `verify()`). In addition to reading the map it checks,
+    /// that every referenced file exists, isn't empty and has a valid checksum. Instead of logging warnings and continuing, as `open`
+    /// does, it returns `E::Verification` with the detailed report of all found problems.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage directory.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Self, E>` - Returns the opened `Storage` instance or an error.
+    pub fn open_strict<P: AsRef<Path>>(cwd: P) -> Result<Self, E> {
+        let storage = Storage::open(cwd)?;
+        let mut report = storage.verify()?;
+        for corruption in storage.take_corruptions() {
+            report.checked += 1;
+            report.issues.push(Issue {
+                key: corruption.key,
+                path: corruption.path,
+                problem: Problem::Missing,
+            });
+        }
+        if report.is_ok() {
+            Ok(storage)
+        } else {
+            Err(E::Verification(report))
+        }
+    }
+
+    /// Verifies all records of the storage: every record file should exist, shouldn't be empty and
+    /// should have a valid checksum (records written by previous versions of `bstorage` don't have
+    /// checksums and are not checked). Values of records with SHA-256 digests (see
+    /// `StorageOptions::digests`) should match their digests; values, which cannot be decoded to
+    /// compute digests, are reported as well.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<VerifyReport, E>` - Returns the report with found problems, or an error.
+    pub fn verify(&self) -> Result<VerifyReport, E> {
+        self.verify_cancellable(&cancel::NEVER)
+    }
+
+    /// Verifies all records as `verify()` does; the verification can be cancelled by setting the
+    /// flag from another thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `cancel` - Stops the verification with `E::Cancelled` when set.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<VerifyReport, E>` - Returns the report with found problems, or an error.
+    pub fn verify_cancellable(&self, cancel: &AtomicBool) -> Result<VerifyReport, E> {
+        let mut report = VerifyReport::default();
+        for (key, field) in self.fields.iter() {
+            cancel::check(cancel)?;
+            report.checked += 1;
+            let problem = match field.extract(&*self.fs, &self.cwd) {
+                Ok(content) if content.is_empty() => Problem::Empty,
+                Ok(content) if Field::payload(&content).is_err() => Problem::Checksum,
+                Ok(_) if field.meta().digest.is_none() => continue,
+                Ok(_) => match self.digest_of(key, field) {
+                    Ok(digest) if Some(digest) == field.meta().digest => continue,
+                    Ok(_) => Problem::Digest,
+                    Err(err) => Problem::Undecodable(err.to_string()),
+                },
+                Err(E::IO(err)) if err.kind() == io::ErrorKind::NotFound => Problem::Missing,
+                Err(err) => return Err(err.record(Operation::Read, key, &field.path(&self.cwd))),
+            };
+            report.issues.push(Issue {
+                key: key.to_owned(),
+                path: field.path(&self.cwd),
+                problem,
+            });
+        }
+        Ok(report)
     }
 
     /// Retrieves a value associated with the specified key. Returns None of case of deserializing error.
@@ -108,6 +388,10 @@ impl Storage {
     /// field is correct, you might want to use the method `get_sensitive` instead `get`.  `get_sensitive` will
     /// return an error if an attempt to deserialize the field fails.
     ///
+    /// What happens with a broken or missing record file is defined by `StorageOptions::corruption`. By default
+    /// (`CorruptionPolicy::Skip`) the record is treated as absent and the problem is reported (see
+    /// `take_corruptions()`).
+    ///
     /// # Arguments
     ///
     /// * `key` - A reference to the key as a string slice.
@@ -115,17 +399,176 @@ impl Storage {
     /// # Returns
     ///
     /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
-    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: AsRef<str>>(
+    pub fn get<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
         &self,
         key: K,
     ) -> Result<Option<V>, E> {
+        let key = self.normalized(key.to_key());
+        let op = op!("get", key, key.as_ref());
+        if !self.layers.is_empty() {
+            let Some((tag, payload)) = self.get_through(key.as_ref())? else {
+                return Ok(None);
+            };
+            let payload = Wiped(payload);
+            return self.sealed(|| Ok(Some(deserialize_tagged::<V>(tag, &payload)?)));
+        }
         let Some(field) = self.fields.get(key.as_ref()) else {
+            return self.archived(key.as_ref());
+        };
+        if self.expired(key.as_ref()) {
+            return Ok(None);
+        }
+        op.size(|| field.size(&*self.fs, &self.cwd).ok());
+        self.accessed(field);
+        match self.sealed(|| {
+            self.extract(field)
+                .and_then(|content| Field::value::<V>(&Wiped(content)))
+        }) {
+            Ok(value) => Ok(value),
+            Err(err) => self.corrupted(key.as_ref(), field, err),
+        }
+    }
+
+    /// Applies `CorruptionPolicy` to the record, which cannot be read. Only damaged (wrong
+    /// checksum or header, empty file) and missing files are corrupted; a value, which cannot be
+    /// deserialized as `V`, is most likely a value of another type, so None is returned.
+    pub(crate) fn corrupted<V: 'static>(
+        &self,
+        key: &str,
+        field: &Field,
+        err: E,
+    ) -> Result<Option<V>, E> {
+        let path = field.path(&self.cwd);
+        let kind = match &err {
+            E::Bincode(_) if !self.fs.size(&path).is_ok_and(|size| size == 0) => {
+                if field.meta().tag == type_tag::<V>() {
+                    debug!("Record \"{key}\" cannot be deserialized as its own type: {err}");
+                } else {
+                    debug!("Record \"{key}\" keeps a value of another type: {err}");
+                }
+                return Ok(None);
+            }
+            E::Bincode(_) => CorruptionKind::Invalid(String::from("empty file")),
+            E::ChecksumMismatch | E::InvalidHeader(_) => CorruptionKind::Invalid(err.to_string()),
+            E::IO(err) if err.kind() == io::ErrorKind::NotFound => CorruptionKind::Missing,
+            _ => return Err(err.record(Operation::Get, key, &path)),
+        };
+        if self.options.corruption == CorruptionPolicy::Error {
+            return Err(err.record(Operation::Get, key, &path));
+        }
+        let report = Corruption::handle(self.options.corruption, &self.cwd, key, &path, kind)
+            .map_err(|e| e.record(Operation::Get, key, &path))?;
+        if report.detached() {
+            self.detached
+                .lock()
+                .map_err(|_| E::Unknown)?
+                .push(key.to_owned());
+        }
+        self.corruptions
+            .lock()
+            .map_err(|_| E::Unknown)?
+            .push(report);
+        Ok(None)
+    }
+
+    /// Returns true if the record couldn't be read, because its file doesn't exist anymore.
+    pub(crate) fn vanished(&self, field: &Field, err: &E) -> bool {
+        matches!(err.root(), E::IO(err) if err.kind() == io::ErrorKind::NotFound)
+            && !self.fs.exists(&field.path(&self.cwd))
+    }
+
+    /// Reads the value of the key listed by an iteration over keys (`Search`, `scan()`, etc.).
+    /// Iterations work on a snapshot of keys taken when the iteration starts. The record, which
+    /// file has been removed since then by another handle of the storage folder (another
+    /// `Storage` opened on the same folder or another process), is skipped instead of being
+    /// reported as corrupted.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value, None if the record doesn't exist anymore,
+    ///   or an error.
+    pub(crate) fn get_listed<V: for<'a> Deserialize<'a> + 'static>(
+        &self,
+        key: &str,
+    ) -> Result<Option<V>, E> {
+        let Some(field) = self.fields.get(key) else {
             return Ok(None);
         };
-        field.get::<V>()
+        if self.expired(key) {
+            return Ok(None);
+        }
+        self.accessed(field);
+        match self.sealed(|| {
+            self.extract(field)
+                .and_then(|content| Field::value::<V>(&Wiped(content)))
+        }) {
+            Ok(value) => Ok(value),
+            Err(err) => self.skipped(key, field, err),
+        }
+    }
+
+    /// Skips the record listed by an iteration over keys, which file has been removed
+    /// concurrently; other failures are handled according to `CorruptionPolicy`.
+    pub(crate) fn skipped<V: 'static>(
+        &self,
+        key: &str,
+        field: &Field,
+        err: E,
+    ) -> Result<Option<V>, E> {
+        if self.vanished(field, &err) {
+            debug!("Record \"{key}\" has been removed during iteration; skipped");
+            return Ok(None);
+        }
+        self.corrupted(key, field, err)
+    }
+
+    /// Removes from the storage keys, which files have been quarantined or removed according
+    /// to `CorruptionPolicy`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if some keys have been removed, or an error.
+    pub(crate) fn prune(&mut self) -> Result<bool, E> {
+        let detached: Vec<String> = self
+            .detached
+            .get_mut()
+            .map_err(|_| E::Unknown)?
+            .drain(..)
+            .collect();
+        let mut pruned = false;
+        for key in detached {
+            if self
+                .fields
+                .get(&key)
+                .is_some_and(|f| !self.fs.exists(&f.path(&self.cwd)))
+            {
+                self.forget(&key);
+                pruned = true;
+            }
+        }
+        self.purge_expired()?;
+        Ok(self.restore_archived()? || pruned)
+    }
+
+    /// Takes reports about corrupted records detected since the storage has been opened (or since
+    /// the previous call of this method).
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Corruption>` - Reports about corrupted records.
+    pub fn take_corruptions(&self) -> Vec<Corruption> {
+        self.corruptions
+            .lock()
+            .map(|mut reports| reports.drain(..).collect())
+            .unwrap_or_default()
     }
 
     /// Retrieves a value associated with the specified key.Returns error in case of case of deserializing error.
+    /// Like `get`, the call passes through middlewares and falls back to archived records.
     ///
     /// # Arguments
     ///
@@ -134,14 +577,45 @@ impl Storage {
     /// # Returns
     ///
     /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
-    pub fn get_sensitive<V: for<'a> Deserialize<'a> + 'static, K: AsRef<str>>(
+    pub fn get_sensitive<V: for<'a> Deserialize<'a> + 'static, K: StorageKey>(
         &self,
         key: K,
     ) -> Result<Option<V>, E> {
-        let Some(field) = self.fields.get(key.as_ref()) else {
+        let key = self.normalized(key.to_key());
+        if !self.layers.is_empty() {
+            let _op = op!("get", key, key.as_ref());
+            let Some((tag, payload)) = self.get_through(key.as_ref())? else {
+                return Ok(None);
+            };
+            let payload = Wiped(payload);
+            return self.sealed(|| Ok(Some(deserialize_tagged::<V>(tag, &payload)?)));
+        }
+        if !self.fields.contains_key(key.as_ref()) {
+            return self.archived(key.as_ref());
+        }
+        self.get_stored(key.as_ref())
+    }
+
+    /// Reads the record of the listed key as it is stored (without middlewares), returning
+    /// errors of deserializing (see `Storage::get_sensitive()`).
+    pub(crate) fn get_stored<V: for<'a> Deserialize<'a> + 'static>(
+        &self,
+        key: &str,
+    ) -> Result<Option<V>, E> {
+        let op = op!("get", key, key);
+        let Some(field) = self.fields.get(key) else {
             return Ok(None);
         };
-        field.get_sensitive::<V>()
+        if self.expired(key) {
+            return Ok(None);
+        }
+        op.size(|| field.size(&*self.fs, &self.cwd).ok());
+        self.accessed(field);
+        self.sealed(|| {
+            self.extract(field)
+                .and_then(|content| Field::value::<V>(&Wiped(content)))
+        })
+        .map_err(|e| e.record(Operation::Get, key, &field.path(&self.cwd)))
     }
 
     /// Retrieves a value associated with the specified key, or returns a default value if the key does not exist.
@@ -153,7 +627,7 @@ impl Storage {
     /// # Returns
     ///
     /// * `Result<V, E>` - Returns the value or the default value, or an error.
-    pub fn get_or_default<V: for<'a> Deserialize<'a> + 'static + Default, K: AsRef<str>>(
+    pub fn get_or_default<V: for<'a> Deserialize<'a> + 'static + Default, K: StorageKey>(
         &self,
         key: K,
     ) -> Result<V, E> {
@@ -169,8 +643,12 @@ impl Storage {
     /// # Returns
     ///
     /// * `bool` - Returns true if the key exists, false otherwise.
-    pub fn has<K: AsRef<str>>(&self, key: K) -> bool {
-        self.fields.contains_key(key.as_ref())
+    pub fn has<K: StorageKey>(&self, key: K) -> bool {
+        let key = self.normalized(key.to_key());
+        if !self.fields.contains_key(key.as_ref()) {
+            return self.is_archived(key.as_ref());
+        }
+        !self.expired(key.as_ref())
     }
 
     /// Sets a value for the specified key.
@@ -183,22 +661,241 @@ impl Storage {
     /// # Returns
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
-    pub fn set<V: Serialize + 'static, K: AsRef<str>>(
+    pub fn set<V: Serialize + 'static, K: StorageKey>(
         &mut self,
         key: K,
         value: &V,
     ) -> Result<(), E> {
-        if !self.cwd().exists() {
+        let key = self.normalized(key.to_key());
+        self.check_key(key.as_ref())?;
+        self.validators.check(key.as_ref(), value)?;
+        let buffer = Wiped(self.sealed(|| Ok(bincode::serialize(value)?))?);
+        self.set_through(key.as_ref(), type_tag::<V>(), &buffer)
+    }
+
+    /// Sets already serialized value for the specified key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A reference to the key as a string slice.
+    /// * `tag` - Type tag of the value.
+    /// * `buffer` - Value serialized with `bincode`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub(crate) fn set_bytes<K: AsRef<str>>(
+        &mut self,
+        key: K,
+        tag: u64,
+        buffer: &[u8],
+    ) -> Result<(), E> {
+        let op = op!("set", key, key.as_ref());
+        op.size(|| Some(buffer.len() as u64));
+        if !self.fs.exists(self.cwd()) {
             return Err(E::PathIsNotFolder(fs::as_path_buf(self.cwd().clone())));
         }
-        let field = if let Some(field) = self.fields.remove(key.as_ref()) {
+        self.prune()?;
+        self.put(key.as_ref(), tag, buffer)?;
+        self.evict(&[key.as_ref()])?;
+        self.map.write(&self.fields)?;
+        self.save_deadlines()?;
+        self.publish(key.as_ref(), Some((tag, buffer)));
+        Ok(())
+    }
+
+    /// Writes the record of the key without writing the map. Fails with `E::QuotaExceeded`
+    /// if the change exceeds `StorageOptions::limits`.
+    pub(crate) fn put(&mut self, key: &str, tag: u64, buffer: &[u8]) -> Result<(), E> {
+        // Files of a stale instance would be left in the folder of the new storage
+        self.map.check()?;
+        let shared = self.options.layout == Layout::ContentAddressed;
+        // Shared files don't keep keys, because the same file belongs to many keys
+        let owner = if shared { "" } else { key };
+        let transformed = self.transformers.encode(owner, buffer)?;
+        let transformers = self.transformers.names();
+        let digest = self.options.digests.then(|| manifest::digest(buffer));
+        let codec = self.options.codecs.of(key);
+        let payload = codec.encode(&transformed, self.options.encryption.as_ref())?;
+        let header = Header::new(owner, tag, &payload).encoded(codec, transformers.clone());
+        let content = Wiped(Field::encode_with(header, &payload)?);
+        self.admit(key, buffer.len() as u64, content.len() as u64)?;
+        if shared {
+            return self.put_shared(key, tag, codec, transformers, digest, &content);
+        }
+        // Pins and tags of the record are kept, even if its file is replaced
+        let kept = self.fields.get(key).map(|field| field.meta().clone());
+        if self.is_shared(key) {
+            self.discard(key)?;
+        }
+        if let Some(field) = self.fields.get(key) {
+            self.release(field);
+        }
+        if let Some(field) = self.fields.get_mut(key) {
             field
-        } else {
-            Field::create(&self.cwd)
+                .store(&*self.fs, &self.cwd, tag, &content, self.options.durability)
+                .map_err(|e| e.record(Operation::Set, key, &field.path(&self.cwd)))?;
+            field.meta_mut().codec = codec;
+            field.meta_mut().transformers = transformers;
+            field.meta_mut().digest = digest;
+            return Ok(());
+        }
+        let mut field = Field::create();
+        if let Some(kept) = kept {
+            field.meta_mut().pinned = kept.pinned;
+            field.meta_mut().tags = kept.tags;
+        }
+        field
+            .store(&*self.fs, &self.cwd, tag, &content, self.options.durability)
+            .map_err(|e| e.record(Operation::Set, key, &field.path(&self.cwd)))?;
+        field.meta_mut().codec = codec;
+        field.meta_mut().transformers = transformers;
+        field.meta_mut().digest = digest;
+        self.fields.insert(key.to_owned(), field);
+        self.index_key(key);
+        Ok(())
+    }
+
+    /// Prepares the key for a new record file: checks `StorageOptions::limits`, keeps the
+    /// previous value in the history and cancels the expiry of the key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    /// * `record` - Size of the serialized value.
+    /// * `file` - Size of the new record file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns `E::QuotaExceeded` if the record doesn't fit the limits, or
+    ///   Ok(()).
+    pub(crate) fn admit(&mut self, key: &str, record: u64, file: u64) -> Result<(), E> {
+        self.options
+            .limits
+            .check(
+                || self.usage(),
+                self.fields.get(key).map(|field| field.meta().size),
+                record,
+                file,
+            )
+            .map_err(E::QuotaExceeded)?;
+        self.retain(&[key])?;
+        self.deadlines.cancel(key);
+        if let Some(limit) = self.options.limits.warn_record_size {
+            if record > limit {
+                log::warn!(
+                    "Value of \"{key}\" has {record} bytes, which exceeds {limit} bytes; consider splitting it into several keys"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the current usage of the storage: number of keys and total size of record files.
+    ///
+    /// # Returns
+    ///
+    /// * `Usage` - Current usage of the storage.
+    pub fn usage(&self) -> Usage {
+        Usage {
+            keys: self.fields.len(),
+            bytes: self.total_size(),
+        }
+    }
+
+    /// Returns records, which files are larger than `Limits::warn_record_size`, sorted by size
+    /// (largest first). Returns nothing if the threshold isn't set.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(String, u64)>` - Keys and sizes of record files in bytes.
+    pub fn large_records(&self) -> Vec<(String, u64)> {
+        let Some(limit) = self.options.limits.warn_record_size else {
+            return Vec::new();
         };
-        field.set::<V>(value)?;
-        self.fields.insert(key.as_ref().to_owned(), field);
-        self.map.write(&self.fields)
+        let mut large: Vec<(String, u64)> = self
+            .fields
+            .iter()
+            .filter(|(_, field)| field.meta().size > limit)
+            .map(|(key, field)| (key.to_owned(), field.meta().size))
+            .collect();
+        large.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+        large
+    }
+
+    /// Returns the size of the record file of the key: its length and the space allocated for it
+    /// on the disk (see `RecordSize`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<RecordSize>, E>` - Returns the size of the record file, None if the key
+    ///   doesn't exist, or an error.
+    pub fn record_size<K: AsRef<str>>(&self, key: K) -> Result<Option<RecordSize>, E> {
+        let key = self.normalized(Cow::Borrowed(key.as_ref()));
+        let Some(field) = self.fields.get(key.as_ref()) else {
+            return Ok(None);
+        };
+        let map = |e: E| e.record(Operation::Get, key.as_ref(), &field.path(&self.cwd));
+        Ok(Some(RecordSize {
+            logical: field.size(&*self.fs, &self.cwd).map_err(map)?,
+            allocated: field.allocated(&*self.fs, &self.cwd).map_err(map)?,
+        }))
+    }
+
+    /// Applies a batch of changes with a single write of the map. `Some((tag, buffer))` sets
+    /// a serialized value with the given type tag for the key, `None` removes the key. Keys of
+    /// set values are checked against `StorageOptions::key_policy`; changes don't pass through
+    /// middlewares (see `Middleware`).
+    ///
+    /// # Arguments
+    ///
+    /// * `changes` - Changes to apply.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub(crate) fn apply<I: IntoIterator<Item = (String, Option<(u64, Vec<u8>)>)>>(
+        &mut self,
+        changes: I,
+    ) -> Result<(), E> {
+        if !self.fs.exists(self.cwd()) {
+            return Err(E::PathIsNotFolder(fs::as_path_buf(self.cwd().clone())));
+        }
+        self.prune()?;
+        let mut written = Vec::new();
+        let mut applied = Vec::new();
+        for (key, change) in changes {
+            match change {
+                Some((tag, buffer)) => {
+                    self.check_key(&key)?;
+                    self.put(&key, tag, &buffer)?;
+                    written.push(key.clone());
+                    applied.push((key, Some((tag, buffer))));
+                }
+                None if self.fields.contains_key(&key) => {
+                    self.retain(&[&key])?;
+                    self.discard(&key)?;
+                    applied.push((key, None));
+                }
+                None => {}
+            }
+        }
+        self.evict(&written.iter().map(|k| k.as_str()).collect::<Vec<&str>>())?;
+        self.map.write(&self.fields)?;
+        self.save_deadlines()?;
+        for (key, change) in applied {
+            self.publish(
+                &key,
+                change
+                    .as_ref()
+                    .map(|(tag, buffer)| (*tag, buffer.as_slice())),
+            );
+        }
+        Ok(())
     }
 
     /// Removes the value associated with the specified key.
@@ -210,16 +907,65 @@ impl Storage {
     /// # Returns
     ///
     /// * `Result<bool, E>` - Returns true if the key was found and removed, false otherwise, or an error.
-    pub fn remove<K: AsRef<str>>(&mut self, key: K) -> Result<bool, E> {
-        let Some(field) = self.fields.get(key.as_ref()) else {
-            return Ok(false);
-        };
-        field.remove()?;
-        self.fields.remove(key.as_ref());
+    pub fn remove<K: StorageKey>(&mut self, key: K) -> Result<bool, E> {
+        let key = self.normalized(key.to_key());
+        self.remove_through(key.as_ref())
+    }
+
+    /// Removes the record of the key as it is (without normalization).
+    pub(crate) fn remove_key(&mut self, key: &str) -> Result<bool, E> {
+        if self.prune()? {
+            self.map.write(&self.fields)?;
+        }
+        if !self.fields.contains_key(key) {
+            return Ok(self.remove_archived(key));
+        }
+        self.retain(&[key])?;
+        self.discard(key)?;
         self.map.write(&self.fields)?;
+        self.save_deadlines()?;
+        self.publish(key, None);
         Ok(true)
     }
 
+    /// Returns keys, which may keep values of the type `V`: if `V` has a stable name (see
+    /// `TypeName`), keys of records of other types with stable names are skipped without reading
+    /// their files. Other records are read and skipped if they cannot be deserialized.
+    ///
+    /// # Returns
+    ///
+    /// * `impl Iterator<Item = &String>` - Keys of records, which may keep values of `V`, sorted
+    ///   with `StorageOptions::sorted`.
+    pub(crate) fn keys_of<V: ?Sized + 'static>(&self) -> impl Iterator<Item = &String> {
+        let tag = type_tag::<V>();
+        let mut keys = self
+            .fields
+            .iter()
+            .filter(move |(_, field)| field.is_of(tag))
+            .map(|(key, _)| key)
+            .collect::<Vec<&String>>();
+        if self.options.sorted {
+            keys.sort();
+        }
+        keys.into_iter()
+    }
+
+    /// Returns fields in the order they are read for packing: sorted by keys with
+    /// `StorageOptions::sorted`, otherwise by names of files.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(&String, &Field)>` - Keys and fields.
+    pub(crate) fn packing_order(&self) -> Vec<(&String, &Field)> {
+        let mut fields = self.fields.iter().collect::<Vec<(&String, &Field)>>();
+        if self.options.sorted {
+            fields.sort_by_key(|(key, _)| *key);
+        } else {
+            fields.sort_by(|(_, a), (_, b)| a.file_name().cmp(b.file_name()));
+        }
+        fields
+    }
+
     /// Returns a number of fields in storage
     ///
     /// # Returns
@@ -239,30 +985,139 @@ impl Storage {
         self.len() == 0
     }
 
-    /// Clears all entries from the storage and removes bound files. This method will not remove a storage folder.
+    /// Clears all entries from the storage and removes bound files and child storages. This method will not
+    /// remove a storage folder. Pinned records (see `Storage::pin()`) are kept. The generation of
+    /// the storage is advanced (see `Storage::generation()`).
     ///
     /// # Returns
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
     pub fn clear(&mut self) -> Result<(), E> {
-        for (_, field) in self.fields.iter() {
-            field.remove()?;
+        self.map.check()?;
+        self.map.advance();
+        if self.fields.values().any(|field| field.meta().pinned) {
+            self.clear_children()?;
+            self.clear_archive()?;
+            if self.clear_where(|_| true)?.is_empty() {
+                self.map.write(&self.fields)?;
+            }
+            return Ok(());
+        }
+        self.prune()?;
+        self.clear_children()?;
+        self.clear_archive()?;
+        let keys: Vec<String> = self.fields.keys().cloned().collect();
+        self.retain(&keys.iter().map(|k| k.as_str()).collect::<Vec<&str>>())?;
+        for (key, field) in self.fields.iter() {
+            field
+                .remove(&*self.fs, &self.cwd)
+                .map_err(|e| e.record(Operation::Remove, key, &field.path(&self.cwd)))?;
         }
         self.fields.clear();
-        self.map.write(&self.fields)
+        self.build_ordered();
+        self.refs.clear();
+        self.deadlines.clear();
+        self.preloaded.clear();
+        if let Some(pool) = self.handles.as_ref() {
+            pool.clear();
+        }
+        self.map.write(&self.fields)?;
+        self.save_deadlines()?;
+        for key in keys {
+            self.publish(&key, None);
+        }
+        Ok(())
     }
 
-    /// Remove all files and folder of this storage
+    /// Removes records, which keys match the predicate, with their files. Values aren't read and
+    /// the map is written once, so clearing a namespace of keys doesn't need a removal of each
+    /// key. Pinned records (see `Storage::pin()`) and child storages aren't touched.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - A closure, which takes the key and returns true if the record should be
+    ///   removed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns removed keys, sorted, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("cache/a", &1u8).unwrap();
+    /// storage.set("cache/b", &2u8).unwrap();
+    /// storage.set("settings", &3u8).unwrap();
+    /// let removed = storage.clear_where(|key| key.starts_with("cache/")).unwrap();
+    /// assert_eq!(removed, vec!["cache/a", "cache/b"]);
+    /// assert_eq!(storage.len(), 1);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn clear_where<F: Fn(&str) -> bool>(&mut self, predicate: F) -> Result<Vec<String>, E> {
+        let pruned = self.prune()?;
+        let mut keys = self
+            .fields
+            .iter()
+            .filter(|(key, field)| !field.meta().pinned && predicate(key))
+            .map(|(key, _)| key.to_owned())
+            .collect::<Vec<String>>();
+        keys.sort();
+        if keys.is_empty() {
+            if pruned {
+                self.map.write(&self.fields)?;
+            }
+            return Ok(keys);
+        }
+        self.retain(&keys.iter().map(|k| k.as_str()).collect::<Vec<&str>>())?;
+        for key in keys.iter() {
+            self.discard(key)?;
+        }
+        self.map.write(&self.fields)?;
+        self.save_deadlines()?;
+        for key in keys.iter() {
+            self.publish(key, None);
+        }
+        Ok(keys)
+    }
+
+    /// Commits deferred changes of the storage's map (see `GroupCommit`) and waits until all
+    /// pending changes are written to disk (see `FlushMode::Background`). With default options
+    /// the map is always written synchronously and this method does nothing.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    pub fn flush(&mut self) -> Result<(), E> {
+        if self.prune()? | self.touched.swap(false, Ordering::Relaxed) {
+            self.map.write(&self.fields)?;
+        }
+        self.save_deadlines()?;
+        self.map.flush(&self.fields)
+    }
+
+    /// Remove all files and folder of this storage, including child storages
     ///
     /// # Returns
     ///
     /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
     pub fn destroy(&mut self) -> Result<(), E> {
-        if !self.cwd().exists() {
+        if !self.fs.exists(self.cwd()) {
             return Err(E::PathIsNotFolder(fs::as_path_buf(self.cwd().clone())));
         }
+        self.map.flush(&self.fields)?;
         self.fields.clear();
-        remove_dir_all(self.cwd())?;
+        self.build_ordered();
+        self.refs.clear();
+        self.preloaded.clear();
+        if let Some(pool) = self.handles.as_ref() {
+            pool.clear();
+        }
+        self.fs.remove_dir_all(self.cwd())?;
         self.cwd = PathBuf::new();
         Ok(())
     }
@@ -277,7 +1132,27 @@ impl Storage {
     }
 }
 
-/// Iterator for iterating over keys in the storage.
+impl Drop for Storage {
+    fn drop(&mut self) {
+        if self.cwd.as_os_str().is_empty() {
+            // Storage has been destroyed
+            return;
+        }
+        if self.temporary {
+            drop(self.lock.take());
+            if let Err(err) = self.fs.remove_dir_all(&self.cwd) {
+                error!("Fail to remove temporary storage {:?}: {err}", self.cwd);
+            }
+            return;
+        }
+        if let Err(err) = self.flush() {
+            error!("Fail to flush storage {:?}: {err}", self.cwd);
+        }
+    }
+}
+
+/// Iterator for iterating over keys in the storage. It works on a snapshot of keys taken when
+/// the iterator is created; expired keys are skipped.
 pub struct StorageIter<'a> {
     keys: Vec<&'a String>,
     pos: usize,
@@ -312,7 +1187,11 @@ impl<'a> IntoIterator for &'a Storage {
     /// * `StorageIter<'a>` - An iterator over the keys in the storage.
     fn into_iter(self) -> Self::IntoIter {
         StorageIter {
-            keys: self.fields.keys().collect(),
+            keys: self
+                .fields
+                .keys()
+                .filter(|key| !self.expired(key))
+                .collect(),
             pos: 0,
         }
     }
@@ -320,9 +1199,12 @@ impl<'a> IntoIterator for &'a Storage {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Storage, E};
+    use crate::{
+        map::MAP_COPY_FILE_NAME, CorruptionKind, CorruptionPolicy, FlushMode, GroupCommit, Limits,
+        Problem, Quota, Storage, StorageOptions, E,
+    };
     use serde::{Deserialize, Serialize};
-    use std::env::temp_dir;
+    use std::{env::temp_dir, fs::read, time::Duration};
     use uuid::Uuid;
 
     #[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -364,4 +1246,333 @@ mod tests {
         assert!(!storage_path.exists());
         Ok(())
     }
+
+    #[test]
+    fn background_flush() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            flush: FlushMode::Background {
+                queue: 4,
+                fsync: false,
+            },
+            map_copy: true,
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        for i in 0..100u8 {
+            storage.set(i.to_string(), &i)?;
+        }
+        storage.flush()?;
+        // The map and its copy are replaced atomically, leaving no temporary files
+        assert_eq!(
+            read(storage_path.join("map.bstorage"))?,
+            read(storage_path.join("map.bstorage.1"))?
+        );
+        assert!(!storage_path.join("map.bstorage.tmp").exists());
+        let reopened = Storage::open(&storage_path)?;
+        assert_eq!(reopened.len(), 100);
+        drop(reopened);
+        storage.remove("0")?;
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert_eq!(storage.len(), 99);
+        assert_eq!(storage.get::<u8, &str>("99")?, Some(99));
+        storage.destroy()?;
+        assert!(!storage_path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn group_commit() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            group_commit: Some(GroupCommit {
+                max_pending: 10,
+                max_delay: Duration::from_secs(60),
+            }),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options)?;
+        for i in 0..15u8 {
+            storage.set(i.to_string(), &i)?;
+        }
+        // Only the first 10 updates are committed so far
+        assert_eq!(Storage::open(&storage_path)?.len(), 10);
+        storage.flush()?;
+        assert_eq!(Storage::open(&storage_path)?.len(), 15);
+        storage.set("15", &15u8)?;
+        drop(storage);
+        let mut storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.len(), 16);
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn error_context() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            corruption: CorruptionPolicy::Error,
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options)?;
+        storage.set("a", &1u8)?;
+        let path = storage
+            .fields
+            .get("a")
+            .expect("Field exists")
+            .path(&storage.cwd);
+        std::fs::remove_file(&path)?;
+        match storage.get::<u8, &str>("a") {
+            Err(E::Record {
+                key, path: failed, ..
+            }) => {
+                assert_eq!(key, "a");
+                assert_eq!(failed, path);
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn corruption_policy() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            corruption: CorruptionPolicy::Quarantine,
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options)?;
+        storage.set("a", &String::from("broken"))?;
+        storage.set("b", &1u8)?;
+        let path = storage
+            .fields
+            .get("a")
+            .expect("Field exists")
+            .path(&storage.cwd);
+        // Values of other types aren't corrupted
+        assert!(storage.get::<Vec<u64>, &str>("a")?.is_none());
+        assert!(storage.get::<u8, &str>("b")?.is_some());
+        assert!(storage.take_corruptions().is_empty());
+        assert!(path.exists());
+        let mut content = std::fs::read(&path)?;
+        *content.last_mut().expect("Not empty") ^= 0xff;
+        std::fs::write(&path, content)?;
+        assert!(storage.get::<String, &str>("a")?.is_none());
+        let reports = storage.take_corruptions();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].key, "a");
+        assert!(matches!(reports[0].kind, CorruptionKind::Invalid(_)));
+        let quarantined = reports[0].quarantined.clone().expect("File quarantined");
+        assert!(quarantined.exists());
+        assert!(!path.exists());
+        storage.flush()?;
+        assert!(!storage.has("a"));
+        drop(storage);
+        let storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.len(), 1);
+        assert!(storage.take_corruptions().is_empty());
+        drop(storage);
+        std::fs::remove_dir_all(&storage_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn open_strict() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for key in ["a", "b", "c"] {
+            storage.set(key, &String::from(key))?;
+        }
+        let path = |key: &str| {
+            storage
+                .fields
+                .get(key)
+                .expect("Field exists")
+                .path(&storage.cwd)
+        };
+        let (a, b) = (path("a"), path("b"));
+        drop(storage);
+        assert!(Storage::open_strict(&storage_path).is_ok());
+        std::fs::remove_file(&a)?;
+        std::fs::write(&b, [])?;
+        match Storage::open_strict(&storage_path) {
+            Err(E::Verification(report)) => {
+                assert_eq!(report.checked, 3);
+                assert_eq!(report.issues.len(), 2);
+                for issue in report.issues.iter() {
+                    match issue.key.as_str() {
+                        "a" => assert_eq!(issue.problem, Problem::Missing),
+                        "b" => assert_eq!(issue.problem, Problem::Empty),
+                        _ => panic!("Unexpected issue: {issue:?}"),
+                    }
+                }
+            }
+            other => panic!("Unexpected result: {other:?}"),
+        }
+        std::fs::remove_dir_all(&storage_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn quota() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            limits: Limits {
+                max_keys: Some(2),
+                max_record_size: Some(16),
+                warn_record_size: Some(8),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options)?;
+        storage.set("a", &1u8)?;
+        storage.set("b", &2u8)?;
+        // Existing keys can still be updated
+        storage.set("a", &3u8)?;
+        assert!(matches!(
+            storage.set("c", &4u8),
+            Err(E::QuotaExceeded(Quota::Keys { limit: 2, .. }))
+        ));
+        assert!(matches!(
+            storage.set("a", &vec![0u8; 32]),
+            Err(E::QuotaExceeded(Quota::RecordSize { limit: 16, .. }))
+        ));
+        assert_eq!(storage.get::<u8, &str>("a")?, Some(3));
+        // Large values are written with a warning; headers make all record files large
+        storage.set("b", &vec![0u8; 4])?;
+        let large = storage.large_records();
+        assert_eq!(large.len(), 2);
+        assert_eq!(
+            large[0],
+            (
+                String::from("b"),
+                storage.fields.get("b").unwrap().meta().size
+            )
+        );
+        let usage = storage.usage();
+        assert_eq!(usage.keys, 2);
+        drop(storage);
+        let total = usage.bytes;
+        let options = StorageOptions {
+            limits: Limits {
+                max_total_size: Some(total),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert_eq!(storage.usage(), usage);
+        assert!(matches!(
+            storage.set("c", &4u8),
+            Err(E::QuotaExceeded(Quota::TotalSize { .. }))
+        ));
+        storage.remove("b")?;
+        storage.set("c", &4u8)?;
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn damaged_map() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for i in 0..100u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        drop(storage);
+        let map = storage_path.join(crate::MAP_FILE_NAME);
+        let content = std::fs::read(&map)?;
+        // Partial write
+        std::fs::write(&map, &content[..content.len() - 8])?;
+        assert!(matches!(
+            Storage::open(&storage_path),
+            Err(E::Map { source, .. }) if matches!(*source, E::MapIsTruncated { .. })
+        ));
+        // Damaged content
+        let mut damaged = content.clone();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 0xff;
+        std::fs::write(&map, &damaged)?;
+        assert!(matches!(
+            Storage::open(&storage_path),
+            Err(E::Map { source, .. }) if matches!(*source, E::ChecksumMismatch)
+        ));
+        std::fs::write(&map, &content)?;
+        let mut storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.get::<u32, &str>("42")?, Some(42));
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn map_copy() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            map_copy: true,
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        for i in 0..100u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        drop(storage);
+        let map = storage_path.join(crate::MAP_FILE_NAME);
+        let content = std::fs::read(&map)?;
+        assert_eq!(
+            std::fs::read(storage_path.join(MAP_COPY_FILE_NAME))?,
+            content
+        );
+        // Damaged map is restored from the copy
+        let mut damaged = content.clone();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 0xff;
+        std::fs::write(&map, &damaged)?;
+        let storage = Storage::open_with_options(&storage_path, options.clone())?;
+        assert_eq!(storage.len(), 100);
+        assert_eq!(std::fs::read(&map)?, content);
+        drop(storage);
+        // Map truncated to 0 bytes as well
+        std::fs::write(&map, [])?;
+        let storage = Storage::open_with_options(&storage_path, options.clone())?;
+        assert_eq!(storage.len(), 100);
+        assert_eq!(std::fs::read(&map)?, content);
+        drop(storage);
+        // Missing map as well
+        std::fs::remove_file(&map)?;
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert_eq!(storage.get::<u32, &str>("42")?, Some(42));
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn clear_where() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        for i in 0..10u32 {
+            storage.set(format!("cache/{i}"), &i)?;
+            storage.set(format!("user/{i}"), &i)?;
+        }
+        storage.child("cache")?.set("kept", &true)?;
+        let files = (0..10)
+            .map(|i| storage.fields[&format!("cache/{i}")].path(storage.cwd()))
+            .collect::<Vec<_>>();
+        let removed = storage.clear_where(|key| key.starts_with("cache/"))?;
+        assert_eq!(removed.len(), 10);
+        assert!(files.iter().all(|file| !file.exists()));
+        assert!(storage
+            .clear_where(|key| key.starts_with("cache/"))?
+            .is_empty());
+        drop(storage);
+        let mut storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.len(), 10);
+        assert!(!storage.has("cache/1"));
+        assert_eq!(storage.get::<u32, _>("user/1")?, Some(1));
+        assert_eq!(storage.child("cache")?.get::<bool, _>("kept")?, Some(true));
+        storage.destroy()?;
+        Ok(())
+    }
 }
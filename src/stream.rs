@@ -0,0 +1,447 @@
+//! Packing into and unpacking from a stream. Unlike `Bundle`, which keeps the index of records
+//! at the end of the file and needs `Seek` to unpack, the stream is a sequence of frames, each
+//! carrying a whole record, so it can be unpacked as it's read (e.g. from the body of an HTTP
+//! response or from stdin) without downloading it into a temporary file first.
+//!
+//! Identical payloads are written once: later records with the same payload refer to the file of
+//! the first one, which is unpacked by then.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read_dir, remove_dir_all},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    batch::read_chunk,
+    bundle::{split_record, write_map, Progress, Unpacked},
+    fs,
+    header::Header,
+    nested::{children_of, CHILDREN_DIR},
+    trace::op,
+    Meta, Operation, Storage, E,
+};
+
+/// Marks the beginning of the stream, which frames keep metadata of records (pins, tags, etc.)
+const STREAM_MAGIC: [u8; 8] = *b"BSSTRM\x00\x02";
+/// Marks streams written by previous versions of `bstorage`, which frames don't keep metadata of
+/// records (see `FrameV1`)
+const STREAM_MAGIC_V1: [u8; 8] = *b"BSSTRM\x00\x01";
+
+/// Frame of the stream. Storages are identified by their paths: names of child storages
+/// separated by `/`, empty for the root storage.
+#[derive(Debug, Serialize, Deserialize)]
+enum Frame {
+    /// Storage, which records follow
+    Storage(String),
+    /// Record with its payload
+    Record {
+        key: String,
+        file: String,
+        header: Vec<u8>,
+        payload: Vec<u8>,
+        /// Metadata of the record; `None` for records of streams without metadata
+        meta: Option<Meta>,
+    },
+    /// Record, which payload is the same as the payload of the record file written before
+    Same {
+        key: String,
+        file: String,
+        header: Vec<u8>,
+        storage: String,
+        of: String,
+        meta: Option<Meta>,
+    },
+    /// End of the stream
+    End,
+}
+
+/// Frame of streams written by previous versions of `bstorage` (see `STREAM_MAGIC_V1`).
+#[derive(Debug, Serialize, Deserialize)]
+enum FrameV1 {
+    Storage(String),
+    Record {
+        key: String,
+        file: String,
+        header: Vec<u8>,
+        payload: Vec<u8>,
+    },
+    Same {
+        key: String,
+        file: String,
+        header: Vec<u8>,
+        storage: String,
+        of: String,
+    },
+    End,
+}
+
+impl From<FrameV1> for Frame {
+    fn from(frame: FrameV1) -> Self {
+        match frame {
+            FrameV1::Storage(path) => Frame::Storage(path),
+            FrameV1::Record {
+                key,
+                file,
+                header,
+                payload,
+            } => Frame::Record {
+                key,
+                file,
+                header,
+                payload,
+                meta: None,
+            },
+            FrameV1::Same {
+                key,
+                file,
+                header,
+                storage,
+                of,
+            } => Frame::Same {
+                key,
+                file,
+                header,
+                storage,
+                of,
+                meta: None,
+            },
+            FrameV1::End => Frame::End,
+        }
+    }
+}
+
+/// Returns the folder of the storage by its path in the stream.
+fn folder_of(cwd: &Path, storage: &str) -> PathBuf {
+    storage
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .fold(cwd.to_path_buf(), |cwd, name| {
+            cwd.join(CHILDREN_DIR).join(name)
+        })
+}
+
+/// Writes records of the storage and all its child storages into the stream.
+///
+/// # Arguments
+///
+/// * `storage` - The storage to write.
+/// * `path` - Path of the storage in the stream.
+/// * `writer` - The stream.
+/// * `payloads` - Storages and record files of written payloads by their SHA-256 hashes.
+///
+/// # Returns
+///
+/// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+fn write_tree<W: Write>(
+    storage: &Storage,
+    path: &str,
+    writer: &mut W,
+    payloads: &mut HashMap<Vec<u8>, (String, String)>,
+) -> Result<(), E> {
+    bincode::serialize_into(&mut *writer, &Frame::Storage(path.to_owned()))?;
+    let fields = storage.packing_order();
+    let batch = &storage.options.batch;
+    for chunk in fields.chunks(batch.chunk.max(1)) {
+        let contents = read_chunk(storage.cwd(), chunk, batch.readahead);
+        for ((key, field), content) in chunk.iter().zip(contents) {
+            // Records are written as they are, so encrypted values stay encrypted
+            let content =
+                content.map_err(|e| e.record(Operation::Pack, key, &field.path(storage.cwd())))?;
+            if content.is_empty() {
+                continue;
+            }
+            let (header, payload) = split_record(&content)?;
+            let (key, file) = (key.to_string(), field.file_name().to_owned());
+            let meta = Some(field.meta().clone());
+            let hash = Sha256::digest(payload).to_vec();
+            let frame = match payloads.get(&hash) {
+                Some((storage, of)) => Frame::Same {
+                    key,
+                    file,
+                    header,
+                    storage: storage.to_owned(),
+                    of: of.to_owned(),
+                    meta,
+                },
+                None => {
+                    payloads.insert(hash, (path.to_owned(), file.clone()));
+                    Frame::Record {
+                        key,
+                        file,
+                        header,
+                        payload: payload.to_vec(),
+                        meta,
+                    }
+                }
+            };
+            bincode::serialize_into(&mut *writer, &frame)?;
+        }
+    }
+    for (name, cwd) in children_of(storage.cwd())? {
+        let child = Storage::open_with_options(cwd, storage.options.clone())?;
+        let path = if path.is_empty() {
+            name
+        } else {
+            format!("{path}/{name}")
+        };
+        write_tree(&child, &path, writer, payloads)?;
+    }
+    Ok(())
+}
+
+/// Unpacks frames of the stream into the folder and writes maps of unpacked storages.
+fn unpack_frames<R: Read>(reader: &mut R, cwd: &Path) -> Result<(), E> {
+    let mut magic = [0u8; STREAM_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    let legacy = match magic {
+        STREAM_MAGIC => false,
+        STREAM_MAGIC_V1 => true,
+        _ => return Err(E::PackageFileInvalid(cwd.to_path_buf())),
+    };
+    let mut maps: Vec<(PathBuf, Unpacked)> = Vec::new();
+    loop {
+        let frame = if legacy {
+            bincode::deserialize_from::<_, FrameV1>(&mut *reader)?.into()
+        } else {
+            bincode::deserialize_from::<_, Frame>(&mut *reader)?
+        };
+        let (content, key, file, meta) = match frame {
+            Frame::Storage(path) => {
+                let folder = folder_of(cwd, &path);
+                create_dir_all(&folder)?;
+                maps.push((folder, HashMap::new()));
+                continue;
+            }
+            Frame::Record {
+                key,
+                file,
+                mut header,
+                payload,
+                meta,
+            } => {
+                header.extend_from_slice(&payload);
+                (header, key, file, meta)
+            }
+            Frame::Same {
+                key,
+                file,
+                mut header,
+                storage,
+                of,
+                meta,
+            } => {
+                let path = folder_of(cwd, &storage).join(of);
+                let content = std::fs::read(&path).map_err(|e| E::io(e, &path))?;
+                let (_, payload) = Header::decode(&content)?;
+                header.extend_from_slice(payload);
+                (header, key, file, meta)
+            }
+            Frame::End => break,
+        };
+        let Some((folder, map)) = maps.last_mut() else {
+            return Err(E::PackageFileInvalid(cwd.to_path_buf()));
+        };
+        let path = folder.join(&file);
+        fs::create(&path)
+            .and_then(|mut f| f.write_all(&content))
+            .map_err(|e| E::from(e).record(Operation::Unpack, &key, &path))?;
+        map.insert(key, (file, meta));
+    }
+    for (folder, records) in maps {
+        write_map(&folder, &records)?;
+    }
+    Ok(())
+}
+
+impl Storage {
+    /// Packs the storage (with child storages) into the stream, which can be unpacked without
+    /// `Seek` (see `unpack_from()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The stream.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("my_record", &1u8).unwrap();
+    /// let mut stream = Vec::new();
+    /// storage.pack_to(&mut stream).unwrap();
+    /// let mut unpacked =
+    ///     Storage::unpack_from(stream.as_slice(), temp_dir().join(Uuid::new_v4().to_string()))
+    ///         .unwrap();
+    /// assert_eq!(unpacked.get::<u8, _>("my_record").unwrap(), Some(1));
+    /// unpacked.destroy().unwrap();
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn pack_to<W: Write>(&mut self, mut writer: W) -> Result<(), E> {
+        let _op = op!("pack", path, self.cwd());
+        writer.write_all(&STREAM_MAGIC)?;
+        write_tree(self, "", &mut writer, &mut HashMap::new())?;
+        bincode::serialize_into(&mut writer, &Frame::End)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Unpacks the storage from the stream written by `pack_to()`. Records are written as they
+    /// are read, so the stream doesn't need `Seek`. Until the whole stream is unpacked, the
+    /// folder cannot be opened as a storage; if unpacking fails (e.g. the stream is truncated),
+    /// the folder is removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream.
+    /// * `dest` - A path reference to the destination directory. It should not exist or should
+    ///   be empty.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Storage, E>` - Returns the unpacked storage, or an error.
+    pub fn unpack_from<R: Read, D: AsRef<Path>>(mut reader: R, dest: D) -> Result<Storage, E> {
+        let dest = fs::as_path_buf(dest);
+        let _op = op!("unpack", path, dest);
+        if dest.exists() && read_dir(&dest)?.next().is_some() {
+            return Err(E::DestinationIsNotEmpty(dest));
+        }
+        create_dir_all(&dest)?;
+        // The manifest of unpacking keeps the folder from being opened until it's unpacked
+        let unpacked = Progress::open(&dest, "stream").and_then(|progress| {
+            unpack_frames(&mut reader, &dest)?;
+            progress.finish(&dest)
+        });
+        if let Err(err) = unpacked {
+            if let Err(err) = remove_dir_all(&dest) {
+                warn!("Fail to remove partially unpacked storage {dest:?}: {err}");
+            }
+            return Err(match err {
+                E::Bincode(bincode::ErrorKind::Io(err))
+                    if err.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    E::PackageFileInvalid(dest)
+                }
+                err => err,
+            });
+        }
+        Storage::open(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameV1, STREAM_MAGIC_V1};
+    use crate::{bundle::split_record, Storage, E};
+    use std::{env::temp_dir, io::Read};
+    use uuid::Uuid;
+
+    /// Reader, which returns data in small portions and doesn't implement `Seek`
+    struct Pipe<'a>(&'a [u8]);
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(7);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn stream() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let config = vec![String::from("default"); 1000];
+        for i in 0..10 {
+            storage.set(format!("config_{i}"), &config)?;
+        }
+        storage.child("plugins/foo")?.set("config", &config)?;
+        storage.child("plugins/foo")?.set("enabled", &true)?;
+        storage.tag("config_1", "shared")?;
+        storage.pin("config_2")?;
+        let mut stream = Vec::new();
+        storage.pack_to(&mut stream)?;
+        // Identical payloads are written once
+        assert!(stream.len() < bincode::serialize(&config)?.len() * 2);
+        let dest = temp_dir().join(Uuid::new_v4().to_string());
+        let mut unpacked = Storage::unpack_from(Pipe(&stream), &dest)?;
+        for i in 0..10 {
+            assert_eq!(
+                unpacked.get::<Vec<String>, _>(format!("config_{i}"))?,
+                Some(config.clone())
+            );
+        }
+        let foo = unpacked.child("plugins/foo")?;
+        assert_eq!(foo.get::<bool, _>("enabled")?, Some(true));
+        assert_eq!(foo.get::<Vec<String>, _>("config")?, Some(config));
+        assert!(unpacked.verify()?.is_ok());
+        // Metadata of records is kept
+        assert_eq!(unpacked.keys_with_tag("shared"), vec!["config_1"]);
+        assert!(unpacked.is_pinned("config_2"));
+        drop(foo);
+        unpacked.destroy()?;
+        // Truncated stream
+        let dest = temp_dir().join(Uuid::new_v4().to_string());
+        assert!(matches!(
+            Storage::unpack_from(Pipe(&stream[..stream.len() / 2]), &dest),
+            Err(E::PackageFileInvalid(_))
+        ));
+        assert!(!dest.exists());
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[test]
+    fn legacy() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.set("a", &1u8)?;
+        storage.set("b", &1u8)?;
+        let record = |key: &str| -> Result<(String, Vec<u8>, Vec<u8>), E> {
+            let field = &storage.fields[key];
+            let content = std::fs::read(field.path(storage.cwd()))?;
+            let (header, payload) = split_record(&content)?;
+            Ok((field.file_name().to_owned(), header, payload.to_vec()))
+        };
+        let ((a, header_a, payload), (b, header_b, _)) = (record("a")?, record("b")?);
+        let mut stream = STREAM_MAGIC_V1.to_vec();
+        for frame in [
+            FrameV1::Storage(String::new()),
+            FrameV1::Record {
+                key: String::from("a"),
+                file: a.clone(),
+                header: header_a,
+                payload,
+            },
+            FrameV1::Same {
+                key: String::from("b"),
+                file: b,
+                header: header_b,
+                storage: String::new(),
+                of: a,
+            },
+            FrameV1::End,
+        ] {
+            bincode::serialize_into(&mut stream, &frame)?;
+        }
+        let mut unpacked = Storage::unpack_from(
+            stream.as_slice(),
+            temp_dir().join(Uuid::new_v4().to_string()),
+        )?;
+        assert_eq!(unpacked.get::<u8, _>("a")?, Some(1));
+        assert_eq!(unpacked.get::<u8, _>("b")?, Some(1));
+        unpacked.destroy()?;
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+//! Synchronization of two storages, e.g. a local storage and its replica in a shared folder.
+//! `bidirectional()` compares versions (digests of values) of each key in both storages with the
+//! versions, which the key had after the previous synchronization of these storages, and copies
+//! only changed records in each direction. Keys changed in both storages since the previous
+//! synchronization are conflicts resolved according to `ConflictPolicy`; records of mergeable
+//! types (see `Merge` and `StorageOptions::merge` of the first storage) are merged instead.
+//!
+//! The state of the last synchronization is kept in the `sync` folder of both storages (one file
+//! per peer storage, identified by its path).
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{self, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::{header::fnv1a, Field, Operation, Storage, E};
+
+/// Folder of the storage with states of synchronizations
+pub(crate) const SYNC_DIR: &str = "sync";
+
+/// Defines what to do with a key changed in both storages since the previous synchronization.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// The version of the first storage wins.
+    PreferA,
+    /// The version of the second storage wins.
+    PreferB,
+    /// The version written later wins (by the modification time of the record file). If the
+    /// key has been removed in one of the storages, the existing value wins.
+    #[default]
+    Newest,
+    /// Conflicting keys are left as they are and reported; they stay conflicts until they get
+    /// equal values in both storages.
+    Skip,
+}
+
+/// Result of `bidirectional()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Keys changed (written or removed) in the first storage
+    pub to_a: Vec<String>,
+    /// Keys changed (written or removed) in the second storage
+    pub to_b: Vec<String>,
+    /// Keys changed in both storages
+    pub conflicts: Vec<String>,
+    /// Keys of mergeable types changed in both storages, which values have been merged
+    pub merged: Vec<String>,
+}
+
+/// Version of a value: type tag and digest of the serialized value
+type Version = (u64, [u8; 32]);
+
+/// Versions of keys after the last synchronization
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    versions: HashMap<String, Version>,
+}
+
+/// Source of the value of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    A,
+    B,
+    /// Both storages have the same value
+    Both,
+}
+
+/// Resolves the conflict according to the policy; returns None if the conflict is skipped.
+fn resolve(
+    a: &Storage,
+    b: &Storage,
+    key: &str,
+    policy: ConflictPolicy,
+    a_value: Option<&(Version, Vec<u8>)>,
+    b_value: Option<&(Version, Vec<u8>)>,
+) -> Result<Option<Side>, E> {
+    Ok(match policy {
+        ConflictPolicy::PreferA => Some(Side::A),
+        ConflictPolicy::PreferB => Some(Side::B),
+        ConflictPolicy::Newest => match (a_value, b_value) {
+            (Some(_), None) => Some(Side::A),
+            (None, Some(_)) => Some(Side::B),
+            _ if modified(a, key)? >= modified(b, key)? => Some(Side::A),
+            _ => Some(Side::B),
+        },
+        ConflictPolicy::Skip => None,
+    })
+}
+
+/// Synchronizes two storages: changes made in one storage since the previous synchronization
+/// are copied into the other one (new and changed records are written, removed records are
+/// removed). Values of all keys are read to compare them, so it suits small storages (settings,
+/// configurations), which are synchronized regularly.
+///
+/// # Arguments
+///
+/// * `a` - The first storage.
+/// * `b` - The second storage.
+/// * `policy` - Defines how keys changed in both storages are resolved.
+///
+/// # Returns
+///
+/// * `Result<SyncReport, E>` - Returns changed and conflicting keys, or an error.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{sync, Storage};
+/// use std::env::temp_dir;
+/// use uuid::Uuid;
+///
+/// let mut desktop = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// let mut laptop = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+/// desktop.set("theme", &String::from("dark")).unwrap();
+/// laptop.set("font", &12u8).unwrap();
+/// let report =
+///     sync::bidirectional(&mut desktop, &mut laptop, sync::ConflictPolicy::Newest).unwrap();
+/// assert_eq!(report.to_a, vec![String::from("font")]);
+/// assert_eq!(report.to_b, vec![String::from("theme")]);
+/// assert_eq!(laptop.get::<String, _>("theme").unwrap(), Some(String::from("dark")));
+/// desktop.destroy().unwrap();
+/// laptop.destroy().unwrap();
+/// ```
+pub fn bidirectional(
+    a: &mut Storage,
+    b: &mut Storage,
+    policy: ConflictPolicy,
+) -> Result<SyncReport, E> {
+    let a_state = state_path(a, b);
+    let b_state = state_path(b, a);
+    let base = match read_state(a, &a_state)? {
+        Some(state) => state,
+        None => read_state(b, &b_state)?.unwrap_or_default(),
+    };
+    let a_values = values(a)?;
+    let b_values = values(b)?;
+    let keys: BTreeSet<&String> = a_values.keys().chain(b_values.keys()).collect();
+    let mut report = SyncReport::default();
+    let mut to_a = Vec::new();
+    let mut to_b = Vec::new();
+    let mut state = State::default();
+    for key in keys {
+        let a_value = a_values.get(key);
+        let b_value = b_values.get(key);
+        let a_version = a_value.map(|(version, _)| *version);
+        let b_version = b_value.map(|(version, _)| *version);
+        let source = if a_version == b_version {
+            Some(Side::Both)
+        } else if a_version == base.versions.get(key).copied() {
+            Some(Side::B)
+        } else if b_version == base.versions.get(key).copied() {
+            Some(Side::A)
+        } else if let (Some(((tag, _), ours)), Some(((their_tag, _), theirs))) = (a_value, b_value)
+        {
+            // Records of mergeable types are merged instead of picking one of them
+            let merged = if tag == their_tag {
+                a.options.merge.merge(*tag, ours, theirs)?
+            } else {
+                None
+            };
+            match merged {
+                Some(merged) => {
+                    report.merged.push(key.to_owned());
+                    state
+                        .versions
+                        .insert(key.to_owned(), version(*tag, &merged));
+                    if &merged != ours {
+                        to_a.push((key.to_owned(), Some((*tag, merged.clone()))));
+                    }
+                    if &merged != theirs {
+                        to_b.push((key.to_owned(), Some((*tag, merged))));
+                    }
+                    continue;
+                }
+                None => {
+                    report.conflicts.push(key.to_owned());
+                    resolve(a, b, key, policy, a_value, b_value)?
+                }
+            }
+        } else {
+            report.conflicts.push(key.to_owned());
+            resolve(a, b, key, policy, a_value, b_value)?
+        };
+        let Some(source) = source else {
+            // Skipped conflict: the base version is kept, so the key is a conflict until it's
+            // resolved
+            if let Some(version) = base.versions.get(key) {
+                state.versions.insert(key.to_owned(), *version);
+            }
+            continue;
+        };
+        let (winner, change) = match source {
+            Side::Both => (a_value, None),
+            Side::A => (a_value, Some(&mut to_b)),
+            Side::B => (b_value, Some(&mut to_a)),
+        };
+        if let Some(((tag, digest), payload)) = winner {
+            state.versions.insert(key.to_owned(), (*tag, *digest));
+            if let Some(change) = change {
+                change.push((key.to_owned(), Some((*tag, payload.clone()))));
+            }
+        } else if let Some(change) = change {
+            change.push((key.to_owned(), None));
+        }
+    }
+    report.to_a = to_a.iter().map(|(key, _)| key.to_owned()).collect();
+    report.to_b = to_b.iter().map(|(key, _)| key.to_owned()).collect();
+    if !to_a.is_empty() {
+        a.apply(to_a)?;
+    }
+    if !to_b.is_empty() {
+        b.apply(to_b)?;
+    }
+    let content = bincode::serialize(&state)?;
+    write_state(a, &a_state, &content)?;
+    write_state(b, &b_state, &content)?;
+    Ok(report)
+}
+
+/// Returns the path of the file with the state of synchronization of the storage with the peer.
+fn state_path(storage: &Storage, peer: &Storage) -> PathBuf {
+    let peer = path::absolute(peer.cwd()).unwrap_or_else(|_| peer.cwd().to_owned());
+    storage.cwd().join(SYNC_DIR).join(format!(
+        "{:016x}.bstorage",
+        fnv1a(peer.to_string_lossy().as_bytes())
+    ))
+}
+
+fn read_state(storage: &Storage, path: &Path) -> Result<Option<State>, E> {
+    if !storage.fs.exists(path) {
+        return Ok(None);
+    }
+    Ok(Some(bincode::deserialize(&storage.fs.read(path)?)?))
+}
+
+fn write_state(storage: &Storage, path: &Path, content: &[u8]) -> Result<(), E> {
+    if let Some(dir) = path.parent().filter(|dir| !storage.fs.exists(dir)) {
+        storage.fs.create_dir_all(dir)?;
+    }
+    Ok(storage
+        .fs
+        .write(path, content, storage.options.durability)?)
+}
+
+/// Reads versions and serialized values of all keys of the storage.
+fn values(storage: &mut Storage) -> Result<HashMap<String, (Version, Vec<u8>)>, E> {
+    storage.prune()?;
+    let mut values = HashMap::new();
+    for (key, field) in storage.fields.iter() {
+        let path = field.path(storage.cwd());
+        let content = storage
+            .extract(field)
+            .map_err(|e| e.record(Operation::Read, key, &path))?;
+        let (_, payload) =
+            Field::payload(&content).map_err(|e| e.record(Operation::Read, key, &path))?;
+        let tag = field.meta().tag;
+        values.insert(key.to_owned(), (version(tag, payload), payload.to_vec()));
+    }
+    Ok(values)
+}
+
+fn version(tag: u64, payload: &[u8]) -> Version {
+    let mut hasher = Sha256::new();
+    hasher.update(tag.to_le_bytes());
+    hasher.update(payload);
+    (tag, hasher.finalize().into())
+}
+
+/// Returns the modification time of the record file in milliseconds since UNIX epoch. The time
+/// of the last access is used for storages with a custom `FileSystem`.
+fn modified(storage: &Storage, key: &str) -> Result<u64, E> {
+    let Some(field) = storage.fields.get(key) else {
+        return Ok(0);
+    };
+    if storage.options.fs.is_some() {
+        return Ok(field.meta().accessed.get());
+    }
+    Ok(std::fs::metadata(field.path(storage.cwd()))?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        sync::{bidirectional, ConflictPolicy},
+        Storage, E,
+    };
+    use std::{env::temp_dir, thread, time::Duration};
+    use uuid::Uuid;
+
+    #[test]
+    fn bidirectional_sync() -> Result<(), E> {
+        let mut a = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let mut b = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        a.set("a", &1u8)?;
+        a.set("shared", &String::from("a"))?;
+        b.set("b", &2u8)?;
+        let report = bidirectional(&mut a, &mut b, ConflictPolicy::Skip)?;
+        assert_eq!(report.to_a, vec![String::from("b")]);
+        assert_eq!(report.to_b, vec![String::from("a"), String::from("shared")]);
+        assert!(report.conflicts.is_empty());
+        // Nothing changed
+        let report = bidirectional(&mut a, &mut b, ConflictPolicy::Skip)?;
+        assert!(report.to_a.is_empty() && report.to_b.is_empty());
+        // Removals and changes are copied in both directions
+        a.remove("a")?;
+        b.set("b", &3u8)?;
+        let report = bidirectional(&mut a, &mut b, ConflictPolicy::Skip)?;
+        assert_eq!(report.to_a, vec![String::from("b")]);
+        assert_eq!(report.to_b, vec![String::from("a")]);
+        assert!(!b.has("a"));
+        assert_eq!(a.get::<u8, _>("b")?, Some(3));
+        // Conflicts
+        a.set("shared", &String::from("changed in a"))?;
+        thread::sleep(Duration::from_millis(20));
+        b.set("shared", &String::from("changed in b"))?;
+        let report = bidirectional(&mut a, &mut b, ConflictPolicy::Skip)?;
+        assert_eq!(report.conflicts, vec![String::from("shared")]);
+        assert!(report.to_a.is_empty() && report.to_b.is_empty());
+        // Still a conflict
+        let report = bidirectional(&mut b, &mut a, ConflictPolicy::Skip)?;
+        assert_eq!(report.conflicts, vec![String::from("shared")]);
+        let report = bidirectional(&mut a, &mut b, ConflictPolicy::Newest)?;
+        assert_eq!(report.to_a, vec![String::from("shared")]);
+        assert_eq!(
+            a.get::<String, _>("shared")?,
+            Some(String::from("changed in b"))
+        );
+        a.set("shared", &String::from("a wins"))?;
+        b.remove("shared")?;
+        bidirectional(&mut a, &mut b, ConflictPolicy::PreferB)?;
+        assert!(!a.has("shared"));
+        assert_eq!(a.len(), 1);
+        assert_eq!(b.len(), 1);
+        a.destroy()?;
+        b.destroy()?;
+        Ok(())
+    }
+}
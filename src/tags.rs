@@ -0,0 +1,206 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::{Storage, StorageKey, E};
+
+impl Storage {
+    /// Sets a value for the specified key and replaces tags of the record (see `Storage::tag()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    /// * `value` - A reference to the value to be stored.
+    /// * `tags` - Tags of the record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage
+    ///     .set_with_tags("post/1", &String::from("Hello"), &["draft", "news"])
+    ///     .unwrap();
+    /// storage.set("post/2", &String::from("World")).unwrap();
+    /// assert_eq!(storage.keys_with_tag("draft"), vec!["post/1"]);
+    /// assert_eq!(storage.tags("post/1"), ["draft", "news"]);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn set_with_tags<V: Serialize + 'static, K: StorageKey, T: AsRef<str>>(
+        &mut self,
+        key: K,
+        value: &V,
+        tags: &[T],
+    ) -> Result<(), E> {
+        let key = self.normalized(key.to_key());
+        self.set(key.as_ref(), value)?;
+        let mut tags = tags
+            .iter()
+            .map(|tag| tag.as_ref().to_owned())
+            .collect::<Vec<String>>();
+        tags.sort();
+        tags.dedup();
+        self.retag(key.as_ref(), |current| *current = tags)?;
+        Ok(())
+    }
+
+    /// Adds the tag to the record. Tags are kept in the map of the storage, so records can be
+    /// categorized and found by tags without reading their values. Tags survive overwriting of
+    /// the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    /// * `tag` - The tag.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the record has the tag, false if the key doesn't
+    ///   exist, or an error.
+    pub fn tag<K: StorageKey, T: AsRef<str>>(&mut self, key: K, tag: T) -> Result<bool, E> {
+        let key = self.normalized(key.to_key());
+        let tag = tag.as_ref();
+        self.retag(key.as_ref(), |tags| {
+            if let Err(pos) = tags.binary_search_by(|current| current.as_str().cmp(tag)) {
+                tags.insert(pos, tag.to_owned());
+            }
+        })
+    }
+
+    /// Removes the tag from the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    /// * `tag` - The tag.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if the record doesn't have the tag anymore, false if
+    ///   the key doesn't exist, or an error.
+    pub fn untag<K: StorageKey, T: AsRef<str>>(&mut self, key: K, tag: T) -> Result<bool, E> {
+        let key = self.normalized(key.to_key());
+        let tag = tag.as_ref();
+        self.retag(key.as_ref(), |tags| tags.retain(|current| current != tag))
+    }
+
+    /// Returns tags of the record.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `&[String]` - Tags of the record, sorted; empty if the key doesn't exist.
+    pub fn tags<K: StorageKey>(&self, key: K) -> &[String] {
+        let key = self.normalized(key.to_key());
+        if self.expired(key.as_ref()) {
+            return &[];
+        }
+        self.fields
+            .get(key.as_ref())
+            .map(|field| field.meta().tags.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns keys of records, which have the tag. Values aren't read.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The tag.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<&String>` - Keys of records with the tag, sorted.
+    pub fn keys_with_tag<T: AsRef<str>>(&self, tag: T) -> Vec<&String> {
+        let tag = tag.as_ref();
+        let mut keys = self
+            .fields
+            .iter()
+            .filter(|(key, field)| {
+                field.meta().tags.iter().any(|current| current == tag) && !self.expired(key)
+            })
+            .map(|(key, _)| key)
+            .collect::<Vec<&String>>();
+        keys.sort();
+        keys
+    }
+
+    /// Removes records, which have the tag (see `Storage::clear_where()`). Pinned records are
+    /// kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The tag.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns removed keys, sorted, or an error.
+    pub fn remove_by_tag<T: AsRef<str>>(&mut self, tag: T) -> Result<Vec<String>, E> {
+        let keys = self
+            .keys_with_tag(tag)
+            .into_iter()
+            .cloned()
+            .collect::<HashSet<String>>();
+        self.clear_where(|key| keys.contains(key))
+    }
+
+    /// Changes tags of the record and writes the map, if they are changed.
+    fn retag<F: FnOnce(&mut Vec<String>)>(&mut self, key: &str, change: F) -> Result<bool, E> {
+        if !self.has(key) {
+            return Ok(false);
+        }
+        let Some(field) = self.fields.get_mut(key) else {
+            return Ok(false);
+        };
+        let tags = &mut field.meta_mut().tags;
+        let before = tags.clone();
+        change(tags);
+        if *tags != before {
+            self.map.write(&self.fields)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn tags() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        storage.set_with_tags("a", &1u8, &["draft", "news", "draft"])?;
+        storage.set("b", &2u8)?;
+        storage.set("c", &3u8)?;
+        assert!(storage.tag("b", "news")?);
+        assert!(storage.tag("c", "draft")?);
+        assert!(!storage.tag("missing", "draft")?);
+        assert_eq!(storage.tags("a"), ["draft", "news"]);
+        assert!(storage.tags("missing").is_empty());
+        // Tags survive overwriting and reopening
+        storage.set("a", &4u8)?;
+        drop(storage);
+        let mut storage = Storage::open(&storage_path)?;
+        assert_eq!(storage.keys_with_tag("draft"), vec!["a", "c"]);
+        assert_eq!(storage.keys_with_tag("news"), vec!["a", "b"]);
+        assert!(storage.untag("a", "draft")?);
+        assert_eq!(storage.keys_with_tag("draft"), vec!["c"]);
+        storage.pin("b")?;
+        assert_eq!(storage.remove_by_tag("news")?, vec![String::from("a")]);
+        assert!(storage.keys_with_tag("unknown").is_empty());
+        assert_eq!(storage.len(), 2);
+        storage.destroy()?;
+        Ok(())
+    }
+}
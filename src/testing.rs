@@ -0,0 +1,382 @@
+//! Test support: file systems for testing of error handling around `Storage` without real disks.
+//! `MemoryFs` keeps files in memory; `FaultyFs` wraps a file system and injects failures (failed
+//! writes, short reads, no space left on device, denied permissions).
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{Durability, FileSystem, StdFs, StorageOptions};
+
+fn poisoned() -> io::Error {
+    io::Error::other("lock of the file system is poisoned")
+}
+
+/// `MemoryFs` keeps files and folders in memory.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    files: Mutex<HashMap<PathBuf, Vec<u8>>>,
+    dirs: Mutex<HashSet<PathBuf>>,
+}
+
+impl MemoryFs {
+    /// Returns paths of all files.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<PathBuf>` - Paths of files.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files
+            .lock()
+            .map(|files| files.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the total size of all files in bytes.
+    ///
+    /// # Returns
+    ///
+    /// * `u64` - Size of files.
+    pub fn size_of_files(&self) -> u64 {
+        self.files
+            .lock()
+            .map(|files| files.values().map(|content| content.len() as u64).sum())
+            .unwrap_or_default()
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files
+            .lock()
+            .map_err(|_| poisoned())?
+            .get(path)
+            .cloned()
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+
+    fn write(&self, path: &Path, content: &[u8], _durability: Durability) -> io::Result<()> {
+        self.files
+            .lock()
+            .map_err(|_| poisoned())?
+            .insert(path.to_path_buf(), content.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.files
+            .lock()
+            .map_err(|_| poisoned())?
+            .remove(path)
+            .map(|_| ())
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files
+            .lock()
+            .is_ok_and(|files| files.contains_key(path))
+            || self.dirs.lock().is_ok_and(|dirs| dirs.contains(path))
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.files
+            .lock()
+            .map_err(|_| poisoned())?
+            .get(path)
+            .map(|content| content.len() as u64)
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut dirs = self.dirs.lock().map_err(|_| poisoned())?;
+        for dir in path.ancestors() {
+            dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.dirs
+            .lock()
+            .map_err(|_| poisoned())?
+            .retain(|dir| !dir.starts_with(path));
+        self.files
+            .lock()
+            .map_err(|_| poisoned())?
+            .retain(|file, _| !file.starts_with(path));
+        Ok(())
+    }
+}
+
+/// `FaultyFs` wraps a file system (`MemoryFs` by default) and injects failures. Faults are
+/// configured via shared reference, so they can be changed while the storage is in use.
+///
+/// # Example
+///
+/// ```rust
+/// use bstorage::{FaultyFs, Storage};
+/// use std::sync::Arc;
+///
+/// let fs = Arc::new(FaultyFs::memory());
+/// let mut storage = Storage::create_with_options("/storage", fs.options()).unwrap();
+/// storage.set("a", &1u8).unwrap();
+/// fs.fail_nth_write(1);
+/// assert!(storage.set("b", &2u8).is_err());
+/// assert!(storage.set("b", &2u8).is_ok());
+/// ```
+#[derive(Debug, Default)]
+pub struct FaultyFs<F: FileSystem = MemoryFs> {
+    inner: F,
+    /// Number of writes done
+    writes: AtomicUsize,
+    /// Number of the write, which fails (counted by `writes`); 0 if disabled
+    fail_at: AtomicUsize,
+    /// Maximum number of bytes returned by a read; 0 if disabled
+    short_reads: AtomicU64,
+    /// Number of bytes, which still can be written; None if not limited
+    space: Mutex<Option<u64>>,
+    /// Denies writes and removals
+    read_only: AtomicBool,
+}
+
+impl FaultyFs<MemoryFs> {
+    /// Creates the file system, which injects failures into `MemoryFs`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The file system.
+    pub fn memory() -> Self {
+        FaultyFs::new(MemoryFs::default())
+    }
+}
+
+impl FaultyFs<StdFs> {
+    /// Creates the file system, which injects failures into `std::fs`.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The file system.
+    pub fn std() -> Self {
+        FaultyFs::new(StdFs)
+    }
+}
+
+impl<F: FileSystem + 'static> FaultyFs<F> {
+    /// Creates the file system, which injects failures into the given one.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The wrapped file system.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The file system.
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            writes: AtomicUsize::new(0),
+            fail_at: AtomicUsize::new(0),
+            short_reads: AtomicU64::new(0),
+            space: Mutex::new(None),
+            read_only: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns options of the storage, which uses this file system.
+    ///
+    /// # Returns
+    ///
+    /// * `StorageOptions` - Default options with this file system.
+    pub fn options(self: &Arc<Self>) -> StorageOptions {
+        StorageOptions {
+            fs: Some(self.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the wrapped file system.
+    ///
+    /// # Returns
+    ///
+    /// * `&F` - The wrapped file system.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns the number of writes done (including failed ones).
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The number of writes.
+    pub fn writes(&self) -> usize {
+        self.writes.load(Ordering::SeqCst)
+    }
+
+    /// Makes the n-th write from now (starting from 1) fail with an IO error. Other writes are
+    /// not affected.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - Number of the write to fail.
+    pub fn fail_nth_write(&self, n: usize) {
+        self.fail_at
+            .store(if n == 0 { 0 } else { self.writes() + n }, Ordering::SeqCst);
+    }
+
+    /// Makes reads return at most the given number of bytes (the content is truncated); 0
+    /// disables short reads.
+    ///
+    /// # Arguments
+    ///
+    /// * `max` - Maximum number of bytes returned by a read.
+    pub fn short_reads(&self, max: u64) {
+        self.short_reads.store(max, Ordering::SeqCst);
+    }
+
+    /// Limits the number of bytes, which can be written from now; writes beyond the limit fail
+    /// with `io::ErrorKind::StorageFull` (ENOSPC). `None` removes the limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Available space in bytes.
+    pub fn space(&self, bytes: Option<u64>) {
+        if let Ok(mut space) = self.space.lock() {
+            *space = bytes;
+        }
+    }
+
+    /// Makes writes and removals fail with `io::ErrorKind::PermissionDenied`.
+    ///
+    /// # Arguments
+    ///
+    /// * `read_only` - True to deny changes.
+    pub fn read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::SeqCst);
+    }
+
+    /// Removes all injected failures.
+    pub fn heal(&self) {
+        self.fail_at.store(0, Ordering::SeqCst);
+        self.short_reads(0);
+        self.space(None);
+        self.read_only(false);
+    }
+
+    fn deny(&self) -> io::Result<()> {
+        if self.read_only.load(Ordering::SeqCst) {
+            Err(io::ErrorKind::PermissionDenied.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<F: FileSystem + 'static> FileSystem for FaultyFs<F> {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut content = self.inner.read(path)?;
+        let max = self.short_reads.load(Ordering::SeqCst);
+        if max > 0 {
+            content.truncate(max as usize);
+        }
+        Ok(content)
+    }
+
+    fn write(&self, path: &Path, content: &[u8], durability: Durability) -> io::Result<()> {
+        let n = self.writes.fetch_add(1, Ordering::SeqCst) + 1;
+        if n == self.fail_at.load(Ordering::SeqCst) {
+            return Err(io::Error::other(format!("injected failure of write #{n}")));
+        }
+        self.deny()?;
+        let mut space = self.space.lock().map_err(|_| poisoned())?;
+        if let Some(space) = space.as_mut() {
+            *space = space
+                .checked_sub(content.len() as u64)
+                .ok_or(io::Error::from(io::ErrorKind::StorageFull))?;
+        }
+        self.inner.write(path, content, durability)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.deny()?;
+        self.inner.remove(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.inner.size(path)
+    }
+
+    fn allocated(&self, path: &Path) -> io::Result<u64> {
+        self.inner.allocated(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.deny()?;
+        self.inner.create_dir_all(path)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.sync_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.deny()?;
+        self.inner.remove_dir_all(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FaultyFs, Storage, E};
+    use std::sync::Arc;
+
+    #[test]
+    fn faults() -> Result<(), E> {
+        let fs = Arc::new(FaultyFs::memory());
+        let mut storage = Storage::create_with_options("/faulty", fs.options())?;
+        storage.set("a", &String::from("value of a"))?;
+        // Failed write of the record
+        fs.fail_nth_write(1);
+        assert!(storage.set("b", &1u32).is_err());
+        assert!(!storage.has("b"));
+        // Failed write of the map
+        fs.fail_nth_write(2);
+        assert!(storage.set("b", &1u32).is_err());
+        storage.set("b", &1u32)?;
+        // No space left
+        fs.space(Some(8));
+        let err = storage.set("c", &String::from("long value")).unwrap_err();
+        assert!(matches!(err.root(), E::DiskFull));
+        fs.space(None);
+        // Short reads break checksums
+        fs.short_reads(4);
+        assert!(storage.get_sensitive::<String, _>("a").is_err());
+        fs.heal();
+        assert_eq!(
+            storage.get::<String, _>("a")?,
+            Some(String::from("value of a"))
+        );
+        // Read only
+        fs.read_only(true);
+        let err = storage.remove("b").unwrap_err();
+        assert!(matches!(err.root(), E::PermissionDenied { .. }));
+        fs.heal();
+        drop(storage);
+        let storage = Storage::open_with_options("/faulty", fs.options())?;
+        assert_eq!(storage.get::<u32, _>("b")?, Some(1));
+        // Failed writes don't leave records referenced by the map
+        assert!(!storage.has("c"));
+        assert!(fs.inner().size_of_files() > 0);
+        Ok(())
+    }
+}
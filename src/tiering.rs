@@ -0,0 +1,456 @@
+use log::warn;
+use serde::Deserialize;
+use std::{collections::HashSet, env::temp_dir, io, path::Path, sync::Mutex, time::Duration};
+use uuid::Uuid;
+
+use crate::{
+    deserialize_tagged, fs, sensitive::Wiped, Bundle, Field, Header, Operation, PackedStorage,
+    Storage, StorageKey, E,
+};
+
+/// Bundle in the storage folder with archived records (see `Storage::archive_older_than()`)
+pub const ARCHIVE_FILE_NAME: &str = "archive.bundle";
+/// Keys of archived records, which have been restored, overwritten or removed since the archive
+/// has been written
+const OBSOLETE_FILE_NAME: &str = "archive.obsolete";
+
+/// Policy of moving rarely used records into the archive (see `Storage::archive_cold()`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tiering {
+    /// Records, which haven't been accessed for this period, are archived. Without access
+    /// tracking (see `StorageOptions::access`) only writes of records are taken into account.
+    pub cold_after: Duration,
+}
+
+impl Default for Tiering {
+    fn default() -> Self {
+        Self {
+            cold_after: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Writes records into the bundle, replacing it. Record files are written as they are, so
+/// records written with a codec or transformers (e.g. encrypted ones) stay encoded.
+///
+/// # Arguments
+///
+/// * `bundle` - A path reference to the bundle.
+/// * `records` - Keys and contents of record files.
+///
+/// # Returns
+///
+/// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+pub(crate) fn pack_records<I: IntoIterator<Item = (String, Vec<u8>)>>(
+    bundle: &Path,
+    records: I,
+) -> Result<(), E> {
+    let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+    let packed = records
+        .into_iter()
+        .try_for_each(|(key, content)| {
+            let tag = Header::decode(&content)?
+                .0
+                .map(|header| header.tag)
+                .unwrap_or_default();
+            let mut field = Field::create();
+            field
+                .store(
+                    &*storage.fs,
+                    &storage.cwd,
+                    tag,
+                    &content,
+                    storage.options.durability,
+                )
+                .map_err(|e| e.record(Operation::Set, &key, &field.path(&storage.cwd)))?;
+            storage.fields.insert(key, field);
+            Ok(())
+        })
+        .and_then(|_| storage.map.write(&storage.fields))
+        .and_then(|_| {
+            let tmp = bundle.with_extension("tmp");
+            storage.pack(&tmp)?;
+            std::fs::rename(fs::long_path(&tmp), fs::long_path(bundle))
+                .map_err(|e| E::io(e, bundle))
+        });
+    storage.destroy()?;
+    packed
+}
+
+/// Archive of rarely used records of the storage
+#[derive(Debug)]
+pub(crate) struct Archive {
+    packed: PackedStorage,
+    /// Archived keys, which have been restored, overwritten or removed
+    obsolete: HashSet<String>,
+    /// Keys read from the archive; they are restored with the next change of the storage
+    restore: Mutex<Vec<String>>,
+}
+
+impl Archive {
+    /// Opens the archive of the storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `cwd` - A path reference to the storage folder.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<Self>, E>` - Returns the archive, None if there is no archive, or an
+    ///   error.
+    pub(crate) fn load(cwd: &Path) -> Result<Option<Self>, E> {
+        let path = cwd.join(ARCHIVE_FILE_NAME);
+        if !fs::long_path(&path).is_file() {
+            return Ok(None);
+        }
+        let packed = Storage::open_bundle(&path)?;
+        let path = cwd.join(OBSOLETE_FILE_NAME);
+        let obsolete = match std::fs::read(fs::long_path(&path)) {
+            Ok(content) => bincode::deserialize::<HashSet<String>>(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(E::io(err, &path)),
+        };
+        Ok(Some(Self {
+            packed,
+            obsolete,
+            restore: Mutex::new(Vec::new()),
+        }))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.packed.has(key) && !self.obsolete.contains(key)
+    }
+
+    /// Reads the content of the archived record file as it has been written.
+    fn content_of(&self, key: &str) -> Result<Option<Vec<u8>>, E> {
+        if !self.contains(key) {
+            return Ok(None);
+        }
+        self.packed.content_of(key)
+    }
+}
+
+impl Storage {
+    /// Moves records, which haven't been used for `Tiering::cold_after`, into the archive (see
+    /// `Storage::archive_older_than()`). Nothing is archived, if `StorageOptions::tiering` isn't
+    /// set.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns archived keys, sorted, or an error.
+    pub fn archive_cold(&mut self) -> Result<Vec<String>, E> {
+        match self
+            .options
+            .tiering
+            .as_ref()
+            .map(|tiering| tiering.cold_after)
+        {
+            Some(age) => self.archive_older_than(age),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Moves records, which haven't been used for the given period, into the archive: the bundle
+    /// `archive.bundle` in the storage folder. Archived records don't take separate files, but
+    /// stay available: `get()` and `get_ref()` read them from the archive and they are restored
+    /// into the storage with the next change; `has()`, `set()` and `remove()` work as with other
+    /// records.
+    /// Other methods (e.g. `len()`, `keys()`, searches) see records of the storage only; use
+    /// `archived_keys()` to list the archive. Pinned records and records with the time to live
+    /// aren't archived.
+    ///
+    /// The archive is rewritten with each call, so it's worth archiving records in batches, e.g.
+    /// once a day.
+    ///
+    /// # Arguments
+    ///
+    /// * `age` - Records, which haven't been accessed for this period, are archived. Without
+    ///   access tracking (see `StorageOptions::access`) only writes of records are taken into
+    ///   account.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<String>, E>` - Returns archived keys, sorted, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::Storage;
+    /// use std::{env::temp_dir, time::Duration};
+    /// use uuid::Uuid;
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.set("report/2020", &vec![1u32; 1024]).unwrap();
+    /// assert_eq!(storage.archive_older_than(Duration::ZERO).unwrap(), vec!["report/2020"]);
+    /// assert_eq!(storage.len(), 0);
+    /// assert_eq!(storage.archived_keys(), vec!["report/2020"]);
+    /// // Archived records are still available
+    /// assert!(storage.has("report/2020"));
+    /// assert_eq!(
+    ///     storage.get::<Vec<u32>, _>("report/2020").unwrap(),
+    ///     Some(vec![1u32; 1024])
+    /// );
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn archive_older_than(&mut self, age: Duration) -> Result<Vec<String>, E> {
+        self.map.check()?;
+        if self.prune()? {
+            self.map.write(&self.fields)?;
+        }
+        let mut cold = self
+            .fields
+            .iter()
+            .filter(|(key, field)| {
+                !field.meta().pinned
+                    && field.meta().accessed.elapsed() >= age
+                    && !self.deadlines.contains(key)
+            })
+            .map(|(key, _)| key.to_owned())
+            .collect::<Vec<String>>();
+        if cold.is_empty() {
+            return Ok(cold);
+        }
+        cold.sort();
+        let mut records = Vec::new();
+        if let Some(archive) = self.archive.as_ref() {
+            for key in archive.packed.keys() {
+                if self.fields.contains_key(key) {
+                    continue;
+                }
+                if let Some(content) = archive.content_of(key)? {
+                    records.push((key.to_owned(), content));
+                }
+            }
+        }
+        for key in cold.iter() {
+            if let Some(field) = self.fields.get(key) {
+                let content = self
+                    .extract_raw(field)
+                    .map_err(|e| e.record(Operation::Read, key, &field.path(&self.cwd)))?;
+                records.push((key.to_owned(), content));
+            }
+        }
+        pack_records(&self.cwd.join(ARCHIVE_FILE_NAME), records)?;
+        let obsolete = self.cwd.join(OBSOLETE_FILE_NAME);
+        if self.fs.exists(&obsolete) {
+            self.fs.remove(&obsolete)?;
+        }
+        self.archive = Archive::load(&self.cwd)?;
+        for key in cold.iter() {
+            self.discard(key)?;
+        }
+        self.map.write(&self.fields)?;
+        Ok(cold)
+    }
+
+    /// Returns keys of archived records (see `Storage::archive_older_than()`).
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<&String>` - Keys of archived records, sorted.
+    pub fn archived_keys(&self) -> Vec<&String> {
+        let Some(archive) = self.archive.as_ref() else {
+            return Vec::new();
+        };
+        let mut keys = archive
+            .packed
+            .keys()
+            .filter(|key| !self.fields.contains_key(*key) && archive.contains(key))
+            .collect::<Vec<&String>>();
+        keys.sort();
+        keys
+    }
+
+    /// Returns true if the key is in the archive and isn't shadowed by the record of the
+    /// storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if the record of the key is archived.
+    pub fn is_archived<K: StorageKey>(&self, key: K) -> bool {
+        let key = self.normalized(key.to_key());
+        !self.fields.contains_key(key.as_ref())
+            && self
+                .archive
+                .as_ref()
+                .is_some_and(|archive| archive.contains(key.as_ref()))
+    }
+
+    /// Reads the type tag and the serialized value of the archived record.
+    fn payload_of_archived(&self, key: &str) -> Result<Option<(u64, Vec<u8>)>, E> {
+        let Some(content) = self
+            .archive
+            .as_ref()
+            .map(|archive| archive.content_of(key))
+            .transpose()?
+            .flatten()
+        else {
+            return Ok(None);
+        };
+        let content = Wiped(self.decoded(content)?);
+        let (header, payload) = Field::payload(&content)?;
+        Ok(Some((
+            header.map(|header| header.tag).unwrap_or_default(),
+            payload.to_vec(),
+        )))
+    }
+
+    /// Reads the archived record and schedules its restoring into the storage.
+    pub(crate) fn archived_payload(&self, key: &str) -> Result<Option<(u64, Vec<u8>)>, E> {
+        let Some(archive) = self.archive.as_ref() else {
+            return Ok(None);
+        };
+        let payload = self.payload_of_archived(key)?;
+        if payload.is_some() {
+            archive
+                .restore
+                .lock()
+                .map_err(|_| E::Unknown)?
+                .push(key.to_owned());
+        }
+        Ok(payload)
+    }
+
+    /// Reads the archived value of the key (see `archived_payload()`).
+    pub(crate) fn archived<V: for<'a> Deserialize<'a> + 'static>(
+        &self,
+        key: &str,
+    ) -> Result<Option<V>, E> {
+        let Some((tag, payload)) = self.archived_payload(key)? else {
+            return Ok(None);
+        };
+        self.sealed(|| Ok(Some(deserialize_tagged::<V>(tag, &payload)?)))
+    }
+
+    /// Restores archived records, which have been read, into the storage without writing the
+    /// map.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<bool, E>` - Returns true if some records have been restored, or an error.
+    pub(crate) fn restore_archived(&mut self) -> Result<bool, E> {
+        let Some(archive) = self.archive.as_mut() else {
+            return Ok(false);
+        };
+        let keys = std::mem::take(archive.restore.get_mut().map_err(|_| E::Unknown)?);
+        let mut restored = false;
+        for key in keys {
+            if self.fields.contains_key(&key) {
+                continue;
+            }
+            let Some((tag, payload)) = self.payload_of_archived(&key)? else {
+                continue;
+            };
+            self.put(&key, tag, &payload)?;
+            self.obsolete_archived(&key);
+            restored = true;
+        }
+        Ok(restored)
+    }
+
+    /// Marks the archived record of the key as obsolete, because the key has been restored,
+    /// overwritten or removed. Failures are logged only.
+    pub(crate) fn obsolete_archived(&mut self, key: &str) {
+        let Some(archive) = self.archive.as_mut() else {
+            return;
+        };
+        if !archive.contains(key) {
+            return;
+        }
+        archive.obsolete.insert(key.to_owned());
+        let path = self.cwd.join(OBSOLETE_FILE_NAME);
+        let saved = bincode::serialize(&archive.obsolete)
+            .map_err(E::from)
+            .and_then(|content| {
+                self.fs
+                    .write(&path, &content, self.options.durability)
+                    .map_err(|e| E::io(e, &path))
+            });
+        if let Err(err) = saved {
+            warn!("Archived record of \"{key}\" isn't marked as obsolete: {err}");
+        }
+    }
+
+    /// Removes the archived record of the key, which isn't in the storage.
+    pub(crate) fn remove_archived(&mut self, key: &str) -> bool {
+        if !self.is_archived(key) {
+            return false;
+        }
+        self.publish(key, None);
+        true
+    }
+
+    /// Removes the archive with all archived records.
+    pub(crate) fn clear_archive(&mut self) -> Result<(), E> {
+        if self.archive.take().is_none() {
+            return Ok(());
+        }
+        for name in [ARCHIVE_FILE_NAME, OBSOLETE_FILE_NAME] {
+            let path = self.cwd.join(name);
+            if self.fs.exists(&path) {
+                self.fs.remove(&path).map_err(|e| E::io(e, &path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, StorageOptions, Tiering, E};
+    use std::{env::temp_dir, thread, time::Duration};
+    use uuid::Uuid;
+
+    #[test]
+    fn tiering() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let options = StorageOptions {
+            tiering: Some(Tiering {
+                cold_after: Duration::from_millis(100),
+            }),
+            ..Default::default()
+        };
+        let mut storage = Storage::create_with_options(&storage_path, options.clone())?;
+        for n in 0..10u32 {
+            storage.set(format!("old/{n}"), &n)?;
+        }
+        thread::sleep(Duration::from_millis(150));
+        storage.set("new", &String::from("hot"))?;
+        storage.pin("old/0")?;
+        assert_eq!(storage.archive_cold()?.len(), 9);
+        assert_eq!(storage.len(), 2);
+        // The archive survives reopening
+        drop(storage);
+        let mut storage = Storage::open_with_options(&storage_path, options)?;
+        assert_eq!(storage.archived_keys().len(), 9);
+        assert!(storage.is_archived("old/1"));
+        assert_eq!(storage.get_sensitive::<u32, _>("old/1")?, Some(1));
+        // Read records are restored with the next change
+        assert_eq!(storage.get::<u32, _>("old/1")?, Some(1));
+        storage.set("other", &1u32)?;
+        assert!(!storage.is_archived("old/1"));
+        assert_eq!(storage.get::<u32, _>("old/1")?, Some(1));
+        // Overwritten and removed records don't come back from the archive
+        storage.set("old/2", &20u32)?;
+        assert!(storage.remove("old/3")?);
+        assert!(!storage.has("old/3"));
+        assert!(storage.remove("old/2")?);
+        assert!(!storage.has("old/2"));
+        assert_eq!(storage.archived_keys().len(), 6);
+        // Archiving again keeps records of the archive
+        thread::sleep(Duration::from_millis(150));
+        storage.archive_cold()?;
+        assert_eq!(storage.archived_keys().len(), 9);
+        assert_eq!(storage.get::<u32, _>("old/9")?, Some(9));
+        assert_eq!(storage.get::<u32, _>("old/1")?, Some(1));
+        storage.clear()?;
+        assert!(storage.archived_keys().is_empty());
+        assert!(!storage.has("old/9"));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! Instrumentation of storage operations. With the `tracing` feature each operation is wrapped
+//! into a span with the key (or path of bundle), the size of data in bytes and the duration of
+//! operation in microseconds. Without the feature `Op` is a no-op.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// Creates an `Op` for the given operation; the first argument is a name of the span, the
+/// second one is a name of the identifying field (`key` or `path`).
+macro_rules! op {
+    ($name:literal, $field:ident, $value:expr) => {{
+        #[cfg(feature = "tracing")]
+        {
+            $crate::trace::Op::new(tracing::debug_span!(
+                $name,
+                $field = tracing::field::debug(&$value),
+                size = tracing::field::Empty,
+                duration_us = tracing::field::Empty,
+            ))
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            let _ = &$value;
+            $crate::trace::Op::default()
+        }
+    }};
+}
+pub(crate) use op;
+
+/// A guard of an instrumented operation. The span is entered while the guard is alive; the
+/// duration is recorded when the guard is dropped.
+#[derive(Default)]
+pub(crate) struct Op {
+    #[cfg(feature = "tracing")]
+    span: Option<(tracing::span::EnteredSpan, Instant)>,
+}
+
+impl Op {
+    #[cfg(feature = "tracing")]
+    pub fn new(span: tracing::Span) -> Self {
+        Self {
+            span: Some((span.entered(), Instant::now())),
+        }
+    }
+
+    /// Records the size of data in bytes. The closure is called only if the `tracing` feature
+    /// is enabled, which allows getting the size lazily.
+    #[allow(unused_variables)]
+    pub fn size<F: FnOnce() -> Option<u64>>(&self, size: F) {
+        #[cfg(feature = "tracing")]
+        if let (Some((span, _)), Some(size)) = (self.span.as_ref(), size()) {
+            span.record("size", size);
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Op {
+    fn drop(&mut self) {
+        if let Some((span, started)) = self.span.take() {
+            span.record("duration_us", started.elapsed().as_micros() as u64);
+        }
+    }
+}
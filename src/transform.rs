@@ -0,0 +1,280 @@
+use std::{borrow::Cow, fmt, sync::Arc};
+
+use crate::{sensitive::Wiped, Storage, StorageKey, E};
+
+/// Transformation of serialized values in record files registered with `Storage::transformer()`
+/// (e.g. compression, encryption, signing with own algorithms). Transformers are stacked: values
+/// are written through all registered transformers in order of registration and read back in
+/// the reverse order. Names of transformers are kept in the header of each record file (and in the
+/// map), so a record is read back with the transformers it has been written with, even if
+/// transformers of the storage have changed since then or the map has been rebuilt.
+///
+/// Transformers are applied before the codec of the record (see `Codecs`), so they get values as
+/// they are serialized.
+pub trait Transformer: Send + Sync {
+    /// Returns the name of the transformer, which is kept with records; it should be unique
+    /// within the storage and never change.
+    fn name(&self) -> &str;
+
+    /// Transforms the serialized value before it's written.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the record; empty with `Layout::ContentAddressed`, because record
+    ///   files are shared by keys.
+    /// * `payload` - The value as it's passed by the previous transformer.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the transformed value, or an error.
+    fn encode(&self, key: &str, payload: &[u8]) -> Result<Vec<u8>, E>;
+
+    /// Reverts `Transformer::encode()`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the record (see `Transformer::encode()`).
+    /// * `payload` - The value as it has been returned by `Transformer::encode()`.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the value as it has been passed to
+    ///   `Transformer::encode()`, or an error (e.g. if the signature doesn't match).
+    fn decode(&self, key: &str, payload: &[u8]) -> Result<Vec<u8>, E>;
+}
+
+/// Transformers registered with `Storage::transformer()`
+#[derive(Clone, Default)]
+pub(crate) struct Transformers(Vec<Arc<dyn Transformer>>);
+
+impl fmt::Debug for Transformers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|transformer| transformer.name()))
+            .finish()
+    }
+}
+
+impl Transformers {
+    /// Returns names of transformers in order of registration.
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|transformer| transformer.name().to_owned())
+            .collect()
+    }
+
+    /// Writes the serialized value through all transformers.
+    pub(crate) fn encode<'a>(&self, key: &str, payload: &'a [u8]) -> Result<Cow<'a, [u8]>, E> {
+        let mut buffer = Cow::Borrowed(payload);
+        for transformer in self.0.iter() {
+            let encoded = transformer.encode(key, &buffer)?;
+            // Intermediate values may still be readable, so they are wiped
+            if let Cow::Owned(previous) = std::mem::replace(&mut buffer, Cow::Owned(encoded)) {
+                drop(Wiped(previous));
+            }
+        }
+        Ok(buffer)
+    }
+
+    /// Reads the value back through transformers of the given names in the reverse order.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the record.
+    /// * `names` - Names of transformers, which the record has been written with.
+    /// * `payload` - The transformed value.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<u8>, E>` - Returns the serialized value, or `E::UnknownTransformer` if
+    ///   some of transformers isn't registered.
+    pub(crate) fn decode(
+        &self,
+        key: &str,
+        names: &[String],
+        payload: Vec<u8>,
+    ) -> Result<Vec<u8>, E> {
+        let mut buffer = payload;
+        for name in names.iter().rev() {
+            let transformer = self
+                .0
+                .iter()
+                .find(|transformer| transformer.name() == name)
+                .ok_or_else(|| E::UnknownTransformer(name.to_owned()))?;
+            let decoded = transformer.decode(key, &buffer)?;
+            drop(Wiped(std::mem::replace(&mut buffer, decoded)));
+        }
+        Ok(buffer)
+    }
+}
+
+impl Storage {
+    /// Registers the transformer of values (see `Transformer`). Records written after the
+    /// registration go through the transformer; records written before stay as they are. Records
+    /// written with a transformer can be read only while it's registered, so the transformer
+    /// should be registered each time the storage is opened; otherwise reading fails with
+    /// `E::UnknownTransformer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `transformer` - The transformer.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()), or `E::DuplicateTransformer` if a transformer with
+    ///   the same name is registered already.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Storage, Transformer, E};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// /// Appends the checksum of the key and the value
+    /// struct Signed;
+    ///
+    /// impl Transformer for Signed {
+    ///     fn name(&self) -> &str {
+    ///         "signed"
+    ///     }
+    ///
+    ///     fn encode(&self, key: &str, payload: &[u8]) -> Result<Vec<u8>, E> {
+    ///         let mut signed = payload.to_vec();
+    ///         signed.extend_from_slice(&crc32fast::hash(&[key.as_bytes(), payload].concat()).to_le_bytes());
+    ///         Ok(signed)
+    ///     }
+    ///
+    ///     fn decode(&self, key: &str, payload: &[u8]) -> Result<Vec<u8>, E> {
+    ///         let (value, signature) = payload.split_at(payload.len().saturating_sub(4));
+    ///         if signature != crc32fast::hash(&[key.as_bytes(), value].concat()).to_le_bytes() {
+    ///             return Err(E::Rejected(format!("\"{key}\" isn't signed")));
+    ///         }
+    ///         Ok(value.to_vec())
+    ///     }
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.transformer(Signed).unwrap();
+    /// storage.set("name", &String::from("value")).unwrap();
+    /// assert_eq!(storage.transformers_of("name"), Some(vec![String::from("signed")]));
+    /// assert_eq!(
+    ///     storage.get::<String, _>("name").unwrap(),
+    ///     Some(String::from("value"))
+    /// );
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn transformer<T: Transformer + 'static>(&mut self, transformer: T) -> Result<(), E> {
+        if self
+            .transformers
+            .0
+            .iter()
+            .any(|registered| registered.name() == transformer.name())
+        {
+            return Err(E::DuplicateTransformer(transformer.name().to_owned()));
+        }
+        self.transformers.0.push(Arc::new(transformer));
+        Ok(())
+    }
+
+    /// Returns names of transformers, which the record of the key has been written with.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<String>>` - Names of transformers in order of applying, or None if the key
+    ///   doesn't exist.
+    pub fn transformers_of<K: StorageKey>(&self, key: K) -> Option<Vec<String>> {
+        let key = self.normalized(key.to_key());
+        self.fields
+            .get(key.as_ref())
+            .map(|field| field.meta().transformers.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{map, KeyResolver, Storage, Transformer, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    /// Xors bytes of values with the mask
+    struct Xor(&'static str, u8);
+
+    impl Transformer for Xor {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn encode(&self, _key: &str, payload: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(payload.iter().map(|b| b ^ self.1).collect())
+        }
+
+        fn decode(&self, key: &str, payload: &[u8]) -> Result<Vec<u8>, E> {
+            self.encode(key, payload)
+        }
+    }
+
+    #[test]
+    fn transformers() -> Result<(), E> {
+        let storage_path = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create(&storage_path)?;
+        storage.set("plain", &String::from("plain text"))?;
+        storage.transformer(Xor("a", 0x55))?;
+        storage.transformer(Xor("b", 0x0f))?;
+        assert!(matches!(
+            storage.transformer(Xor("a", 0x0f)),
+            Err(E::DuplicateTransformer(name)) if name == "a"
+        ));
+        storage.set("name", &String::from("hidden text"))?;
+        let content = std::fs::read(storage.fields["name"].path(storage.cwd()))?;
+        assert!(!content.windows(6).any(|w| w == b"hidden"));
+        assert_eq!(
+            storage.transformers_of("name"),
+            Some(vec![String::from("a"), String::from("b")])
+        );
+        assert_eq!(storage.transformers_of("plain"), Some(Vec::new()));
+        drop(storage);
+        // Records are read with transformers they have been written with
+        let mut storage = Storage::open(&storage_path)?;
+        storage.transformer(Xor("b", 0x0f))?;
+        storage.transformer(Xor("a", 0x55))?;
+        storage.transformer(Xor("unused", 0xff))?;
+        assert_eq!(
+            storage.get::<String, _>("name")?,
+            Some(String::from("hidden text"))
+        );
+        assert_eq!(
+            storage.get::<String, _>("plain")?,
+            Some(String::from("plain text"))
+        );
+        drop(storage);
+        // Names of transformers are kept in headers of records, so they survive the loss of the map
+        std::fs::remove_file(storage_path.join(map::MAP_FILE_NAME))?;
+        drop(Storage::recover(&storage_path, KeyResolver::Embedded)?);
+        let mut storage = Storage::open(&storage_path)?;
+        assert_eq!(
+            storage.transformers_of("name"),
+            Some(vec![String::from("a"), String::from("b")])
+        );
+        storage.transformer(Xor("a", 0x55))?;
+        storage.transformer(Xor("b", 0x0f))?;
+        assert_eq!(
+            storage.get::<String, _>("name")?,
+            Some(String::from("hidden text"))
+        );
+        drop(storage);
+        // Records cannot be read without their transformers
+        let mut storage = Storage::open(&storage_path)?;
+        assert!(matches!(
+            storage.get::<String, _>("name"),
+            Err(E::Record { source, .. }) if matches!(*source, E::UnknownTransformer(ref name) if name == "b")
+        ));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Storage, E};
+
+/// `Record` is a value, which knows its own key. The key is built from fields of the value, so
+/// the same record is always stored under the same key and can be loaded by its id. With the
+/// `derive` feature it's derived with `#[derive(Record)]` and the key template
+/// `#[record(key = "user:{id}")]`.
+pub trait Record: Serialize + DeserializeOwned + 'static {
+    /// Id of the record: values of fields used in the key.
+    type Id;
+
+    /// Returns the key of the record.
+    fn key(&self) -> String;
+
+    /// Returns the key of the record with the given id.
+    fn key_of(id: &Self::Id) -> String;
+}
+
+impl Storage {
+    /// Saves the record under its own key (see `Record`).
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - A reference to the record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns Ok(()) if successful, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Record, Storage};
+    /// use serde::{Deserialize, Serialize};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    /// struct User {
+    ///     id: u64,
+    ///     name: String,
+    /// }
+    ///
+    /// // Can be derived with the `derive` feature: #[record(key = "user:{id}")]
+    /// impl Record for User {
+    ///     type Id = u64;
+    ///
+    ///     fn key(&self) -> String {
+    ///         User::key_of(&self.id)
+    ///     }
+    ///
+    ///     fn key_of(id: &u64) -> String {
+    ///         format!("user:{id}")
+    ///     }
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// let user = User {
+    ///     id: 7,
+    ///     name: String::from("Alice"),
+    /// };
+    /// storage.save(&user).unwrap();
+    /// assert!(storage.has("user:7"));
+    /// assert_eq!(storage.load::<User>(&7).unwrap(), Some(user));
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn save<R: Record>(&mut self, record: &R) -> Result<(), E> {
+        self.set(record.key(), record)
+    }
+
+    /// Loads the record by its id (see `Record`).
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - A reference to the id of the record.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<R>, E>` - Returns the record if found, or None if not found, or an error.
+    pub fn load<R: Record>(&self, id: &R::Id) -> Result<Option<R>, E> {
+        self.get(R::key_of(id))
+    }
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use crate::{Record, Storage, E};
+    use serde::{Deserialize, Serialize};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[derive(Serialize, Deserialize, Record, Debug, PartialEq)]
+    #[record(key = "user:{id}")]
+    struct User {
+        id: u64,
+        name: String,
+    }
+
+    #[derive(Serialize, Deserialize, Record, Debug, PartialEq)]
+    #[record(key = "member:{team}/{id}")]
+    struct Member {
+        team: String,
+        id: u32,
+    }
+
+    #[test]
+    fn derive() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let user = User {
+            id: 1,
+            name: String::from("Alice"),
+        };
+        storage.save(&user)?;
+        assert_eq!(storage.load::<User>(&1)?, Some(user));
+        assert!(storage.load::<User>(&2)?.is_none());
+        let member = Member {
+            team: String::from("core"),
+            id: 3,
+        };
+        storage.save(&member)?;
+        assert!(storage.has("member:core/3"));
+        assert_eq!(
+            storage.load::<Member>(&(String::from("core"), 3))?,
+            Some(member)
+        );
+        storage.destroy()?;
+        Ok(())
+    }
+}
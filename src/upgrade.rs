@@ -0,0 +1,246 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+use crate::{sensitive::Wiped, type_tag, Field, Storage, StorageKey, E};
+
+/// Decodes the serialized value of an older type and converts it into the current one
+type DecodeFn<V> = Box<dyn Fn(&[u8]) -> Result<V, E>>;
+
+/// Older types of records of the type `V` with conversions into `V`; passed into
+/// `Storage::get_as()`. Helps to evolve types of records without a migration of the whole storage:
+/// records are upgraded one by one when they are read.
+///
+/// Older types should be added from the newest to the oldest. A record is decoded with the type
+/// it has been written with (see `type_tag()`); records of unknown types (e.g. written by previous
+/// versions of the crate or with types, which have been moved to other modules) are decoded with
+/// `V` and then with older types in the order they have been added.
+pub struct FallbackDecode<V> {
+    older: Vec<(u64, DecodeFn<V>)>,
+}
+
+impl<V> fmt::Debug for FallbackDecode<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FallbackDecode")
+            .field("older", &self.older.len())
+            .finish()
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + 'static> Default for FallbackDecode<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Serialize + DeserializeOwned + 'static> FallbackDecode<V> {
+    /// Creates the chain without older types.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The chain, which decodes values of `V` only.
+    pub fn new() -> Self {
+        Self { older: Vec::new() }
+    }
+
+    /// Adds the older type `O` with the conversion of its values into `V`.
+    ///
+    /// # Arguments
+    ///
+    /// * `convert` - A closure, which converts the value of the older type.
+    ///
+    /// # Returns
+    ///
+    /// * `Self` - The chain with the older type.
+    pub fn or<O: DeserializeOwned + 'static, F: Fn(O) -> V + 'static>(
+        mut self,
+        convert: F,
+    ) -> Self {
+        self.older.push((
+            type_tag::<O>(),
+            Box::new(move |payload| Ok(convert(bincode::deserialize::<O>(payload)?))),
+        ));
+        self
+    }
+
+    /// Decodes the payload written with the type tag.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(V, bool), E>` - Returns the value and true if it has been converted from an
+    ///   older type, or an error of the last attempt.
+    fn decode(&self, tag: u64, payload: &[u8]) -> Result<(V, bool), E> {
+        if tag == type_tag::<V>() {
+            return Ok((bincode::deserialize(payload)?, false));
+        }
+        if let Some((_, decode)) = self.older.iter().find(|(older, _)| *older == tag) {
+            return Ok((decode(payload)?, true));
+        }
+        let mut last = match bincode::deserialize(payload) {
+            Ok(value) => return Ok((value, tag != 0)),
+            Err(err) => E::from(err),
+        };
+        for (_, decode) in self.older.iter() {
+            match decode(payload) {
+                Ok(value) => return Ok((value, true)),
+                Err(err) => last = err,
+            }
+        }
+        Err(last)
+    }
+}
+
+impl Storage {
+    /// Retrieves the value of the key, which may have been written with an older type of the
+    /// value (see `FallbackDecode`). The value of an older type is converted and written back
+    /// as `V`, so the record is upgraded once. If the record cannot be decoded with any of the
+    /// types, it's handled according to `CorruptionPolicy` as `get()` does.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key.
+    /// * `fallback` - Older types of the value with conversions.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<V>, E>` - Returns the value if found, or None if not found, or an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{FallbackDecode, Storage};
+    /// use serde::{Deserialize, Serialize};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct SettingsV1 {
+    ///     theme: String,
+    /// }
+    ///
+    /// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    /// struct SettingsV2 {
+    ///     theme: String,
+    ///     font_size: u8,
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage
+    ///     .set("settings", &SettingsV1 { theme: String::from("dark") })
+    ///     .unwrap();
+    /// let fallback = FallbackDecode::<SettingsV2>::new().or(|v1: SettingsV1| SettingsV2 {
+    ///     theme: v1.theme,
+    ///     font_size: 12,
+    /// });
+    /// let settings = storage.get_as("settings", &fallback).unwrap();
+    /// assert_eq!(settings.map(|s| s.font_size), Some(12));
+    /// // The record is upgraded
+    /// assert!(storage.get::<SettingsV2, _>("settings").unwrap().is_some());
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn get_as<V: Serialize + DeserializeOwned + 'static, K: StorageKey>(
+        &mut self,
+        key: K,
+        fallback: &FallbackDecode<V>,
+    ) -> Result<Option<V>, E> {
+        let key = self.normalized(key.to_key());
+        let Some(field) = self.fields.get(key.as_ref()) else {
+            return Ok(None);
+        };
+        if self.expired(key.as_ref()) {
+            return Ok(None);
+        }
+        self.accessed(field);
+        let decoded = self.sealed(|| {
+            let content = Wiped(self.extract(field)?);
+            let (_, payload) = Field::payload(&content)?;
+            fallback.decode(field.meta().tag, payload)
+        });
+        let (value, upgraded) = match decoded {
+            Ok(decoded) => decoded,
+            Err(err) => return self.corrupted(key.as_ref(), field, err),
+        };
+        if upgraded {
+            let buffer = Wiped(self.sealed(|| Ok(bincode::serialize(&value)?))?);
+            self.set_bytes(key.as_ref(), type_tag::<V>(), &buffer)?;
+        }
+        Ok(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{type_tag, FallbackDecode, Storage, E};
+    use serde::{Deserialize, Serialize};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[derive(Serialize, Deserialize)]
+    struct V1 {
+        name: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct V2 {
+        name: String,
+        age: u32,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct V3 {
+        name: String,
+        age: u32,
+        email: Option<String>,
+    }
+
+    #[test]
+    fn fallback() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        let v1 = V1 {
+            name: String::from("Alice"),
+        };
+        storage.set("v1", &v1)?;
+        storage.set(
+            "v2",
+            &V2 {
+                name: String::from("Bob"),
+                age: 30,
+            },
+        )?;
+        // The record of an unknown type (e.g. the type has been moved to another module)
+        storage.set_bytes("unknown", 42, &bincode::serialize(&v1)?)?;
+        storage.set("v3", &1u8)?;
+        let fallback = FallbackDecode::<V3>::new()
+            .or(|v2: V2| V3 {
+                name: v2.name,
+                age: v2.age,
+                email: None,
+            })
+            .or(|v1: V1| V3 {
+                name: v1.name,
+                age: 0,
+                email: None,
+            });
+        let alice = V3 {
+            name: String::from("Alice"),
+            age: 0,
+            email: None,
+        };
+        assert_eq!(storage.get_as("v1", &fallback)?, Some(alice));
+        assert_eq!(storage.get_as("v2", &fallback)?.map(|v| v.age), Some(30));
+        assert_eq!(
+            storage.get_as("unknown", &fallback)?.map(|v| v.name),
+            Some(String::from("Alice"))
+        );
+        // Records are upgraded
+        for key in ["v1", "v2", "unknown"] {
+            assert_eq!(storage.fields[key].meta().tag, type_tag::<V3>());
+        }
+        assert!(storage.get_as("missing", &fallback)?.is_none());
+        // The record, which cannot be decoded, keeps a value of another type
+        assert!(storage.get_as("v3", &fallback)?.is_none());
+        assert!(storage.take_corruptions().is_empty());
+        assert!(storage.has("v3"));
+        storage.destroy()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! IO path based on io_uring (Linux, `io-uring` feature). Reads and writes of many record files are
+//! queued into the ring and submitted with one syscall per batch instead of one syscall per file.
+//! If io_uring isn't available (old kernels, restricted containers), `None` is returned and the
+//! caller falls back to regular reads and writes.
+
+use io_uring::{opcode, squeue, types, IoUring};
+use log::debug;
+use std::{cell::RefCell, fs::File, io, os::unix::fs::FileExt, os::unix::io::AsRawFd};
+
+/// Number of entries of the submission queue; larger batches are submitted in parts.
+const QUEUE_DEPTH: u32 = 256;
+
+thread_local! {
+    /// Ring of the current thread; `None` if io_uring isn't available.
+    static RING: RefCell<Option<IoUring>> = RefCell::new(match IoUring::new(QUEUE_DEPTH) {
+        Ok(ring) => Some(ring),
+        Err(err) => {
+            debug!("io_uring isn't available, regular IO is used: {err}");
+            None
+        }
+    });
+}
+
+/// Returns true if waiting for completions can be repeated.
+fn retryable(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    ) || err.raw_os_error() == Some(libc::EBUSY)
+}
+
+/// Submits operations and waits for their results. The call doesn't return while some of
+/// submitted operations are in flight, because they reference buffers and files of the caller.
+///
+/// # Arguments
+///
+/// * `ring` - The ring.
+/// * `count` - Number of operations.
+/// * `entry` - Returns the operation by its index.
+///
+/// # Returns
+///
+/// * `io::Result<Vec<i32>>` - Results of operations by indexes, or an error of the ring, if no
+///   operations have been submitted.
+fn submit<F: Fn(usize) -> squeue::Entry>(
+    ring: &mut IoUring,
+    count: usize,
+    entry: F,
+) -> io::Result<Vec<i32>> {
+    let mut results = vec![0; count];
+    let mut next = 0;
+    let mut in_flight = 0;
+    while next < count || in_flight > 0 {
+        {
+            let mut sq = ring.submission();
+            while next < count {
+                // SAFETY: buffers and files of operations are owned by the caller and outlive
+                // this call, which waits for completion of all submitted operations.
+                if unsafe { sq.push(&entry(next).user_data(next as u64)) }.is_err() {
+                    // The queue is full; the rest is pushed after completions
+                    break;
+                }
+                next += 1;
+                in_flight += 1;
+            }
+        }
+        if in_flight == 0 {
+            return Err(io::Error::other("io_uring submission queue is full"));
+        }
+        match ring.submit_and_wait(in_flight) {
+            Ok(_) => {}
+            Err(err) if retryable(&err) => {}
+            Err(err) => {
+                // Kernel operations cannot be abandoned safely: they would write into freed
+                // buffers or into files, which have reused descriptors
+                log::error!("io_uring failed with {in_flight} operations in flight: {err}");
+                std::process::abort();
+            }
+        }
+        for cqe in ring.completion() {
+            match results.get_mut(cqe.user_data() as usize) {
+                Some(result) => {
+                    *result = cqe.result();
+                    in_flight -= 1;
+                }
+                None => debug!("Unexpected io_uring completion {}", cqe.user_data()),
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Converts the result of an operation into the number of processed bytes.
+fn processed(result: i32) -> io::Result<usize> {
+    if result < 0 {
+        Err(io::Error::from_raw_os_error(-result))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Reads the whole content of files.
+///
+/// # Arguments
+///
+/// * `files` - Opened files.
+///
+/// # Returns
+///
+/// * `Option<Vec<io::Result<Vec<u8>>>>` - Contents of files in the same order, or `None` if
+///   io_uring isn't available.
+pub fn read_all(files: &[File]) -> Option<Vec<io::Result<Vec<u8>>>> {
+    let mut buffers = Vec::with_capacity(files.len());
+    for file in files {
+        buffers.push(
+            file.metadata()
+                .map(|metadata| vec![0u8; metadata.len() as usize]),
+        );
+    }
+    let ptrs = buffers
+        .iter_mut()
+        .map(|buffer| match buffer {
+            Ok(buffer) => (buffer.as_mut_ptr(), buffer.len() as u32),
+            Err(_) => (std::ptr::null_mut(), 0),
+        })
+        .collect::<Vec<_>>();
+    let results = RING.with_borrow_mut(|ring| {
+        let ring = ring.as_mut()?;
+        Some(submit(ring, files.len(), |i| {
+            opcode::Read::new(types::Fd(files[i].as_raw_fd()), ptrs[i].0, ptrs[i].1)
+                .offset(0)
+                .build()
+        }))
+    })?;
+    let results = match results {
+        Ok(results) => results,
+        Err(err) => {
+            debug!("io_uring batch failed, regular IO is used: {err}");
+            return None;
+        }
+    };
+    Some(
+        buffers
+            .into_iter()
+            .zip(results)
+            .zip(files)
+            .map(|((buffer, result), file)| {
+                let mut buffer = buffer?;
+                let read = processed(result)?;
+                if read < buffer.len() {
+                    // Short read: the rest is read in a regular way
+                    file.read_exact_at(&mut buffer[read..], read as u64)?;
+                }
+                Ok(buffer)
+            })
+            .collect(),
+    )
+}
+
+/// Writes the content into each file from its beginning.
+///
+/// # Arguments
+///
+/// * `files` - Opened files and their content.
+///
+/// # Returns
+///
+/// * `Option<Vec<io::Result<()>>>` - Results of writes in the same order, or `None` if io_uring
+///   isn't available.
+pub fn write_all(files: &[(File, &[u8])]) -> Option<Vec<io::Result<()>>> {
+    let results = RING.with_borrow_mut(|ring| {
+        let ring = ring.as_mut()?;
+        Some(submit(ring, files.len(), |i| {
+            let (file, content) = &files[i];
+            opcode::Write::new(
+                types::Fd(file.as_raw_fd()),
+                content.as_ptr(),
+                content.len() as u32,
+            )
+            .offset(0)
+            .build()
+        }))
+    })?;
+    let results = match results {
+        Ok(results) => results,
+        Err(err) => {
+            debug!("io_uring batch failed, regular IO is used: {err}");
+            return None;
+        }
+    };
+    Some(
+        results
+            .into_iter()
+            .zip(files)
+            .map(|(result, (file, content))| {
+                let written = processed(result)?;
+                if written < content.len() {
+                    // Short write: the rest is written in a regular way
+                    file.write_all_at(&content[written..], written as u64)?;
+                }
+                Ok(())
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs;
+    use std::{env::temp_dir, fs::remove_dir_all};
+    use uuid::Uuid;
+
+    #[test]
+    fn uring() -> std::io::Result<()> {
+        let dir = temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir)?;
+        let contents = (0..300u32)
+            .map(|i| i.to_string().repeat(i as usize))
+            .collect::<Vec<String>>();
+        let files = contents
+            .iter()
+            .enumerate()
+            .map(|(i, content)| Ok((fs::create(dir.join(i.to_string()))?, content.as_bytes())))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let available = super::write_all(&files).is_some();
+        for result in fs::write_many(&files) {
+            result?;
+        }
+        let files = (0..contents.len())
+            .map(|i| fs::read(dir.join(i.to_string())))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        assert_eq!(super::read_all(&files).is_some(), available);
+        for (read, content) in fs::read_many(&files).into_iter().zip(contents.iter()) {
+            assert_eq!(read?, content.as_bytes());
+        }
+        remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    any::{Any, TypeId},
+    fmt,
+};
+
+use crate::{Storage, E};
+
+/// Checks the value given as `Any`; values of other types pass
+type Check = Box<dyn Fn(&dyn Any) -> Result<(), String> + Send + Sync>;
+
+/// Validator registered with `Storage::validator()` or `Storage::validator_for_prefix()`
+struct Validator {
+    /// Type of validated values
+    type_id: TypeId,
+    /// Prefix of validated keys; None to validate values of the type with any key
+    prefix: Option<String>,
+    check: Check,
+}
+
+/// Validators of values of the storage
+#[derive(Default)]
+pub(crate) struct Validators {
+    validators: Vec<Validator>,
+}
+
+impl fmt::Debug for Validators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Validators")
+            .field("validators", &self.validators.len())
+            .finish()
+    }
+}
+
+impl Validators {
+    fn add<V: 'static, F: Fn(&V) -> Result<(), String> + Send + Sync + 'static>(
+        &mut self,
+        prefix: Option<String>,
+        check: F,
+    ) {
+        self.validators.push(Validator {
+            type_id: TypeId::of::<V>(),
+            prefix,
+            check: Box::new(move |value| value.downcast_ref::<V>().map(&check).unwrap_or(Ok(()))),
+        });
+    }
+
+    /// Runs validators matching the key and the type of the value.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key (normalized).
+    /// * `value` - The value to be stored.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), E>` - Returns `E::InvalidValue` with the reason of the first failed
+    ///   validator, or Ok(()).
+    pub(crate) fn check<V: 'static>(&self, key: &str, value: &V) -> Result<(), E> {
+        self.validators
+            .iter()
+            .filter(|validator| {
+                validator.type_id == TypeId::of::<V>()
+                    && validator
+                        .prefix
+                        .as_ref()
+                        .is_none_or(|prefix| key.starts_with(prefix.as_str()))
+            })
+            .try_for_each(|validator| {
+                (validator.check)(value).map_err(|reason| E::InvalidValue {
+                    key: key.to_owned(),
+                    reason,
+                })
+            })
+    }
+}
+
+impl Storage {
+    /// Registers the validator of values of the type `V`. Validators run in `set()` (and methods
+    /// based on it) before the value is serialized, so invalid values never reach the disk; the
+    /// failed call returns `E::InvalidValue` with the reason returned by the validator. Values of
+    /// other types aren't checked by the validator. Records written in bulk (e.g. `apply()`,
+    /// merging, unpacking) aren't validated.
+    ///
+    /// # Arguments
+    ///
+    /// * `check` - Returns Ok(()) if the value is valid, or the reason why it isn't.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bstorage::{Storage, E};
+    /// use serde::{Deserialize, Serialize};
+    /// use std::env::temp_dir;
+    /// use uuid::Uuid;
+    ///
+    /// #[derive(Deserialize, Serialize)]
+    /// struct Settings {
+    ///     threads: usize,
+    /// }
+    ///
+    /// let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string())).unwrap();
+    /// storage.validator::<Settings, _>(|settings| {
+    ///     if settings.threads == 0 {
+    ///         Err(String::from("at least one thread is required"))
+    ///     } else {
+    ///         Ok(())
+    ///     }
+    /// });
+    /// storage.set("settings", &Settings { threads: 4 }).unwrap();
+    /// assert!(matches!(
+    ///     storage.set("settings", &Settings { threads: 0 }),
+    ///     Err(E::InvalidValue { .. })
+    /// ));
+    /// assert_eq!(storage.get::<Settings, _>("settings").unwrap().unwrap().threads, 4);
+    /// storage.destroy().unwrap();
+    /// ```
+    pub fn validator<V: 'static, F: Fn(&V) -> Result<(), String> + Send + Sync + 'static>(
+        &mut self,
+        check: F,
+    ) {
+        self.validators.add(None, check);
+    }
+
+    /// Registers the validator of values of the type `V` stored with keys, which start with the
+    /// prefix (see `Storage::validator()`).
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Prefix of keys (normalized in the same way as keys).
+    /// * `check` - Returns Ok(()) if the value is valid, or the reason why it isn't.
+    pub fn validator_for_prefix<
+        V: 'static,
+        P: AsRef<str>,
+        F: Fn(&V) -> Result<(), String> + Send + Sync + 'static,
+    >(
+        &mut self,
+        prefix: P,
+        check: F,
+    ) {
+        let prefix = self.normalized(prefix.as_ref().into()).into_owned();
+        self.validators.add(Some(prefix), check);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Storage, E};
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    #[test]
+    fn validation() -> Result<(), E> {
+        let mut storage = Storage::create(temp_dir().join(Uuid::new_v4().to_string()))?;
+        storage.validator::<String, _>(|value| {
+            if value.is_empty() {
+                Err(String::from("empty"))
+            } else {
+                Ok(())
+            }
+        });
+        storage.validator_for_prefix::<u32, _, _>("port/", |port| {
+            if *port > 0 && *port <= u16::MAX as u32 {
+                Ok(())
+            } else {
+                Err(format!("{port} is out of range"))
+            }
+        });
+        storage.set("name", &String::from("value"))?;
+        assert!(matches!(
+            storage.set("name", &String::new()),
+            Err(E::InvalidValue { ref key, ref reason }) if key == "name" && reason == "empty"
+        ));
+        assert_eq!(
+            storage.get::<String, _>("name")?,
+            Some(String::from("value"))
+        );
+        storage.set("port/http", &80u32)?;
+        assert!(storage.set("port/other", &70_000u32).is_err());
+        assert!(!storage.has("port/other"));
+        // The prefix validator doesn't apply to other keys and types
+        storage.set("count", &70_000u32)?;
+        storage.set("port/name", &String::from("http"))?;
+        storage.destroy()?;
+        Ok(())
+    }
+}
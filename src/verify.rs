@@ -0,0 +1,80 @@
+use std::{fmt, path::PathBuf};
+
+/// A problem found by verification of the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The record file referenced by the map doesn't exist.
+    Missing,
+    /// The record file is empty.
+    Empty,
+    /// The header of the record is damaged or the checksum doesn't match its content.
+    Checksum,
+    /// The SHA-256 digest of the value doesn't match the digest kept in the map (see
+    /// `StorageOptions::digests`).
+    Digest,
+    /// The value cannot be decoded to compute its digest (e.g. a wrong key of encryption or a
+    /// missing transformer). Contains the error message.
+    Undecodable(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "file doesn't exist"),
+            Self::Empty => write!(f, "file is empty"),
+            Self::Checksum => write!(f, "checksum doesn't match"),
+            Self::Digest => write!(f, "digest doesn't match"),
+            Self::Undecodable(err) => write!(f, "value cannot be decoded: {err}"),
+        }
+    }
+}
+
+/// A problem with a particular record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Key of the record
+    pub key: String,
+    /// Path of the record file
+    pub path: PathBuf,
+    /// What's wrong with the record
+    pub problem: Problem,
+}
+
+/// The result of verification of the storage (see `Storage::verify()` and `Storage::open_strict()`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of checked records
+    pub checked: usize,
+    /// Found problems
+    pub issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems have been found.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - true if no problems have been found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} problem(s) in {} record(s)",
+            self.issues.len(),
+            self.checked
+        )?;
+        for issue in self.issues.iter() {
+            write!(
+                f,
+                "; \"{}\" ({:?}): {}",
+                issue.key, issue.path, issue.problem
+            )?;
+        }
+        Ok(())
+    }
+}
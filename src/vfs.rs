@@ -0,0 +1,365 @@
+use std::{
+    fmt::Debug,
+    io::{self, Read},
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{fs, Durability, Retrying, StorageOptions};
+
+/// `FileSystem` provides file primitives used by the core of the storage: reading and writing of
+/// record files and of the map, creating the storage folder. By default (`StdFs`) `std::fs` is
+/// used; targets with their own file system (e.g. littlefs on flash of a microcontroller) can
+/// implement this trait and pass it with `StorageOptions::fs`.
+///
+/// Only the core of the storage (`create`, `open`, `get`, `set`, `remove`, `clear`, `Search`)
+/// with the synchronous map (`FlushMode::Sync`) goes through this trait. Features, which work
+/// with files directly (bundles, archives, recovery, child and lazy storages, the background
+/// flusher, the pool of open files, io_uring, locks), keep using `std::fs`; the pool of open files and batched
+/// reads are not used with a custom file system.
+pub trait FileSystem: Debug + Send + Sync {
+    /// Reads the whole content of the file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Reads at most `len` first bytes of the file. Reads the whole file and cuts it by default.
+    fn read_head(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let mut content = self.read(path)?;
+        content.truncate(len);
+        Ok(content)
+    }
+
+    /// Replaces the content of the file (creates the file if it doesn't exist). The file should
+    /// be replaced atomically: a reader should see either the previous or the new content.
+    /// `durability` defines whether the file should be synced to the disk.
+    fn write(&self, path: &Path, content: &[u8], durability: Durability) -> io::Result<()>;
+
+    /// Removes the file.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Checks if the file or the folder exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Returns the size of the file in bytes.
+    fn size(&self, path: &Path) -> io::Result<u64>;
+
+    /// Returns the space allocated for the file in bytes. Returns the size of the file by
+    /// default.
+    fn allocated(&self, path: &Path) -> io::Result<u64> {
+        self.size(path)
+    }
+
+    /// Creates the folder with all its parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Syncs the folder, so its entries survive a power loss. Does nothing by default.
+    fn sync_dir(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Removes the folder with all its content.
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+}
+
+/// `StdFs` is the default implementation of `FileSystem` on top of `std::fs`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(fs::long_path(path))
+    }
+
+    fn read_head(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let mut content = Vec::with_capacity(len);
+        std::fs::File::open(fs::long_path(path))?
+            .take(len as u64)
+            .read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    fn write(&self, path: &Path, content: &[u8], durability: Durability) -> io::Result<()> {
+        fs::replace(path, content, durability)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(fs::long_path(path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        fs::long_path(path).exists()
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::long_path(path).metadata()?.len())
+    }
+
+    fn allocated(&self, path: &Path) -> io::Result<u64> {
+        fs::allocated(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(fs::long_path(path))
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        fs::sync_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_dir_all(fs::long_path(path))
+    }
+}
+
+/// `StdFs`, which creates and opens files and folders according to `StorageOptions::permissions`
+/// and `StorageOptions::symlinks`.
+#[derive(Debug)]
+struct ConfiguredFs {
+    access: fs::Access,
+    /// Permissions of created folders
+    dir: Option<u32>,
+}
+
+impl FileSystem for ConfiguredFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        fs::open_with(path, self.access)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    fn read_head(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let mut content = Vec::with_capacity(len);
+        fs::open_with(path, self.access)?
+            .take(len as u64)
+            .read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    fn write(&self, path: &Path, content: &[u8], durability: Durability) -> io::Result<()> {
+        fs::replace_with(path, content, durability, self.access)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        StdFs.remove(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        StdFs.exists(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        StdFs.size(path)
+    }
+
+    fn allocated(&self, path: &Path) -> io::Result<u64> {
+        StdFs.allocated(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path, self.dir)
+    }
+
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        StdFs.sync_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        StdFs.remove_dir_all(path)
+    }
+}
+
+/// Returns the file system given with options (or the shared instance of `StdFs`), which retries
+/// operations if `StorageOptions::retry` is set.
+///
+/// # Arguments
+///
+/// * `options` - Options of the storage.
+///
+/// # Returns
+///
+/// * `Arc<dyn FileSystem>` - The file system to use.
+pub(crate) fn resolve(options: &StorageOptions) -> Arc<dyn FileSystem> {
+    static STD: OnceLock<Arc<dyn FileSystem>> = OnceLock::new();
+    let access = fs::Access::new(options);
+    let fs = match options.fs.clone() {
+        Some(fs) => fs,
+        None if access != fs::Access::default() || options.permissions.dir.is_some() => {
+            Arc::new(ConfiguredFs {
+                access,
+                dir: options.permissions.dir,
+            })
+        }
+        None => STD.get_or_init(|| Arc::new(StdFs)).clone(),
+    };
+    match options.retry.as_ref() {
+        Some(policy) => Arc::new(Retrying {
+            inner: fs,
+            policy: policy.clone(),
+        }),
+        None => fs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Durability, FileSystem, Search, Storage, StorageOptions, E};
+    use std::{
+        collections::{HashMap, HashSet},
+        env::temp_dir,
+        io,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+    use uuid::Uuid;
+
+    /// Keeps files and folders in memory
+    #[derive(Debug, Default)]
+    struct Flash {
+        files: Mutex<HashMap<PathBuf, Vec<u8>>>,
+        dirs: Mutex<HashSet<PathBuf>>,
+    }
+
+    impl FileSystem for Flash {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.files
+                .lock()
+                .unwrap()
+                .get(path)
+                .cloned()
+                .ok_or(io::ErrorKind::NotFound.into())
+        }
+
+        fn write(&self, path: &Path, content: &[u8], _durability: Durability) -> io::Result<()> {
+            self.files
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), content.to_vec());
+            Ok(())
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            self.files
+                .lock()
+                .unwrap()
+                .remove(path)
+                .map(|_| ())
+                .ok_or(io::ErrorKind::NotFound.into())
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.files.lock().unwrap().contains_key(path)
+                || self.dirs.lock().unwrap().contains(path)
+        }
+
+        fn size(&self, path: &Path) -> io::Result<u64> {
+            self.read(path).map(|content| content.len() as u64)
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.dirs.lock().unwrap().insert(path.to_path_buf());
+            Ok(())
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.dirs.lock().unwrap().remove(path);
+            self.files
+                .lock()
+                .unwrap()
+                .retain(|file, _| !file.starts_with(path));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn custom_fs() -> Result<(), E> {
+        let flash = Arc::new(Flash::default());
+        let options = StorageOptions {
+            fs: Some(flash.clone()),
+            ..Default::default()
+        };
+        let cwd = temp_dir().join(Uuid::new_v4().to_string());
+        let mut storage = Storage::create_with_options(&cwd, options.clone())?;
+        for i in 0..50u32 {
+            storage.set(i.to_string(), &i)?;
+        }
+        assert!(storage.remove("0")?);
+        drop(storage);
+        assert!(!cwd.exists());
+        // Map and records
+        assert_eq!(flash.files.lock().unwrap().len(), 50);
+        let mut storage = Storage::open_with_options(&cwd, options)?;
+        assert_eq!(storage.len(), 49);
+        assert_eq!(storage.get::<u32, _>("7")?, Some(7));
+        assert_eq!(storage.filter(|v: &u32| *v < 10)?.len(), 9);
+        storage.destroy()?;
+        assert!(flash.files.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions() -> Result<(), E> {
+        use crate::Permissions;
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path: &Path| path.metadata().unwrap().permissions().mode() & 0o777;
+        let mut storage = Storage::create_with_options(
+            temp_dir().join(Uuid::new_v4().to_string()),
+            StorageOptions {
+                permissions: Permissions {
+                    file: Some(0o600),
+                    dir: Some(0o700),
+                },
+                ..Default::default()
+            },
+        )?;
+        storage.set("secret", &String::from("value"))?;
+        let mut child = storage.child("child")?;
+        child.set("secret", &String::from("value"))?;
+        assert_eq!(mode(storage.cwd()), 0o700);
+        assert_eq!(mode(child.cwd()), 0o700);
+        for storage in [&storage, &child] {
+            assert_eq!(mode(&storage.cwd().join("map.bstorage")), 0o600);
+            assert_eq!(mode(&storage.fields["secret"].path(storage.cwd())), 0o600);
+        }
+        drop(child);
+        storage.destroy()?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() -> Result<(), E> {
+        use crate::SymlinkPolicy;
+        use std::os::unix::fs::symlink;
+
+        let options = |symlinks| StorageOptions {
+            symlinks,
+            ..Default::default()
+        };
+        let real = temp_dir().join(Uuid::new_v4().to_string());
+        let link = temp_dir().join(Uuid::new_v4().to_string());
+        let outside = temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::write(&outside, b"outside")?;
+        let mut storage = Storage::create(&real)?;
+        storage.set("a", &1u8)?;
+        symlink(&real, &link)?;
+        assert!(matches!(
+            Storage::open_with_options(&link, options(SymlinkPolicy::Reject)),
+            Err(E::SymlinkRejected(_))
+        ));
+        let mut storage = Storage::open_with_options(&link, options(SymlinkPolicy::NoFollow))?;
+        assert_eq!(storage.cwd(), &real.canonicalize()?);
+        // The record file is swapped with a symlink to a file outside of the storage
+        let record = storage.fields["a"].path(storage.cwd());
+        std::fs::remove_file(&record)?;
+        symlink(&outside, &record)?;
+        assert!(storage.get::<u8, _>("a").is_err());
+        storage.set("a", &2u8)?;
+        assert_eq!(storage.get::<u8, _>("a")?, Some(2));
+        assert_eq!(std::fs::read(&outside)?, b"outside");
+        storage.destroy()?;
+        std::fs::remove_file(link)?;
+        std::fs::remove_file(outside)?;
+        Ok(())
+    }
+}